@@ -0,0 +1,364 @@
+use std::fs::{self, File, OpenOptions};
+use std::io::{self, BufRead, Write};
+use std::path::{Path, PathBuf};
+use std::str::FromStr;
+use std::sync::atomic::{AtomicBool, AtomicU64, Ordering};
+use std::sync::mpsc::{self, Sender};
+use std::sync::{Arc, Mutex};
+use std::thread;
+use std::time::{Duration, Instant};
+
+use anyhow::{bail, Result};
+
+use crate::log::warning;
+use crate::protocol::command::CommandRequest;
+use crate::protocol::resp::{self, ProtocolError, RequestLimits, RESP};
+
+/// interval of the background fsync of everysec
+const FSYNC_INTERVAL: Duration = Duration::from_secs(1);
+/// writes wait at most this long for a slow background fsync, then they are written while it still runs
+const MAX_FSYNC_POSTPONE: Duration = Duration::from_secs(2);
+
+/// when the written commands are flushed to the disk, as configured by appendfsync
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub(crate) enum FsyncPolicy {
+    /// after every write, before the command is replied
+    Always,
+    /// once per second in a background thread, at most a second of writes is lost
+    EverySec,
+    /// when the operating system flushes the file
+    No,
+}
+
+impl FromStr for FsyncPolicy {
+    type Err = anyhow::Error;
+
+    fn from_str(policy: &str) -> Result<Self, Self::Err> {
+        match policy.to_lowercase().as_str() {
+            "always" => Ok(FsyncPolicy::Always),
+            "everysec" => Ok(FsyncPolicy::EverySec),
+            "no" => Ok(FsyncPolicy::No),
+            _ => bail!("invalid appendfsync policy {}", policy),
+        }
+    }
+}
+
+/// commands read from an append only file
+#[derive(Debug, PartialEq)]
+pub(crate) struct AofContents {
+    /// number of complete commands
+    pub(crate) commands: u64,
+    /// byte offset where the last complete command ends
+    pub(crate) valid_len: u64,
+    /// the file continues after the last complete command with a command which is cut off
+    pub(crate) truncated: bool,
+}
+
+/// reads the commands of an append only file in order, passing each of them to `apply`.
+/// a file ending within a command is accepted up to the last complete command with `load_truncated`,
+/// else it fails like a malformed command, with the offset where the last complete command ends
+pub(crate) fn read_commands(
+    mut reader: impl BufRead,
+    load_truncated: bool,
+    mut apply: impl FnMut(CommandRequest) -> Result<()>,
+) -> Result<AofContents> {
+    let limits = RequestLimits::default();
+    let mut contents = AofContents { commands: 0, valid_len: 0, truncated: false };
+    while !reader.fill_buf()?.is_empty() {
+        let (len, message) = match resp::read_message(&mut reader, &limits) {
+            Ok((len, message)) => (len, message.expect("message decoded")),
+            // the file ended within the command, anything else is malformed or failed to be read
+            Err(err) if !err.is::<ProtocolError>() && !err.is::<io::Error>() => {
+                contents.truncated = true;
+                break;
+            }
+            Err(err) => bail!("AOF is invalid at byte offset {} after {} commands: {}", contents.valid_len, contents.commands, err),
+        };
+        let command = match CommandRequest::try_from(message) {
+            Ok(command) => command,
+            Err(err) => bail!("AOF is invalid at byte offset {} after {} commands: {}", contents.valid_len, contents.commands, err),
+        };
+        apply(command)?;
+        contents.commands += 1;
+        contents.valid_len += len as u64;
+    }
+    if contents.truncated && !load_truncated {
+        bail!(
+            "AOF is truncated after {} commands, the last complete command ends at byte offset {}. aof-load-truncated is no",
+            contents.commands, contents.valid_len,
+        );
+    }
+    Ok(contents)
+}
+
+/// append only file open for appending the replicated commands
+struct AofFile {
+    file: File,
+    path: PathBuf,
+    policy: FsyncPolicy,
+    /// commands appended which are not written to the file yet
+    buffer: Vec<u8>,
+    /// commands appended since the rewrite of the file started, which are added to the rewritten file
+    rewrite_buffer: Option<Vec<u8>>,
+    /// since when writes wait for the background fsync to complete
+    postponed_since: Option<Instant>,
+    last_fsync: Instant,
+    /// commands were written since the last fsync
+    unsynced: bool,
+    size: u64,
+    /// size of the file when it was opened or last rewritten
+    base_size: u64,
+}
+
+/// append only file of the commands changing the store, written with the fsync policy of appendfsync.
+/// the fsync of everysec runs in a background thread, so that a slow disk does not block the clients
+#[derive(Clone)]
+pub(crate) struct AppendOnlyFile {
+    file: Arc<Mutex<Option<AofFile>>>,
+    fsyncs: Sender<File>,
+    /// number of fsyncs handed to the background thread and not completed yet
+    pending_fsyncs: Arc<AtomicU64>,
+    /// number of times writes waited for a background fsync longer than they may
+    delayed_fsyncs: Arc<AtomicU64>,
+    last_write_ok: Arc<AtomicBool>,
+}
+
+impl AppendOnlyFile {
+    pub(crate) fn start() -> Result<Self> {
+        let (tx, rx) = mpsc::channel::<File>();
+        let pending_fsyncs = Arc::new(AtomicU64::new(0));
+        let thread_pending = pending_fsyncs.clone();
+        thread::Builder::new()
+            .name("aof-fsync".to_string())
+            .spawn(move || {
+                for file in rx {
+                    if let Err(err) = file.sync_data() {
+                        warning!("fsync of the AOF file failed: {}", err);
+                    }
+                    thread_pending.fetch_sub(1, Ordering::Relaxed);
+                }
+            })?;
+        Ok(AppendOnlyFile {
+            file: Arc::new(Mutex::new(None)),
+            fsyncs: tx,
+            pending_fsyncs,
+            delayed_fsyncs: Arc::new(AtomicU64::new(0)),
+            last_write_ok: Arc::new(AtomicBool::new(true)),
+        })
+    }
+
+    /// starts appending to the file with the policy, or only changes the policy if the file is open already
+    pub(crate) fn open(&self, path: &Path, policy: FsyncPolicy) -> Result<()> {
+        let mut aof = self.file.lock().unwrap();
+        if let Some(aof) = aof.as_mut().filter(|aof| aof.path == path) {
+            aof.policy = policy;
+            return Ok(());
+        }
+        let file = OpenOptions::new().create(true).append(true).open(path)?;
+        let size = file.metadata()?.len();
+        *aof = Some(AofFile {
+            file,
+            path: path.to_path_buf(),
+            policy,
+            buffer: vec![],
+            rewrite_buffer: None,
+            postponed_since: None,
+            last_fsync: Instant::now(),
+            unsynced: false,
+            size,
+            base_size: size,
+        });
+        Ok(())
+    }
+
+    /// stops appending, after writing and flushing the commands appended so far
+    pub(crate) fn close(&self) {
+        let mut aof = self.file.lock().unwrap();
+        if let Some(mut closed) = aof.take() {
+            self.write(&mut closed, true);
+            if let Err(err) = closed.file.sync_data() {
+                warning!("fsync of the AOF file failed: {}", err);
+            }
+        }
+    }
+
+    /// appends the command if the file is open, with appendfsync always it is on the disk once this returns
+    pub(crate) fn append(&self, message: &RESP) -> Result<()> {
+        let mut aof = self.file.lock().unwrap();
+        let Some(aof) = aof.as_mut() else {
+            return Ok(());
+        };
+        let start = aof.buffer.len();
+        message.write_to(&mut aof.buffer)?;
+        if let Some(rewrite_buffer) = aof.rewrite_buffer.as_mut() {
+            rewrite_buffer.extend_from_slice(&aof.buffer[start..]);
+        }
+        self.write(aof, false);
+        Ok(())
+    }
+
+    /// writes the commands postponed by a slow fsync and starts the background fsync of everysec, on every tick
+    pub(crate) fn flush(&self) {
+        if let Some(aof) = self.file.lock().unwrap().as_mut() {
+            self.write(aof, false);
+        }
+    }
+
+    /// writes the buffered commands to the file and flushes them as the policy requires.
+    /// with everysec the writes are postponed while a background fsync runs, as writing to a file being synced
+    /// blocks, unless forced or postponed for too long
+    fn write(&self, aof: &mut AofFile, force: bool) {
+        let fsync_pending = self.pending_fsyncs.load(Ordering::Relaxed) > 0;
+        if aof.policy == FsyncPolicy::EverySec && fsync_pending && !force && !aof.buffer.is_empty() {
+            let postponed_since = *aof.postponed_since.get_or_insert_with(Instant::now);
+            if postponed_since.elapsed() < MAX_FSYNC_POSTPONE {
+                return;
+            }
+            self.delayed_fsyncs.fetch_add(1, Ordering::Relaxed);
+            warning!("Asynchronous AOF fsync is taking too long (disk is busy?). Writing the AOF buffer without waiting for fsync to complete, this may slow down Redis.");
+        }
+        aof.postponed_since = None;
+        let written = !aof.buffer.is_empty();
+        if written {
+            if let Err(err) = aof.file.write_all(&aof.buffer) {
+                // the commands stay buffered and are written again with the next write
+                warning!("Error writing to the AOF file: {}", err);
+                self.last_write_ok.store(false, Ordering::Relaxed);
+                return;
+            }
+            aof.size += aof.buffer.len() as u64;
+            aof.buffer.clear();
+            aof.unsynced = true;
+            self.last_write_ok.store(true, Ordering::Relaxed);
+        }
+        match aof.policy {
+            FsyncPolicy::Always if written => {
+                if let Err(err) = aof.file.sync_data() {
+                    warning!("fsync of the AOF file failed: {}", err);
+                    self.last_write_ok.store(false, Ordering::Relaxed);
+                }
+                (aof.last_fsync, aof.unsynced) = (Instant::now(), false);
+            }
+            FsyncPolicy::EverySec if aof.unsynced && !fsync_pending && aof.last_fsync.elapsed() >= FSYNC_INTERVAL => {
+                match aof.file.try_clone() {
+                    Ok(file) => {
+                        self.pending_fsyncs.fetch_add(1, Ordering::Relaxed);
+                        if self.fsyncs.send(file).is_err() {
+                            self.pending_fsyncs.fetch_sub(1, Ordering::Relaxed);
+                        }
+                    }
+                    Err(err) => warning!("fsync of the AOF file failed: {}", err),
+                }
+                (aof.last_fsync, aof.unsynced) = (Instant::now(), false);
+            }
+            _ => {}
+        }
+    }
+
+    /// the file is rewritten from a snapshot taken now, the commands appended from now on are kept for the rewritten file
+    pub(crate) fn start_rewrite(&self) {
+        if let Some(aof) = self.file.lock().unwrap().as_mut() {
+            aof.rewrite_buffer = Some(vec![]);
+        }
+    }
+
+    /// replaces the file with the rewritten one, after adding the commands appended during the rewrite to it.
+    /// appending continues in the rewritten file
+    pub(crate) fn finish_rewrite(&self, rewritten: &Path, path: &Path) -> Result<()> {
+        let mut aof = self.file.lock().unwrap();
+        let Some(open) = aof.as_mut() else {
+            fs::rename(rewritten, path)?;
+            return Ok(());
+        };
+        if let Some(rewrite_buffer) = open.rewrite_buffer.take() {
+            let mut file = OpenOptions::new().append(true).open(rewritten)?;
+            file.write_all(&rewrite_buffer)?;
+            file.sync_all()?;
+        }
+        fs::rename(rewritten, path)?;
+        // the commands waiting to be written are in the rewritten file already
+        let file = OpenOptions::new().append(true).open(path)?;
+        let size = file.metadata()?.len();
+        open.file = file;
+        open.path = path.to_path_buf();
+        open.buffer.clear();
+        open.postponed_since = None;
+        (open.size, open.base_size) = (size, size);
+        Ok(())
+    }
+
+    /// the rewrite failed, the commands appended during it are only in the current file
+    pub(crate) fn abort_rewrite(&self) {
+        if let Some(aof) = self.file.lock().unwrap().as_mut() {
+            aof.rewrite_buffer = None;
+        }
+    }
+
+    /// fields of the append only file in INFO persistence, the sizes only while it is open
+    pub(crate) fn info(&self) -> Vec<(&'static str, String)> {
+        let aof = self.file.lock().unwrap();
+        let mut fields = vec![
+            ("aof_enabled", (aof.is_some() as u8).to_string()),
+            ("aof_last_write_status", if self.last_write_ok.load(Ordering::Relaxed) { "ok" } else { "err" }.to_string()),
+        ];
+        if let Some(aof) = aof.as_ref() {
+            fields.extend([
+                ("aof_current_size", aof.size.to_string()),
+                ("aof_base_size", aof.base_size.to_string()),
+                ("aof_buffer_length", aof.buffer.len().to_string()),
+                ("aof_pending_bio_fsync", self.pending_fsyncs.load(Ordering::Relaxed).to_string()),
+                ("aof_delayed_fsync", self.delayed_fsyncs.load(Ordering::Relaxed).to_string()),
+            ]);
+        }
+        fields
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::protocol::command::Command;
+
+    const SET: &[u8] = b"*3\r\n$3\r\nSET\r\n$3\r\nkey\r\n$5\r\nvalue\r\n";
+    const DEL: &[u8] = b"*2\r\n$3\r\nDEL\r\n$3\r\nkey\r\n";
+
+    fn read(aof: &[u8], load_truncated: bool) -> Result<(AofContents, Vec<CommandRequest>)> {
+        let mut commands = vec![];
+        let contents = read_commands(aof, load_truncated, |command| {
+            commands.push(command);
+            Ok(())
+        })?;
+        Ok((contents, commands))
+    }
+
+    #[test]
+    fn reads_the_commands_in_order() {
+        let (contents, commands) = read(&[SET, DEL].concat(), false).unwrap();
+        assert_eq!(contents, AofContents { commands: 2, valid_len: (SET.len() + DEL.len()) as u64, truncated: false });
+        assert_eq!(commands.iter().map(|command| command.0).collect::<Vec<_>>(), [Command::SET, Command::DEL]);
+    }
+
+    #[test]
+    fn truncated_tail_is_accepted_with_load_truncated() {
+        for cut in 1..DEL.len() {
+            let (contents, commands) = read(&[SET, &DEL[..cut]].concat(), true).unwrap();
+            assert_eq!(contents, AofContents { commands: 1, valid_len: SET.len() as u64, truncated: true }, "cut after {}", cut);
+            assert_eq!(commands.len(), 1);
+        }
+    }
+
+    #[test]
+    fn truncated_tail_is_refused_without_load_truncated() {
+        let err = read(&[SET, &DEL[..10]].concat(), false).unwrap_err();
+        assert!(err.to_string().contains(&format!("byte offset {}", SET.len())), "{}", err);
+    }
+
+    #[test]
+    fn malformed_command_is_refused() {
+        let aof = [SET, b"*2\r\n$x\r\n", DEL].concat();
+        for load_truncated in [false, true] {
+            let err = read(&aof, load_truncated).unwrap_err();
+            assert!(err.to_string().contains(&format!("invalid at byte offset {}", SET.len())), "{}", err);
+        }
+    }
+}
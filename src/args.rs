@@ -1,21 +1,47 @@
+use std::borrow::Cow;
 use std::str::FromStr;
 
-/// finds a name in a list of strings and returns the following value if it exists.
+/// argument of a command as sent by the client: text for names, options and numbers,
+/// but any bytes for keys, members and values
+pub trait Arg {
+    /// parses the argument as text, an argument which is not text fails to parse like invalid text does
+    fn parse<R: FromStr>(&self) -> Result<R, R::Err>;
+
+    /// the argument in upper case, for matching the names of subcommands and options
+    fn to_uppercase(&self) -> String;
+
+    /// the argument as text for messages and logs, with the bytes which are not text replaced
+    fn text(&self) -> Cow<'_, str>;
+}
+
+impl Arg for [u8] {
+    fn parse<R: FromStr>(&self) -> Result<R, R::Err> {
+        self.text().parse::<R>()
+    }
+
+    fn to_uppercase(&self) -> String {
+        self.text().to_uppercase()
+    }
+
+    fn text(&self) -> Cow<'_, str> {
+        String::from_utf8_lossy(self)
+    }
+}
+
+/// finds a name in a list of arguments and returns the following value if it exists.
 ///
 ///  E.g. for arguments ["--port", "123"] it returns "123"
-pub fn named_option<R: FromStr>(args: &[String], name: &str) -> Result<Option<R>, R::Err> {
-    let option_name = name.to_uppercase();
+pub fn named_option<R: FromStr>(args: &[impl AsRef<[u8]>], name: &str) -> Result<Option<R>, R::Err> {
     args.iter()
-        .position(|a| *a.to_uppercase() == option_name)
+        .position(|a| a.as_ref().eq_ignore_ascii_case(name.as_bytes()))
         .and_then(|i| args.get(i + 1))
-        .map(|a| a.parse::<R>())
+        .map(|a| a.as_ref().parse::<R>())
         .transpose()
 }
 
-/// finds a name in a list of strings and returns the following values
-pub fn named_option_list<'a>(params: &'a [String], name: &str) -> Option<&'a [String]> {
-    let option_name = name.to_uppercase();
+/// finds a name in a list of arguments and returns the following values
+pub fn named_option_list<'a, T: AsRef<[u8]>>(params: &'a [T], name: &str) -> Option<&'a [T]> {
     params.iter()
-        .position(|e| e.to_string().to_uppercase() == option_name)
+        .position(|e| e.as_ref().eq_ignore_ascii_case(name.as_bytes()))
         .map(|i| &params[i + 1..])
 }
@@ -0,0 +1,101 @@
+use std::borrow::Cow;
+use std::fs::{File, OpenOptions};
+use std::io::{BufWriter, Write};
+use std::net::SocketAddr;
+use std::sync::{Arc, Mutex};
+use std::time::SystemTime;
+
+use anyhow::Result;
+
+use crate::args::Arg;
+use crate::config;
+use crate::protocol::command::{Command, CommandRequest};
+
+/// structured log of the administrative commands executed by clients, one json object per line
+#[derive(Clone, Default)]
+pub struct AuditLog(Arc<Mutex<Option<LogFile>>>);
+
+/// path of the open log file with its writer
+type LogFile = (String, BufWriter<File>);
+
+impl AuditLog {
+    /// appends an entry to the log file at the path, an empty path disables the log.
+    /// the file is reopened when the path changes
+    pub fn log(&self, path: &str, client: &SocketAddr, command: &CommandRequest) -> Result<()> {
+        let mut log = self.0.lock().unwrap();
+        if path.is_empty() {
+            *log = None;
+            return Ok(());
+        }
+        let reopen = match log.as_ref() {
+            Some((current, _)) => current != path,
+            None => true,
+        };
+        if reopen {
+            let file = OpenOptions::new().create(true).append(true).open(path)?;
+            *log = Some((path.to_string(), BufWriter::new(file)));
+        }
+        let (_, writer) = log.as_mut().expect("log is open");
+
+        let time_ms = SystemTime::now().duration_since(SystemTime::UNIX_EPOCH)?.as_millis();
+        let args = redacted_args(command).iter()
+            .map(|arg| format!("\"{}\"", json_escape(arg)))
+            .collect::<Vec<String>>()
+            .join(",");
+        writeln!(writer, "{{\"time_ms\":{},\"client\":\"{}\",\"command\":\"{}\",\"args\":[{}]}}",
+                 time_ms, client, command.0, args)?;
+        writer.flush()?;
+        Ok(())
+    }
+}
+
+/// arguments of the command, with the values of sensitive parameters set by CONFIG SET replaced
+fn redacted_args(command: &CommandRequest) -> Vec<Cow<'_, str>> {
+    let mut args = command.1.iter().map(|arg| arg.text()).collect::<Vec<Cow<str>>>();
+    if let (Command::CONFIG, [subcommand, pairs @ ..]) = (&command.0, &mut args[..]) {
+        if subcommand.eq_ignore_ascii_case("SET") {
+            for pair in pairs.chunks_mut(2) {
+                if let [name, value] = pair {
+                    if config::is_sensitive(name) {
+                        *value = Cow::Borrowed("(redacted)");
+                    }
+                }
+            }
+        }
+    }
+    args
+}
+
+fn json_escape(value: &str) -> String {
+    let mut escaped = String::with_capacity(value.len());
+    for c in value.chars() {
+        match c {
+            '"' => escaped.push_str("\\\""),
+            '\\' => escaped.push_str("\\\\"),
+            '\n' => escaped.push_str("\\n"),
+            '\r' => escaped.push_str("\\r"),
+            '\t' => escaped.push_str("\\t"),
+            c if (c as u32) < 0x20 => escaped.push_str(&format!("\\u{:04x}", c as u32)),
+            c => escaped.push(c),
+        }
+    }
+    escaped
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn args(command: Command, args: &[&str]) -> Vec<String> {
+        let command = CommandRequest::new(command, args.iter().map(|arg| arg.as_bytes().to_vec()).collect());
+        redacted_args(&command).iter().map(|arg| arg.to_string()).collect()
+    }
+
+    #[test]
+    fn sensitive_config_values_are_redacted() {
+        assert_eq!(args(Command::CONFIG, &["set", "requirepass", "secret", "hz", "10", "MASTERAUTH", "other"]),
+                   ["set", "requirepass", "(redacted)", "hz", "10", "MASTERAUTH", "(redacted)"]);
+        assert_eq!(args(Command::CONFIG, &["GET", "requirepass"]), ["GET", "requirepass"]);
+        assert_eq!(args(Command::CONFIG, &["SET", "requirepass"]), ["SET", "requirepass"]);
+    }
+}
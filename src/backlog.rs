@@ -0,0 +1,95 @@
+/// most recent bytes of the replication stream in a circular buffer of a fixed size, addressed by their offset
+/// in the whole stream. replicas continuing the stream from an offset still in the buffer are sent the bytes after it,
+/// older bytes are overwritten by the new ones
+pub(crate) struct ReplicationBacklog {
+    buffer: Vec<u8>,
+    /// size of the buffer as configured by repl-backlog-size, the buffer grows up to it as the stream is written
+    size: usize,
+    /// position in the buffer the next byte is written at
+    next: usize,
+    /// offset of the stream after its last byte, the master_repl_offset
+    offset: usize,
+}
+
+impl ReplicationBacklog {
+    pub(crate) fn new(size: usize) -> Self {
+        ReplicationBacklog { buffer: vec![], size: size.max(1), next: 0, offset: 0 }
+    }
+
+    /// appends the bytes of a replicated message, overwriting the oldest bytes once the buffer is full
+    pub(crate) fn append(&mut self, mut bytes: &[u8]) {
+        self.offset += bytes.len();
+        // only the end of a message longer than the buffer is kept
+        if bytes.len() > self.size {
+            bytes = &bytes[bytes.len() - self.size..];
+        }
+        while !bytes.is_empty() {
+            let len = if self.buffer.len() < self.size {
+                // the buffer grows until it has its size
+                let len = bytes.len().min(self.size - self.buffer.len());
+                self.buffer.extend_from_slice(&bytes[..len]);
+                len
+            } else {
+                let len = bytes.len().min(self.size - self.next);
+                self.buffer[self.next..self.next + len].copy_from_slice(&bytes[..len]);
+                len
+            };
+            bytes = &bytes[len..];
+            self.next = (self.next + len) % self.size;
+        }
+    }
+
+    /// offset of the stream after its last byte
+    pub(crate) fn offset(&self) -> usize {
+        self.offset
+    }
+
+    /// offset of the oldest byte still held
+    pub(crate) fn first_offset(&self) -> usize {
+        self.offset - self.buffer.len()
+    }
+
+    /// number of bytes of the stream held
+    pub(crate) fn histlen(&self) -> usize {
+        self.buffer.len()
+    }
+
+    pub(crate) fn size(&self) -> usize {
+        self.size
+    }
+
+    /// the stream can be continued from the offset: its bytes up to the end are held
+    pub(crate) fn contains(&self, offset: usize) -> bool {
+        offset >= self.first_offset() && offset <= self.offset
+    }
+
+    /// bytes of the stream from the offset to its end, none if the offset is not held anymore or is past the end
+    pub(crate) fn bytes_from(&self, offset: usize) -> Option<Vec<u8>> {
+        if !self.contains(offset) {
+            return None;
+        }
+        let len = self.offset - offset;
+        // the bytes end just before the write position, wrapping around the start of the buffer
+        let start = (self.next + self.buffer.len() - len) % self.buffer.len().max(1);
+        let mut bytes = Vec::with_capacity(len);
+        if start + len <= self.buffer.len() {
+            bytes.extend_from_slice(&self.buffer[start..start + len]);
+        } else {
+            bytes.extend_from_slice(&self.buffer[start..]);
+            bytes.extend_from_slice(&self.buffer[..len - (self.buffer.len() - start)]);
+        }
+        Some(bytes)
+    }
+
+    /// changes the size of the buffer, keeping the most recent bytes which fit into it
+    pub(crate) fn resize(&mut self, size: usize) {
+        let size = size.max(1);
+        if size == self.size {
+            return;
+        }
+        let kept = self.buffer.len().min(size);
+        self.buffer = self.bytes_from(self.offset - kept).unwrap_or_default();
+        self.size = size;
+        self.next = self.buffer.len() % size;
+    }
+}
@@ -0,0 +1,254 @@
+use std::str::FromStr;
+
+use anyhow::bail;
+
+use crate::args::Arg;
+use crate::range::normalize_range;
+
+/// bitwise operation of BITOP
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub(crate) enum BitOp {
+    And,
+    Or,
+    Xor,
+    Not,
+}
+
+impl FromStr for BitOp {
+    type Err = anyhow::Error;
+
+    fn from_str(op: &str) -> Result<Self, Self::Err> {
+        match op.to_uppercase().as_str() {
+            "AND" => Ok(BitOp::And),
+            "OR" => Ok(BitOp::Or),
+            "XOR" => Ok(BitOp::Xor),
+            "NOT" => Ok(BitOp::Not),
+            _ => bail!("ERR syntax error"),
+        }
+    }
+}
+
+/// combines the strings byte by byte, as long as the longest one. shorter and missing strings count as zero bytes
+pub(crate) fn bit_op(op: BitOp, values: &[&[u8]]) -> Vec<u8> {
+    let len = values.iter().map(|value| value.len()).max().unwrap_or(0);
+    (0..len)
+        .map(|i| {
+            let mut bytes = values.iter().map(|value| value.get(i).copied().unwrap_or(0));
+            let first = bytes.next().unwrap_or(0);
+            match op {
+                BitOp::And => bytes.fold(first, |result, byte| result & byte),
+                BitOp::Or => bytes.fold(first, |result, byte| result | byte),
+                BitOp::Xor => bytes.fold(first, |result, byte| result ^ byte),
+                BitOp::Not => !first,
+            }
+        })
+        .collect()
+}
+
+/// position of the first bit set to `bit` within the start and end offsets inclusive, of bytes or of bits
+/// when `bits` is set. -1 if there is none, except that the string counts as followed by clear bits
+/// when looking for a clear bit without an end
+pub(crate) fn bit_pos(bytes: &[u8], bit: bool, start: i64, end: Option<i64>, bits: bool) -> i64 {
+    let unit = if bits { 1 } else { 8 };
+    let Some(range) = normalize_range(bytes.len() * 8 / unit, start, end.unwrap_or(-1)) else {
+        return -1;
+    };
+    let is_set = |position: usize| bytes[position / 8] & (0x80 >> (position % 8)) != 0;
+    match (range.start * unit..range.end * unit).find(|&position| is_set(position) == bit) {
+        Some(position) => position as i64,
+        None if !bit && end.is_none() => (bytes.len() * 8) as i64,
+        None => -1,
+    }
+}
+
+/// integer type of a field of BITFIELD, like i16 or u8
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub(crate) struct FieldType {
+    signed: bool,
+    bits: u32,
+}
+
+impl FromStr for FieldType {
+    type Err = anyhow::Error;
+
+    fn from_str(field_type: &str) -> Result<Self, Self::Err> {
+        let (signed, bits) = match field_type.split_at_checked(1) {
+            Some(("i" | "I", bits)) => (true, bits.parse::<u32>().ok().filter(|bits| (1..=64).contains(bits))),
+            Some(("u" | "U", bits)) => (false, bits.parse::<u32>().ok().filter(|bits| (1..=63).contains(bits))),
+            _ => (false, None),
+        };
+        match bits {
+            Some(bits) => Ok(FieldType { signed, bits }),
+            None => bail!("ERR Invalid bitfield type. Use something like i16 u8. Note that u64 is not supported but i64 is."),
+        }
+    }
+}
+
+impl FieldType {
+    fn min(&self) -> i128 {
+        if self.signed { -(1 << (self.bits - 1)) } else { 0 }
+    }
+
+    fn max(&self) -> i128 {
+        if self.signed { (1 << (self.bits - 1)) - 1 } else { (1 << self.bits) - 1 }
+    }
+
+    /// the value as stored in the bits of the type, None if it doesn't fit and overflows fail
+    fn fit(&self, value: i128, overflow: Overflow) -> Option<i64> {
+        if (self.min()..=self.max()).contains(&value) {
+            return Some(value as i64);
+        }
+        match overflow {
+            Overflow::Wrap => {
+                let wrapped = (value - self.min()).rem_euclid(1 << self.bits) + self.min();
+                Some(wrapped as i64)
+            }
+            Overflow::Sat => Some(value.clamp(self.min(), self.max()) as i64),
+            Overflow::Fail => None,
+        }
+    }
+}
+
+/// handling of the values of SET and INCRBY which don't fit their field
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub(crate) enum Overflow {
+    /// the value wraps around, for signed fields from the highest to the lowest value
+    Wrap,
+    /// the value saturates at the lowest or highest value
+    Sat,
+    /// the field is not changed and the operation returns nil
+    Fail,
+}
+
+/// field of BITFIELD: an integer of the type stored in the bits of a string from the offset on,
+/// the most significant bit first
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub(crate) struct Field {
+    field_type: FieldType,
+    offset: usize,
+}
+
+impl Field {
+    /// value of the field, bits past the end of the string are clear
+    pub(crate) fn read(&self, bytes: &[u8]) -> i64 {
+        let bits = self.field_type.bits;
+        let value = (0..bits as usize).fold(0_u64, |value, i| {
+            let position = self.offset + i;
+            let bit = bytes.get(position / 8).is_some_and(|byte| byte & (0x80 >> (position % 8)) != 0);
+            (value << 1) | bit as u64
+        });
+        if self.field_type.signed && bits < 64 && value & (1 << (bits - 1)) != 0 {
+            // sign extension of the negative value
+            (value | (u64::MAX << bits)) as i64
+        } else {
+            value as i64
+        }
+    }
+
+    /// stores the value in the bits of the field, padding the string with zero bytes up to the field
+    fn write(&self, bytes: &mut Vec<u8>, value: i64) {
+        let bits = self.field_type.bits as usize;
+        let end = (self.offset + bits).div_ceil(8);
+        if bytes.len() < end {
+            bytes.resize(end, 0);
+        }
+        for i in 0..bits {
+            let position = self.offset + i;
+            let mask = 0x80 >> (position % 8);
+            if (value as u64 >> (bits - 1 - i)) & 1 != 0 {
+                bytes[position / 8] |= mask;
+            } else {
+                bytes[position / 8] &= !mask;
+            }
+        }
+    }
+}
+
+/// operation of BITFIELD, with the overflow handling in effect for it
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub(crate) enum BitfieldOp {
+    Get(Field),
+    Set(Field, i64, Overflow),
+    IncrBy(Field, i64, Overflow),
+}
+
+impl BitfieldOp {
+    /// field read by a GET, None for operations writing to their field
+    pub(crate) fn read_field(&self) -> Option<Field> {
+        match self {
+            BitfieldOp::Get(field) => Some(*field),
+            _ => None,
+        }
+    }
+
+    /// runs the operation on the string. returns the value of GET, the previous value of SET and the new value
+    /// of INCRBY, None if the value overflows and overflows fail
+    pub(crate) fn apply(&self, bytes: &mut Vec<u8>) -> Option<i64> {
+        match *self {
+            BitfieldOp::Get(field) => Some(field.read(bytes)),
+            BitfieldOp::Set(field, value, overflow) => {
+                let value = field.field_type.fit(value as i128, overflow)?;
+                let previous = field.read(bytes);
+                field.write(bytes, value);
+                Some(previous)
+            }
+            BitfieldOp::IncrBy(field, increment, overflow) => {
+                let value = field.field_type.fit(field.read(bytes) as i128 + increment as i128, overflow)?;
+                field.write(bytes, value);
+                Some(value)
+            }
+        }
+    }
+}
+
+/// parses the operations of BITFIELD: GET type offset, SET type offset value, INCRBY type offset increment
+/// and OVERFLOW WRAP | SAT | FAIL, which applies to the following operations.
+/// offsets prefixed with # are multiplied by the size of the type. fields must end within `max_bits`
+pub(crate) fn parse_bitfield(params: &[Vec<u8>], max_bits: u64) -> anyhow::Result<Vec<BitfieldOp>> {
+    let mut ops = vec![];
+    let mut overflow = Overflow::Wrap;
+    let mut params = params.iter();
+    while let Some(op) = params.next() {
+        let op = op.to_uppercase();
+        if op == "OVERFLOW" {
+            overflow = match params.next().map(|overflow| overflow.to_uppercase()).as_deref() {
+                Some("WRAP") => Overflow::Wrap,
+                Some("SAT") => Overflow::Sat,
+                Some("FAIL") => Overflow::Fail,
+                Some(_) => bail!("ERR Invalid OVERFLOW type specified"),
+                None => bail!("ERR syntax error"),
+            };
+            continue;
+        }
+        let (Some(field_type), Some(offset)) = (params.next(), params.next()) else {
+            bail!("ERR syntax error");
+        };
+        let field = parse_field(&field_type.text(), &offset.text(), max_bits)?;
+        let mut value = || match params.next().map(|value| value.parse::<i64>()) {
+            Some(Ok(value)) => Ok(value),
+            Some(Err(_)) => bail!("ERR value is not an integer or out of range"),
+            None => bail!("ERR syntax error"),
+        };
+        ops.push(match op.as_str() {
+            "GET" => BitfieldOp::Get(field),
+            "SET" => BitfieldOp::Set(field, value()?, overflow),
+            "INCRBY" => BitfieldOp::IncrBy(field, value()?, overflow),
+            _ => bail!("ERR syntax error"),
+        });
+    }
+    Ok(ops)
+}
+
+fn parse_field(field_type: &str, offset: &str, max_bits: u64) -> anyhow::Result<Field> {
+    let field_type = field_type.parse::<FieldType>()?;
+    let offset = match offset.strip_prefix('#') {
+        Some(index) => index.parse::<u64>().ok().and_then(|index| index.checked_mul(field_type.bits as u64)),
+        None => offset.parse::<u64>().ok(),
+    };
+    match offset {
+        Some(offset) if offset.checked_add(field_type.bits as u64).is_some_and(|end| end <= max_bits) => {
+            Ok(Field { field_type, offset: offset as usize })
+        }
+        _ => bail!("ERR bit offset is not an integer or out of range"),
+    }
+}
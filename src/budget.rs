@@ -0,0 +1,35 @@
+use std::sync::Arc;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::time::{Duration, Instant};
+
+use anyhow::bail;
+
+/// number of steps between reads of the clock
+const CHECK_INTERVAL: u64 = 1024;
+
+/// execution time budget of a command walking many keys or members.
+/// once the budget is spent the command is aborted rather than holding the store for seconds
+pub(crate) struct TimeBudget {
+    /// unlimited when zero
+    limit: Duration,
+    started_at: Instant,
+    steps: u64,
+    /// counter of the commands aborted on this server
+    aborted: Arc<AtomicU64>,
+}
+
+impl TimeBudget {
+    pub(crate) fn new(limit: Duration, aborted: Arc<AtomicU64>) -> Self {
+        Self { limit, started_at: Instant::now(), steps: 0, aborted }
+    }
+
+    /// accounts a step of the command, failing once the budget is spent
+    pub(crate) fn step(&mut self) -> anyhow::Result<()> {
+        self.steps += 1;
+        if self.limit.is_zero() || !self.steps.is_multiple_of(CHECK_INTERVAL) || self.started_at.elapsed() <= self.limit {
+            return Ok(());
+        }
+        self.aborted.fetch_add(1, Ordering::Relaxed);
+        bail!("BUSY command exceeded its time budget of {} ms and was aborted", self.limit.as_millis())
+    }
+}
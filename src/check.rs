@@ -0,0 +1,45 @@
+use std::fs::File;
+use std::io::BufReader;
+
+use anyhow::{Context, Result};
+
+use crate::aof;
+use crate::store::Store;
+
+/// validates an RDB file without starting the server, as `--check-rdb <file>`.
+/// the file is loaded the same way the server loads it at startup, into a store which is then dropped
+pub fn check_rdb(path: &str) -> Result<()> {
+    println!("[check-rdb] checking RDB file {}", path);
+    let file = File::open(path).with_context(|| format!("opening {} failed", path))?;
+    let mut store = Store::new();
+    // unknown opcodes and checksum mismatches are reported, even though the server may be configured to accept them
+    if let Err(err) = store.load_rdb(BufReader::new(file), false, true) {
+        println!("[check-rdb] RDB file {} is invalid: {}", path, err);
+        return Err(err);
+    }
+    println!("[check-rdb] {} keys, {} with an expiration", store.key_count(), store.volatile_key_count());
+    println!("[check-rdb] RDB looks OK");
+    Ok(())
+}
+
+/// validates an append only file without starting the server, as `--check-aof <file>`.
+/// the commands are parsed the same way the server reads them, a file ending within a command is accepted
+/// up to the last complete command with `load_truncated`, as configured by aof-load-truncated
+pub fn check_aof(path: &str, load_truncated: bool) -> Result<()> {
+    println!("[check-aof] checking AOF file {}", path);
+    let file = File::open(path).with_context(|| format!("opening {} failed", path))?;
+    let size = file.metadata()?.len();
+    let contents = match aof::read_commands(BufReader::new(file), load_truncated, |_| Ok(())) {
+        Ok(contents) => contents,
+        Err(err) => {
+            println!("[check-aof] AOF file {} is invalid: {}", path, err);
+            return Err(err);
+        }
+    };
+    println!("[check-aof] {} commands, the last valid command ends at byte offset {}", contents.commands, contents.valid_len);
+    if contents.truncated {
+        println!("[check-aof] the last {} bytes are a truncated command, which is discarded when loading", size - contents.valid_len);
+    }
+    println!("[check-aof] AOF looks OK");
+    Ok(())
+}
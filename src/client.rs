@@ -3,10 +3,18 @@ use std::net::TcpStream;
 use anyhow::{bail, Result};
 
 use crate::io::net::Binding;
+use crate::log::{notice, verbose};
 use crate::protocol::resp::RESP;
-use crate::protocol::resp::RESP::Bulk;
 use crate::protocol::resp::RESPConnection;
 
+/// how the master continues the replication stream requested with PSYNC
+pub enum PsyncReply {
+    /// a snapshot of the store, with the id of the stream and the offset it continues from after the snapshot
+    FullResync(String, usize, Vec<u8>),
+    /// the stream continues from the requested offset, with the id of the stream if the master sent it
+    Continue(Option<String>),
+}
+
 pub struct ReplicaClient {
     _binding: Binding,
     pub(crate) stream: RESPConnection,
@@ -15,13 +23,22 @@ pub struct ReplicaClient {
 impl ReplicaClient {
     pub fn new(master: &Binding) -> Result<Self> {
         let stream = TcpStream::connect(master.to_string())?;
-        println!("connected to master: {}", master);
+        notice!("connected to master: {}", master);
         Ok(ReplicaClient {
             stream: RESPConnection::new(stream),
             _binding: master.clone(),
         })
     }
 
+    /// authenticates as the default user of the master
+    pub fn auth(&mut self, password: &str) -> Result<()> {
+        self.stream.send_message(&RESP::Array(vec![RESP::bulk("AUTH"), RESP::bulk(password)]))?;
+        match self.stream.read_message()? {
+            (_, Some(RESP::String(str))) if str.to_uppercase() == "OK" => Ok(()),
+            (_, response) => bail!("auth failed: {:?}", response),
+        }
+    }
+
     pub fn ping_pong(&mut self) -> Result<()> {
         self.stream
             .send_message(&RESP::Array(vec![RESP::bulk("PING")]))?;
@@ -37,7 +54,7 @@ impl ReplicaClient {
         let mut command = vec![RESP::bulk("REPLCONF")];
 
         let mut bulk_params = params
-            .into_iter()
+            .iter()
             .map(|param| RESP::bulk(param))
             .collect::<Vec<RESP>>();
         command.append(&mut bulk_params);
@@ -50,24 +67,31 @@ impl ReplicaClient {
         }
         bail!("replconfig failed");
     }
-    pub fn psync(&mut self, replication_id: &str, offset: i64) -> Result<Vec<u8>> {
+    /// requests the stream of the id from the offset, which the master continues or starts over with a snapshot
+    pub fn psync(&mut self, replication_id: &str, offset: i64) -> Result<PsyncReply> {
         let command = vec![
             RESP::bulk("PSYNC"),
             RESP::bulk(replication_id),
-            Bulk(format!("{}", offset)),
+            RESP::bulk(&offset.to_string()),
         ];
 
         self.stream.send_message(&RESP::Array(command))?;
 
         let (_, psync_response) = self.stream.read_message()?;
         if let Some(RESP::String(str)) = psync_response {
-            if str.to_uppercase().starts_with("FULLRESYNC ") {
-                println!("waiting for rds data");
-                // expect master to send the RDB in a Bulk like binary
-                if let RESP::File(rds) = self.stream.read_binary()? {
-                    println!("got binary rds of: {} bytes", rds.len());
-                    return Ok(rds);
+            match str.split(' ').collect::<Vec<&str>>().as_slice() {
+                ["FULLRESYNC", replication_id, offset] => {
+                    let offset = offset.parse::<usize>()?;
+                    verbose!("waiting for rds data");
+                    // expect master to send the RDB in a Bulk like binary
+                    if let RESP::File(rds) = self.stream.read_binary()? {
+                        notice!("got binary rds of: {} bytes", rds.len());
+                        return Ok(PsyncReply::FullResync(replication_id.to_string(), offset, rds));
+                    }
                 }
+                ["CONTINUE"] => return Ok(PsyncReply::Continue(None)),
+                ["CONTINUE", replication_id] => return Ok(PsyncReply::Continue(Some(replication_id.to_string()))),
+                _ => {}
             }
             bail!("psync unknown response: {}", str);
         }
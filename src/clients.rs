@@ -0,0 +1,212 @@
+use std::collections::HashMap;
+use std::net::{Shutdown, SocketAddr, TcpStream};
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+
+use crate::args::Arg;
+use crate::log::warning;
+use crate::protocol::command::{Command, CommandRequest};
+use crate::protocol::resp::Protocol;
+
+/// state of a connection between two requests, as reported by CLIENT LIST
+#[derive(Clone, Copy, Debug, Default)]
+pub(crate) struct ConnectionState {
+    /// number of subscribed channels, patterns and shard channels
+    pub(crate) subscriptions: (usize, usize, usize),
+    /// number of commands queued by an open transaction
+    pub(crate) multi: Option<usize>,
+    pub(crate) protocol: Protocol,
+}
+
+impl ConnectionState {
+    fn is_subscribed(&self) -> bool {
+        self.subscriptions != (0, 0, 0)
+    }
+}
+
+/// numbers of the clients in the states reported by the clients section of INFO
+pub(crate) struct ClientCounts {
+    pub(crate) blocked: usize,
+    pub(crate) pubsub: usize,
+    pub(crate) replicas: usize,
+}
+
+/// connection of a client as seen by the server
+struct Client {
+    /// handle of the socket, which the server shuts down to close the connection from another thread
+    stream: TcpStream,
+    addr: Option<SocketAddr>,
+    local_addr: Option<SocketAddr>,
+    name: Option<String>,
+    connected_at: Instant,
+    last_interaction: Instant,
+    /// name of the last command, with its subcommand for container commands like client|list
+    last_command: String,
+    /// the connection waits for the next request, rather than executing or blocking in a command
+    waiting: bool,
+    /// the command executing may block, waiting for other clients
+    blocked: bool,
+    /// the connection was handed over to replication
+    replica: bool,
+    state: ConnectionState,
+}
+
+impl Client {
+    /// flags of CLIENT LIST: S for replicas, P for subscribed clients, x in a transaction, b blocked, N for none
+    fn flags(&self) -> String {
+        let flags = [(self.replica, 'S'), (self.state.is_subscribed(), 'P'), (self.state.multi.is_some(), 'x'), (self.blocked, 'b')]
+            .iter()
+            .filter(|(set, _)| *set)
+            .map(|(_, flag)| *flag)
+            .collect::<String>();
+        if flags.is_empty() { "N".to_string() } else { flags }
+    }
+
+    /// CLIENT LIST type of the client: replica, pubsub or normal
+    fn client_type(&self) -> &'static str {
+        if self.replica {
+            "replica"
+        } else if self.state.is_subscribed() {
+            "pubsub"
+        } else {
+            "normal"
+        }
+    }
+
+    /// properties of the client in the line format of CLIENT LIST and CLIENT INFO
+    fn info(&self, id: u64) -> String {
+        let addr = |addr: Option<SocketAddr>| addr.map_or(String::new(), |addr| addr.to_string());
+        let (sub, psub, ssub) = self.state.subscriptions;
+        let resp = match self.state.protocol {
+            Protocol::Resp2 => 2,
+            Protocol::Resp3 => 3,
+        };
+        format!(
+            "id={} addr={} laddr={} name={} age={} idle={} flags={} db=0 sub={} psub={} ssub={} multi={} cmd={} user=default resp={}",
+            id, addr(self.addr), addr(self.local_addr), self.name.as_deref().unwrap_or_default(),
+            self.connected_at.elapsed().as_secs(), self.last_interaction.elapsed().as_secs(), self.flags(),
+            sub, psub, ssub, self.state.multi.map_or(-1, |queued| queued as i64), self.last_command, resp,
+        )
+    }
+}
+
+/// connections of all clients by client id
+#[derive(Clone, Default)]
+pub(crate) struct Clients(Arc<Mutex<HashMap<u64, Client>>>);
+
+impl Clients {
+    /// adds the connection of the client, which is removed when the registration is dropped
+    pub(crate) fn register(&self, id: u64, stream: &TcpStream) -> std::io::Result<ClientRegistration> {
+        let now = Instant::now();
+        let client = Client {
+            stream: stream.try_clone()?,
+            addr: stream.peer_addr().ok(),
+            local_addr: stream.local_addr().ok(),
+            name: None,
+            connected_at: now,
+            last_interaction: now,
+            last_command: "NULL".to_string(),
+            waiting: false,
+            blocked: false,
+            replica: false,
+            state: ConnectionState::default(),
+        };
+        self.0.lock().unwrap().insert(id, client);
+        Ok(ClientRegistration { id, clients: self.clone() })
+    }
+
+    /// the connection starts waiting for the next request, in the state left by the previous one
+    pub(crate) fn waiting(&self, id: u64, state: ConnectionState) {
+        if let Some(client) = self.0.lock().unwrap().get_mut(&id) {
+            client.waiting = true;
+            client.blocked = false;
+            client.state = state;
+        }
+    }
+
+    /// the connection received the command, which it executes until it waits again
+    pub(crate) fn received(&self, id: u64, command: &CommandRequest) {
+        if let Some(client) = self.0.lock().unwrap().get_mut(&id) {
+            client.last_interaction = Instant::now();
+            client.last_command = command_name(command);
+            client.waiting = false;
+            // the connection of a replica continues as the replication stream
+            client.replica |= command.0 == Command::PSYNC;
+            client.blocked = command.may_block() && !client.replica;
+        }
+    }
+
+    pub(crate) fn set_name(&self, id: u64, name: Option<String>) {
+        if let Some(client) = self.0.lock().unwrap().get_mut(&id) {
+            client.name = name;
+        }
+    }
+
+    pub(crate) fn name(&self, id: u64) -> Option<String> {
+        self.0.lock().unwrap().get(&id).and_then(|client| client.name.clone())
+    }
+
+    /// CLIENT INFO line of the client, None if it is not connected
+    pub(crate) fn info(&self, id: u64) -> Option<String> {
+        self.0.lock().unwrap().get(&id).map(|client| client.info(id))
+    }
+
+    /// CLIENT LIST lines of the clients of the type and with the ids if given, ordered by id
+    pub(crate) fn list(&self, client_type: Option<&str>, ids: Option<&[u64]>) -> Vec<String> {
+        let clients = self.0.lock().unwrap();
+        let mut listed = clients.iter()
+            .filter(|(_, client)| client_type.is_none() || client_type == Some(client.client_type()))
+            .filter(|(id, _)| ids.is_none() || ids.is_some_and(|ids| ids.contains(id)))
+            .map(|(id, client)| (*id, client.info(*id)))
+            .collect::<Vec<(u64, String)>>();
+        listed.sort_by_key(|(id, _)| *id);
+        listed.into_iter().map(|(_, info)| info).collect()
+    }
+
+    /// numbers of clients blocked in a command, subscribed to channels and of replicas, as reported by INFO
+    pub(crate) fn counts(&self) -> ClientCounts {
+        let clients = self.0.lock().unwrap();
+        ClientCounts {
+            blocked: clients.values().filter(|client| client.blocked).count(),
+            pubsub: clients.values().filter(|client| client.state.is_subscribed()).count(),
+            replicas: clients.values().filter(|client| client.replica).count(),
+        }
+    }
+
+    /// closes the connections waiting for a request for longer than the timeout, returns the number of closed connections.
+    /// subscribed clients and clients executing a command, like blocked clients and replicas, are not closed
+    pub(crate) fn close_idle(&self, timeout: Duration) -> usize {
+        let clients = self.0.lock().unwrap();
+        let idle = clients.values()
+            .filter(|client| client.waiting && !client.state.is_subscribed() && client.last_interaction.elapsed() > timeout)
+            .collect::<Vec<&Client>>();
+        for client in &idle {
+            // the thread of the connection reads the end of the stream and ends the connection
+            client.stream.shutdown(Shutdown::Both).unwrap_or_else(|err| warning!("closing idle client failed: {}", err));
+        }
+        idle.len()
+    }
+}
+
+/// name of the command as listed by CLIENT LIST, with the subcommand of container commands
+fn command_name(command: &CommandRequest) -> String {
+    let name = command.0.to_string().to_lowercase();
+    match (&command.0, command.1.first()) {
+        (Command::CLIENT | Command::COMMAND | Command::CONFIG | Command::CLUSTER | Command::OBJECT | Command::MEMORY | Command::DEBUG | Command::XGROUP | Command::XINFO, Some(sub_command)) => {
+            format!("{}|{}", name, sub_command.text().to_lowercase())
+        }
+        _ => name,
+    }
+}
+
+/// registration of a connection, removing it from the clients when dropped with the end of the connection
+pub(crate) struct ClientRegistration {
+    id: u64,
+    clients: Clients,
+}
+
+impl Drop for ClientRegistration {
+    fn drop(&mut self) {
+        self.clients.0.lock().unwrap().remove(&self.id);
+    }
+}
@@ -0,0 +1,402 @@
+use std::collections::HashMap;
+use std::net::{TcpListener, TcpStream, ToSocketAddrs};
+use std::sync::{Arc, RwLock};
+use std::thread;
+use std::time::{Duration, Instant, SystemTime};
+
+use anyhow::{bail, format_err, Result};
+
+use crate::io::net::{Binding, Port};
+use crate::log::{notice, verbose, warning};
+use crate::protocol::resp::{RESP, RESPConnection};
+use crate::random::random_id;
+use crate::slots::SLOTS;
+
+/// the cluster bus listens on the client port shifted by this offset
+pub const BUS_PORT_OFFSET: Port = 10000;
+/// port of the cluster bus of a node, none for client ports so high that the bus port doesn't exist
+pub fn bus_port(port: Port) -> Option<Port> {
+    port.checked_add(BUS_PORT_OFFSET).filter(|bus_port| *bus_port <= u16::MAX as Port)
+}
+
+/// interval of the pings sent to every known node
+const PING_INTERVAL: Duration = Duration::from_secs(1);
+/// nodes not answering pings for longer are flagged as failing
+const NODE_TIMEOUT: Duration = Duration::from_secs(15);
+
+#[derive(Debug, Clone)]
+pub struct ClusterNode {
+    pub id: String,
+    pub host: String,
+    pub port: Port,
+    /// last time the node answered to a ping, never for nodes only known through gossip
+    pub pong_received: Option<SystemTime>,
+}
+
+impl ClusterNode {
+    fn is_failing(&self) -> bool {
+        match self.pong_received {
+            Some(pong) => pong.elapsed().unwrap_or_default() > NODE_TIMEOUT,
+            None => true,
+        }
+    }
+}
+
+/// node table and slot ownership as known by this node
+pub struct ClusterState {
+    myself: ClusterNode,
+    nodes: HashMap<String, ClusterNode>,
+    /// owner node id of every hash slot
+    slot_owners: Vec<Option<String>>,
+    /// nodes requested with CLUSTER MEET which did not answer yet
+    pending_meets: Vec<Binding>,
+}
+
+impl ClusterState {
+    fn new(binding: &Binding) -> Self {
+        ClusterState {
+            myself: ClusterNode {
+                id: random_id(),
+                host: binding.0.clone(),
+                port: binding.1,
+                pong_received: None,
+            },
+            nodes: HashMap::new(),
+            slot_owners: vec![None; SLOTS as usize],
+            pending_meets: vec![],
+        }
+    }
+
+    /// slot ranges owned by a node
+    fn slot_ranges(&self, id: &str) -> Vec<(u16, u16)> {
+        let mut ranges: Vec<(u16, u16)> = vec![];
+        for (slot, owner) in self.slot_owners.iter().enumerate() {
+            if owner.as_deref() != Some(id) {
+                continue;
+            }
+            let slot = slot as u16;
+            match ranges.last_mut() {
+                Some((_, end)) if *end + 1 == slot => *end = slot,
+                _ => ranges.push((slot, slot)),
+            }
+        }
+        ranges
+    }
+
+    fn node(&self, id: &str) -> Option<&ClusterNode> {
+        if id == self.myself.id {
+            Some(&self.myself)
+        } else {
+            self.nodes.get(id)
+        }
+    }
+
+    /// node table as sent over the bus, this node first.
+    /// each node is encoded as `id host port slot-ranges`, where ranges are like `0-100,200` or `-` for none
+    fn node_table(&self) -> Vec<RESP> {
+        std::iter::once(&self.myself)
+            .chain(self.nodes.values())
+            .map(|node| {
+                let ranges = self.slot_ranges(&node.id).iter()
+                    .map(|(start, end)| if start == end { start.to_string() } else { format!("{}-{}", start, end) })
+                    .collect::<Vec<String>>();
+                let ranges = if ranges.is_empty() { "-".to_string() } else { ranges.join(",") };
+                RESP::bulk(&format!("{} {} {} {}", node.id, node.host, node.port, ranges))
+            })
+            .collect()
+    }
+
+    /// merges the node table received from another node.
+    /// the sender (first entry) is authoritative for its own slots, other nodes are only learned if unknown
+    fn merge_node_table(&mut self, table: &[RESP]) -> Result<()> {
+        for (i, entry) in table.iter().enumerate() {
+            let (node, slots) = parse_node_entry(&entry.to_string())?;
+            if node.id == self.myself.id {
+                continue;
+            }
+            if bus_port(node.port).is_none() {
+                verbose!("cluster: ignoring node {} without a bus port for port {}", node.id, node.port);
+                continue;
+            }
+            let is_sender = i == 0;
+            if is_sender {
+                self.pending_meets.retain(|b| !(b.0 == node.host && b.1 == node.port));
+                self.nodes.insert(node.id.clone(), ClusterNode { pong_received: Some(SystemTime::now()), ..node.clone() });
+            } else if !self.nodes.contains_key(&node.id) {
+                self.nodes.insert(node.id.clone(), node.clone());
+            } else {
+                continue;
+            }
+            for slot in slots {
+                let owner = &mut self.slot_owners[slot as usize];
+                match owner {
+                    // this node keeps its own slots
+                    Some(id) if *id == self.myself.id => {
+                        notice!("cluster: node {} claims slot {} owned by this node", node.id, slot);
+                    }
+                    Some(_) if !is_sender => {}
+                    _ => *owner = Some(node.id.clone()),
+                }
+            }
+        }
+        Ok(())
+    }
+}
+
+/// handle to the cluster state shared by the client connections and the cluster bus
+#[derive(Clone)]
+pub struct Cluster(Arc<RwLock<ClusterState>>);
+
+impl Cluster {
+    pub fn new(binding: &Binding) -> Self {
+        Cluster(Arc::new(RwLock::new(ClusterState::new(binding))))
+    }
+
+    pub fn myself_id(&self) -> String {
+        self.0.read().unwrap().myself.id.clone()
+    }
+
+    /// node which serves the slot, None if the slot is not assigned
+    pub fn slot_owner(&self, slot: u16) -> Option<ClusterNode> {
+        let state = self.0.read().unwrap();
+        state.slot_owners[slot as usize].as_ref()
+            .and_then(|id| state.node(id))
+            .cloned()
+    }
+
+    pub fn meet(&self, host: &str, port: Port) {
+        self.0.write().unwrap().pending_meets.push(Binding(host.to_string(), port));
+    }
+
+    /// assigns slots to this node, fails if any of them is already assigned
+    pub fn add_slots(&self, slots: &[u16]) -> Result<()> {
+        let mut state = self.0.write().unwrap();
+        for &slot in slots {
+            if state.slot_owners[slot as usize].is_some() {
+                bail!("ERR Slot {} is already busy", slot);
+            }
+        }
+        let id = state.myself.id.clone();
+        for &slot in slots {
+            state.slot_owners[slot as usize] = Some(id.clone());
+        }
+        Ok(())
+    }
+
+    /// CLUSTER NODES description of the node table
+    pub fn nodes_description(&self) -> String {
+        let state = self.0.read().unwrap();
+        let to_ms = |time: Option<SystemTime>| time
+            .and_then(|t| t.duration_since(SystemTime::UNIX_EPOCH).ok())
+            .map_or(0, |d| d.as_millis());
+        std::iter::once(&state.myself)
+            .chain(state.nodes.values())
+            .map(|node| {
+                let is_myself = node.id == state.myself.id;
+                let flags = match (is_myself, node.is_failing()) {
+                    (true, _) => "myself,master",
+                    (false, true) => "master,fail?",
+                    (false, false) => "master",
+                };
+                let link = if is_myself || !node.is_failing() { "connected" } else { "disconnected" };
+                let slots = state.slot_ranges(&node.id).iter()
+                    .map(|(start, end)| if start == end { format!(" {}", start) } else { format!(" {}-{}", start, end) })
+                    .collect::<String>();
+                format!("{} {}:{}@{} {} - 0 {} 0 {}{}\n",
+                        node.id, node.host, node.port, bus_port(node.port).unwrap_or_default(),
+                        flags, to_ms(node.pong_received), link, slots)
+            })
+            .collect()
+    }
+
+    /// CLUSTER SLOTS description: slot ranges with the serving node
+    pub fn slots_description(&self) -> RESP {
+        let state = self.0.read().unwrap();
+        let mut ranges = vec![];
+        for node in std::iter::once(&state.myself).chain(state.nodes.values()) {
+            for (start, end) in state.slot_ranges(&node.id) {
+                ranges.push((start, end, node));
+            }
+        }
+        ranges.sort_by_key(|(start, _, _)| *start);
+        RESP::Array(ranges.into_iter().map(|(start, end, node)| {
+            RESP::Array(vec![
+                RESP::Int(start as i64),
+                RESP::Int(end as i64),
+                RESP::Array(vec![RESP::bulk(&node.host), RESP::Int(node.port as i64), RESP::bulk(&node.id)]),
+            ])
+        }).collect())
+    }
+
+    /// CLUSTER INFO fields
+    pub fn info(&self) -> String {
+        let state = self.0.read().unwrap();
+        let assigned = state.slot_owners.iter().filter(|o| o.is_some()).count();
+        let size = std::iter::once(&state.myself)
+            .chain(state.nodes.values())
+            .filter(|node| !state.slot_ranges(&node.id).is_empty())
+            .count();
+        let cluster_state = if assigned == SLOTS as usize { "ok" } else { "fail" };
+        [
+            ("cluster_enabled", "1".to_string()),
+            ("cluster_state", cluster_state.to_string()),
+            ("cluster_slots_assigned", assigned.to_string()),
+            ("cluster_known_nodes", (state.nodes.len() + 1).to_string()),
+            ("cluster_size", size.to_string()),
+        ]
+            .map(|(k, v)| format!("{}:{}", k, v))
+            .join("\r\n")
+    }
+
+    /// starts listening on the cluster bus and pinging the known nodes
+    pub fn start(&self) -> Result<()> {
+        let (host, port) = {
+            let state = self.0.read().unwrap();
+            (state.myself.host.clone(), state.myself.port)
+        };
+        let Some(bus_port) = bus_port(port) else {
+            bail!("port {} is too high for the cluster bus port, which is {} above it", port, BUS_PORT_OFFSET);
+        };
+        let listener = TcpListener::bind(Binding(host, bus_port).to_string())?;
+        notice!("cluster bus listening on port {}", bus_port);
+
+        let cluster = self.clone();
+        thread::Builder::new()
+            .name("cluster-bus".to_string())
+            .spawn(move || {
+                for stream in listener.incoming().flatten() {
+                    let cluster = cluster.clone();
+                    let _ = thread::Builder::new()
+                        .name("cluster-bus-link".to_string())
+                        .spawn(move || {
+                            cluster.handle_bus_connection(stream).unwrap_or_else(|err| {
+                                verbose!("cluster bus connection closed: {}", err);
+                            });
+                        });
+                }
+            })?;
+
+        // pings wait for nodes which don't answer, so they have their own thread rather than holding up the cron tasks
+        let cluster = self.clone();
+        thread::Builder::new()
+            .name("cluster-ping".to_string())
+            .spawn(move || loop {
+                let started_at = Instant::now();
+                cluster.ping_nodes();
+                thread::sleep(PING_INTERVAL.saturating_sub(started_at.elapsed()));
+            })?;
+        Ok(())
+    }
+
+    /// answers pings from other nodes with the own node table
+    fn handle_bus_connection(&self, stream: TcpStream) -> Result<()> {
+        let mut connection = RESPConnection::new(stream);
+        loop {
+            match connection.read_message()? {
+                (_, Some(RESP::Array(message))) => match message.split_first() {
+                    Some((RESP::Bulk(kind), table)) if kind == b"PING" => {
+                        self.0.write().unwrap().merge_node_table(table)?;
+                        connection.send_message(&self.message("PONG"))?;
+                    }
+                    _ => bail!("invalid cluster bus message"),
+                },
+                _ => bail!("invalid cluster bus message"),
+            }
+        }
+    }
+
+    fn message(&self, kind: &str) -> RESP {
+        let mut message = vec![RESP::bulk(kind)];
+        message.append(&mut self.0.read().unwrap().node_table());
+        RESP::Array(message)
+    }
+
+    /// pings all known nodes and the ones to meet, merging the node tables they respond with.
+    /// the nodes are pinged at the same time, so that a node which doesn't answer doesn't delay the pings of the others
+    fn ping_nodes(&self) {
+        let targets = {
+            let state = self.0.read().unwrap();
+            state.nodes.values()
+                .map(|node| Binding(node.host.clone(), node.port))
+                .chain(state.pending_meets.iter().cloned())
+                .collect::<Vec<Binding>>()
+        };
+        thread::scope(|scope| {
+            for target in &targets {
+                scope.spawn(move || {
+                    if let Err(err) = self.ping(target) {
+                        warning!("cluster: ping to {} failed: {}", target, err);
+                    }
+                });
+            }
+        });
+    }
+
+    fn ping(&self, target: &Binding) -> Result<()> {
+        let bus_port = bus_port(target.1).ok_or_else(|| format_err!("no cluster bus port for {}", target))?;
+        let bus_address = Binding(target.0.clone(), bus_port)
+            .to_string()
+            .to_socket_addrs()?
+            .next()
+            .ok_or_else(|| format_err!("can't resolve {}", target))?;
+        let stream = TcpStream::connect_timeout(&bus_address, PING_INTERVAL)?;
+        let mut connection = RESPConnection::new(stream);
+        connection.set_read_timeout(Some(PING_INTERVAL))?;
+        connection.send_message(&self.message("PING"))?;
+        match connection.read_message()? {
+            (_, Some(RESP::Array(message))) => match message.split_first() {
+                Some((RESP::Bulk(kind), table)) if kind == b"PONG" => {
+                    self.0.write().unwrap().merge_node_table(table)
+                }
+                _ => bail!("invalid cluster bus response"),
+            },
+            _ => bail!("invalid cluster bus response"),
+        }
+    }
+}
+
+/// parses a node entry of the bus node table into the node and the slots it owns
+fn parse_node_entry(entry: &str) -> Result<(ClusterNode, Vec<u16>)> {
+    let [id, host, port, ranges] = entry.split(' ').collect::<Vec<&str>>()[..] else {
+        bail!("invalid node entry: {}", entry);
+    };
+    let mut slots = vec![];
+    for range in ranges.split(',').filter(|r| *r != "-") {
+        let (start, end) = range.split_once('-').unwrap_or((range, range));
+        let (start, end) = (start.parse::<u16>()?, end.parse::<u16>()?);
+        if end >= SLOTS || start > end {
+            bail!("invalid slot range: {}", range);
+        }
+        slots.extend(start..=end);
+    }
+    let node = ClusterNode {
+        id: id.to_string(),
+        host: host.to_string(),
+        port: port.parse()?,
+        pong_received: None,
+    };
+    Ok((node, slots))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn bus_port_is_above_the_client_port_within_the_port_range() {
+        assert_eq!(bus_port(7000), Some(17000));
+        assert_eq!(bus_port(55535), Some(65535));
+        assert_eq!(bus_port(55536), None);
+        assert_eq!(bus_port(Port::MAX), None);
+    }
+
+    #[test]
+    fn nodes_without_a_bus_port_are_ignored() {
+        let mut state = ClusterState::new(&Binding("127.0.0.1".to_string(), 7000));
+        let table = [RESP::bulk("a 127.0.0.1 7001 0-10"), RESP::bulk("b 127.0.0.1 65535 11-20")];
+        state.merge_node_table(&table).unwrap();
+        assert!(state.nodes.contains_key("a"));
+        assert!(!state.nodes.contains_key("b"));
+        assert_eq!(state.slot_owners[11], None);
+    }
+}
@@ -0,0 +1,367 @@
+use std::collections::{HashMap, HashSet};
+use std::fs;
+
+use anyhow::{bail, format_err, Context, Result};
+
+use crate::io::net::Cidr;
+use crate::notify::NotifyFlags;
+
+/// configuration parameters with their default values
+const DEFAULTS: &[(&str, &str)] = &[
+    ("stop-writes-on-bgsave-error", "yes"),
+    ("cluster-enabled", "no"),
+    ("client-query-buffer-limit", "1gb"),
+    ("proto-max-bulk-len", "512mb"),
+    // number of elements of an array in a request
+    ("proto-max-multibulk-len", "1048576"),
+    // number of arrays nested in each other in a request, the commands of clients are not nested
+    ("proto-max-multibulk-depth", "1"),
+    // name of the append only file written by BGREWRITEAOF in the data directory
+    ("appendfilename", "appendonly.aof"),
+    // the replicated commands are appended to the append only file, which is loaded at startup instead of the RDB file
+    ("appendonly", "no"),
+    // an append only file ending within a command, e.g. after a crash, is loaded up to its last complete command
+    // and truncated there, else the server doesn't start
+    ("aof-load-truncated", "yes"),
+    // when the append only file is flushed to the disk: always after every write, everysec once per second
+    // in the background or no, leaving it to the operating system
+    ("appendfsync", "everysec"),
+    // path of the audit log of administrative commands, disabled when empty
+    ("audit-log", ""),
+    // memory limit of the dataset, unlimited when 0
+    ("maxmemory", "0"),
+    ("maxmemory-policy", "noeviction"),
+    // number of keys sampled to pick a key to evict
+    ("maxmemory-samples", "5"),
+    // memory limit of all clients together, for the commands queued by their transactions, unlimited when 0
+    ("maxmemory-clients", "0"),
+    // number of clients which can be connected at the same time, further connections are rejected
+    ("maxclients", "10000"),
+    // address the server listens on, read at startup
+    ("bind", "127.0.0.1"),
+    // when listening on all interfaces without a client allowlist, only loopback clients are accepted
+    ("protected-mode", "yes"),
+    // space separated networks in CIDR notation which clients may connect from, any network when empty.
+    // loopback clients are always accepted
+    ("client-allowlist", ""),
+    // milliseconds a command scanning keys or members may run before it is aborted, unlimited when 0
+    ("command-time-budget", "0"),
+    // password clients authenticate with as the default user, no authentication when empty
+    ("requirepass", ""),
+    // every frame sent over replication connections is logged, by the master and by the replica
+    ("repl-debug-frames", "no"),
+    // size of the backlog of the most recent replication stream, which replicas reconnecting within it catch up from
+    ("repl-backlog-size", "1mb"),
+    // clients of a replica can't run commands writing to the store, else their writes are kept
+    // until the master overwrites the keys or the next full resync replaces the store
+    ("replica-read-only", "yes"),
+    // a replica serves reads while its link to the master is down or its first sync is running,
+    // else it replies MASTERDOWN to the commands using the data
+    ("replica-serve-stale-data", "yes"),
+    // seconds between the PINGs the master sends to its replicas, never when 0
+    ("repl-ping-replica-period", "10"),
+    // password a replica authenticates with to its master
+    ("masterauth", ""),
+    // an unknown opcode in the RDB file loaded at startup ends the load keeping the keys before it,
+    // instead of failing the startup
+    ("rdb-skip-unknown-opcodes", "no"),
+    // RDB files are saved with a CRC64 checksum, which is verified when they are loaded.
+    // without it files end with a zero checksum and loading does not verify them
+    ("rdbchecksum", "yes"),
+    // classes of keyspace events published to the keyspace and keyevent channels, none when empty
+    ("notify-keyspace-events", ""),
+    // frequency of the background tasks of the server in ticks per second, between 1 and 500
+    ("hz", "10"),
+    // seconds after which a client waiting for its next request is disconnected, never when 0
+    ("timeout", "0"),
+    // seconds of inactivity after which TCP keepalive probes are sent to clients, disabled when 0. set on new connections
+    ("tcp-keepalive", "300"),
+    // least severe messages which are logged: debug, verbose, notice or warning
+    ("loglevel", "notice"),
+    // path of the file the log is appended to, the standard output when empty
+    ("logfile", ""),
+    // values with many elements removed by eviction, by the active expiry, by DEL or by FLUSHALL and FLUSHDB
+    // without ASYNC or SYNC are freed in a background thread, rather than by the command removing them
+    ("lazyfree-lazy-eviction", "no"),
+    ("lazyfree-lazy-expire", "no"),
+    ("lazyfree-lazy-user-del", "no"),
+    ("lazyfree-lazy-user-flush", "no"),
+    // rules of automatic background saves as pairs of seconds and changes: the store is saved once it was changed
+    // at least that many times and the seconds passed since the last save. no automatic saves when empty
+    ("save", "3600 1 300 100 60 10000"),
+];
+
+/// parameters holding secrets, which are left out of the audit log as redis leaves them out of the slowlog
+const SENSITIVE: &[&str] = &["requirepass", "masterauth"];
+
+/// line of a config file after which CONFIG REWRITE appends the parameters missing from the file
+const REWRITE_MARKER: &str = "# Generated by CONFIG REWRITE";
+
+/// parameters accepting one of a fixed set of values
+const ENUMS: &[(&str, &[&str])] = &[
+    ("maxmemory-policy", &["noeviction", "allkeys-lru", "volatile-lru", "allkeys-random", "volatile-random", "volatile-ttl"]),
+    ("loglevel", &["debug", "verbose", "notice", "warning"]),
+    ("appendfsync", &["always", "everysec", "no"]),
+];
+
+/// runtime configuration, readable and writable with CONFIG GET / CONFIG SET.
+/// values are kept as strings as they are sent over the wire and parsed on access.
+pub struct Config {
+    values: HashMap<String, String>,
+    /// config file the server was started with, which CONFIG REWRITE writes back to
+    file: Option<String>,
+}
+
+impl Config {
+    /// default configuration overridden by command line options in the form `--name value`,
+    /// which include the directives of the config file if the server was started with one
+    pub fn from_args(args: &[String], file: Option<String>) -> Result<Self> {
+        let mut config = Config {
+            values: DEFAULTS.iter().map(|(k, v)| (k.to_string(), v.to_string())).collect(),
+            file,
+        };
+        for (name, _) in DEFAULTS {
+            if let Some(value) = crate::args::named_option::<String>(args, &format!("--{}", name))? {
+                config.set(name, &value)?;
+            }
+        }
+        Ok(config)
+    }
+
+    pub fn get(&self, name: &str) -> Option<&str> {
+        self.values.get(&name.to_lowercase()).map(|v| v.as_str())
+    }
+
+    pub fn set(&mut self, name: &str, value: &str) -> Result<()> {
+        let name = name.to_lowercase();
+        let value = match self.values.get(&name) {
+            None => bail!("ERR Unknown option or number of arguments for CONFIG SET - '{}'", name),
+            Some(_) if ENUMS.iter().any(|(enum_name, _)| *enum_name == name) => {
+                let (_, allowed) = ENUMS.iter().find(|(enum_name, _)| *enum_name == name).expect("enum exists");
+                let value = value.to_lowercase();
+                if !allowed.contains(&value.as_str()) {
+                    bail!("ERR CONFIG SET failed (possibly related to argument '{}') - argument(s) must be one of the following: {}", name, allowed.join(", "));
+                }
+                value
+            }
+            Some(_) if name == "client-allowlist" => {
+                if let Some(invalid) = value.split_whitespace().find(|cidr| cidr.parse::<Cidr>().is_err()) {
+                    bail!("ERR CONFIG SET failed (possibly related to argument '{}') - invalid network '{}'", name, invalid);
+                }
+                value.to_string()
+            }
+            Some(_) if name == "save" => {
+                let words = value.split_whitespace().collect::<Vec<&str>>();
+                if words.len() % 2 != 0 || words.iter().any(|word| word.parse::<u64>().is_err()) {
+                    bail!("ERR CONFIG SET failed (possibly related to argument '{}') - Invalid save parameters", name);
+                }
+                words.join(" ")
+            }
+            Some(_) if name == "notify-keyspace-events" => {
+                match value.parse::<NotifyFlags>() {
+                    Ok(flags) => flags.to_string(),
+                    Err(err) => bail!("ERR CONFIG SET failed (possibly related to argument '{}') - {}", name, err),
+                }
+            }
+            Some(current) if is_bool(current) => {
+                let value = value.to_lowercase();
+                if !is_bool(&value) {
+                    bail!("ERR CONFIG SET failed (possibly related to argument '{}') - argument must be 'yes' or 'no'", name);
+                }
+                value
+            }
+            Some(current) if parse_bytes(current).is_some() => {
+                if parse_bytes(value).is_none() {
+                    bail!("ERR CONFIG SET failed (possibly related to argument '{}') - argument must be a memory or number value", name);
+                }
+                value.to_lowercase()
+            }
+            Some(_) => value.to_string(),
+        };
+        self.values.insert(name, value);
+        Ok(())
+    }
+
+    pub fn get_bool(&self, name: &str) -> bool {
+        self.get(name) == Some("yes")
+    }
+
+    /// memory size in bytes, values can have a unit like 100mb
+    pub fn get_bytes(&self, name: &str) -> Option<u64> {
+        self.get(name).and_then(parse_bytes)
+    }
+
+    /// writes the current values back to the config file. the directives of the parameters are updated in place,
+    /// comments and other directives like port are kept as they are, and parameters changed from their default
+    /// which are not in the file yet are appended after a marker line.
+    /// the file is replaced at once, so that a failure leaves the previous file
+    pub fn rewrite(&self) -> Result<()> {
+        let Some(path) = &self.file else {
+            bail!("ERR The server is running without a config file");
+        };
+        // a file removed since the start is written again
+        let content = fs::read_to_string(path).unwrap_or_default();
+        let mut rewritten = HashSet::new();
+        let mut lines = vec![];
+        for line in content.lines() {
+            let name = split_directive(line).and_then(|directive| directive.first().map(|name| name.to_lowercase()));
+            match name.and_then(|name| self.values.get(&name).map(|value| (name, value))) {
+                // repeated directives of a parameter are dropped, the first one holds the value
+                Some((name, value)) => {
+                    if rewritten.insert(name.clone()) {
+                        lines.push(format!("{} {}", name, quote(value)));
+                    }
+                }
+                None => lines.push(line.to_string()),
+            }
+        }
+        let changed = DEFAULTS.iter()
+            .filter(|(name, default)| !rewritten.contains(*name) && self.get(name) != Some(default))
+            .map(|(name, _)| format!("{} {}", name, quote(self.get(name).unwrap_or_default())))
+            .collect::<Vec<String>>();
+        if !changed.is_empty() {
+            // the marker is written once, the parameters of later rewrites follow the ones appended before
+            if !lines.iter().any(|line| line == REWRITE_MARKER) {
+                lines.push(REWRITE_MARKER.to_string());
+            }
+            lines.extend(changed);
+        }
+        let temp_path = format!("{}.rewrite", path);
+        let mut content = lines.join("\n");
+        content.push('\n');
+        fs::write(&temp_path, content)
+            .and_then(|_| fs::rename(&temp_path, path))
+            .map_err(|err| format_err!("ERR Rewriting config file: {}", err))
+    }
+}
+
+/// reads the directives of a config file as command line options: `--name value` for each line `name value`.
+/// the arguments of a directive with several of them, like `replicaof host port`, make a single value
+pub fn read_file(path: &str) -> Result<Vec<String>> {
+    let content = fs::read_to_string(path).with_context(|| format!("reading config file {}", path))?;
+    let mut args: Vec<String> = vec![];
+    for (number, line) in content.lines().enumerate() {
+        let Some(directive) = split_directive(line) else {
+            bail!("invalid config file {} at line {}: unbalanced quotes", path, number + 1);
+        };
+        if let [name, values @ ..] = &directive[..] {
+            let name = format!("--{}", name.to_lowercase());
+            let value = values.join(" ");
+            // the rules of save directives add up, an empty save directive clears the rules before it
+            let previous_save = args.iter().position(|arg| *arg == name && name == "--save");
+            match previous_save {
+                Some(position) if !value.is_empty() && !args[position + 1].is_empty() => {
+                    args[position + 1] = format!("{} {}", args[position + 1], value);
+                }
+                Some(position) => args[position + 1] = value,
+                None => {
+                    args.push(name);
+                    args.push(value);
+                }
+            }
+        }
+    }
+    Ok(args)
+}
+
+/// words of a line of a config file, words in double quotes may contain spaces and escaped characters and
+/// words in single quotes are taken as they are. no words for comments and empty lines, None on unbalanced quotes
+fn split_directive(line: &str) -> Option<Vec<String>> {
+    let line = line.trim();
+    if line.starts_with('#') {
+        return Some(vec![]);
+    }
+    let mut words = vec![];
+    let mut chars = line.chars().peekable();
+    while let Some(&c) = chars.peek() {
+        if c.is_whitespace() {
+            chars.next();
+            continue;
+        }
+        let mut word = String::new();
+        match c {
+            '"' => {
+                chars.next();
+                loop {
+                    match chars.next()? {
+                        '"' => break,
+                        '\\' => match chars.next()? {
+                            'n' => word.push('\n'),
+                            't' => word.push('\t'),
+                            'r' => word.push('\r'),
+                            escaped => word.push(escaped),
+                        },
+                        c => word.push(c),
+                    }
+                }
+            }
+            '\'' => {
+                chars.next();
+                loop {
+                    match chars.next()? {
+                        '\'' => break,
+                        c => word.push(c),
+                    }
+                }
+            }
+            _ => {
+                while let Some(c) = chars.next_if(|c| !c.is_whitespace()) {
+                    word.push(c);
+                }
+            }
+        }
+        words.push(word);
+    }
+    Some(words)
+}
+
+/// value as written to a config file, in double quotes when it is empty or has spaces or quotes
+fn quote(value: &str) -> String {
+    if !value.is_empty() && !value.contains(|c: char| c.is_whitespace() || c == '"' || c == '\'' || c == '\\') {
+        return value.to_string();
+    }
+    let escaped = value.chars()
+        .map(|c| match c {
+            '"' => "\\\"".to_string(),
+            '\\' => "\\\\".to_string(),
+            '\n' => "\\n".to_string(),
+            '\t' => "\\t".to_string(),
+            '\r' => "\\r".to_string(),
+            c => c.to_string(),
+        })
+        .collect::<String>();
+    format!("\"{}\"", escaped)
+}
+
+/// the value of the parameter is a secret
+pub fn is_sensitive(name: &str) -> bool {
+    SENSITIVE.iter().any(|sensitive| sensitive.eq_ignore_ascii_case(name))
+}
+
+/// parses a memory size with an optional unit: b, k, kb, m, mb, g, gb
+pub fn parse_bytes(value: &str) -> Option<u64> {
+    let value = value.to_lowercase();
+    let digits = value.find(|c: char| !c.is_ascii_digit()).unwrap_or(value.len());
+    let (amount, unit) = value.split_at(digits);
+    let multiplier = match unit {
+        "" | "b" => 1,
+        "k" => 1000,
+        "kb" => 1024,
+        "m" => 1000 * 1000,
+        "mb" => 1024 * 1024,
+        "g" => 1000 * 1000 * 1000,
+        "gb" => 1024 * 1024 * 1024,
+        _ => return None,
+    };
+    amount.parse::<u64>().ok()?.checked_mul(multiplier)
+}
+
+/// rules of the save parameter as pairs of seconds and changes
+pub fn save_rules(value: &str) -> Vec<(u64, u64)> {
+    let numbers = value.split_whitespace().filter_map(|number| number.parse::<u64>().ok()).collect::<Vec<u64>>();
+    numbers.chunks_exact(2).map(|rule| (rule[0], rule[1])).collect()
+}
+
+fn is_bool(value: &str) -> bool {
+    matches!(value, "yes" | "no")
+}
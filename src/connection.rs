@@ -1,33 +1,286 @@
-use std::net::TcpStream;
+use std::net::{SocketAddr, TcpStream};
 use std::thread;
 
 use anyhow::Result;
 
-use crate::protocol::command::CommandRequest;
-use crate::protocol::resp::{RESP, RESPConnection};
+use crate::clients::ConnectionState;
+use crate::log::debug;
+use crate::protocol::command::{Command, CommandRequest};
+use crate::protocol::resp::{ProtocolError, RESP, RESPConnection};
+use crate::pubsub::Subscriptions;
+use crate::redis::RedisServer;
+use crate::session::Session;
+use crate::transaction::{QueuedCommand, Transaction, WatchedKeys};
 
 pub trait ClientConnectionHandler {
-    /// processing messages from a tcp stream
+    /// processing messages from a tcp stream.
+    ///
+    /// the commands of a connection are read, executed and replied one at a time on the thread of the connection,
+    /// so replies are sent in the order of the requests and every command sees the writes of the commands before it,
+    /// also while a command waits for a CLIENT PAUSE to end, or the commands of a transaction wait for EXEC
     fn handle_connection(&mut self, stream: TcpStream) -> Result<()> {
+        let peer_addr = stream.peer_addr()?;
+        let mut session = Session::new(self.server());
+        let clients = self.server().clients.clone();
+        let _registration = clients.register(session.id(), &stream)?;
         let mut connection = RESPConnection::new(stream);
+        let mut subscriptions = Subscriptions::new(self.server().pubsub.clone(), connection.writer());
+        let mut transaction: Option<Transaction> = None;
+        let mut watched = WatchedKeys::default();
         loop {
             let current = thread::current();
             let thread_name = current.name().unwrap();
 
-            let (message_bytes, message) = connection.read_message()?;
+            // the replies of pipelined requests are sent together, once all requests received so far are served
+            if !connection.has_buffered_input() {
+                connection.flush()?;
+            }
+            connection.set_limits(self.server().request_limits());
+            // the client is idle while the connection waits for its request
+            let state = ConnectionState {
+                subscriptions: subscriptions.counts(),
+                multi: transaction.as_ref().map(|transaction| transaction.queued_count()),
+                protocol: session.protocol(),
+            };
+            clients.waiting(session.id(), state);
+            let (message_bytes, message) = match connection.read_message() {
+                Err(err) if err.is::<ProtocolError>() => {
+                    // the rest of the input can't be interpreted, the client is disconnected
+                    connection.send_message(&RESP::Error(format!("ERR {}", err)))?;
+                    return Err(err);
+                }
+                result => result?,
+            };
             let message = message.expect("message not read");
-            let command: CommandRequest = message.clone().try_into()?;
+            let command: CommandRequest = match message.clone().try_into() {
+                Ok(command) => command,
+                // empty and null arrays are ignored
+                Err(_) if message == RESP::Null || message == RESP::Array(vec![]) => continue,
+                Err(err) if matches!(message, RESP::Array(_)) => {
+                    connection.write_message(&refuse(&mut transaction, err.to_string()))?;
+                    continue;
+                }
+                Err(err) => return Err(err),
+            };
 
-            println!("@{}: received command: {:?} ", thread_name, command);
+            debug!("@{}: received command: {:?} ", thread_name, command);
+            self.server().command_received();
+            clients.received(session.id(), &command);
 
-            self.handle_request(message_bytes, message, command, &mut connection)?;
+            if let Some(RESP::Error(error)) = command.arity_error() {
+                connection.write_message(&refuse(&mut transaction, error))?;
+                continue;
+            }
+
+            if let Some(responses) = session.handle_command(self.server(), &command) {
+                // HELLO switches the protocol, starting with its own reply
+                connection.set_protocol(session.protocol());
+                connection.write_messages(&responses.iter().collect::<Vec<&RESP>>())?;
+                continue;
+            }
+
+            // replication connections are never paused, only client commands.
+            // commands which wait send the replies before them first
+            if command.may_block() || self.server().is_client_paused(&command) {
+                connection.flush()?;
+            }
+            self.server().wait_client_pause(&command);
+
+            // shard channels are subscribed on the node serving their slot
+            let redirect = (command.0 == Command::SSUBSCRIBE).then(|| self.server().check_cluster_slot(&command)).flatten();
+            if let Some(redirect) = redirect {
+                connection.write_message(&redirect)?;
+                continue;
+            }
+
+            if let Some(responses) = subscriptions.handle_command(&command, session.protocol()) {
+                connection.write_messages(&responses.iter().collect::<Vec<&RESP>>())?;
+                continue;
+            }
+
+            match (transaction.as_mut(), command.0) {
+                (None, Command::MULTI) => {
+                    // minimal implementation of https://redis.io/docs/latest/commands/multi/
+                    transaction = Some(Transaction::new(self.server()));
+                    connection.write_message(&RESP::String("OK".to_string()))?;
+                }
+                (Some(_), Command::MULTI) => {
+                    connection.write_message(&RESP::Error("ERR MULTI calls can not be nested".to_string()))?;
+                }
+                (Some(_), Command::EXEC) => {
+                    // minimal implementation of https://redis.io/docs/latest/commands/exec/
+                    let reply = match transaction.take().expect("transaction started").into_commands() {
+                        Ok(queued) => self.exec(queued, &watched, &peer_addr)?,
+                        Err(abort) => abort,
+                    };
+                    watched.clear();
+                    connection.write_message(&reply)?;
+                }
+                (Some(_), Command::DISCARD) => {
+                    // minimal implementation of https://redis.io/docs/latest/commands/discard/
+                    transaction = None;
+                    watched.clear();
+                    connection.write_message(&RESP::String("OK".to_string()))?;
+                }
+                (None, Command::WATCH) if !command.1.is_empty() => {
+                    // minimal implementation of https://redis.io/docs/latest/commands/watch/
+                    // WATCH key [key ...]
+                    watched.watch(self.server(), &command.1);
+                    connection.write_message(&RESP::String("OK".to_string()))?;
+                }
+                (Some(_), Command::WATCH) => {
+                    connection.write_message(&RESP::Error("ERR WATCH inside MULTI is not allowed".to_string()))?;
+                }
+                (None, Command::UNWATCH) => {
+                    // minimal implementation of https://redis.io/docs/latest/commands/unwatch/
+                    watched.clear();
+                    connection.write_message(&RESP::String("OK".to_string()))?;
+                }
+                (None, Command::EXEC | Command::DISCARD) => {
+                    connection.write_message(&RESP::Error(format!("ERR {} without MULTI", command.0)))?;
+                }
+                (Some(transaction), _) => {
+                    let reply = transaction.queue(self.server(), message_bytes, message, command);
+                    connection.write_message(&reply)?;
+                }
+                (None, _) => {
+                    // the lock is shared until the changes of the command are replicated, blocked commands release it
+                    // while they wait. PSYNC takes it exclusively for the snapshot and WAIT only waits for replicas
+                    let exec_lock = self.server().exec_lock.clone();
+                    let _shared = (!matches!(command.0, Command::PSYNC | Command::WAIT)).then(|| exec_lock.shared());
+                    audit(self.server(), &peer_addr, &command);
+                    self.handle_request(message_bytes, message, command, &mut connection)?;
+                }
+            }
+        }
+    }
+
+    /// executes the commands of a transaction one after another, with no other command running in between.
+    /// replies with the responses of all commands, or nil without executing them if a watched key changed
+    fn exec(&mut self, queued: Vec<QueuedCommand>, watched: &WatchedKeys, peer_addr: &SocketAddr) -> Result<RESP> {
+        let exec_lock = self.server().exec_lock.clone();
+        let _exclusive = exec_lock.exclusive();
+        if watched.is_modified(self.server()) {
+            return Ok(RESP::Null);
+        }
+        let mut responses = vec![];
+        for (message_bytes, message, command) in queued {
+            audit(self.server(), peer_addr, &command);
+            // a failing command replies its error in place, the following commands still run
+            match self.execute_request(message_bytes, message, &command) {
+                Ok(command_responses) => responses.extend(command_responses),
+                Err(err) => responses.push(error_reply(&err)),
+            }
         }
+        Ok(RESP::Array(responses))
     }
+
+    /// server the connection belongs to
+    fn server(&self) -> &RedisServer;
+
+    /// executes a client command, returning its responses
+    fn execute_request(&mut self, message_bytes: usize, message: RESP, command: &CommandRequest) -> Result<Vec<RESP>>;
+
     fn handle_request(
         &mut self,
         message_bytes: usize,
         message: RESP,
         command: CommandRequest,
         connection: &mut RESPConnection,
-    ) -> Result<()>;
+    ) -> Result<()> {
+        let responses = self.execute_request(message_bytes, message, &command)?;
+        connection.write_messages(&responses.iter().collect::<Vec<&RESP>>())?;
+        Ok(())
+    }
+}
+
+/// refuses a command which is unknown or has the wrong number of arguments,
+/// which also fails the transaction it was sent in so that EXEC discards it
+fn refuse(transaction: &mut Option<Transaction>, error: String) -> RESP {
+    match transaction {
+        Some(transaction) => transaction.abort(error),
+        None => RESP::Error(error),
+    }
+}
+
+/// records an administrative command in the audit log as it is executed, not when a transaction queues it
+fn audit(server: &RedisServer, peer_addr: &SocketAddr, command: &CommandRequest) {
+    if command.is_admin() {
+        server.audit(peer_addr, command);
+    }
+}
+
+/// error reply of a command which failed, prefixed with the generic error code unless it has one
+fn error_reply(err: &anyhow::Error) -> RESP {
+    let error = err.to_string();
+    let has_code = error.split_once(' ').is_some_and(|(code, _)| !code.is_empty() && code.chars().all(|c| c.is_ascii_uppercase()));
+    RESP::Error(if has_code { error } else { format!("ERR {}", error) })
+}
+
+#[cfg(test)]
+mod tests {
+    use std::env;
+    use std::net::TcpListener;
+    use std::time::{Duration, Instant};
+
+    use super::*;
+    use crate::config::Config;
+    use crate::io::net::Binding;
+    use crate::master::{MasterConnection, MasterServer};
+
+    /// master serving every connection on its own thread, like the server does
+    fn start_master() -> SocketAddr {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let address = listener.local_addr().unwrap();
+        let binding = Binding("127.0.0.1".to_string(), address.port() as u32);
+        let config = Config::from_args(&[], None).unwrap();
+        let db_filename = format!("ordering-{}.rdb", std::process::id());
+        let redis = RedisServer::new(binding, true, env::temp_dir().to_string_lossy().into_owned(), db_filename, config).unwrap();
+        let master = MasterServer::new(redis);
+        thread::spawn(move || {
+            for stream in listener.incoming() {
+                let stream = stream.unwrap();
+                let peer = stream.peer_addr().unwrap();
+                let mut connection = MasterConnection::new(master.clone(), peer);
+                thread::Builder::new()
+                    .name(format!("client-master-{}", peer))
+                    .spawn(move || connection.handle_connection(stream))
+                    .unwrap();
+            }
+        });
+        address
+    }
+
+    fn connect(address: SocketAddr) -> RESPConnection {
+        RESPConnection::new(TcpStream::connect(address).unwrap())
+    }
+
+    fn read_reply(connection: &mut RESPConnection) -> RESP {
+        connection.read_message().unwrap().1.unwrap()
+    }
+
+    fn bulks(items: &[&str]) -> RESP {
+        RESP::Array(items.iter().map(|item| RESP::bulk(item)).collect())
+    }
+
+    #[test]
+    fn pipelined_replies_follow_the_requests_and_see_their_writes_during_client_pause() {
+        let address = start_master();
+        let mut admin = connect(address);
+        let paused = Instant::now();
+        admin.send_raw(b"CLIENT PAUSE 200 WRITE\r\n").unwrap();
+        assert_eq!(read_reply(&mut admin), RESP::String("OK".to_string()));
+
+        let mut client = connect(address);
+        client.send_raw(b"RPUSH list a\r\nLRANGE list 0 -1\r\nMULTI\r\nRPUSH list b\r\nLRANGE list 0 -1\r\nEXEC\r\nLRANGE list 0 -1\r\n").unwrap();
+        // the write waits for the pause, and the read after it waits for the write
+        assert_eq!(read_reply(&mut client), RESP::Int(1));
+        assert!(paused.elapsed() >= Duration::from_millis(200), "replied {:?} after the pause", paused.elapsed());
+        assert_eq!(read_reply(&mut client), bulks(&["a"]));
+        assert_eq!(read_reply(&mut client), RESP::String("OK".to_string()));
+        assert_eq!(read_reply(&mut client), RESP::String("QUEUED".to_string()));
+        assert_eq!(read_reply(&mut client), RESP::String("QUEUED".to_string()));
+        assert_eq!(read_reply(&mut client), RESP::Array(vec![RESP::Int(2), bulks(&["a", "b"])]));
+        assert_eq!(read_reply(&mut client), bulks(&["a", "b"]));
+    }
 }
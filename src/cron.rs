@@ -0,0 +1,62 @@
+use std::sync::{Arc, RwLock};
+use std::thread;
+use std::time::{Duration, Instant};
+
+use anyhow::Result;
+
+use crate::config::Config;
+use crate::log::verbose;
+
+/// bounds of the hz config, the number of cron ticks per second
+const MIN_HZ: u32 = 1;
+const MAX_HZ: u32 = 500;
+
+type Task = Box<dyn FnMut(Duration) + Send>;
+
+struct CronTask {
+    name: &'static str,
+    interval: Duration,
+    last_run: Option<Instant>,
+    run: Task,
+}
+
+/// periodic background work of the server run from a single scheduler thread.
+/// the thread ticks hz times per second, read from the config on every tick, and runs the tasks which are due.
+/// tasks get the tick period, so that they can bound the work done in a tick
+#[derive(Default)]
+pub(crate) struct Cron {
+    tasks: Vec<CronTask>,
+}
+
+impl Cron {
+    /// registers a task run at most every interval, a zero interval runs it on every tick
+    pub(crate) fn every(&mut self, name: &'static str, interval: Duration, task: impl FnMut(Duration) + Send + 'static) {
+        self.tasks.push(CronTask { name, interval, last_run: None, run: Box::new(task) });
+    }
+
+    pub(crate) fn start(mut self, config: Arc<RwLock<Config>>) -> Result<()> {
+        verbose!("cron starting with tasks: {:?}", self.tasks.iter().map(|task| task.name).collect::<Vec<&str>>());
+        thread::Builder::new()
+            .name("cron".to_string())
+            .spawn(move || loop {
+                let hz = config.read().unwrap().get("hz")
+                    .and_then(|hz| hz.parse::<u32>().ok())
+                    .unwrap_or(10)
+                    .clamp(MIN_HZ, MAX_HZ);
+                let tick = Duration::from_secs(1) / hz;
+                let started_at = Instant::now();
+                for task in &mut self.tasks {
+                    let due = match task.last_run {
+                        Some(last_run) => started_at.duration_since(last_run) >= task.interval,
+                        None => true,
+                    };
+                    if due {
+                        task.last_run = Some(started_at);
+                        (task.run)(tick);
+                    }
+                }
+                thread::sleep(tick.saturating_sub(started_at.elapsed()));
+            })?;
+        Ok(())
+    }
+}
@@ -0,0 +1,49 @@
+use std::collections::VecDeque;
+use std::sync::{Condvar, Mutex};
+
+use crate::stream::StreamRecordId;
+
+#[derive(Clone, Debug, PartialEq)]
+pub(crate) enum KeyEventKind {
+    /// the key expired and was removed
+    Expired,
+    /// an entry was added to the stream stored under the key
+    StreamAdded(StreamRecordId),
+    /// elements were pushed to the list stored under the key
+    ListPushed,
+    /// elements were popped from the list stored under the key
+    ListPopped,
+    /// members were added to the set stored under the key
+    SetAdded,
+    /// members were removed from the set stored under the key
+    SetRemoved,
+    /// members were added to the sorted set stored under the key
+    ZSetAdded,
+    /// members were removed from the sorted set stored under the key
+    ZSetRemoved,
+    /// the value of the key was renamed to another key, which emits [KeyEventKind::RenamedTo]
+    RenamedFrom,
+    /// the key received the value of a renamed key
+    RenamedTo,
+    /// the key received a copy of the value of another key
+    CopiedTo,
+}
+
+impl KeyEventKind {
+    /// the key may hold a list or a sorted set with elements after the event, which blocked pops wait for
+    pub(crate) fn may_fill(&self) -> bool {
+        matches!(self, KeyEventKind::ListPushed | KeyEventKind::ZSetAdded | KeyEventKind::RenamedTo | KeyEventKind::CopiedTo)
+    }
+}
+
+/// change of a key. events carry a sequence number increasing in the order the store made the changes,
+/// e.g. the expiration of a key is always emitted before the write which replaces it
+#[derive(Clone, Debug)]
+pub(crate) struct KeyEvent {
+    pub(crate) seq: u64,
+    pub(crate) key: Vec<u8>,
+    pub(crate) kind: KeyEventKind,
+}
+
+/// queue of the events of the watched keys, with the condition signalled on every new event
+pub(crate) type KeyWatch = (Mutex<VecDeque<KeyEvent>>, Condvar);
@@ -0,0 +1,43 @@
+use std::str::FromStr;
+
+use anyhow::bail;
+
+/// keys chosen for eviction once maxmemory is reached, as described in https://redis.io/docs/latest/develop/reference/eviction/
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum EvictionPolicy {
+    /// nothing is evicted, writes are refused instead
+    NoEviction,
+    /// least recently used keys
+    AllKeysLru,
+    /// least recently used keys with an expiration
+    VolatileLru,
+    /// random keys
+    AllKeysRandom,
+    /// random keys with an expiration
+    VolatileRandom,
+    /// keys with the nearest expiration
+    VolatileTtl,
+}
+
+impl EvictionPolicy {
+    /// only keys with an expiration are candidates
+    pub fn is_volatile(&self) -> bool {
+        matches!(self, EvictionPolicy::VolatileLru | EvictionPolicy::VolatileRandom | EvictionPolicy::VolatileTtl)
+    }
+}
+
+impl FromStr for EvictionPolicy {
+    type Err = anyhow::Error;
+
+    fn from_str(input: &str) -> anyhow::Result<Self, Self::Err> {
+        match input.to_lowercase().as_str() {
+            "noeviction" => Ok(EvictionPolicy::NoEviction),
+            "allkeys-lru" => Ok(EvictionPolicy::AllKeysLru),
+            "volatile-lru" => Ok(EvictionPolicy::VolatileLru),
+            "allkeys-random" => Ok(EvictionPolicy::AllKeysRandom),
+            "volatile-random" => Ok(EvictionPolicy::VolatileRandom),
+            "volatile-ttl" => Ok(EvictionPolicy::VolatileTtl),
+            _ => bail!("unknown eviction policy: {}", input),
+        }
+    }
+}
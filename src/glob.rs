@@ -0,0 +1,84 @@
+/// glob-style matching of the bytes of keys and channels against patterns as described in https://redis.io/docs/latest/commands/keys/
+///
+/// supported patterns:
+/// - `?` matches a single byte
+/// - `*` matches any number of bytes, including none
+/// - `[abc]`, `[a-z]` and `[^a]` match a single byte from, or not from, the class
+/// - `\` escapes the following byte so that it is matched literally
+pub fn glob_match(pattern: &[u8], string: &[u8]) -> bool {
+    let (mut p, mut s) = (0, 0);
+    // position in the pattern after the last `*` and the position in the string it was tried at,
+    // a mismatch retries with the `*` consuming one more character
+    let mut backtrack: Option<(usize, usize)> = None;
+
+    while s < string.len() {
+        if p < pattern.len() && pattern[p] == b'*' {
+            while p < pattern.len() && pattern[p] == b'*' {
+                p += 1;
+            }
+            if p == pattern.len() {
+                return true;
+            }
+            backtrack = Some((p, s));
+            continue;
+        }
+        match match_single(pattern, p, string[s]) {
+            Some(next) => {
+                p = next;
+                s += 1;
+            }
+            None => match backtrack {
+                Some((star_p, star_s)) => {
+                    p = star_p;
+                    s = star_s + 1;
+                    backtrack = Some((star_p, s));
+                }
+                None => return false,
+            },
+        }
+    }
+    // the string is consumed, only stars can remain in the pattern
+    pattern[p..].iter().all(|&b| b == b'*')
+}
+
+/// the part of the pattern before its first special character, which every matching string starts with
+pub fn literal_prefix(pattern: &[u8]) -> &[u8] {
+    let end = pattern.iter().position(|b| b"*?[\\".contains(b)).unwrap_or(pattern.len());
+    &pattern[..end]
+}
+
+/// matches the pattern element at `p` against a single character,
+/// returning the position of the next pattern element on a match
+fn match_single(pattern: &[u8], p: usize, c: u8) -> Option<usize> {
+    match pattern.get(p)? {
+        b'?' => Some(p + 1),
+        b'[' => match_class(pattern, p + 1, c),
+        b'\\' if p + 1 < pattern.len() => (pattern[p + 1] == c).then_some(p + 2),
+        &literal => (literal == c).then_some(p + 1),
+    }
+}
+
+/// matches a character class starting after the `[`, an unterminated class extends to the end of the pattern
+fn match_class(pattern: &[u8], mut p: usize, c: u8) -> Option<usize> {
+    let negated = pattern.get(p) == Some(&b'^');
+    if negated {
+        p += 1;
+    }
+    let mut matched = false;
+    while p < pattern.len() && pattern[p] != b']' {
+        if pattern[p] == b'\\' && p + 1 < pattern.len() {
+            matched |= pattern[p + 1] == c;
+            p += 2;
+        } else if p + 2 < pattern.len() && pattern[p + 1] == b'-' && pattern[p + 2] != b']' {
+            let (start, end) = (pattern[p].min(pattern[p + 2]), pattern[p].max(pattern[p + 2]));
+            matched |= (start..=end).contains(&c);
+            p += 3;
+        } else {
+            matched |= pattern[p] == c;
+            p += 1;
+        }
+    }
+    // skip the closing bracket
+    let next = (p + 1).min(pattern.len());
+    (matched != negated).then_some(next)
+}
@@ -0,0 +1,192 @@
+use anyhow::bail;
+
+/// bits of the hash selecting the register
+const P: u32 = 14;
+/// number of registers
+const REGISTERS: usize = 1 << P;
+/// bits of the hash counted for the run of zeros
+const Q: u32 = 64 - P;
+/// bits of a register in the dense encoding
+const REGISTER_BITS: usize = 6;
+const HEADER: &[u8; 4] = b"HYLL";
+const HEADER_LEN: usize = 16;
+const DENSE: u8 = 0;
+const SPARSE: u8 = 1;
+/// set in the last byte of the cached cardinality when it must be computed again
+const CACHE_INVALID: u8 = 1 << 7;
+const INVALID: &str = "WRONGTYPE Key is not a valid HyperLogLog string value.";
+
+/// HyperLogLog estimating the number of distinct elements added to it, as used by PFADD, PFCOUNT and PFMERGE.
+///
+/// it is stored as a string in the format of redis: a header followed by the registers, so that it can be read
+/// with GET and restored with SET, also from redis. sparse strings written by redis are read, but the registers
+/// are always written in the dense encoding, 6 bits each from the least significant bit of the first byte
+#[derive(Clone)]
+pub(crate) struct HyperLogLog {
+    registers: Vec<u8>,
+}
+
+impl Default for HyperLogLog {
+    fn default() -> Self {
+        HyperLogLog { registers: vec![0; REGISTERS] }
+    }
+}
+
+impl HyperLogLog {
+    /// reads a HyperLogLog from the bytes of a string
+    pub(crate) fn from_bytes(bytes: &[u8]) -> anyhow::Result<Self> {
+        if bytes.len() < HEADER_LEN || &bytes[..4] != HEADER {
+            bail!(INVALID);
+        }
+        let data = &bytes[HEADER_LEN..];
+        match bytes[4] {
+            DENSE if data.len() == REGISTERS * REGISTER_BITS / 8 => {
+                Ok(HyperLogLog { registers: (0..REGISTERS).map(|index| dense_register(data, index)).collect() })
+            }
+            SPARSE => Self::from_sparse(data),
+            _ => bail!(INVALID),
+        }
+    }
+
+    /// sparse encoding: runs of zero registers, of up to 64 (ZERO) or 16384 registers (XZERO),
+    /// and runs of up to 4 registers with the same value up to 32 (VAL)
+    fn from_sparse(data: &[u8]) -> anyhow::Result<Self> {
+        let mut registers = Vec::with_capacity(REGISTERS);
+        let mut bytes = data.iter();
+        while let Some(&byte) = bytes.next() {
+            let (value, run) = match byte >> 6 {
+                0b00 => (0, (byte & 0x3f) as usize + 1),
+                0b01 => match bytes.next() {
+                    Some(&next) => (0, (((byte & 0x3f) as usize) << 8 | next as usize) + 1),
+                    None => bail!(INVALID),
+                },
+                _ => (((byte >> 2) & 0x1f) + 1, (byte & 0x03) as usize + 1),
+            };
+            registers.extend(std::iter::repeat_n(value, run));
+        }
+        if registers.len() != REGISTERS {
+            bail!(INVALID);
+        }
+        Ok(HyperLogLog { registers })
+    }
+
+    /// bytes of the string storing the HyperLogLog, in the dense encoding without a cached cardinality
+    pub(crate) fn to_bytes(&self) -> Vec<u8> {
+        let mut bytes = vec![0; HEADER_LEN + REGISTERS * REGISTER_BITS / 8];
+        bytes[..4].copy_from_slice(HEADER);
+        bytes[4] = DENSE;
+        bytes[HEADER_LEN - 1] = CACHE_INVALID;
+        let data = &mut bytes[HEADER_LEN..];
+        for (index, &value) in self.registers.iter().enumerate() {
+            let bit = index * REGISTER_BITS;
+            let (byte, shift) = (bit / 8, bit % 8);
+            data[byte] |= value << shift;
+            if shift > 8 - REGISTER_BITS {
+                data[byte + 1] |= value >> (8 - shift);
+            }
+        }
+        bytes
+    }
+
+    /// adds the element, returns whether a register changed so that the estimation may have changed
+    pub(crate) fn add(&mut self, element: &[u8]) -> bool {
+        let hash = murmur_hash64a(element, 0xadc83b19);
+        let index = (hash & (REGISTERS as u64 - 1)) as usize;
+        // the run of zeros is counted on the remaining bits, ending at the latest after all of them
+        let count = ((hash >> P) | (1 << Q)).trailing_zeros() as u8 + 1;
+        if count > self.registers[index] {
+            self.registers[index] = count;
+            return true;
+        }
+        false
+    }
+
+    /// merges the other HyperLogLog, which then estimates the union of the elements of both
+    pub(crate) fn merge(&mut self, other: &HyperLogLog) {
+        for (register, &value) in self.registers.iter_mut().zip(&other.registers) {
+            *register = (*register).max(value);
+        }
+    }
+
+    /// estimated number of distinct elements, with the estimator of Otmar Ertl used by redis
+    pub(crate) fn count(&self) -> u64 {
+        let m = REGISTERS as f64;
+        let mut histogram = [0_u32; Q as usize + 2];
+        for &value in &self.registers {
+            histogram[value as usize] += 1;
+        }
+        let mut z = m * tau((m - histogram[Q as usize + 1] as f64) / m);
+        for &registers in histogram[1..=Q as usize].iter().rev() {
+            z += registers as f64;
+            z *= 0.5;
+        }
+        z += m * sigma(histogram[0] as f64 / m);
+        (0.5 / 2_f64.ln() * m * m / z).round() as u64
+    }
+}
+
+fn dense_register(data: &[u8], index: usize) -> u8 {
+    let bit = index * REGISTER_BITS;
+    let (byte, shift) = (bit / 8, bit % 8);
+    let high = data.get(byte + 1).copied().unwrap_or(0) as u16;
+    (((data[byte] as u16 | high << 8) >> shift) & 0x3f) as u8
+}
+
+fn sigma(mut x: f64) -> f64 {
+    if x == 1.0 {
+        return f64::INFINITY;
+    }
+    let (mut y, mut z) = (1.0, x);
+    loop {
+        x *= x;
+        let previous = z;
+        z += x * y;
+        y += y;
+        if z == previous {
+            return z;
+        }
+    }
+}
+
+fn tau(mut x: f64) -> f64 {
+    if x == 0.0 || x == 1.0 {
+        return 0.0;
+    }
+    let (mut y, mut z) = (1.0, 1.0 - x);
+    loop {
+        x = x.sqrt();
+        let previous = z;
+        y *= 0.5;
+        z -= (1.0 - x).powi(2) * y;
+        if z == previous {
+            return z / 3.0;
+        }
+    }
+}
+
+/// 64 bit MurmurHash2 by Austin Appleby, which redis hashes the elements of HyperLogLogs with
+fn murmur_hash64a(key: &[u8], seed: u64) -> u64 {
+    const M: u64 = 0xc6a4a7935bd1e995;
+    const R: u32 = 47;
+    let mut h = seed ^ (key.len() as u64).wrapping_mul(M);
+    let mut chunks = key.chunks_exact(8);
+    for chunk in &mut chunks {
+        let mut k = u64::from_le_bytes(chunk.try_into().expect("8 bytes"));
+        k = k.wrapping_mul(M);
+        k ^= k >> R;
+        k = k.wrapping_mul(M);
+        h ^= k;
+        h = h.wrapping_mul(M);
+    }
+    let tail = chunks.remainder();
+    if !tail.is_empty() {
+        for (i, &byte) in tail.iter().enumerate() {
+            h ^= (byte as u64) << (8 * i);
+        }
+        h = h.wrapping_mul(M);
+    }
+    h ^= h >> R;
+    h = h.wrapping_mul(M);
+    h ^= h >> R;
+    h
+}
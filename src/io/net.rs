@@ -1,5 +1,7 @@
 use std::fmt::Display;
+use std::net::{IpAddr, TcpStream};
 use std::str::FromStr;
+use std::time::Duration;
 
 pub const DEFAULT_PORT: Port = 6379;
 
@@ -11,7 +13,7 @@ pub struct Binding(pub Hostname, pub Port);
 
 impl Display for Binding {
     fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
-        write!(f, "{}", format!("{}:{}", self.0, self.1))
+        write!(f, "{}:{}", self.0, self.1)
     }
 }
 
@@ -26,4 +28,90 @@ impl FromStr for Binding {
         let master = Binding(host.to_string(), port);
         Ok(master)
     }
-}
\ No newline at end of file
+}
+/// network in CIDR notation, e.g. 10.0.0.0/8 or fd00::/8. an address without a prefix length is a single host
+#[derive(Debug, Clone, PartialEq)]
+pub struct Cidr(pub IpAddr, pub u8);
+
+impl Cidr {
+    pub fn contains(&self, address: &IpAddr) -> bool {
+        match (self.0, address) {
+            (IpAddr::V4(network), IpAddr::V4(address)) => {
+                prefix_matches(&network.octets(), &address.octets(), self.1)
+            }
+            (IpAddr::V6(network), IpAddr::V6(address)) => {
+                prefix_matches(&network.octets(), &address.octets(), self.1)
+            }
+            (IpAddr::V4(_), IpAddr::V6(address)) => {
+                // ipv4 clients of a dual stack socket are seen as mapped ipv6 addresses
+                address.to_ipv4_mapped().is_some_and(|address| self.contains(&IpAddr::V4(address)))
+            }
+            _ => false,
+        }
+    }
+}
+
+/// the first `prefix_len` bits of the addresses are equal
+fn prefix_matches(network: &[u8], address: &[u8], prefix_len: u8) -> bool {
+    let prefix_len = prefix_len as usize;
+    let (full_bytes, rest_bits) = (prefix_len / 8, prefix_len % 8);
+    if network[..full_bytes] != address[..full_bytes] {
+        return false;
+    }
+    if rest_bits == 0 {
+        return true;
+    }
+    let mask = 0xFF_u8 << (8 - rest_bits);
+    network[full_bytes] & mask == address[full_bytes] & mask
+}
+
+impl FromStr for Cidr {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let (address, prefix_len) = match s.split_once('/') {
+            Some((address, prefix_len)) => (address.parse::<IpAddr>()?, Some(prefix_len.parse::<u8>()?)),
+            None => (s.parse::<IpAddr>()?, None),
+        };
+        let max_len = if address.is_ipv4() { 32 } else { 128 };
+        let prefix_len = prefix_len.unwrap_or(max_len);
+        if prefix_len > max_len {
+            anyhow::bail!("invalid prefix length: {}", s);
+        }
+        Ok(Cidr(address, prefix_len))
+    }
+}
+
+/// enables TCP keepalive on the socket, so that dead peers are detected and connections through middleboxes are kept
+/// open. probes start after `interval` of inactivity and are repeated every third of it, the connection is dropped
+/// after 3 unanswered probes, as redis does. only linux sockets are configured, the socket is left as it is elsewhere
+pub fn set_keepalive(stream: &TcpStream, interval: Duration) -> std::io::Result<()> {
+    #[cfg(target_os = "linux")]
+    {
+        use std::os::fd::AsRawFd;
+
+        // setsockopt of the C library std links against, with the option numbers of linux
+        extern "C" {
+            fn setsockopt(socket: i32, level: i32, name: i32, value: *const std::ffi::c_void, len: u32) -> i32;
+        }
+        const SOL_SOCKET: i32 = 1;
+        const SO_KEEPALIVE: i32 = 9;
+        const IPPROTO_TCP: i32 = 6;
+        const TCP_KEEPIDLE: i32 = 4;
+        const TCP_KEEPINTVL: i32 = 5;
+        const TCP_KEEPCNT: i32 = 6;
+
+        let idle = interval.as_secs().clamp(1, i32::MAX as u64) as i32;
+        let options = [(SOL_SOCKET, SO_KEEPALIVE, 1), (IPPROTO_TCP, TCP_KEEPIDLE, idle), (IPPROTO_TCP, TCP_KEEPINTVL, (idle / 3).max(1)), (IPPROTO_TCP, TCP_KEEPCNT, 3)];
+        for (level, name, value) in options {
+            // SAFETY: the descriptor is open while the stream is borrowed, and the value is an int living through the call
+            let result = unsafe { setsockopt(stream.as_raw_fd(), level, name, &value as *const i32 as *const std::ffi::c_void, size_of::<i32>() as u32) };
+            if result != 0 {
+                return Err(std::io::Error::last_os_error());
+            }
+        }
+    }
+    #[cfg(not(target_os = "linux"))]
+    let _ = (stream, interval);
+    Ok(())
+}
@@ -0,0 +1,96 @@
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::mpsc;
+use std::sync::mpsc::Sender;
+use std::sync::Arc;
+use std::thread;
+
+use anyhow::Result;
+
+use crate::store::StoreEntry;
+
+/// values with more elements than this are freed in the background, smaller ones are cheaper to free in place
+const LAZYFREE_THRESHOLD: usize = 64;
+
+/// work of the background thread, done in the order it was queued
+enum Job {
+    Free(Vec<StoreEntry>),
+    /// signals once the values queued before are freed
+    Drain(Sender<()>),
+}
+
+/// releases removed values in a background thread, so that freeing big values does not block the clients
+#[derive(Clone)]
+pub struct LazyFree {
+    sender: Sender<Job>,
+    /// number of values handed to the background thread and not freed yet
+    pending: Arc<AtomicU64>,
+    /// number of values freed by the background thread
+    freed: Arc<AtomicU64>,
+}
+
+impl LazyFree {
+    pub fn start() -> Result<Self> {
+        let (tx, rx) = mpsc::channel::<Job>();
+        let pending = Arc::new(AtomicU64::new(0));
+        let freed = Arc::new(AtomicU64::new(0));
+        let (thread_pending, thread_freed) = (pending.clone(), freed.clone());
+        thread::Builder::new()
+            .name("lazyfree".to_string())
+            .spawn(move || {
+                for job in rx {
+                    match job {
+                        Job::Free(entries) => {
+                            let count = entries.len() as u64;
+                            drop(entries);
+                            thread_pending.fetch_sub(count, Ordering::Relaxed);
+                            thread_freed.fetch_add(count, Ordering::Relaxed);
+                        }
+                        Job::Drain(done) => {
+                            let _ = done.send(());
+                        }
+                    }
+                }
+            })?;
+        Ok(LazyFree { sender: tx, pending, freed })
+    }
+
+    /// frees the values with many elements in the background and the others in place
+    pub fn free(&self, entries: Vec<StoreEntry>) {
+        let (large, small): (Vec<StoreEntry>, Vec<StoreEntry>) = entries.into_iter()
+            .partition(|entry| entry.len() > LAZYFREE_THRESHOLD);
+        drop(small);
+        self.free_all(large);
+    }
+
+    /// frees all values in the background, as a flushed dataset
+    pub fn free_all(&self, entries: Vec<StoreEntry>) {
+        if entries.is_empty() {
+            return;
+        }
+        let count = entries.len() as u64;
+        self.pending.fetch_add(count, Ordering::Relaxed);
+        if let Err(mpsc::SendError(job)) = self.sender.send(Job::Free(entries)) {
+            // the background thread is gone, free in place
+            self.pending.fetch_sub(count, Ordering::Relaxed);
+            drop(job);
+        }
+    }
+
+    /// waits until the values queued so far are freed
+    pub fn drain(&self) {
+        let (done, wait) = mpsc::channel();
+        if self.sender.send(Job::Drain(done)).is_ok() {
+            let _ = wait.recv();
+        }
+    }
+
+    /// number of values waiting to be freed by the background thread
+    pub fn pending(&self) -> u64 {
+        self.pending.load(Ordering::Relaxed)
+    }
+
+    /// number of values freed by the background thread since the start
+    pub fn freed(&self) -> u64 {
+        self.freed.load(Ordering::Relaxed)
+    }
+}
@@ -0,0 +1,169 @@
+use std::fmt::Arguments;
+use std::fs::{File, OpenOptions};
+use std::io::Write;
+use std::str::FromStr;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Mutex;
+use std::time::SystemTime;
+
+use anyhow::bail;
+
+const MONTHS: [&str; 12] = ["Jan", "Feb", "Mar", "Apr", "May", "Jun", "Jul", "Aug", "Sep", "Oct", "Nov", "Dec"];
+
+/// severity of a log message, messages below the loglevel are dropped
+#[derive(Clone, Copy, Debug, PartialEq, PartialOrd)]
+pub(crate) enum Level {
+    Debug,
+    Verbose,
+    Notice,
+    Warning,
+}
+
+impl FromStr for Level {
+    type Err = anyhow::Error;
+
+    fn from_str(level: &str) -> Result<Self, Self::Err> {
+        match level.to_lowercase().as_str() {
+            "debug" => Ok(Level::Debug),
+            "verbose" => Ok(Level::Verbose),
+            "notice" => Ok(Level::Notice),
+            "warning" => Ok(Level::Warning),
+            _ => bail!("invalid log level {}", level),
+        }
+    }
+}
+
+impl Level {
+    /// mark of the level in the log lines of redis
+    fn mark(&self) -> char {
+        match self {
+            Level::Debug => '.',
+            Level::Verbose => '-',
+            Level::Notice => '*',
+            Level::Warning => '#',
+        }
+    }
+}
+
+/// destination of the log lines of the server, as configured by loglevel and logfile
+struct Logger {
+    level: Level,
+    /// path of the log file, the standard output when empty
+    path: String,
+    file: Option<File>,
+    /// tag of the role of the server in the log lines: M for a master, S for a replica
+    role: char,
+}
+
+static LOGGER: Mutex<Logger> = Mutex::new(Logger { level: Level::Notice, path: String::new(), file: None, role: 'M' });
+
+/// set by SIGHUP, the log file is opened again before the next line is written to it,
+/// so that a rotated log file is written from then on
+static REOPEN: AtomicBool = AtomicBool::new(false);
+
+/// sets the level and the file of the log, opening the file for appending
+pub(crate) fn configure(level: Level, path: &str) -> std::io::Result<()> {
+    let file = open(path)?;
+    let mut logger = LOGGER.lock().unwrap();
+    logger.level = level;
+    logger.path = path.to_string();
+    logger.file = file;
+    Ok(())
+}
+
+/// tags the lines logged from now on with the role of the server
+pub(crate) fn set_role(is_master: bool) {
+    LOGGER.lock().unwrap().role = if is_master { 'M' } else { 'S' };
+}
+
+/// opens the log file again on SIGHUP, as sent after rotating the log file
+pub(crate) fn reopen_on_hangup() {
+    #[cfg(unix)]
+    {
+        // signal of the C library std links against
+        extern "C" {
+            fn signal(signum: i32, handler: extern "C" fn(i32)) -> usize;
+        }
+        const SIGHUP: i32 = 1;
+
+        extern "C" fn on_hangup(_: i32) {
+            REOPEN.store(true, Ordering::Relaxed);
+        }
+        unsafe {
+            signal(SIGHUP, on_hangup);
+        }
+    }
+}
+
+fn open(path: &str) -> std::io::Result<Option<File>> {
+    if path.is_empty() {
+        return Ok(None);
+    }
+    OpenOptions::new().create(true).append(true).open(path).map(Some)
+}
+
+/// writes a line to the log if the level is enabled, in the format of redis: pid:role date time.ms mark message
+pub(crate) fn log(level: Level, message: Arguments) {
+    let mut logger = LOGGER.lock().unwrap();
+    if level < logger.level {
+        return;
+    }
+    if REOPEN.swap(false, Ordering::Relaxed) {
+        match open(&logger.path) {
+            Ok(file) => logger.file = file,
+            Err(err) => eprintln!("reopening log file {} failed: {}", logger.path, err),
+        }
+    }
+    let line = format!("{}:{} {} {} {}\n", std::process::id(), logger.role, timestamp(SystemTime::now()), level.mark(), message);
+    let written = match &mut logger.file {
+        Some(file) => file.write_all(line.as_bytes()),
+        None => std::io::stdout().write_all(line.as_bytes()),
+    };
+    if let Err(err) = written {
+        eprintln!("writing log failed: {}", err);
+    }
+}
+
+/// time in UTC as in the log lines of redis, like 16 Oct 2026 10:30:00.123
+fn timestamp(time: SystemTime) -> String {
+    let since_epoch = time.duration_since(SystemTime::UNIX_EPOCH).unwrap_or_default();
+    let secs = since_epoch.as_secs();
+    let (year, month, day) = civil_date((secs / 86400) as i64);
+    let secs_of_day = secs % 86400;
+    format!(
+        "{:02} {} {} {:02}:{:02}:{:02}.{:03}",
+        day, MONTHS[month as usize - 1], year, secs_of_day / 3600, secs_of_day / 60 % 60, secs_of_day % 60, since_epoch.subsec_millis(),
+    )
+}
+
+/// year, month and day of the days since 1970-01-01, with the algorithm of Howard Hinnant
+fn civil_date(days: i64) -> (i64, u32, u32) {
+    let days = days + 719468;
+    let era = days.div_euclid(146097);
+    let day_of_era = days.rem_euclid(146097);
+    let year_of_era = (day_of_era - day_of_era / 1460 + day_of_era / 36524 - day_of_era / 146096) / 365;
+    let day_of_year = day_of_era - (365 * year_of_era + year_of_era / 4 - year_of_era / 100);
+    let month_index = (5 * day_of_year + 2) / 153;
+    let day = (day_of_year - (153 * month_index + 2) / 5 + 1) as u32;
+    let month = if month_index < 10 { month_index + 3 } else { month_index - 9 } as u32;
+    let year = year_of_era + era * 400 + (month <= 2) as i64;
+    (year, month, day)
+}
+
+macro_rules! debug {
+    ($($arg:tt)*) => { $crate::log::log($crate::log::Level::Debug, format_args!($($arg)*)) };
+}
+
+macro_rules! verbose {
+    ($($arg:tt)*) => { $crate::log::log($crate::log::Level::Verbose, format_args!($($arg)*)) };
+}
+
+macro_rules! notice {
+    ($($arg:tt)*) => { $crate::log::log($crate::log::Level::Notice, format_args!($($arg)*)) };
+}
+
+macro_rules! warning {
+    ($($arg:tt)*) => { $crate::log::log($crate::log::Level::Warning, format_args!($($arg)*)) };
+}
+
+pub(crate) use {debug, notice, verbose, warning};
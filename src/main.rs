@@ -2,28 +2,76 @@ extern crate core;
 
 use std::{env, thread};
 use std::net::TcpListener;
+use std::time::Duration;
 
 use anyhow::Result;
 
+use crate::config::Config;
+use crate::cron::Cron;
 use crate::connection::ClientConnectionHandler;
-use crate::io::net::{Binding, DEFAULT_PORT, Port};
+use crate::io::net::{set_keepalive, Binding, DEFAULT_PORT, Port};
+use crate::log::{notice, verbose, warning};
 use crate::master::{MasterConnection, MasterServer};
+use crate::protocol::resp::RESPConnection;
 use crate::redis::RedisServer;
 use crate::replica::{ReplicaConnection, start_replication};
 
+mod bitmap;
+mod aof;
+mod auditlog;
+mod backlog;
+mod budget;
+mod check;
 mod client;
+mod clients;
+mod cluster;
+mod config;
+mod cron;
+mod events;
+mod eviction;
+mod glob;
+mod hyperloglog;
 mod connection;
 mod io;
+mod lazyfree;
+mod log;
 mod master;
+mod memory;
+mod notify;
 mod protocol;
+mod pubsub;
+mod random;
+mod range;
 mod redis;
 mod replica;
+mod scan;
+mod session;
+mod slots;
+mod stats;
 mod store;
+mod zset;
+mod transaction;
 mod args;
 mod stream;
 
 fn main() -> Result<()> {
-    let args: Vec<String> = env::args().skip(1).collect();
+    let mut args: Vec<String> = env::args().skip(1).collect();
+
+    // tool modes checking persistence files instead of starting the server
+    if let Some(path) = args::named_option::<String>(&args, "--check-rdb")? {
+        return check::check_rdb(&path);
+    }
+    if let Some(path) = args::named_option::<String>(&args, "--check-aof")? {
+        let config = Config::from_args(&args, None)?;
+        return check::check_aof(&path, config.get_bool("aof-load-truncated"));
+    }
+
+    // the config file is given before the options, which override its directives
+    let config_file = args.first().filter(|arg| !arg.starts_with("--")).cloned();
+    if let Some(path) = &config_file {
+        let file_args = config::read_file(path)?;
+        args.extend(file_args);
+    }
 
     // parse options
     let port = args::named_option::<Port>(&args, "--port")?.unwrap_or(DEFAULT_PORT);
@@ -31,18 +79,45 @@ fn main() -> Result<()> {
     let db_dir = args::named_option::<String>(&args, "--dir")?.unwrap_or(".".to_string());
     let db_filename = args::named_option::<String>(&args, "--dbfilename")?.unwrap_or("rds".to_string());
 
+    // TODO serve TLS. this is only a refusal: serving TLS needs a TLS library, which the server is not built with.
+    // clients expecting encryption must not be served in plain text, so the server doesn't start instead of
+    // ignoring the options
+    let tls_options = ["--tls-port", "--tls-cert-file", "--tls-key-file", "--tls-ca-cert-file"];
+    if let Some(option) = tls_options.iter().find(|option| args.iter().any(|arg| arg.eq_ignore_ascii_case(option))) {
+        anyhow::bail!("{} is not supported: this build has no TLS support", option);
+    }
+
     let is_replica = replica_of.is_some();
     let label = if is_replica { "replica" } else { "master" };
 
-    println!("starting redis {} on port {}", label, port);
+    let config = Config::from_args(&args, config_file)?;
+    log::set_role(!is_replica);
+    log::configure(config.get("loglevel").unwrap_or_default().parse()?, config.get("logfile").unwrap_or_default())?;
+    log::reopen_on_hangup();
+
+    notice!("starting redis {} on port {}", label, port);
 
     let bind_address = Binding("127.0.0.1".to_string(), port);
-    let listener = TcpListener::bind(bind_address.to_string()).unwrap();
+    let bind = match config.get("bind") {
+        Some("*") => "0.0.0.0",
+        Some(bind) => bind,
+        None => "127.0.0.1",
+    };
+    let listener = if bind.contains(':') {
+        TcpListener::bind(format!("[{}]:{}", bind, port))?
+    } else {
+        TcpListener::bind(format!("{}:{}", bind, port))?
+    };
 
-    let redis = RedisServer::new(bind_address, !is_replica, db_dir, db_filename)?;
+    let redis = RedisServer::new(bind_address, !is_replica, db_dir, db_filename, config)?;
 
-    if is_replica {
-        start_replication(redis.clone(), replica_of.clone().unwrap())?;
+    if let Some(cluster) = &redis.cluster {
+        cluster.start()?;
+    }
+
+    let replica: Option<ReplicaConnection> = replica_of.map(|replica_of| ReplicaConnection::new(redis.clone(), replica_of));
+    if let Some(replica) = &replica {
+        start_replication(replica.clone())?;
     }
 
     let master: Option<MasterServer> = if !is_replica {
@@ -51,33 +126,62 @@ fn main() -> Result<()> {
         None
     };
 
+    let mut cron = Cron::default();
+    redis.schedule(&mut cron);
+    if let Some(master) = &master {
+        master.schedule(&mut cron);
+    }
+    cron.start(redis.config.clone())?;
+
     for stream in listener.incoming() {
         match stream {
             Ok(_stream) => {
-                let redis = redis.clone(); // cheap op since server contains mostly references
-                let thread_name = format!("client-{}-{}", label, _stream.peer_addr()?);
-                let remote_host = _stream.peer_addr()?;
-                let replica_of = replica_of.clone();
+                let client = match redis.accept_connection(&_stream.peer_addr()?) {
+                    Ok(client) => client,
+                    Err(denied) => {
+                        notice!("refused connection from {}", _stream.peer_addr()?);
+                        RESPConnection::new(_stream).send_message(&denied).unwrap_or_else(|err| {
+                            warning!("refusing connection failed: {}", err);
+                            0
+                        });
+                        continue;
+                    }
+                };
+                let keepalive_secs = redis.config.read().unwrap().get("tcp-keepalive")
+                    .and_then(|keepalive| keepalive.parse::<u64>().ok())
+                    .unwrap_or(0);
+                if keepalive_secs > 0 {
+                    set_keepalive(&_stream, Duration::from_secs(keepalive_secs)).unwrap_or_else(|err| {
+                        warning!("setting keepalive of {} failed: {}", client.peer, err);
+                    });
+                }
+                let thread_name = format!("client-{}-{}", label, client.peer);
+                let remote_host = client.peer;
+                let replica = replica.clone();
                 let master = master.clone();
                 thread::Builder::new()
                     .name(thread_name.clone())
                     .spawn(move || {
-                        println!("accepted new connection @{}", thread_name);
+                        verbose!("accepted new connection @{}", thread_name);
                         if is_replica {
-                            let mut server = ReplicaConnection::new(redis, replica_of.unwrap());
+                            let mut server = replica.unwrap();
                             server.handle_connection(_stream).unwrap_or_else(|err| {
-                                println!("@{}:connection thread failed: {}", thread_name, err);
+                                verbose!("@{}: connection closed: {}", thread_name, err);
                             });
                         } else {
                             let mut server = MasterConnection::new(master.unwrap(), remote_host);
                             server.handle_connection(_stream).unwrap_or_else(|err| {
-                                println!("@{}:connection thread failed: {}", thread_name, err);
+                                verbose!("@{}: connection closed: {}", thread_name, err);
                             });
                         };
+                        let connected = client.accepted_at.elapsed().unwrap_or_default();
+                        verbose!("closed connection @{} after {:.3}s", thread_name, connected.as_secs_f64());
+                        // the client counts as connected until its thread ends
+                        drop(client);
                     })?;
             }
             Err(e) => {
-                println!("receiving connection failed: {}", e);
+                warning!("receiving connection failed: {}", e);
             }
         }
     }
@@ -1,31 +1,86 @@
 use std::net::SocketAddr;
-use std::sync::{Arc, mpsc, RwLock};
-use std::sync::mpsc::Sender;
+use std::sync::{Arc, mpsc, Mutex, RwLock};
+use std::sync::atomic::{AtomicU64, AtomicUsize, Ordering};
+use std::sync::mpsc::{Receiver, Sender};
 use std::thread;
 use std::time::{Duration, Instant};
 
 use anyhow::{bail, Result};
 
+use crate::args::Arg;
+use crate::backlog::ReplicationBacklog;
 use crate::connection::ClientConnectionHandler;
+use crate::cron::Cron;
 use crate::io::net::{Binding, Port};
-use crate::protocol::command::{Command, CommandRequest};
-use crate::protocol::rdb::empty_rdb;
+use crate::log::{debug, notice, verbose, warning};
+use crate::protocol::command::{Command, CommandRequest, Replication};
 use crate::protocol::resp::{RESP, RESPConnection};
 use crate::redis::RedisServer;
 
-type ReplicaResponse = (usize, usize); // offset, replica index
+/// how often the master checks whether the replicas are due for a PING
+const REPLICA_PING_CHECK_INTERVAL: Duration = Duration::from_millis(1000);
+
 #[derive(Clone, Debug)]
 enum ReplicaMessage {
     // message for replication and no response is expected
     Replicate(RESP),
-    // command that expects a response from the replica
-    Command(RESP, Sender<ReplicaResponse>, usize, Duration),
+    // part of the replication stream taken from the backlog, already encoded
+    Stream(Vec<u8>),
 }
 
 #[derive(Debug)]
 struct Replica {
     sender: Sender<ReplicaMessage>,
-    offset: usize,
+    binding: Option<Binding>,
+    state: Arc<ReplicaState>,
+}
+
+/// state of a replica kept up to date by its replication connection, reported by REPLDEBUG
+#[derive(Debug, Default)]
+struct ReplicaState {
+    /// messages sent to the replication connection which it didn't take up yet
+    queued: AtomicUsize,
+    /// offset the replica acknowledged last, with the ACK it sends every second or with the reply to GETACK
+    ack_offset: AtomicUsize,
+    /// when the last GETACK was sent, until an ACK is received
+    getack_sent_at: Mutex<Option<Instant>>,
+    /// microseconds between sending the last GETACK and receiving its ACK, 0 until the first ACK
+    last_getack_rtt_us: AtomicU64,
+}
+
+impl ReplicaState {
+    /// records the offset acknowledged by the replica, and the round trip of the GETACK waiting for it
+    fn acked(&self, offset: usize) {
+        self.ack_offset.fetch_max(offset, Ordering::Relaxed);
+        if let Some(sent_at) = self.getack_sent_at.lock().unwrap().take() {
+            self.last_getack_rtt_us.store(sent_at.elapsed().as_micros() as u64, Ordering::Relaxed);
+        }
+    }
+}
+
+impl Replica {
+    /// queues the message for the replication connection, false if the connection is gone
+    fn send(&self, message: ReplicaMessage) -> bool {
+        self.state.queued.fetch_add(1, Ordering::Relaxed);
+        if self.sender.send(message).is_err() {
+            self.state.queued.fetch_sub(1, Ordering::Relaxed);
+            return false;
+        }
+        true
+    }
+
+    /// state of the replica as pairs of field and value
+    fn debug_info(&self, master_offset: usize) -> RESP {
+        let address = self.binding.as_ref().map(|binding| binding.to_string()).unwrap_or_default();
+        let ack_offset = self.state.ack_offset.load(Ordering::Relaxed);
+        RESP::Array(vec![
+            RESP::bulk("address"), RESP::bulk(&address),
+            RESP::bulk("ack_offset"), RESP::Int(ack_offset as i64),
+            RESP::bulk("lag_bytes"), RESP::Int(master_offset.saturating_sub(ack_offset) as i64),
+            RESP::bulk("queued_messages"), RESP::Int(self.state.queued.load(Ordering::Relaxed) as i64),
+            RESP::bulk("last_getack_rtt_us"), RESP::Int(self.state.last_getack_rtt_us.load(Ordering::Relaxed) as i64),
+        ])
+    }
 }
 
 #[derive(Clone)]
@@ -42,12 +97,105 @@ impl MasterServer {
             replicas: Arc::new(RwLock::new(vec![])),
         }
     }
+
+    /// background tasks of the master: expired keys are removed actively and replicated as deletes,
+    /// so that replicas remove them at the same point of the replication stream
+    pub(crate) fn schedule(&self, cron: &mut Cron) {
+        let master = self.clone();
+        cron.every("active-expire", Duration::ZERO, move |tick| {
+            // expiring keys takes at most a quarter of the tick
+            for key in master.redis.active_expire(tick / 4) {
+                let del = RESP::Array(vec![RESP::bulk("DEL"), RESP::Bulk(key.clone())]);
+                if let Err(err) = del.encoded_len().and_then(|len| master.send_replicas(len, &del)) {
+                    warning!("replicating the expiration of {} failed: {}", key.text(), err);
+                }
+            }
+        });
+
+        // replicas see the master alive and their offsets advance also while nothing is written
+        let master = self.clone();
+        let mut last_ping = Instant::now();
+        cron.every("replica-ping", REPLICA_PING_CHECK_INTERVAL, move |_| {
+            let period_secs = master.redis.config.read().unwrap().get("repl-ping-replica-period")
+                .and_then(|period| period.parse::<u64>().ok())
+                .unwrap_or(0);
+            if period_secs == 0 || last_ping.elapsed() < Duration::from_secs(period_secs) {
+                return;
+            }
+            last_ping = Instant::now();
+            if master.replicas.read().unwrap().is_empty() {
+                return;
+            }
+            let ping = RESP::Array(vec![RESP::bulk("PING")]);
+            if let Err(err) = ping.encoded_len().and_then(|len| master.feed_replicas(len, &ping)) {
+                warning!("sending PING to the replicas failed: {}", err);
+            }
+        });
+    }
+
+    /// appends the message to the replication backlog and to the append only file, and sends it to the connected replicas
+    pub(crate) fn send_replicas(&self, message_bytes: usize, message: &RESP) -> Result<()> {
+        assert!(matches!(&message, RESP::Array(_)), "not an array: {}", message);
+
+        self.redis.aof.append(message)?;
+        self.feed_replicas(message_bytes, message)
+    }
+
+    /// appends the message to the replication backlog and sends it to the connected replicas,
+    /// also messages of the replication stream which don't change the store, like the PING of the replicas
+    fn feed_replicas(&self, message_bytes: usize, message: &RESP) -> Result<()> {
+        let mut encoded = Vec::with_capacity(message_bytes);
+        message.write_to(&mut encoded)?;
+
+        let logs_frames = self.redis.logs_replication_frames();
+        let mut failed_indexes = vec![];
+        // the backlog is appended while the replicas are locked, so that a registering replica
+        // continues from the backlog without missing or repeating a message
+        let mut replicas = self.replicas.write().unwrap();
+        self.redis.backlog.write().unwrap().append(&encoded);
+        if logs_frames {
+            notice!("replicating {} to {} replicas", message, replicas.len());
+        }
+        for (i, replica) in replicas.iter().enumerate() {
+            if !replica.send(ReplicaMessage::Replicate(message.clone())) {
+                failed_indexes.push(i);
+            }
+        }
+        if !failed_indexes.is_empty() {
+            notice!("replica connections failed: {:?}", failed_indexes);
+        }
+        for (items_removed, i) in failed_indexes.iter().enumerate() {
+            replicas.remove(i - items_removed);
+        }
+
+        Ok(())
+    }
+
+    /// replication state of the master as pairs of field and value: the offsets of the replication backlog
+    /// and the acknowledged offset, queued messages and last GETACK round trip of every replica
+    fn debug_info(&self) -> RESP {
+        let (master_offset, first_offset) = {
+            let backlog = self.redis.backlog.read().unwrap();
+            (backlog.offset(), backlog.first_offset())
+        };
+        let replicas = self.replicas.read().unwrap();
+        RESP::Array(vec![
+            RESP::bulk("role"), RESP::bulk("master"),
+            RESP::bulk("master_replid"), RESP::bulk(&self.redis.master_replid),
+            RESP::bulk("master_repl_offset"), RESP::Int(master_offset as i64),
+            RESP::bulk("repl_backlog_first_offset"), RESP::Int(first_offset as i64),
+            RESP::bulk("connected_replicas"), RESP::Int(replicas.len() as i64),
+            RESP::bulk("replicas"), RESP::Array(replicas.iter().map(|replica| replica.debug_info(master_offset)).collect()),
+        ])
+    }
 }
 
 pub struct MasterConnection {
     master: MasterServer,
     replica_binding: Option<Binding>,
     remote_host: SocketAddr,
+    /// offset of the snapshot sent by PSYNC, which the replication stream continues from
+    sync_offset: Option<usize>,
 }
 
 impl MasterConnection {
@@ -56,6 +204,7 @@ impl MasterConnection {
             master,
             replica_binding: None,
             remote_host,
+            sync_offset: None,
         }
     }
 
@@ -87,13 +236,13 @@ impl MasterConnection {
                 let required_replicas = required_replicas.parse::<i64>().unwrap_or(-1);
                 let timeout_ms = timeout_ms.parse::<i64>().unwrap_or(-1);
                 if required_replicas >= 0 && timeout_ms >= 0 {
-                    if self.master.redis.log_store.read().unwrap().log.is_empty() {
-                        // for empty log we don't need to check replicas
+                    if self.master.redis.backlog.read().unwrap().offset() == 0 {
+                        // nothing was replicated yet, we don't need to check replicas
                         let active_replicas = self.master.replicas.read().unwrap().len();
                         Ok(vec![RESP::Int(active_replicas as i64)])
                     } else {
                         // ack from all replicas
-                        let ack_replicas = self.request_ack(required_replicas.abs() as u32, Duration::from_millis(timeout_ms.abs() as u64))?;
+                        let ack_replicas = self.request_ack(required_replicas.unsigned_abs() as u32, Duration::from_millis(timeout_ms.unsigned_abs()))?;
                         Ok(vec![RESP::Int(ack_replicas as i64)])
                     }
                 } else {
@@ -105,102 +254,61 @@ impl MasterConnection {
                 // minimal implementation of https://redis.io/docs/latest/commands/psync/
                 // PSYNC replication-id offset
 
-                // replica does not know where to start
-                if (repl_id == "?" && offset == "-1") || repl_id == &self.master.redis.master_replid {
-                    // this makes the current connection a replication connection
-                    let mut sync_response = vec![RESP::String(format!("FULLRESYNC {} 0", self.master.redis.master_replid)), RESP::File(empty_rdb())];
-                    // add current log for replication on top of the rds image
-                    let mut current_log = self.master.redis.log_store.read().unwrap().log.clone();
-                    sync_response.append(&mut current_log);
-                    Ok(sync_response)
-                } else {
-                    bail!("invalid psync command {:?}", cmd)
+                // this makes the current connection a replication connection.
+                // a replica which followed this stream continues from its offset while the backlog still has it
+                let replid = &self.master.redis.master_replid;
+                let continued = continued_offset(&self.master.redis.backlog.read().unwrap(), replid, repl_id, offset);
+                if let Some(offset) = continued {
+                    notice!("partial resynchronization of replica {:?} accepted, continuing from offset {}", self.replica_binding, offset);
+                    self.sync_offset = Some(offset);
+                    return Ok(vec![RESP::String(format!("CONTINUE {}", replid))]);
                 }
+                // any other replica, also one which does not know where to start, starts with a snapshot of the store
+                let (offset, rdb) = self.master.redis.replication_snapshot()?;
+                self.sync_offset = Some(offset);
+                Ok(vec![RESP::String(format!("FULLRESYNC {} {}", replid, offset)), RESP::File(rdb)])
             }
 
-            _ => self.master.redis.handle_command(cmd),
-        }
-    }
-
-
-    fn send_replicas(&self, message_bytes: usize, message: &RESP) -> Result<()> {
-        assert!(matches!(&message, RESP::Array(_)), "not an array: {}", message);
-
-        // append to the command log
-        {
-            let mut log_store = self.master.redis.log_store.write().unwrap();
-            log_store.log.push(message.clone());
-        }
-
-        let mut failed_indexes = vec![];
-        let mut replicas = self.master.replicas.write().unwrap();
-        println!("replicating {} to {} replicas", message, replicas.len());
-        for (i, replica) in replicas.iter().enumerate() {
-            if replica.sender.send(ReplicaMessage::Replicate(message.clone())).is_err() {
-                failed_indexes.push(i);
+            (Command::REPLDEBUG, []) => {
+                // REPLDEBUG
+                // dumps the replication state for diagnosing replication issues
+                Ok(vec![self.master.debug_info()])
             }
-        }
-        println!("replica connections failed: {:?}", failed_indexes);
-        for (items_removed, i) in failed_indexes.iter().enumerate() {
-            replicas.remove(i - items_removed);
-        }
 
-        if replicas.len() - failed_indexes.len() > 0 {
-            // update stored offset
-            self.master.redis.log_store.write().unwrap().log_bytes += message_bytes;
+            _ => self.master.redis.handle_command(cmd),
         }
-
-        Ok(())
     }
 
-    fn request_ack(&self, expected_replicas: u32, timeout: Duration) -> Result<u32> {
-        println!("sending getack to all replicas ");
-
-        let (tx, rx) = mpsc::channel::<ReplicaResponse>();
-
-        let master_offset = self.master.redis.log_store.read().unwrap().log_bytes;
-
-        let getack = RESP::Array(vec![RESP::bulk("REPLCONF"), RESP::bulk("GETACK"), RESP::bulk("*")]);
 
-        let mut replicas = self.master.replicas.write().unwrap();
-
-        let mut replicated = 0_u32;
-        let mut requested_ack = 0;
-
-        for (i, replica) in replicas.iter().enumerate() {
-            if replicated >= expected_replicas {
-                break;
-            }
-            // TODO check replica last ack
-            if master_offset == replica.offset {
-                replicated += 1;
-            } else if replica.sender.send(ReplicaMessage::Command(getack.clone(), tx.clone(), i, timeout)).is_ok()
-            {
-                requested_ack += 1;
+    /// waits until the expected number of replicas acknowledged the current offset of the stream, or the timeout passed.
+    /// the replicas are asked for their offset unless enough of them acknowledged it already
+    fn request_ack(&self, expected_replicas: u32, timeout: Duration) -> Result<u32> {
+        let master_offset = self.master.redis.backlog.read().unwrap().offset();
+        let acked_replicas = || self.master.replicas.read().unwrap().iter()
+            .filter(|replica| replica.state.ack_offset.load(Ordering::Relaxed) >= master_offset)
+            .count() as u32;
+
+        if acked_replicas() < expected_replicas {
+            debug!("sending getack to all replicas ");
+            let sent_at = Instant::now();
+            for replica in self.master.replicas.read().unwrap().iter() {
+                *replica.state.getack_sent_at.lock().unwrap() = Some(sent_at);
             }
+            // the GETACK is part of the stream, the replicas count it in their offsets as the master does
+            let getack = RESP::Array(vec![RESP::bulk("REPLCONF"), RESP::bulk("GETACK"), RESP::bulk("*")]);
+            self.master.feed_replicas(getack.encoded_len()?, &getack)?;
         }
 
-        println!("waiting for ack from {} replicas offset {}", requested_ack, master_offset);
+        debug!("waiting for ack of offset {}", master_offset);
         let started_at = Instant::now();
         loop {
-            if replicated >= expected_replicas {
-                break;
-            }
-            if let Ok((off, replica_index)) = rx.try_recv() {
-                if off >= master_offset {
-                    replicated += 1;
-                    replicas[replica_index].offset = off;
-                }
-                continue;
-            }
-            if Instant::now() - started_at > timeout {
-                break;
+            let replicated = acked_replicas();
+            if replicated >= expected_replicas || started_at.elapsed() > timeout {
+                debug!("expected replicas {} but ack replicas {}", expected_replicas, replicated);
+                return Ok(replicated);
             }
             thread::sleep(Duration::from_millis(10));
         }
-        println!("expected replicas {} but ack replicas {}", expected_replicas, replicated);
-        println!("master replicas updated {:?}", replicas);
-        Ok(replicated)
     }
 
     fn master_replica_connection(&mut self, connection: &mut RESPConnection) -> Result<()> {
@@ -208,56 +316,89 @@ impl MasterConnection {
         let thread_name = &thread_name;
 
         // this connection is turning into replication connection
-        println!("@{}: PSYNC completed, this connection is a replication connection to replica {:?}", thread_name, self.replica_binding);
+        notice!("@{}: PSYNC completed, this connection is a replication connection to replica {:?}", thread_name, self.replica_binding);
+
+        // the snapshot is sent now, not once the first message of the stream follows it
+        connection.flush()?;
 
         // register listener for messages
         let (tx, rx) = mpsc::channel();
+        let state = Arc::new(ReplicaState::default());
         {
+            let sync_offset = self.sync_offset.take().expect("snapshot sent by PSYNC");
             let mut replicas = self.master.replicas.write().unwrap();
-            replicas.push(Replica { sender: tx, offset: 0 });
-            println!("@{}: active replicas now {:?}", thread_name, replicas);
+            // the messages replicated since the snapshot was taken are sent first
+            let Some(missed) = self.master.redis.backlog.read().unwrap().bytes_from(sync_offset) else {
+                bail!("the replication stream since the snapshot at offset {} is not in the backlog anymore", sync_offset);
+            };
+            let replica = Replica { sender: tx, binding: self.replica_binding.clone(), state: state.clone() };
+            if !missed.is_empty() {
+                replica.send(ReplicaMessage::Stream(missed));
+            }
+            replicas.push(replica);
+            verbose!("@{}: active replicas now {:?}", thread_name, replicas);
         }
 
-        // TODO remove the TX from the list
+        // the acknowledgements of the replica are read on their own thread, while this one writes the stream
+        let mut acks = connection.try_clone()?;
+        let acks_state = state.clone();
+        let acks_thread = format!("{}-acks", thread_name);
+        thread::Builder::new()
+            .name(acks_thread.clone())
+            .spawn(move || {
+                while let Ok((_, Some(message))) = acks.read_message() {
+                    let command = CommandRequest::try_from(message).ok();
+                    match command.as_ref().map(|command| command.as_ref()) {
+                        Some((Command::REPLCONF, [sub_command, offset, ..])) if sub_command.eq_ignore_ascii_case(b"ACK") => {
+                            match offset.parse::<usize>() {
+                                Ok(offset) => {
+                                    debug!("@{}: replica ACKED with offset {} ", acks_thread, offset);
+                                    acks_state.acked(offset);
+                                }
+                                Err(_) => warning!("@{}: invalid ACK offset from replica: {}", acks_thread, offset.text()),
+                            }
+                        }
+                        _ => warning!("@{}: unexpected message from replica: {:?}", acks_thread, command),
+                    }
+                }
+            })?;
+
+        let result = self.stream_to_replica(connection, rx, &state, thread_name);
+        // the replica is not fed anymore once its connection is gone
+        self.master.replicas.write().unwrap().retain(|replica| !Arc::ptr_eq(&replica.state, &state));
+        result
+    }
 
+    /// sends the messages received for the replica to its replication connection, until the connection drops
+    fn stream_to_replica(
+        &self,
+        connection: &mut RESPConnection,
+        rx: Receiver<ReplicaMessage>,
+        state: &ReplicaState,
+        thread_name: &str,
+    ) -> Result<()> {
         // any received messages will be sent to the current replica connection
         for received in rx {
-            println!("@{}: Sending to replica: {:?}", thread_name, received);
+            state.queued.fetch_sub(1, Ordering::Relaxed);
+            if self.master.redis.logs_replication_frames() {
+                notice!("@{}: Sending to replica: {:?}", thread_name, received);
+            }
             match received {
                 ReplicaMessage::Replicate(message) => {
                     if let Err(err) = connection.send_message(&message) {
-                        println!("@{}: returned error: {} while replicating command: {:?}", thread_name, err, message);
+                        warning!("@{}: returned error: {} while replicating command: {:?}", thread_name, err, message);
                         if err.to_string().contains("Broken pipe") {
                             bail!("client connection dropped");
                         }
                     }
                 }
-                ReplicaMessage::Command(message, tx, replica_index, timeout) => {
-                    if let Err(err) = connection.send_message(&message) {
-                        println!("@{}: returned error: {} while requesting: {:?}", thread_name, err, &message);
+                ReplicaMessage::Stream(bytes) => {
+                    if let Err(err) = connection.send_raw(&bytes) {
+                        warning!("@{}: returned error: {} while replicating {} bytes of the backlog", thread_name, err, bytes.len());
                         if err.to_string().contains("Broken pipe") {
                             bail!("client connection dropped");
                         }
                     }
-                    if let Ok(CommandRequest(Command::REPLCONF, _)) = message.try_into() {
-                        // expect ack response from replica
-                        let current_timeout = connection.read_timeout()?;
-                        connection.set_read_timeout(Some(timeout))?;
-                        if let Ok((_, Some(replica_ack))) = connection.read_message() {
-                            if let Ok(CommandRequest(Command::REPLCONF, ack_params)) = replica_ack.try_into() {
-                                if let Some(offset) = ack_params.last() {
-                                    let offset = offset.parse::<usize>().unwrap();
-                                    println!("@{}: replica ACKED with offset {} ", thread_name, offset);
-                                    if tx.send((offset, replica_index)).is_err() {
-                                        // channel already off
-                                    }
-                                }
-                            }
-                        } else {
-                            println!("@{}: gave up waiting ACK from replica", thread_name);
-                        }
-                        connection.set_read_timeout(current_timeout)?;
-                    }
                 }
             }
         }
@@ -266,6 +407,50 @@ impl MasterConnection {
 }
 
 impl ClientConnectionHandler for MasterConnection {
+    fn server(&self) -> &RedisServer {
+        &self.master.redis
+    }
+
+    fn execute_request(&mut self, message_bytes: usize, message: RESP, command: &CommandRequest) -> Result<Vec<RESP>> {
+        if let Some(redirect) = self.master.redis.check_cluster_slot(command) {
+            return Ok(vec![redirect]);
+        }
+
+        // keys are evicted before running commands, replicas delete them along with the master
+        let (evicted, over_limit) = self.master.redis.perform_evictions();
+        for key in evicted {
+            let del = RESP::Array(vec![RESP::bulk("DEL"), RESP::Bulk(key)]);
+            self.master.send_replicas(del.encoded_len()?, &del)?;
+        }
+        if over_limit && command.0.is_deny_oom() {
+            return Ok(vec![RESP::Error("OOM command not allowed when used memory > 'maxmemory'.".to_string())]);
+        }
+
+        if command.0.is_mutating() {
+            if let Some(error) = self.master.redis.check_writes_allowed() {
+                return Ok(vec![error]);
+            }
+        }
+
+        let responses = self.handle_client_command(command)?;
+
+        if command.0.is_replicated() {
+            // replicate mutations only if you are a master
+            match command.replication_form(&responses) {
+                Replication::Rewritten(rewritten) => {
+                    for rewritten in &rewritten {
+                        let rewritten = RESP::from(rewritten);
+                        self.master.send_replicas(rewritten.encoded_len()?, &rewritten)?;
+                    }
+                }
+                Replication::AsReceived => self.master.send_replicas(message_bytes, &message)?,
+                Replication::Skipped => {}
+            }
+        }
+
+        Ok(responses)
+    }
+
     fn handle_request(
         &mut self,
         message_bytes: usize,
@@ -273,19 +458,77 @@ impl ClientConnectionHandler for MasterConnection {
         command: CommandRequest,
         connection: &mut RESPConnection,
     ) -> Result<()> {
-        let responses = self.handle_client_command(&command)?;
-
-        if command.0.is_mutating() {
-            // replicate mutations only if you are a master
-            self.send_replicas(message_bytes, &message)?;
-        }
-
-        connection.send_messages(&responses.iter().map(|r| r).collect::<Vec<&RESP>>())?;
+        let responses = self.execute_request(message_bytes, message, &command)?;
+        connection.write_messages(&responses.iter().collect::<Vec<&RESP>>())?;
 
+        // the connection is handed over to replication once the snapshot was requested
         if command.0 == Command::PSYNC {
             self.master_replica_connection(connection)?;
         }
 
         Ok(())
     }
-}
\ No newline at end of file
+}
+
+/// offset in the stream a replica continues from with PSYNC, None if it needs a full resync.
+/// the replica sends the offset of the next byte it expects counting from 1 as in redis, while the backlog counts from 0
+fn continued_offset(backlog: &ReplicationBacklog, replid: &str, repl_id: &[u8], offset: &[u8]) -> Option<usize> {
+    offset.parse::<usize>().ok()
+        .filter(|_| repl_id == replid.as_bytes())
+        .and_then(|offset| offset.checked_sub(1))
+        .filter(|offset| backlog.contains(*offset))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::replica::ReplicationOffset;
+
+    const REPLID: &str = "8371b4fb1155b71f4a04d3e1bc3e18c4a990aeeb";
+
+    fn continued(backlog: &ReplicationBacklog, offset: usize) -> Option<usize> {
+        continued_offset(backlog, REPLID, REPLID.as_bytes(), offset.to_string().as_bytes())
+    }
+
+    #[test]
+    fn replica_which_processed_the_whole_stream_continues_from_its_end() {
+        let mut backlog = ReplicationBacklog::new(100);
+        backlog.append(b"*1\r\n$4\r\nPING\r\n");
+        let replica = ReplicationOffset::default();
+        replica.process(backlog.offset(), || ());
+        let offset = continued(&backlog, replica.psync_offset());
+        assert_eq!(offset, Some(backlog.offset()));
+        assert_eq!(backlog.bytes_from(offset.unwrap()), Some(vec![]));
+    }
+
+    #[test]
+    fn replica_continues_with_the_bytes_after_its_offset() {
+        let mut backlog = ReplicationBacklog::new(100);
+        backlog.append(b"0123456789");
+        assert_eq!(continued(&backlog, 1), Some(0));
+        assert_eq!(continued(&backlog, 5).and_then(|offset| backlog.bytes_from(offset)), Some(b"456789".to_vec()));
+        assert_eq!(continued(&backlog, 11), Some(10));
+        // past the end of the stream
+        assert_eq!(continued(&backlog, 12), None);
+        // offsets count from 1
+        assert_eq!(continued(&backlog, 0), None);
+    }
+
+    #[test]
+    fn overwritten_offsets_need_a_full_resync() {
+        let mut backlog = ReplicationBacklog::new(4);
+        backlog.append(b"0123456789");
+        assert_eq!(backlog.first_offset(), 6);
+        assert_eq!(continued(&backlog, 6), None);
+        assert_eq!(continued(&backlog, 7), Some(6));
+    }
+
+    #[test]
+    fn other_streams_and_unknown_offsets_need_a_full_resync() {
+        let mut backlog = ReplicationBacklog::new(100);
+        backlog.append(b"0123456789");
+        assert_eq!(continued_offset(&backlog, REPLID, b"?", b"-1"), None);
+        assert_eq!(continued_offset(&backlog, REPLID, b"0000000000000000000000000000000000000000", b"5"), None);
+        assert_eq!(continued_offset(&backlog, REPLID, REPLID.as_bytes(), b"x"), None);
+    }
+}
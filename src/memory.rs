@@ -0,0 +1,125 @@
+use std::fs;
+
+use crate::args::Arg;
+use crate::store::MemoryStats;
+
+/// below this used memory there is too little data to analyze
+const MIN_ANALYZED_MEMORY: usize = 5 * 1024 * 1024;
+/// share of the used memory spent on bookkeeping which is reported as high overhead
+const HIGH_OVERHEAD_RATIO: f64 = 0.5;
+/// number of elements of a collection which is reported as big
+const BIG_COLLECTION_ELEMENTS: usize = 100_000;
+/// share of the used memory held by client buffers which is reported as big
+const BIG_CLIENT_MEMORY_RATIO: f64 = 0.25;
+/// share of maxmemory used which is reported as close to the limit
+const NEAR_MAXMEMORY_RATIO: f64 = 0.9;
+/// ratio of the resident memory of the process to the used memory which is reported as high fragmentation,
+/// when the difference is over the minimal fragmented bytes
+const HIGH_FRAGMENTATION_RATIO: f64 = 1.4;
+const MIN_FRAGMENTED_BYTES: usize = 10 * 1024 * 1024;
+
+/// minimal implementation of https://redis.io/docs/latest/commands/memory-doctor/
+///
+/// analyzes the memory accounting of the dataset and of the clients, with maxmemory if set and the resident memory
+/// of the process if known, and advises on the issues found as human-readable text
+pub fn doctor(stats: &MemoryStats, client_memory: usize, max_memory: usize, rss: Option<usize>) -> String {
+    if stats.used_memory + client_memory < MIN_ANALYZED_MEMORY {
+        return "Hi Sam, this instance is empty or is using very little memory, my issues detector can't be used in these conditions. \
+Please, leave for your mission on Earth and fill it with some data. The new Sam and I will be back to our programming as soon as I finished rebooting.".to_string();
+    }
+    let mut issues = vec![];
+    let overhead_ratio = stats.overhead as f64 / stats.used_memory.max(1) as f64;
+    if overhead_ratio > HIGH_OVERHEAD_RATIO {
+        issues.push(format!(
+            "High overhead ratio: {:.0}% of the used memory of the {} keys is bookkeeping rather than data. \
+Many tiny keys or collections of tiny elements cost more than their data, consider grouping small values into hashes.",
+            overhead_ratio.min(1.0) * 100.0, stats.keys,
+        ));
+    }
+    if let Some((key, elements)) = stats.biggest.as_ref().filter(|(_, elements)| *elements >= BIG_COLLECTION_ELEMENTS) {
+        issues.push(format!(
+            "Big collections: the key '{}' holds {} elements. Commands reading or deleting all of its elements block the server \
+for long, consider splitting it into several keys.",
+            key.text(), elements,
+        ));
+    }
+    if client_memory as f64 > stats.used_memory as f64 * BIG_CLIENT_MEMORY_RATIO {
+        issues.push(format!(
+            "Big client buffers: the clients hold {} bytes of commands queued by their transactions. \
+Long transactions left open by idle clients hold their memory, consider limiting it with maxmemory-clients.",
+            client_memory,
+        ));
+    }
+    if max_memory > 0 && stats.used_memory as f64 > max_memory as f64 * NEAR_MAXMEMORY_RATIO {
+        issues.push(format!(
+            "Near maxmemory: {} of the {} bytes of maxmemory are used. Writes will be refused or keys evicted as configured by \
+maxmemory-policy, consider raising maxmemory.",
+            stats.used_memory, max_memory,
+        ));
+    }
+    let used_memory = stats.used_memory + client_memory;
+    if let Some(rss) = rss.filter(|&rss| rss > used_memory + MIN_FRAGMENTED_BYTES) {
+        let ratio = fragmentation_ratio(rss, used_memory);
+        if ratio > HIGH_FRAGMENTATION_RATIO {
+            issues.push(format!(
+                "High fragmentation: the process holds {} bytes of resident memory for {} bytes used, a ratio of {:.2}. \
+Memory freed by deleted or evicted keys is not returned to the system yet, consider MEMORY PURGE.",
+                rss, used_memory, ratio,
+            ));
+        }
+    }
+    if issues.is_empty() {
+        return "Hi Sam, I can't find any memory issue in your instance. I can only account for what occurs on this base.".to_string();
+    }
+    let issues = issues.iter().map(|issue| format!(" * {}", issue)).collect::<Vec<String>>().join("\n\n");
+    format!("Sam, I detected a few issues in this Redis instance memory implants:\n\n{}\n\nI'm here to keep you safe, Sam. I want to help you.\n", issues)
+}
+
+/// minimal implementation of https://redis.io/docs/latest/commands/memory-purge/
+///
+/// returns the memory the allocator keeps for reuse to the system, where the allocator supports it
+pub fn purge() {
+    #[cfg(all(target_os = "linux", target_env = "gnu"))]
+    {
+        // malloc_trim of the C library std allocates with
+        extern "C" {
+            fn malloc_trim(pad: usize) -> i32;
+        }
+        unsafe {
+            malloc_trim(0);
+        }
+    }
+}
+
+/// resident memory of the process in bytes, None where it is not known
+pub fn rss_bytes() -> Option<usize> {
+    let status = fs::read_to_string("/proc/self/status").ok()?;
+    let kb = status.lines()
+        .find_map(|line| line.strip_prefix("VmRSS:"))?
+        .trim()
+        .strip_suffix("kB")?
+        .trim()
+        .parse::<usize>()
+        .ok()?;
+    Some(kb * 1024)
+}
+
+/// ratio of the resident memory to the used memory, as reported by INFO memory
+pub fn fragmentation_ratio(rss: usize, used_memory: usize) -> f64 {
+    rss as f64 / used_memory.max(1) as f64
+}
+
+/// bytes in the human readable form of INFO, like 1.50M
+pub fn human_bytes(bytes: u64) -> String {
+    const UNITS: [&str; 6] = ["K", "M", "G", "T", "P", "E"];
+    if bytes < 1024 {
+        return format!("{}B", bytes);
+    }
+    let mut value = bytes as f64 / 1024.0;
+    let mut unit = 0;
+    while value >= 1024.0 && unit < UNITS.len() - 1 {
+        value /= 1024.0;
+        unit += 1;
+    }
+    format!("{:.2}{}", value, UNITS[unit])
+}
@@ -0,0 +1,113 @@
+use std::fmt::Display;
+use std::str::FromStr;
+
+use anyhow::bail;
+
+/// classes of the keyspace events, each enabled by a character of notify-keyspace-events
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub(crate) enum EventClass {
+    /// commands not specific to a type, like DEL, EXPIRE or RENAME
+    Generic,
+    String,
+    List,
+    Set,
+    Hash,
+    ZSet,
+    /// keys removed when they expire
+    Expired,
+    /// keys removed by maxmemory-policy
+    Evicted,
+    Stream,
+    /// reads of missing keys, not included in `A`
+    KeyMiss,
+    /// keys added to the dataset, not included in `A`
+    New,
+}
+
+/// the classes with their characters, in the order CONFIG GET lists them
+const CLASSES: &[(char, EventClass)] = &[
+    ('g', EventClass::Generic),
+    ('$', EventClass::String),
+    ('l', EventClass::List),
+    ('s', EventClass::Set),
+    ('h', EventClass::Hash),
+    ('z', EventClass::ZSet),
+    ('x', EventClass::Expired),
+    ('e', EventClass::Evicted),
+    ('t', EventClass::Stream),
+    ('m', EventClass::KeyMiss),
+    ('n', EventClass::New),
+];
+
+/// the classes selected by `A`
+const ALL: &[EventClass] = &[
+    EventClass::Generic, EventClass::String, EventClass::List, EventClass::Set, EventClass::Hash,
+    EventClass::ZSet, EventClass::Expired, EventClass::Evicted, EventClass::Stream,
+];
+
+impl EventClass {
+    fn bit(self) -> u16 {
+        1 << self as u16
+    }
+}
+
+/// keyspace events published as configured by notify-keyspace-events.
+///
+/// `K` publishes events to `__keyspace@0__:<key>` with the event as the message, `E` to `__keyevent@0__:<event>`
+/// with the key as the message, and the other characters select the classes of events. nothing is published
+/// without `K` or `E`, or without any class
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub(crate) struct NotifyFlags {
+    pub(crate) keyspace: bool,
+    pub(crate) keyevent: bool,
+    classes: u16,
+}
+
+impl NotifyFlags {
+    /// events of the class are published to at least one of the channels
+    pub(crate) fn publishes(&self, class: EventClass) -> bool {
+        (self.keyspace || self.keyevent) && self.classes & class.bit() != 0
+    }
+}
+
+impl FromStr for NotifyFlags {
+    type Err = anyhow::Error;
+
+    fn from_str(flags: &str) -> Result<Self, Self::Err> {
+        let mut parsed = NotifyFlags::default();
+        for flag in flags.chars() {
+            match flag {
+                'K' => parsed.keyspace = true,
+                'E' => parsed.keyevent = true,
+                'A' => parsed.classes |= ALL.iter().fold(0, |bits, class| bits | class.bit()),
+                _ => match CLASSES.iter().find(|(c, _)| *c == flag) {
+                    Some((_, class)) => parsed.classes |= class.bit(),
+                    None => bail!("Invalid event class character. Use 'Ag$lshzxeKEtmn'."),
+                },
+            }
+        }
+        Ok(parsed)
+    }
+}
+
+impl Display for NotifyFlags {
+    /// the flags in their canonical form, with `A` standing for all of its classes
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let flags_of = |in_all: bool| CLASSES.iter()
+            .filter(move |(_, class)| ALL.contains(class) == in_all && self.classes & class.bit() != 0)
+            .map(|(flag, _)| *flag)
+            .collect::<String>();
+        if ALL.iter().all(|class| self.classes & class.bit() != 0) {
+            write!(f, "A")?;
+        } else {
+            write!(f, "{}", flags_of(true))?;
+        }
+        if self.keyspace {
+            write!(f, "K")?;
+        }
+        if self.keyevent {
+            write!(f, "E")?;
+        }
+        write!(f, "{}", flags_of(false))
+    }
+}
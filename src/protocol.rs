@@ -1,3 +1,4 @@
 pub mod command;
+pub mod command_table;
 pub mod rdb;
 pub mod resp;
@@ -1,35 +1,268 @@
 use std::fmt::Display;
 use std::str::FromStr;
+use std::time::SystemTime;
 
-use anyhow::bail;
+use anyhow::{bail, format_err};
 
+use crate::args;
+use crate::args::Arg;
 use crate::protocol::resp::RESP;
 
+#[allow(clippy::upper_case_acronyms)]
 #[derive(Debug, PartialEq, Clone, Copy)]
 pub enum Command {
     PING,
     ECHO,
+    // connection commands
+    HELLO,
+    AUTH,
+    // transaction commands
+    MULTI,
+    WATCH,
+    UNWATCH,
+    EXEC,
+    DISCARD,
     // storage commands
     SET,
     GET,
+    GETDEL,
+    GETEX,
+    GETRANGE,
+    SETRANGE,
+    BITCOUNT,
+    SETBIT,
+    GETBIT,
+    BITOP,
+    BITPOS,
+    BITFIELD,
+    // hyperloglog commands
+    PFADD,
+    PFCOUNT,
+    PFMERGE,
     TYPE,
+    OBJECT,
+    MEMORY,
+    DEBUG,
     KEYS,
+    SCAN,
+    DEL,
+    UNLINK,
+    TOUCH,
+    RANDOMKEY,
+    DBSIZE,
+    FLUSHDB,
+    FLUSHALL,
+    RENAME,
+    RENAMENX,
+    COPY,
+    EXPIRE,
+    PEXPIRE,
+    EXPIREAT,
+    PEXPIREAT,
+    TTL,
+    PTTL,
+    EXPIRETIME,
+    PEXPIRETIME,
+    PERSIST,
     // replication commands
     PSYNC,
     INFO,
     REPLCONF,
+    REPLDEBUG,
     WAIT,
     CONFIG,
+    CLIENT,
+    COMMAND,
+    // set commands
+    SADD,
+    SREM,
+    SMEMBERS,
+    SCARD,
+    SISMEMBER,
+    SINTERCARD,
+    SMISMEMBER,
+    SPOP,
+    SRANDMEMBER,
+    SMOVE,
+    SUNION,
+    SINTER,
+    SDIFF,
+    SUNIONSTORE,
+    SINTERSTORE,
+    SDIFFSTORE,
+    // list commands
+    LPUSH,
+    RPUSH,
+    LPOP,
+    RPOP,
+    LLEN,
+    LRANGE,
+    LINDEX,
+    LINSERT,
+    LSET,
+    LREM,
+    LTRIM,
+    LMOVE,
+    BLPOP,
+    BRPOP,
+    BLMOVE,
+    LMPOP,
+    BLMPOP,
+    // hash commands
+    HSET,
+    HGET,
+    HDEL,
+    HGETALL,
+    HMGET,
+    HKEYS,
+    HVALS,
+    HLEN,
+    HEXISTS,
+    HSETNX,
+    HINCRBY,
+    HINCRBYFLOAT,
+    HRANDFIELD,
+    // sorted set commands
+    ZADD,
+    ZSCORE,
+    ZCARD,
+    ZRANGE,
+    ZINCRBY,
+    ZRANK,
+    ZREVRANK,
+    ZCOUNT,
+    ZRANGESTORE,
+    ZREMRANGEBYRANK,
+    ZREMRANGEBYSCORE,
+    ZREMRANGEBYLEX,
+    ZMPOP,
+    BZMPOP,
+    // cluster commands
+    CLUSTER,
+    // persistence commands
+    SAVE,
+    BGSAVE,
+    LASTSAVE,
+    BGREWRITEAOF,
+    // pub/sub commands
+    SUBSCRIBE,
+    UNSUBSCRIBE,
+    PSUBSCRIBE,
+    PUNSUBSCRIBE,
+    SSUBSCRIBE,
+    SUNSUBSCRIBE,
+    SPUBLISH,
+    PUBLISH,
     // stream commands
     XADD,
     XRANGE,
     XREAD,
+    XGROUP,
+    XREADGROUP,
+    XACK,
+    XCLAIM,
+    XPENDING,
+    XDEL,
+    XTRIM,
+    XSETID,
+    XINFO,
 }
 
 impl Command {
     /** command mutates the local storage */
     pub fn is_mutating(&self) -> bool {
-        matches!(self, Command::SET | Command::XADD)
+        matches!(self, Command::SET | Command::DEL | Command::UNLINK | Command::GETDEL | Command::GETEX | Command::XADD | Command::RENAME | Command::RENAMENX | Command::COPY
+            | Command::EXPIRE | Command::PEXPIRE | Command::EXPIREAT | Command::PEXPIREAT | Command::PERSIST
+            | Command::SADD | Command::SREM | Command::SPOP | Command::SMOVE | Command::SUNIONSTORE | Command::SINTERSTORE | Command::SDIFFSTORE | Command::FLUSHDB | Command::FLUSHALL
+            | Command::LPUSH | Command::RPUSH | Command::LPOP | Command::RPOP
+            | Command::LINSERT | Command::LSET | Command::LREM | Command::LTRIM
+            | Command::LMOVE | Command::BLPOP | Command::BRPOP | Command::BLMOVE | Command::LMPOP | Command::BLMPOP
+            | Command::HSET | Command::HDEL | Command::HSETNX | Command::HINCRBY | Command::HINCRBYFLOAT
+            | Command::SETRANGE | Command::SETBIT | Command::BITOP | Command::BITFIELD | Command::PFADD | Command::PFMERGE
+            | Command::ZADD | Command::ZINCRBY
+            | Command::ZRANGESTORE | Command::ZREMRANGEBYRANK | Command::ZREMRANGEBYSCORE | Command::ZREMRANGEBYLEX
+            | Command::ZMPOP | Command::BZMPOP
+            | Command::XGROUP | Command::XREADGROUP | Command::XACK | Command::XCLAIM | Command::XDEL | Command::XTRIM | Command::XSETID)
+    }
+
+    /// number of arguments of the command including its name as in the command table of redis:
+    /// exactly that many when positive, at least as many as the absolute value when negative
+    pub fn arity(&self) -> i32 {
+        match self {
+            Command::MULTI | Command::UNWATCH | Command::EXEC | Command::DISCARD | Command::RANDOMKEY | Command::DBSIZE
+            | Command::SAVE | Command::LASTSAVE | Command::BGREWRITEAOF => 1,
+            Command::ECHO | Command::GET | Command::GETDEL | Command::TYPE | Command::KEYS | Command::TTL
+            | Command::PTTL | Command::EXPIRETIME | Command::PEXPIRETIME | Command::PERSIST | Command::SMEMBERS
+            | Command::SCARD | Command::LLEN | Command::HGETALL | Command::HKEYS | Command::HVALS | Command::HLEN
+            | Command::ZCARD => 2,
+            Command::GETBIT | Command::RENAME | Command::RENAMENX | Command::WAIT | Command::SISMEMBER
+            | Command::LINDEX | Command::HGET | Command::HEXISTS | Command::ZSCORE | Command::PUBLISH
+            | Command::SPUBLISH => 3,
+            Command::GETRANGE | Command::SETRANGE | Command::SETBIT | Command::SMOVE | Command::LRANGE | Command::LSET
+            | Command::LREM | Command::LTRIM | Command::HSETNX | Command::HINCRBY | Command::HINCRBYFLOAT
+            | Command::ZINCRBY | Command::ZCOUNT | Command::ZREMRANGEBYRANK | Command::ZREMRANGEBYSCORE
+            | Command::ZREMRANGEBYLEX => 4,
+            Command::LINSERT | Command::LMOVE => 5,
+            Command::BLMOVE => 6,
+            Command::PING | Command::HELLO | Command::COMMAND | Command::FLUSHDB | Command::FLUSHALL | Command::INFO | Command::REPLCONF
+            | Command::REPLDEBUG | Command::BGSAVE | Command::UNSUBSCRIBE
+            | Command::PUNSUBSCRIBE | Command::SUNSUBSCRIBE => -1,
+            Command::AUTH | Command::WATCH | Command::GETEX | Command::BITCOUNT | Command::BITFIELD | Command::PFADD
+            | Command::PFCOUNT | Command::PFMERGE | Command::OBJECT | Command::MEMORY | Command::DEBUG | Command::SCAN | Command::DEL
+            | Command::UNLINK | Command::TOUCH | Command::CONFIG | Command::CLIENT | Command::SPOP
+            | Command::SRANDMEMBER | Command::SUNION | Command::SINTER | Command::SDIFF | Command::LPOP | Command::RPOP
+            | Command::HRANDFIELD | Command::CLUSTER | Command::SUBSCRIBE | Command::XGROUP | Command::XINFO
+            | Command::PSUBSCRIBE | Command::SSUBSCRIBE => -2,
+            Command::SET | Command::BITPOS | Command::COPY | Command::EXPIRE | Command::PEXPIRE | Command::EXPIREAT
+            | Command::PEXPIREAT | Command::PSYNC | Command::SADD | Command::SREM | Command::SINTERCARD
+            | Command::SMISMEMBER | Command::SUNIONSTORE | Command::SINTERSTORE | Command::SDIFFSTORE | Command::LPUSH
+            | Command::RPUSH | Command::BLPOP | Command::BRPOP | Command::HDEL | Command::HMGET | Command::ZRANK
+            | Command::ZREVRANK | Command::XPENDING | Command::XDEL | Command::XSETID => -3,
+            Command::BITOP | Command::LMPOP | Command::HSET | Command::ZADD | Command::ZRANGE | Command::ZMPOP
+            | Command::XRANGE | Command::XREAD | Command::XACK | Command::XTRIM => -4,
+            Command::BLMPOP | Command::ZRANGESTORE | Command::BZMPOP | Command::XADD => -5,
+            Command::XCLAIM => -6,
+            Command::XREADGROUP => -7,
+        }
+    }
+
+    /// command may grow the memory of the dataset, so it is refused when used memory is over maxmemory.
+    /// writes which only remove or shrink values, like DEL or EXPIRE, are still allowed to free memory
+    pub fn is_deny_oom(&self) -> bool {
+        matches!(self, Command::SET | Command::SETRANGE | Command::SETBIT | Command::BITOP | Command::BITFIELD | Command::COPY
+            | Command::PFADD | Command::PFMERGE
+            | Command::SADD | Command::SUNIONSTORE | Command::SINTERSTORE | Command::SDIFFSTORE
+            | Command::LPUSH | Command::RPUSH | Command::LINSERT | Command::LSET | Command::LMOVE | Command::BLMOVE
+            | Command::HSET | Command::HSETNX | Command::HINCRBY | Command::HINCRBYFLOAT
+            | Command::ZADD | Command::ZINCRBY | Command::ZRANGESTORE | Command::XADD | Command::XGROUP)
+    }
+
+    /// command is propagated to replicas: mutations and published messages
+    pub fn is_replicated(&self) -> bool {
+        self.is_mutating() || matches!(self, Command::PUBLISH | Command::SPUBLISH)
+    }
+
+    /// command does not depend on the data, so a replica runs it also while its data is stale
+    /// and replica-serve-stale-data is disabled
+    pub fn is_stale_allowed(&self) -> bool {
+        matches!(self, Command::PING | Command::HELLO | Command::AUTH | Command::INFO | Command::CONFIG | Command::CLIENT
+            | Command::COMMAND | Command::PSYNC | Command::REPLCONF | Command::REPLDEBUG | Command::DEBUG)
+    }
+}
+
+impl Command {
+    /// unix time in milliseconds of the expiration given to the EXPIRE family of commands,
+    /// seconds or milliseconds relative to now or absolute.
+    /// returns None on overflow
+    pub fn expiration_ms(&self, amount: i64) -> Option<i64> {
+        let now_ms = || SystemTime::now().duration_since(SystemTime::UNIX_EPOCH).map(|d| d.as_millis() as i64).ok();
+        match self {
+            Command::EXPIRE => amount.checked_mul(1000)?.checked_add(now_ms()?),
+            Command::PEXPIRE => amount.checked_add(now_ms()?),
+            Command::EXPIREAT => amount.checked_mul(1000),
+            Command::PEXPIREAT => Some(amount),
+            _ => None,
+        }
     }
 }
 
@@ -41,18 +274,146 @@ impl FromStr for Command {
             "PING" => Ok(Command::PING),
             "GET" => Ok(Command::GET),
             "TYPE" => Ok(Command::TYPE),
+            "OBJECT" => Ok(Command::OBJECT),
+            "MEMORY" => Ok(Command::MEMORY),
+            "DEBUG" => Ok(Command::DEBUG),
             "SET" => Ok(Command::SET),
+            "GETDEL" => Ok(Command::GETDEL),
+            "GETEX" => Ok(Command::GETEX),
             "KEYS" => Ok(Command::KEYS),
+            "SCAN" => Ok(Command::SCAN),
+            "DEL" => Ok(Command::DEL),
+            "UNLINK" => Ok(Command::UNLINK),
+            "TOUCH" => Ok(Command::TOUCH),
+            "RANDOMKEY" => Ok(Command::RANDOMKEY),
+            "GETRANGE" => Ok(Command::GETRANGE),
+            "SETRANGE" => Ok(Command::SETRANGE),
+            "BITCOUNT" => Ok(Command::BITCOUNT),
+            "SETBIT" => Ok(Command::SETBIT),
+            "GETBIT" => Ok(Command::GETBIT),
+            "BITOP" => Ok(Command::BITOP),
+            "BITPOS" => Ok(Command::BITPOS),
+            "BITFIELD" => Ok(Command::BITFIELD),
+            "PFADD" => Ok(Command::PFADD),
+            "PFCOUNT" => Ok(Command::PFCOUNT),
+            "PFMERGE" => Ok(Command::PFMERGE),
+            "CLIENT" => Ok(Command::CLIENT),
+            "COMMAND" => Ok(Command::COMMAND),
+            "LPUSH" => Ok(Command::LPUSH),
+            "RPUSH" => Ok(Command::RPUSH),
+            "LPOP" => Ok(Command::LPOP),
+            "RPOP" => Ok(Command::RPOP),
+            "LLEN" => Ok(Command::LLEN),
+            "LRANGE" => Ok(Command::LRANGE),
+            "LINDEX" => Ok(Command::LINDEX),
+            "LINSERT" => Ok(Command::LINSERT),
+            "LSET" => Ok(Command::LSET),
+            "LREM" => Ok(Command::LREM),
+            "LTRIM" => Ok(Command::LTRIM),
+            "LMOVE" => Ok(Command::LMOVE),
+            "BLPOP" => Ok(Command::BLPOP),
+            "BRPOP" => Ok(Command::BRPOP),
+            "BLMOVE" => Ok(Command::BLMOVE),
+            "LMPOP" => Ok(Command::LMPOP),
+            "BLMPOP" => Ok(Command::BLMPOP),
+            "HSET" => Ok(Command::HSET),
+            "HGET" => Ok(Command::HGET),
+            "HDEL" => Ok(Command::HDEL),
+            "HGETALL" => Ok(Command::HGETALL),
+            "HMGET" => Ok(Command::HMGET),
+            "HKEYS" => Ok(Command::HKEYS),
+            "HVALS" => Ok(Command::HVALS),
+            "HLEN" => Ok(Command::HLEN),
+            "HEXISTS" => Ok(Command::HEXISTS),
+            "HSETNX" => Ok(Command::HSETNX),
+            "HINCRBY" => Ok(Command::HINCRBY),
+            "HINCRBYFLOAT" => Ok(Command::HINCRBYFLOAT),
+            "HRANDFIELD" => Ok(Command::HRANDFIELD),
+            "SUBSCRIBE" => Ok(Command::SUBSCRIBE),
+            "UNSUBSCRIBE" => Ok(Command::UNSUBSCRIBE),
+            "PSUBSCRIBE" => Ok(Command::PSUBSCRIBE),
+            "PUNSUBSCRIBE" => Ok(Command::PUNSUBSCRIBE),
+            "SSUBSCRIBE" => Ok(Command::SSUBSCRIBE),
+            "SUNSUBSCRIBE" => Ok(Command::SUNSUBSCRIBE),
+            "SPUBLISH" => Ok(Command::SPUBLISH),
+            "PUBLISH" => Ok(Command::PUBLISH),
+            "DBSIZE" => Ok(Command::DBSIZE),
+            "FLUSHDB" => Ok(Command::FLUSHDB),
+            "FLUSHALL" => Ok(Command::FLUSHALL),
+            "RENAME" => Ok(Command::RENAME),
+            "RENAMENX" => Ok(Command::RENAMENX),
+            "COPY" => Ok(Command::COPY),
+            "EXPIRE" => Ok(Command::EXPIRE),
+            "PEXPIRE" => Ok(Command::PEXPIRE),
+            "EXPIREAT" => Ok(Command::EXPIREAT),
+            "PEXPIREAT" => Ok(Command::PEXPIREAT),
+            "TTL" => Ok(Command::TTL),
+            "PTTL" => Ok(Command::PTTL),
+            "EXPIRETIME" => Ok(Command::EXPIRETIME),
+            "PEXPIRETIME" => Ok(Command::PEXPIRETIME),
+            "PERSIST" => Ok(Command::PERSIST),
             "PSYNC" => Ok(Command::PSYNC),
+            "REPLDEBUG" => Ok(Command::REPLDEBUG),
             "ECHO" => Ok(Command::ECHO),
+            "HELLO" => Ok(Command::HELLO),
+            "AUTH" => Ok(Command::AUTH),
+            "MULTI" => Ok(Command::MULTI),
+            "WATCH" => Ok(Command::WATCH),
+            "UNWATCH" => Ok(Command::UNWATCH),
+            "EXEC" => Ok(Command::EXEC),
+            "DISCARD" => Ok(Command::DISCARD),
             "INFO" => Ok(Command::INFO),
             "REPLCONF" => Ok(Command::REPLCONF),
             "WAIT" => Ok(Command::WAIT),
             "CONFIG" => Ok(Command::CONFIG),
+            "SAVE" => Ok(Command::SAVE),
+            "BGSAVE" => Ok(Command::BGSAVE),
+            "LASTSAVE" => Ok(Command::LASTSAVE),
+            "BGREWRITEAOF" => Ok(Command::BGREWRITEAOF),
+            "CLUSTER" => Ok(Command::CLUSTER),
+            "SADD" => Ok(Command::SADD),
+            "SREM" => Ok(Command::SREM),
+            "SMEMBERS" => Ok(Command::SMEMBERS),
+            "SCARD" => Ok(Command::SCARD),
+            "SISMEMBER" => Ok(Command::SISMEMBER),
+            "SINTERCARD" => Ok(Command::SINTERCARD),
+            "SMISMEMBER" => Ok(Command::SMISMEMBER),
+            "SPOP" => Ok(Command::SPOP),
+            "SRANDMEMBER" => Ok(Command::SRANDMEMBER),
+            "SMOVE" => Ok(Command::SMOVE),
+            "SUNION" => Ok(Command::SUNION),
+            "SINTER" => Ok(Command::SINTER),
+            "SDIFF" => Ok(Command::SDIFF),
+            "SUNIONSTORE" => Ok(Command::SUNIONSTORE),
+            "SINTERSTORE" => Ok(Command::SINTERSTORE),
+            "SDIFFSTORE" => Ok(Command::SDIFFSTORE),
+            "ZADD" => Ok(Command::ZADD),
+            "ZSCORE" => Ok(Command::ZSCORE),
+            "ZCARD" => Ok(Command::ZCARD),
+            "ZRANGE" => Ok(Command::ZRANGE),
+            "ZINCRBY" => Ok(Command::ZINCRBY),
+            "ZRANK" => Ok(Command::ZRANK),
+            "ZREVRANK" => Ok(Command::ZREVRANK),
+            "ZCOUNT" => Ok(Command::ZCOUNT),
+            "ZRANGESTORE" => Ok(Command::ZRANGESTORE),
+            "ZREMRANGEBYRANK" => Ok(Command::ZREMRANGEBYRANK),
+            "ZREMRANGEBYSCORE" => Ok(Command::ZREMRANGEBYSCORE),
+            "ZREMRANGEBYLEX" => Ok(Command::ZREMRANGEBYLEX),
+            "ZMPOP" => Ok(Command::ZMPOP),
+            "BZMPOP" => Ok(Command::BZMPOP),
             "XADD" => Ok(Command::XADD),
             "XRANGE" => Ok(Command::XRANGE),
             "XREAD" => Ok(Command::XREAD),
-            _ => bail!("unknown command: {}", input),
+            "XGROUP" => Ok(Command::XGROUP),
+            "XREADGROUP" => Ok(Command::XREADGROUP),
+            "XACK" => Ok(Command::XACK),
+            "XCLAIM" => Ok(Command::XCLAIM),
+            "XPENDING" => Ok(Command::XPENDING),
+            "XDEL" => Ok(Command::XDEL),
+            "XTRIM" => Ok(Command::XTRIM),
+            "XSETID" => Ok(Command::XSETID),
+            "XINFO" => Ok(Command::XINFO),
+            _ => bail!("ERR unknown command '{}'", input),
         }
     }
 }
@@ -62,29 +423,424 @@ impl Display for Command {
         match self {
             Command::PING => write!(f, "PING"),
             Command::ECHO => write!(f, "ECHO"),
+            Command::HELLO => write!(f, "HELLO"),
+            Command::AUTH => write!(f, "AUTH"),
+            Command::MULTI => write!(f, "MULTI"),
+            Command::WATCH => write!(f, "WATCH"),
+            Command::UNWATCH => write!(f, "UNWATCH"),
+            Command::EXEC => write!(f, "EXEC"),
+            Command::DISCARD => write!(f, "DISCARD"),
             Command::SET => write!(f, "SET"),
             Command::KEYS => write!(f, "KEYS"),
+            Command::SCAN => write!(f, "SCAN"),
+            Command::DEL => write!(f, "DEL"),
+            Command::UNLINK => write!(f, "UNLINK"),
+            Command::TOUCH => write!(f, "TOUCH"),
+            Command::RANDOMKEY => write!(f, "RANDOMKEY"),
+            Command::GETRANGE => write!(f, "GETRANGE"),
+            Command::SETRANGE => write!(f, "SETRANGE"),
+            Command::BITCOUNT => write!(f, "BITCOUNT"),
+            Command::SETBIT => write!(f, "SETBIT"),
+            Command::GETBIT => write!(f, "GETBIT"),
+            Command::BITOP => write!(f, "BITOP"),
+            Command::BITPOS => write!(f, "BITPOS"),
+            Command::BITFIELD => write!(f, "BITFIELD"),
+            Command::PFADD => write!(f, "PFADD"),
+            Command::PFCOUNT => write!(f, "PFCOUNT"),
+            Command::PFMERGE => write!(f, "PFMERGE"),
+            Command::CLIENT => write!(f, "CLIENT"),
+            Command::COMMAND => write!(f, "COMMAND"),
+            Command::LPUSH => write!(f, "LPUSH"),
+            Command::RPUSH => write!(f, "RPUSH"),
+            Command::LPOP => write!(f, "LPOP"),
+            Command::RPOP => write!(f, "RPOP"),
+            Command::LLEN => write!(f, "LLEN"),
+            Command::LRANGE => write!(f, "LRANGE"),
+            Command::LINDEX => write!(f, "LINDEX"),
+            Command::LINSERT => write!(f, "LINSERT"),
+            Command::LSET => write!(f, "LSET"),
+            Command::LREM => write!(f, "LREM"),
+            Command::LTRIM => write!(f, "LTRIM"),
+            Command::LMOVE => write!(f, "LMOVE"),
+            Command::BLPOP => write!(f, "BLPOP"),
+            Command::BRPOP => write!(f, "BRPOP"),
+            Command::BLMOVE => write!(f, "BLMOVE"),
+            Command::LMPOP => write!(f, "LMPOP"),
+            Command::BLMPOP => write!(f, "BLMPOP"),
+            Command::HSET => write!(f, "HSET"),
+            Command::HGET => write!(f, "HGET"),
+            Command::HDEL => write!(f, "HDEL"),
+            Command::HGETALL => write!(f, "HGETALL"),
+            Command::HMGET => write!(f, "HMGET"),
+            Command::HKEYS => write!(f, "HKEYS"),
+            Command::HVALS => write!(f, "HVALS"),
+            Command::HLEN => write!(f, "HLEN"),
+            Command::HEXISTS => write!(f, "HEXISTS"),
+            Command::HSETNX => write!(f, "HSETNX"),
+            Command::HINCRBY => write!(f, "HINCRBY"),
+            Command::HINCRBYFLOAT => write!(f, "HINCRBYFLOAT"),
+            Command::HRANDFIELD => write!(f, "HRANDFIELD"),
+            Command::SUBSCRIBE => write!(f, "SUBSCRIBE"),
+            Command::UNSUBSCRIBE => write!(f, "UNSUBSCRIBE"),
+            Command::PSUBSCRIBE => write!(f, "PSUBSCRIBE"),
+            Command::PUNSUBSCRIBE => write!(f, "PUNSUBSCRIBE"),
+            Command::SSUBSCRIBE => write!(f, "SSUBSCRIBE"),
+            Command::SUNSUBSCRIBE => write!(f, "SUNSUBSCRIBE"),
+            Command::SPUBLISH => write!(f, "SPUBLISH"),
+            Command::PUBLISH => write!(f, "PUBLISH"),
+            Command::DBSIZE => write!(f, "DBSIZE"),
+            Command::FLUSHDB => write!(f, "FLUSHDB"),
+            Command::FLUSHALL => write!(f, "FLUSHALL"),
+            Command::RENAME => write!(f, "RENAME"),
+            Command::RENAMENX => write!(f, "RENAMENX"),
+            Command::COPY => write!(f, "COPY"),
+            Command::EXPIRE => write!(f, "EXPIRE"),
+            Command::PEXPIRE => write!(f, "PEXPIRE"),
+            Command::EXPIREAT => write!(f, "EXPIREAT"),
+            Command::PEXPIREAT => write!(f, "PEXPIREAT"),
+            Command::TTL => write!(f, "TTL"),
+            Command::PTTL => write!(f, "PTTL"),
+            Command::EXPIRETIME => write!(f, "EXPIRETIME"),
+            Command::PEXPIRETIME => write!(f, "PEXPIRETIME"),
+            Command::PERSIST => write!(f, "PERSIST"),
             Command::GET => write!(f, "GET"),
+            Command::GETDEL => write!(f, "GETDEL"),
+            Command::GETEX => write!(f, "GETEX"),
             Command::TYPE => write!(f, "TYPE"),
+            Command::OBJECT => write!(f, "OBJECT"),
+            Command::MEMORY => write!(f, "MEMORY"),
+            Command::DEBUG => write!(f, "DEBUG"),
             Command::PSYNC => write!(f, "PSYNC"),
             Command::INFO => write!(f, "INFO"),
             Command::REPLCONF => write!(f, "REPLCONF"),
+            Command::REPLDEBUG => write!(f, "REPLDEBUG"),
             Command::WAIT => write!(f, "WAIT"),
             Command::CONFIG => write!(f, "CONFIG"),
+            Command::SAVE => write!(f, "SAVE"),
+            Command::BGSAVE => write!(f, "BGSAVE"),
+            Command::LASTSAVE => write!(f, "LASTSAVE"),
+            Command::BGREWRITEAOF => write!(f, "BGREWRITEAOF"),
+            Command::CLUSTER => write!(f, "CLUSTER"),
+            Command::SADD => write!(f, "SADD"),
+            Command::SREM => write!(f, "SREM"),
+            Command::SMEMBERS => write!(f, "SMEMBERS"),
+            Command::SCARD => write!(f, "SCARD"),
+            Command::SISMEMBER => write!(f, "SISMEMBER"),
+            Command::SINTERCARD => write!(f, "SINTERCARD"),
+            Command::SMISMEMBER => write!(f, "SMISMEMBER"),
+            Command::SPOP => write!(f, "SPOP"),
+            Command::SRANDMEMBER => write!(f, "SRANDMEMBER"),
+            Command::SMOVE => write!(f, "SMOVE"),
+            Command::SUNION => write!(f, "SUNION"),
+            Command::SINTER => write!(f, "SINTER"),
+            Command::SDIFF => write!(f, "SDIFF"),
+            Command::SUNIONSTORE => write!(f, "SUNIONSTORE"),
+            Command::SINTERSTORE => write!(f, "SINTERSTORE"),
+            Command::SDIFFSTORE => write!(f, "SDIFFSTORE"),
+            Command::ZADD => write!(f, "ZADD"),
+            Command::ZSCORE => write!(f, "ZSCORE"),
+            Command::ZCARD => write!(f, "ZCARD"),
+            Command::ZRANGE => write!(f, "ZRANGE"),
+            Command::ZINCRBY => write!(f, "ZINCRBY"),
+            Command::ZRANK => write!(f, "ZRANK"),
+            Command::ZREVRANK => write!(f, "ZREVRANK"),
+            Command::ZCOUNT => write!(f, "ZCOUNT"),
+            Command::ZRANGESTORE => write!(f, "ZRANGESTORE"),
+            Command::ZREMRANGEBYRANK => write!(f, "ZREMRANGEBYRANK"),
+            Command::ZREMRANGEBYSCORE => write!(f, "ZREMRANGEBYSCORE"),
+            Command::ZREMRANGEBYLEX => write!(f, "ZREMRANGEBYLEX"),
+            Command::ZMPOP => write!(f, "ZMPOP"),
+            Command::BZMPOP => write!(f, "BZMPOP"),
             Command::XADD => write!(f, "XADD"),
             Command::XRANGE => write!(f, "XRANGE"),
             Command::XREAD => write!(f, "XREAD"),
+            Command::XGROUP => write!(f, "XGROUP"),
+            Command::XREADGROUP => write!(f, "XREADGROUP"),
+            Command::XACK => write!(f, "XACK"),
+            Command::XCLAIM => write!(f, "XCLAIM"),
+            Command::XPENDING => write!(f, "XPENDING"),
+            Command::XDEL => write!(f, "XDEL"),
+            Command::XTRIM => write!(f, "XTRIM"),
+            Command::XSETID => write!(f, "XSETID"),
+            Command::XINFO => write!(f, "XINFO"),
         }
     }
 }
 
-#[derive(Debug, Clone)]
-pub struct CommandRequest(pub Command, pub Vec<String>);
+/// command with its arguments as sent. arguments are text for names, options and numbers,
+/// but keys, members and values may be any bytes, see [Arg]
+#[derive(Clone)]
+pub struct CommandRequest(pub Command, pub Vec<Vec<u8>>);
+
+impl std::fmt::Debug for CommandRequest {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_tuple("CommandRequest").field(&self.0).field(&self.1.iter().map(|param| param.text()).collect::<Vec<_>>()).finish()
+    }
+}
 
 impl CommandRequest {
-    pub fn as_ref(&self) -> (&Command, &[String]) {
+    pub fn new(command: Command, params: Vec<Vec<u8>>) -> Self {
+        Self(command, params)
+    }
+
+    pub fn as_ref(&self) -> (&Command, &[Vec<u8>]) {
         (&self.0, self.1.as_slice())
     }
+
+    /// error of a command called with a wrong number of arguments, refused before it runs or is queued
+    pub fn arity_error(&self) -> Option<RESP> {
+        let (arity, args) = (self.0.arity(), self.1.len() as i32 + 1);
+        if (arity > 0 && args != arity) || (arity < 0 && args < -arity) {
+            return Some(RESP::Error(format!("ERR wrong number of arguments for '{}' command", self.0.to_string().to_lowercase())));
+        }
+        None
+    }
+
+    /// keys accessed by the command, used to route commands in cluster mode
+    pub fn keys(&self) -> Vec<&[u8]> {
+        match self.as_ref() {
+            (Command::RENAME | Command::RENAMENX | Command::COPY | Command::LMOVE | Command::BLMOVE | Command::SMOVE
+            | Command::ZRANGESTORE, [key, other, ..]) => vec![key, other],
+            // the timeout follows the keys
+            (Command::BLPOP | Command::BRPOP, [keys @ .., _]) => keys.iter().map(|k| k.as_slice()).collect(),
+            (Command::DEL | Command::UNLINK | Command::TOUCH | Command::WATCH
+            | Command::SUNION | Command::SINTER | Command::SDIFF
+            | Command::SUNIONSTORE | Command::SINTERSTORE | Command::SDIFFSTORE
+            | Command::PFCOUNT | Command::PFMERGE, keys) => keys.iter().map(|k| k.as_slice()).collect(),
+            // shard channels hash to slots like keys
+            (Command::SSUBSCRIBE | Command::SUNSUBSCRIBE, shard_channels) => shard_channels.iter().map(|k| k.as_slice()).collect(),
+            (Command::SPUBLISH, [shard_channel, ..]) => vec![shard_channel],
+            (Command::XREAD | Command::XREADGROUP, params) => {
+                let streams = args::named_option_list(params, "streams").unwrap_or_default();
+                streams[..streams.len() / 2].iter().map(|k| k.as_slice()).collect()
+            }
+            (Command::GET | Command::SET | Command::GETDEL | Command::GETEX | Command::TYPE
+            | Command::EXPIRE | Command::PEXPIRE | Command::EXPIREAT | Command::PEXPIREAT
+            | Command::TTL | Command::PTTL | Command::EXPIRETIME | Command::PEXPIRETIME | Command::PERSIST
+            | Command::XADD | Command::XRANGE | Command::XACK | Command::XCLAIM | Command::XPENDING
+            | Command::XDEL | Command::XTRIM | Command::XSETID
+            | Command::SADD | Command::SREM | Command::SMEMBERS | Command::SCARD | Command::SISMEMBER
+            | Command::SMISMEMBER | Command::SPOP | Command::SRANDMEMBER
+            | Command::LPUSH | Command::RPUSH | Command::LPOP | Command::RPOP | Command::LLEN | Command::LRANGE
+            | Command::LINDEX | Command::LINSERT | Command::LSET | Command::LREM | Command::LTRIM
+            | Command::HSET | Command::HGET | Command::HDEL | Command::HGETALL | Command::HMGET | Command::HKEYS
+            | Command::HVALS | Command::HLEN | Command::HEXISTS
+            | Command::HSETNX | Command::HINCRBY | Command::HINCRBYFLOAT | Command::HRANDFIELD
+            | Command::ZADD | Command::ZSCORE | Command::ZCARD | Command::ZRANGE
+            | Command::ZINCRBY | Command::ZRANK | Command::ZREVRANK | Command::ZCOUNT
+            | Command::ZREMRANGEBYRANK | Command::ZREMRANGEBYSCORE | Command::ZREMRANGEBYLEX
+            | Command::GETRANGE | Command::SETRANGE | Command::BITCOUNT | Command::SETBIT | Command::GETBIT
+            | Command::BITPOS | Command::BITFIELD | Command::PFADD, [key, ..]) => vec![key],
+            (Command::BITOP, [_, keys @ ..]) => keys.iter().map(|k| k.as_slice()).collect(),
+            (Command::XGROUP | Command::XINFO | Command::OBJECT, [_, key, ..]) => vec![key],
+            (Command::MEMORY, [sub_command, key, ..]) if sub_command.eq_ignore_ascii_case(b"USAGE") => vec![key],
+            (Command::SINTERCARD | Command::LMPOP | Command::ZMPOP, [numkeys, params @ ..])
+            | (Command::BLMPOP | Command::BZMPOP, [_, numkeys, params @ ..]) => {
+                let numkeys = numkeys.parse::<usize>().unwrap_or_default().min(params.len());
+                params[..numkeys].iter().map(|k| k.as_slice()).collect()
+            }
+            _ => vec![],
+        }
+    }
+
+    /// command may wait for other clients or for replicas, so it neither holds up transactions nor runs inside one
+    pub fn may_block(&self) -> bool {
+        match self.as_ref() {
+            (Command::BLPOP | Command::BRPOP | Command::BLMOVE | Command::BLMPOP | Command::BZMPOP
+            | Command::WAIT | Command::PSYNC, _) => true,
+            (Command::XREAD | Command::XREADGROUP, params) => params.iter().any(|param| param.eq_ignore_ascii_case(b"BLOCK")),
+            _ => false,
+        }
+    }
+
+    /// administrative command changing the server rather than the data, recorded in the audit log
+    pub fn is_admin(&self) -> bool {
+        match self.as_ref() {
+            (Command::CONFIG, [subcommand, ..]) => matches!(subcommand.to_uppercase().as_str(), "SET" | "REWRITE"),
+            (Command::CLIENT, [subcommand, ..]) => matches!(subcommand.to_uppercase().as_str(), "PAUSE" | "UNPAUSE"),
+            (Command::CLUSTER, [subcommand, ..]) => matches!(subcommand.to_uppercase().as_str(), "MEET" | "ADDSLOTS" | "ADDSLOTSRANGE"),
+            (Command::SAVE | Command::BGSAVE | Command::BGREWRITEAOF | Command::FLUSHDB | Command::FLUSHALL, _) => true,
+            _ => false,
+        }
+    }
+
+    /// rewrites a mutating command into a deterministic form for replication,
+    /// e.g. relative expirations become absolute so replicas expire keys at the same time as the master
+    /// and blocking pops become the pop they ended up doing, as told by the responses
+    pub fn replication_form(&self, responses: &[RESP]) -> Replication {
+        match (self.as_ref(), responses) {
+            ((Command::BLPOP | Command::BRPOP, _), [RESP::Array(served)]) => match &served[..] {
+                [RESP::Bulk(key), _] => {
+                    let pop = if self.0 == Command::BLPOP { Command::LPOP } else { Command::RPOP };
+                    Replication::Rewritten(vec![CommandRequest::new(pop, vec![key.clone()])])
+                }
+                _ => Replication::Skipped,
+            },
+            // the float result is replicated as is, replicas could round the increment differently
+            ((Command::HINCRBYFLOAT, [key, field, _]), [RESP::Bulk(value)]) => Replication::Rewritten(vec![
+                CommandRequest::new(Command::HSET, vec![key.clone(), field.clone(), value.clone()])
+            ]),
+            ((Command::HINCRBYFLOAT, _), _) => Replication::Skipped,
+            ((Command::BLMOVE, [source, destination, from, to, _]), [RESP::Bulk(_)]) => Replication::Rewritten(vec![
+                CommandRequest::new(Command::LMOVE, vec![source.clone(), destination.clone(), from.clone(), to.clone()])
+            ]),
+            ((Command::BLMPOP | Command::BZMPOP, [_, numkeys, params @ ..]), [RESP::Array(served)]) => {
+                let end = numkeys.parse::<usize>().ok().and_then(|numkeys| params.get(numkeys));
+                match (&served[..], end) {
+                    ([RESP::Bulk(key), RESP::Array(popped)], Some(end)) => {
+                        let pop = if self.0 == Command::BLMPOP { Command::LMPOP } else { Command::ZMPOP };
+                        let params = vec![b"1".to_vec(), key.clone(), end.clone(), b"COUNT".to_vec(), popped.len().to_string().into_bytes()];
+                        Replication::Rewritten(vec![CommandRequest::new(pop, params)])
+                    }
+                    _ => Replication::Skipped,
+                }
+            }
+            // timed out or failed without changes
+            ((Command::BLPOP | Command::BRPOP | Command::BLMOVE | Command::BLMPOP | Command::BZMPOP, _), _) => Replication::Skipped,
+            ((Command::LMPOP | Command::ZMPOP, _), [RESP::Null]) => Replication::Skipped,
+            // the random members become the removal of the popped ones
+            ((Command::SPOP, [key, ..]), [RESP::Bulk(member)]) => Replication::Rewritten(vec![
+                CommandRequest::new(Command::SREM, vec![key.clone(), member.clone()])
+            ]),
+            ((Command::SPOP, [key, ..]), [RESP::Array(members)]) if !members.is_empty() => {
+                let mut params = vec![key.clone()];
+                params.extend(members.iter().filter_map(|member| match member {
+                    RESP::Bulk(member) => Some(member.clone()),
+                    _ => None,
+                }));
+                Replication::Rewritten(vec![CommandRequest::new(Command::SREM, params)])
+            }
+            ((Command::SPOP, _), _) => Replication::Skipped,
+            // the deliveries become claims, so that replicas track the same pending entries
+            ((Command::XREADGROUP, params), [RESP::Array(streams)]) => Self::group_read_claims(params, streams)
+                .map_or(Replication::Skipped, Replication::Rewritten),
+            ((Command::XREADGROUP, _), _) => Replication::Skipped,
+            ((Command::XCLAIM, params), [RESP::Array(claimed)]) => Self::resolved_claim(params, claimed)
+                .map_or(Replication::Skipped, |claim| Replication::Rewritten(vec![claim])),
+            ((Command::XCLAIM, _), _) => Replication::Skipped,
+            _ => self.deterministic_form().map_or(Replication::AsReceived, |command| Replication::Rewritten(vec![command])),
+        }
+    }
+
+    /// claims of the entries delivered by XREADGROUP to the consumer, read from the response.
+    /// new entries move the last delivered id of the group forward, entries read without acknowledgement
+    /// only move the last delivered id. None when nothing was delivered
+    fn group_read_claims(params: &[Vec<u8>], streams: &[RESP]) -> Option<Vec<CommandRequest>> {
+        let [_, group, consumer, options @ ..] = params else {
+            return None;
+        };
+        let no_ack = options.iter().any(|option| option.eq_ignore_ascii_case(b"NOACK"));
+        let streams_option = args::named_option_list(options, "streams")?;
+        let (keys, ids) = streams_option.split_at(streams_option.len() / 2);
+        let now_ms = SystemTime::now().duration_since(SystemTime::UNIX_EPOCH).ok()?.as_millis() as u64;
+        let mut commands = vec![];
+        for stream in streams {
+            let RESP::Array(stream) = stream else { continue };
+            let [RESP::Bulk(key), RESP::Array(entries)] = &stream[..] else { continue };
+            let delivered = entries.iter()
+                .filter_map(|entry| match entry {
+                    // entries deleted from the stream stay pending without changes on the replicas
+                    RESP::Array(entry) if !matches!(entry.get(1), Some(RESP::Null) | None) => Some(entry[0].to_string().into_bytes()),
+                    _ => None,
+                })
+                .collect::<Vec<Vec<u8>>>();
+            let Some(last) = delivered.last() else { continue };
+            let new_entries = keys.iter().position(|k| k == key).is_some_and(|i| ids[i] == b">");
+            if no_ack && new_entries {
+                commands.push(CommandRequest::new(Command::XGROUP, vec![b"SETID".to_vec(), key.clone(), group.clone(), last.clone()]));
+                continue;
+            }
+            let mut claim = vec![key.clone(), group.clone(), consumer.clone(), b"0".to_vec()];
+            claim.extend(delivered.iter().cloned());
+            claim.extend([b"TIME".to_vec(), now_ms.to_string().into_bytes(), b"FORCE".to_vec()]);
+            if new_entries {
+                claim.extend([b"LASTID".to_vec(), last.clone()]);
+            }
+            commands.push(CommandRequest::new(Command::XCLAIM, claim));
+        }
+        (!commands.is_empty()).then_some(commands)
+    }
+
+    /// XCLAIM of the entries claimed by the master, with the time of delivery resolved,
+    /// so that replicas claim them regardless of their idle time. None when nothing changed
+    fn resolved_claim(params: &[Vec<u8>], claimed: &[RESP]) -> Option<CommandRequest> {
+        let [key, group, consumer, _, rest @ ..] = params else {
+            return None;
+        };
+        let options_start = rest.iter()
+            .position(|param| matches!(param.to_uppercase().as_str(), "IDLE" | "TIME" | "RETRYCOUNT" | "FORCE" | "JUSTID" | "LASTID"))
+            .unwrap_or(rest.len());
+        let options = &rest[options_start..];
+        let last_id = args::named_option::<String>(options, "LASTID").ok()?;
+        if claimed.is_empty() && last_id.is_none() {
+            return None;
+        }
+        let now_ms = SystemTime::now().duration_since(SystemTime::UNIX_EPOCH).ok()?.as_millis() as u64;
+        let delivered_ms = match (args::named_option::<u64>(options, "TIME").ok()?, args::named_option::<u64>(options, "IDLE").ok()?) {
+            (Some(time), _) => time,
+            (None, Some(idle)) => now_ms.saturating_sub(idle),
+            (None, None) => now_ms,
+        };
+        let mut claim = vec![key.clone(), group.clone(), consumer.clone(), b"0".to_vec()];
+        claim.extend(claimed.iter().map(|entry| match entry {
+            RESP::Array(entry) => entry[0].to_string().into_bytes(),
+            id => id.to_string().into_bytes(),
+        }));
+        claim.extend([b"TIME".to_vec(), delivered_ms.to_string().into_bytes()]);
+        if let Some(retry_count) = args::named_option::<String>(options, "RETRYCOUNT").ok()? {
+            claim.extend([b"RETRYCOUNT".to_vec(), retry_count.into_bytes()]);
+        }
+        if options.iter().any(|option| option.eq_ignore_ascii_case(b"JUSTID")) {
+            claim.push(b"JUSTID".to_vec());
+        }
+        claim.push(b"FORCE".to_vec());
+        if let Some(last_id) = last_id {
+            claim.extend([b"LASTID".to_vec(), last_id.into_bytes()]);
+        }
+        Some(CommandRequest::new(Command::XCLAIM, claim))
+    }
+
+    fn deterministic_form(&self) -> Option<CommandRequest> {
+        match self.as_ref() {
+            (Command::GETEX, [key, option, amount]) => {
+                let ttl_ms = match option.to_uppercase().as_str() {
+                    "EX" => amount.parse::<u64>().ok()?.checked_mul(1000)?,
+                    "PX" => amount.parse::<u64>().ok()?,
+                    _ => return None,
+                };
+                if ttl_ms == 0 {
+                    // invalid expiration, replicas fail the same way as the master
+                    return None;
+                }
+                let now_ms = SystemTime::now().duration_since(SystemTime::UNIX_EPOCH).ok()?.as_millis() as u64;
+                Some(CommandRequest::new(Command::GETEX, vec![key.clone(), b"PXAT".to_vec(), (now_ms + ttl_ms).to_string().into_bytes()]))
+            }
+            (Command::EXPIRE | Command::PEXPIRE | Command::EXPIREAT, [key, amount, options @ ..]) => {
+                let valid_until_ms = self.0.expiration_ms(amount.parse::<i64>().ok()?)?;
+                let mut params = vec![key.clone(), valid_until_ms.to_string().into_bytes()];
+                params.extend_from_slice(options);
+                Some(CommandRequest::new(Command::PEXPIREAT, params))
+            }
+            _ => None,
+        }
+    }
+}
+
+/// how a command executed by the master is sent to the replicas
+pub enum Replication {
+    AsReceived,
+    /// replaced by the commands with the same effect
+    Rewritten(Vec<CommandRequest>),
+    /// the command made no changes
+    Skipped,
+}
+
+impl From<&CommandRequest> for RESP {
+    fn from(command: &CommandRequest) -> Self {
+        let mut array = vec![RESP::bulk(&command.0.to_string())];
+        array.extend(command.1.iter().map(|param| RESP::Bulk(param.clone())));
+        RESP::Array(array)
+    }
 }
 
 
@@ -92,21 +848,24 @@ impl TryFrom<RESP> for CommandRequest {
     type Error = anyhow::Error;
 
     fn try_from(value: RESP) -> Result<Self, Self::Error> {
-        match &value {
-            RESP::Array(ref array) => {
-                if array.iter().all(|x| matches!(x, RESP::Bulk(_))) {
-                    let strings = array.iter().map(|r| r.to_string()).collect::<Vec<String>>();
-                    match &strings[..] {
-                        [command, params @ ..] => {
-                            let cmd = command.parse::<Command>()?;
-                            let vec = params.to_owned();
-                            return Ok(Self(cmd, vec));
-                        }
-                        _ => {}
-                    }
-                }
+        if let RESP::Array(ref array) = &value {
+            let bulks = array.iter()
+                .map(|item| match item {
+                    RESP::Bulk(bytes) => Some(bytes.clone()),
+                    _ => None,
+                })
+                .collect::<Option<Vec<Vec<u8>>>>();
+            let Some(bulks) = bulks else {
+                bail!("ERR Protocol error: expected the command and its arguments as bulk strings");
+            };
+            if let [command, params @ ..] = &bulks[..] {
+                let command = String::from_utf8_lossy(command);
+                let cmd = command.parse::<Command>().map_err(|_| format_err!(
+                    "ERR unknown command '{}', with args beginning with: {}",
+                    command, params.iter().map(|param| format!("'{}' ", String::from_utf8_lossy(param))).collect::<String>(),
+                ))?;
+                return Ok(Self::new(cmd, params.to_vec()));
             }
-            _ => {}
         }
         bail!("message is not a valid command: {}", value)
     }
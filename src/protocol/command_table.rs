@@ -0,0 +1,285 @@
+use crate::args::Arg;
+use crate::protocol::command::{Command, CommandRequest};
+use crate::protocol::resp::RESP;
+
+/// entry of the command table served by COMMAND: how a command is called and where its keys are
+pub struct CommandSpec {
+    pub command: Command,
+    /// group of the command in the documentation of redis, like string or sorted-set
+    pub group: &'static str,
+    pub summary: &'static str,
+    /// position of the first key in the arguments, counting the command name. 0 when the command has no keys
+    /// at fixed positions
+    pub first_key: i32,
+    /// position of the last key, negative positions count from the end: -1 for the last argument
+    pub last_key: i32,
+    /// step between the positions of the keys
+    pub step: i32,
+    /// the keys are found by parsing the arguments, like the keys following a numkeys argument
+    pub movable_keys: bool,
+}
+
+const fn keyless(command: Command, group: &'static str, summary: &'static str) -> CommandSpec {
+    CommandSpec { command, group, summary, first_key: 0, last_key: 0, step: 0, movable_keys: false }
+}
+
+const fn keys(command: Command, group: &'static str, summary: &'static str, first_key: i32, last_key: i32) -> CommandSpec {
+    CommandSpec { command, group, summary, first_key, last_key, step: 1, movable_keys: false }
+}
+
+const fn key(command: Command, group: &'static str, summary: &'static str) -> CommandSpec {
+    keys(command, group, summary, 1, 1)
+}
+
+const fn movable(command: Command, group: &'static str, summary: &'static str) -> CommandSpec {
+    CommandSpec { command, group, summary, first_key: 0, last_key: 0, step: 0, movable_keys: true }
+}
+
+/// the commands served, with the summaries of the documentation of redis
+pub static COMMAND_TABLE: &[CommandSpec] = &[
+    keyless(Command::PING, "connection", "Returns the server's liveliness response."),
+    keyless(Command::ECHO, "connection", "Returns the given string."),
+    keyless(Command::HELLO, "connection", "Handshakes with the Redis server."),
+    keyless(Command::AUTH, "connection", "Authenticates the connection."),
+    keyless(Command::MULTI, "transactions", "Starts a transaction."),
+    keys(Command::WATCH, "transactions", "Monitors changes to keys to determine the execution of a transaction.", 1, -1),
+    keyless(Command::UNWATCH, "transactions", "Forgets about watched keys of a transaction."),
+    keyless(Command::EXEC, "transactions", "Executes all commands in a transaction."),
+    keyless(Command::DISCARD, "transactions", "Discards a transaction."),
+    key(Command::SET, "string", "Sets the string value of a key, ignoring its type. The key is created if it doesn't exist."),
+    key(Command::GET, "string", "Returns the string value of a key."),
+    key(Command::GETDEL, "string", "Returns the string value of a key after deleting the key."),
+    key(Command::GETEX, "string", "Returns the string value of a key after setting its expiration time."),
+    key(Command::GETRANGE, "string", "Returns a substring of the string stored at a key."),
+    key(Command::SETRANGE, "string", "Overwrites a part of a string value with another by an offset. Creates the key if it doesn't exist."),
+    key(Command::BITCOUNT, "bitmap", "Counts the number of set bits (population counting) in a string."),
+    key(Command::SETBIT, "bitmap", "Sets or clears the bit at offset of the string value. Creates the key if it doesn't exist."),
+    key(Command::GETBIT, "bitmap", "Returns a bit value by offset."),
+    keys(Command::BITOP, "bitmap", "Performs bitwise operations on multiple strings, and stores the result.", 2, -1),
+    key(Command::BITPOS, "bitmap", "Finds the first set (1) or clear (0) bit in a string."),
+    key(Command::BITFIELD, "bitmap", "Performs arbitrary bitfield integer operations on strings."),
+    key(Command::PFADD, "hyperloglog", "Adds elements to a HyperLogLog key. Creates the key if it doesn't exist."),
+    keys(Command::PFCOUNT, "hyperloglog", "Returns the approximated cardinality of the set(s) observed by the HyperLogLog key(s).", 1, -1),
+    keys(Command::PFMERGE, "hyperloglog", "Merges one or more HyperLogLog values into a single key.", 1, -1),
+    key(Command::TYPE, "generic", "Determines the type of value stored at a key."),
+    keyless(Command::OBJECT, "generic", "A container for object introspection commands."),
+    keyless(Command::MEMORY, "server", "A container for memory diagnostics commands."),
+    keyless(Command::DEBUG, "server", "A container for debugging commands."),
+    keyless(Command::KEYS, "generic", "Returns all key names that match a pattern."),
+    keyless(Command::SCAN, "generic", "Iterates over the key names in the database."),
+    keys(Command::DEL, "generic", "Deletes one or more keys.", 1, -1),
+    keys(Command::UNLINK, "generic", "Asynchronously deletes one or more keys.", 1, -1),
+    keys(Command::TOUCH, "generic", "Returns the number of existing keys out of those specified after updating the time they were last accessed.", 1, -1),
+    keyless(Command::RANDOMKEY, "generic", "Returns a random key name from the database."),
+    keyless(Command::DBSIZE, "server", "Returns the number of keys in the database."),
+    keyless(Command::FLUSHDB, "server", "Removes all keys from the current database."),
+    keyless(Command::FLUSHALL, "server", "Removes all keys from all databases."),
+    keys(Command::RENAME, "generic", "Renames a key and overwrites the destination.", 1, 2),
+    keys(Command::RENAMENX, "generic", "Renames a key only when the target key name doesn't exist.", 1, 2),
+    keys(Command::COPY, "generic", "Copies the value of a key to a new key.", 1, 2),
+    key(Command::EXPIRE, "generic", "Sets the expiration time of a key in seconds."),
+    key(Command::PEXPIRE, "generic", "Sets the expiration time of a key in milliseconds."),
+    key(Command::EXPIREAT, "generic", "Sets the expiration time of a key to a Unix timestamp."),
+    key(Command::PEXPIREAT, "generic", "Sets the expiration time of a key to a Unix milliseconds timestamp."),
+    key(Command::TTL, "generic", "Returns the expiration time in seconds of a key."),
+    key(Command::PTTL, "generic", "Returns the expiration time in milliseconds of a key."),
+    key(Command::EXPIRETIME, "generic", "Returns the expiration time of a key as a Unix timestamp."),
+    key(Command::PEXPIRETIME, "generic", "Returns the expiration time of a key as a Unix milliseconds timestamp."),
+    key(Command::PERSIST, "generic", "Removes the expiration time of a key."),
+    keyless(Command::PSYNC, "server", "An internal command used in replication."),
+    keyless(Command::INFO, "server", "Returns information and statistics about the server."),
+    keyless(Command::REPLCONF, "server", "An internal command for configuring the replication stream."),
+    keyless(Command::REPLDEBUG, "server", "Returns the replication state of the server, for debugging."),
+    keyless(Command::WAIT, "generic", "Blocks until the asynchronous replication of all preceding write commands sent by the connection is completed."),
+    keyless(Command::CONFIG, "server", "A container for server configuration commands."),
+    keyless(Command::CLIENT, "connection", "A container for client connection commands."),
+    keyless(Command::COMMAND, "server", "Returns detailed information about all commands."),
+    key(Command::SADD, "set", "Adds one or more members to a set. Creates the key if it doesn't exist."),
+    key(Command::SREM, "set", "Removes one or more members from a set. Deletes the set if the last member was removed."),
+    key(Command::SMEMBERS, "set", "Returns all members of a set."),
+    key(Command::SCARD, "set", "Returns the number of members in a set."),
+    key(Command::SISMEMBER, "set", "Determines whether a member belongs to a set."),
+    movable(Command::SINTERCARD, "set", "Returns the number of members of the intersect of multiple sets."),
+    key(Command::SMISMEMBER, "set", "Determines whether multiple members belong to a set."),
+    key(Command::SPOP, "set", "Returns one or more random members from a set after removing them. Deletes the set if the last member was popped."),
+    key(Command::SRANDMEMBER, "set", "Get one or multiple random members from a set"),
+    keys(Command::SMOVE, "set", "Moves a member from one set to another.", 1, 2),
+    keys(Command::SUNION, "set", "Returns the union of multiple sets.", 1, -1),
+    keys(Command::SINTER, "set", "Returns the intersect of multiple sets.", 1, -1),
+    keys(Command::SDIFF, "set", "Returns the difference of multiple sets.", 1, -1),
+    keys(Command::SUNIONSTORE, "set", "Stores the union of multiple sets in a key.", 1, -1),
+    keys(Command::SINTERSTORE, "set", "Stores the intersect of multiple sets in a key.", 1, -1),
+    keys(Command::SDIFFSTORE, "set", "Stores the difference of multiple sets in a key.", 1, -1),
+    key(Command::LPUSH, "list", "Prepends one or more elements to a list. Creates the key if it doesn't exist."),
+    key(Command::RPUSH, "list", "Appends one or more elements to a list. Creates the key if it doesn't exist."),
+    key(Command::LPOP, "list", "Returns the first elements in a list after removing it. Deletes the list if the last element was popped."),
+    key(Command::RPOP, "list", "Returns and removes the last elements of a list. Deletes the list if the last element was popped."),
+    key(Command::LLEN, "list", "Returns the length of a list."),
+    key(Command::LRANGE, "list", "Returns a range of elements from a list."),
+    key(Command::LINDEX, "list", "Returns an element from a list by its index."),
+    key(Command::LINSERT, "list", "Inserts an element before or after another element in a list."),
+    key(Command::LSET, "list", "Sets the value of an element in a list by its index."),
+    key(Command::LREM, "list", "Removes elements from a list. Deletes the list if the last element was removed."),
+    key(Command::LTRIM, "list", "Removes elements from both ends a list. Deletes the list if all elements were trimmed."),
+    keys(Command::LMOVE, "list", "Returns an element after popping it from one list and pushing it to another. Deletes the list if the last element was moved.", 1, 2),
+    keys(Command::BLPOP, "list", "Removes and returns the first element in a list. Blocks until an element is available otherwise. Deletes the list if the last element was popped.", 1, -2),
+    keys(Command::BRPOP, "list", "Removes and returns the last element in a list. Blocks until an element is available otherwise. Deletes the list if the last element was popped.", 1, -2),
+    keys(Command::BLMOVE, "list", "Pops an element from a list, pushes it to another list and returns it. Blocks until an element is available otherwise. Deletes the list if the last element was moved.", 1, 2),
+    movable(Command::LMPOP, "list", "Returns multiple elements from a list after removing them. Deletes the list if the last element was popped."),
+    movable(Command::BLMPOP, "list", "Pops the first element from one of multiple lists. Blocks until an element is available otherwise. Deletes the list if the last element was popped."),
+    key(Command::HSET, "hash", "Creates or modifies the value of a field in a hash."),
+    key(Command::HGET, "hash", "Returns the value of a field in a hash."),
+    key(Command::HDEL, "hash", "Deletes one or more fields and their values from a hash. Deletes the hash if no fields remain."),
+    key(Command::HGETALL, "hash", "Returns all fields and values in a hash."),
+    key(Command::HMGET, "hash", "Returns the values of all fields in a hash."),
+    key(Command::HKEYS, "hash", "Returns all fields in a hash."),
+    key(Command::HVALS, "hash", "Returns all values in a hash."),
+    key(Command::HLEN, "hash", "Returns the number of fields in a hash."),
+    key(Command::HEXISTS, "hash", "Determines whether a field exists in a hash."),
+    key(Command::HSETNX, "hash", "Sets the value of a field in a hash only when the field doesn't exist."),
+    key(Command::HINCRBY, "hash", "Increments the integer value of a field in a hash by a number. Uses 0 as initial value if the field doesn't exist."),
+    key(Command::HINCRBYFLOAT, "hash", "Increments the floating point value of a field by a number. Uses 0 as initial value if the field doesn't exist."),
+    key(Command::HRANDFIELD, "hash", "Returns one or more random fields from a hash."),
+    key(Command::ZADD, "sorted-set", "Adds one or more members to a sorted set, or updates their scores. Creates the key if it doesn't exist."),
+    key(Command::ZSCORE, "sorted-set", "Returns the score of a member in a sorted set."),
+    key(Command::ZCARD, "sorted-set", "Returns the number of members in a sorted set."),
+    key(Command::ZRANGE, "sorted-set", "Returns members in a sorted set within a range of indexes."),
+    key(Command::ZINCRBY, "sorted-set", "Increments the score of a member in a sorted set."),
+    key(Command::ZRANK, "sorted-set", "Returns the index of a member in a sorted set ordered by ascending scores."),
+    key(Command::ZREVRANK, "sorted-set", "Returns the index of a member in a sorted set ordered by descending scores."),
+    key(Command::ZCOUNT, "sorted-set", "Returns the count of members in a sorted set that have scores within a range."),
+    keys(Command::ZRANGESTORE, "sorted-set", "Stores a range of members from sorted set in a key.", 1, 2),
+    key(Command::ZREMRANGEBYRANK, "sorted-set", "Removes members in a sorted set within a range of indexes. Deletes the sorted set if all members were removed."),
+    key(Command::ZREMRANGEBYSCORE, "sorted-set", "Removes members in a sorted set within a range of scores. Deletes the sorted set if all members were removed."),
+    key(Command::ZREMRANGEBYLEX, "sorted-set", "Removes members in a sorted set within a lexicographical range. Deletes the sorted set if all members were removed."),
+    movable(Command::ZMPOP, "sorted-set", "Returns the highest- or lowest-scoring members from one or more sorted sets after removing them. Deletes the sorted set if the last member was popped."),
+    movable(Command::BZMPOP, "sorted-set", "Removes and returns a member by score from one or more sorted sets. Blocks until a member is available otherwise. Deletes the sorted set if the last element was popped."),
+    keyless(Command::CLUSTER, "cluster", "A container for Redis Cluster commands."),
+    keyless(Command::SAVE, "server", "Synchronously saves the database(s) to disk."),
+    keyless(Command::BGSAVE, "server", "Asynchronously saves the database(s) to disk."),
+    keyless(Command::LASTSAVE, "server", "Returns the Unix timestamp of the last successful save to disk."),
+    keyless(Command::BGREWRITEAOF, "server", "Asynchronously rewrites the append-only file to disk."),
+    keyless(Command::SUBSCRIBE, "pubsub", "Listens for messages published to channels."),
+    keyless(Command::UNSUBSCRIBE, "pubsub", "Stops listening to messages posted to channels."),
+    keyless(Command::PSUBSCRIBE, "pubsub", "Listens for messages published to channels that match one or more patterns."),
+    keyless(Command::PUNSUBSCRIBE, "pubsub", "Stops listening to messages published to channels that match one or more patterns."),
+    // shard channels are positioned like keys, they are routed to the slot of their name
+    keys(Command::SSUBSCRIBE, "pubsub", "Listens for messages published to shard channels.", 1, -1),
+    keys(Command::SUNSUBSCRIBE, "pubsub", "Stops listening to messages posted to shard channels.", 1, -1),
+    key(Command::SPUBLISH, "pubsub", "Post a message to a shard channel"),
+    keyless(Command::PUBLISH, "pubsub", "Posts a message to a channel."),
+    key(Command::XADD, "stream", "Appends a new message to a stream. Creates the key if it doesn't exist."),
+    key(Command::XRANGE, "stream", "Returns the messages from a stream within a range of IDs."),
+    movable(Command::XREAD, "stream", "Returns messages from multiple streams with IDs greater than the ones requested. Blocks until a message is available otherwise."),
+    keyless(Command::XGROUP, "stream", "A container for consumer groups commands."),
+    movable(Command::XREADGROUP, "stream", "Returns new or historical messages from a stream for a consumer in a group. Blocks until a message is available otherwise."),
+    key(Command::XACK, "stream", "Returns the number of messages that were successfully acknowledged by the consumer group member of a stream."),
+    key(Command::XCLAIM, "stream", "Changes, or acquires, ownership of a message in a consumer group, as if the message was delivered a consumer group member."),
+    key(Command::XPENDING, "stream", "Returns the information and entries from a stream consumer group's pending entries list."),
+    key(Command::XDEL, "stream", "Returns the number of messages after removing them from a stream."),
+    key(Command::XTRIM, "stream", "Deletes messages from the beginning of a stream."),
+    key(Command::XSETID, "stream", "An internal command for replicating stream values."),
+    keyless(Command::XINFO, "stream", "A container for stream introspection commands."),
+];
+
+/// entry of the command in the command table, looked up by name in any case
+pub fn lookup(name: &str) -> Option<&'static CommandSpec> {
+    let command = name.parse::<Command>().ok()?;
+    COMMAND_TABLE.iter().find(|spec| spec.command == command)
+}
+
+impl CommandSpec {
+    fn name(&self) -> String {
+        self.command.to_string().to_lowercase()
+    }
+
+    /// flags of COMMAND INFO, derived from how the server treats the command
+    pub fn flags(&self) -> Vec<&'static str> {
+        let command = self.command;
+        let has_keys = self.first_key != 0 || self.movable_keys;
+        let pubsub = self.group == "pubsub";
+        [
+            (command.is_mutating(), "write"),
+            (!command.is_mutating() && has_keys && !pubsub && self.group != "transactions", "readonly"),
+            (command.is_deny_oom(), "denyoom"),
+            (matches!(command, Command::PSYNC | Command::REPLCONF | Command::REPLDEBUG | Command::SAVE | Command::BGSAVE
+                | Command::BGREWRITEAOF | Command::DEBUG), "admin"),
+            (pubsub, "pubsub"),
+            (matches!(command, Command::BLPOP | Command::BRPOP | Command::BLMOVE | Command::BLMPOP | Command::BZMPOP
+                | Command::WAIT | Command::XREAD | Command::XREADGROUP), "blocking"),
+            (matches!(command, Command::HELLO | Command::AUTH), "no_auth"),
+            (command.is_stale_allowed(), "stale"),
+            (self.movable_keys, "movablekeys"),
+        ]
+        .iter()
+        .filter(|(set, _)| *set)
+        .map(|(_, flag)| *flag)
+        .collect()
+    }
+
+    /// ACL categories of the command: its data type or purpose, and whether it reads or writes
+    fn categories(&self) -> Vec<String> {
+        let group = match self.group {
+            "generic" => Some("keyspace"),
+            "sorted-set" => Some("sortedset"),
+            "transactions" => Some("transaction"),
+            "server" | "cluster" => None,
+            group => Some(group),
+        };
+        let flags = self.flags();
+        let access = if flags.contains(&"write") {
+            Some("write")
+        } else if flags.contains(&"readonly") {
+            Some("read")
+        } else {
+            None
+        };
+        let admin = flags.contains(&"admin").then_some("admin");
+        let blocking = flags.contains(&"blocking").then_some("blocking");
+        [access, group, admin, blocking].into_iter().flatten().map(|category| format!("@{}", category)).collect()
+    }
+
+    /// reply of COMMAND INFO: name, arity, flags, first key, last key, step, ACL categories, tips,
+    /// key specifications and subcommands
+    pub fn info(&self) -> RESP {
+        RESP::Array(vec![
+            RESP::bulk(&self.name()),
+            RESP::Int(self.command.arity() as i64),
+            RESP::Set(self.flags().iter().map(|flag| RESP::String(flag.to_string())).collect()),
+            RESP::Int(self.first_key as i64),
+            RESP::Int(self.last_key as i64),
+            RESP::Int(self.step as i64),
+            RESP::Set(self.categories().iter().map(|category| RESP::String(category.clone())).collect()),
+            RESP::Set(vec![]),
+            RESP::Array(vec![]),
+            RESP::Array(vec![]),
+        ])
+    }
+
+    /// reply of COMMAND DOCS for the command: its name with its summary and group
+    pub fn docs(&self) -> (RESP, RESP) {
+        let docs = vec![
+            (RESP::bulk("summary"), RESP::bulk(self.summary)),
+            (RESP::bulk("group"), RESP::bulk(self.group)),
+        ];
+        (RESP::bulk(&self.name()), RESP::Map(docs))
+    }
+}
+
+/// keys of a command line as found by COMMAND GETKEYS, an error when it has none
+pub fn command_keys(command_line: &[Vec<u8>]) -> RESP {
+    let Some((name, params)) = command_line.split_first() else {
+        return RESP::Error("ERR Invalid command specified".to_string());
+    };
+    let Ok(command) = name.parse::<Command>() else {
+        return RESP::Error("ERR Invalid command specified".to_string());
+    };
+    let request = CommandRequest::new(command, params.to_vec());
+    if request.arity_error().is_some() {
+        return RESP::Error("ERR Invalid number of arguments specified for command".to_string());
+    }
+    match request.keys() {
+        keys if keys.is_empty() => RESP::Error("ERR The command has no key arguments".to_string()),
+        keys => RESP::Array(keys.iter().map(|key| RESP::Bulk(key.to_vec())).collect()),
+    }
+}
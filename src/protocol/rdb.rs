@@ -1,51 +1,59 @@
-use std::fs::File;
-use std::io::{BufReader, Read};
+use std::io::{Read, Write};
 
-use anyhow::{bail, Result};
+use anyhow::{bail, format_err, Result};
 
-/** Empty Encoded RDB */
-pub fn empty_rdb() -> Vec<u8> {
-    hex_to_bytes(RDB_EMPTY_HEX)
-}
+/// opcode of an entry which this version can't parse, e.g. from a file written by a newer version
+#[derive(Debug, thiserror::Error)]
+#[error("unknown opcode 0x{0:02X}")]
+pub struct UnknownOpcode(pub u8);
 
 pub enum LengthEncoding {
-    Len(u32),
-    Byte(u8),
-    Short(u16),
-    Int(u32),
+    Len(u64),
+    /// strings encoded as integers
+    Byte(i8),
+    Short(i16),
+    Int(i32),
+    /// LZF compressed string
+    Lzf,
 }
 
-pub(crate) fn read_length(reader: &mut BufReader<File>) -> Result<LengthEncoding> {
+pub(crate) fn read_length(reader: &mut impl Read) -> Result<LengthEncoding> {
     let head = read_byte(reader)?;
     // This is how length encoding works : Read one byte from the stream, compare the two most significant bits:
     let bits = head & 0b11000000;
     match bits {
-        0b00000000 => Ok(LengthEncoding::Len(head as u32)),
+        0b00000000 => Ok(LengthEncoding::Len(head as u64)),
         0b01000000 => {
             let first6bits = head & 0b00111111;
             let second = read_byte(reader)?;
             Ok(LengthEncoding::Len(
-                u16::from_le_bytes([first6bits, second]) as u32,
+                u16::from_be_bytes([first6bits, second]) as u64,
             ))
         }
-        0b10000000 => {
-            let mut buf = [0; 4];
-            reader.read_exact(&mut buf)?;
-            Ok(LengthEncoding::Len(u32::from_le_bytes(buf)))
-        }
+        0b10000000 => match head {
+            0x80 => {
+                let mut buf = [0; 4];
+                reader.read_exact(&mut buf)?;
+                Ok(LengthEncoding::Len(u32::from_be_bytes(buf) as u64))
+            }
+            0x81 => {
+                let mut buf = [0; 8];
+                reader.read_exact(&mut buf)?;
+                Ok(LengthEncoding::Len(u64::from_be_bytes(buf)))
+            }
+            _ => bail!("invalid length encoding: {}", head),
+        },
         0b11000000 => {
             let first6bits = head & 0b00111111;
             match first6bits {
-                0 => Ok(LengthEncoding::Byte(read_byte(reader)?)),
+                0 => Ok(LengthEncoding::Byte(read_byte(reader)? as i8)),
                 1 => {
                     let mut buf = [0; 2];
                     reader.read_exact(&mut buf)?;
-                    Ok(LengthEncoding::Short(u16::from_le_bytes(buf)))
-                }
-                2 => Ok(LengthEncoding::Int(read_u32(reader)?)),
-                3 => {
-                    bail!("compressed encoding follows"); // TODO
+                    Ok(LengthEncoding::Short(i16::from_le_bytes(buf)))
                 }
+                2 => Ok(LengthEncoding::Int(read_u32(reader)? as i32)),
+                3 => Ok(LengthEncoding::Lzf),
                 _ => {
                     bail!("unknown encoding: {}", head);
                 }
@@ -57,63 +65,502 @@ pub(crate) fn read_length(reader: &mut BufReader<File>) -> Result<LengthEncoding
     }
 }
 
-pub(crate) fn read_crc64(reader: &mut BufReader<File>) -> Result<[u8; 8]> {
-    let mut buf = [0; 8];
-    reader.read_exact(&mut buf)?;
-    Ok(buf)
-}
-
-pub(crate) fn read_int(reader: &mut BufReader<File>) -> Result<i32> {
+pub(crate) fn read_int(reader: &mut impl Read) -> Result<i32> {
     Ok(match read_length(reader)? {
         LengthEncoding::Len(len) => len as i32,
         LengthEncoding::Short(len) => len as i32,
         LengthEncoding::Byte(len) => len as i32,
-        LengthEncoding::Int(len) => len as i32,
+        LengthEncoding::Int(len) => len,
+        LengthEncoding::Lzf => bail!("compressed string instead of a number"),
     })
 }
 
-pub(crate) fn read_string(reader: &mut BufReader<File>) -> Result<String> {
+/// length of a collection or of a string
+pub(crate) fn read_len(reader: &mut impl Read) -> Result<usize> {
+    match read_length(reader)? {
+        LengthEncoding::Len(len) => Ok(len as usize),
+        _ => bail!("string encoding instead of a length"),
+    }
+}
+
+/// unsigned integer stored as a length, like the parts of the ids of stream entries
+pub(crate) fn read_uint(reader: &mut impl Read) -> Result<u64> {
+    match read_length(reader)? {
+        LengthEncoding::Len(value) => Ok(value),
+        _ => bail!("string encoding instead of a number"),
+    }
+}
+
+pub(crate) fn read_string(reader: &mut impl Read) -> Result<String> {
+    Ok(String::from_utf8(read_bytes(reader)?)?)
+}
+
+/// string which may not be text, like the value of a bitmap
+pub(crate) fn read_bytes(reader: &mut impl Read) -> Result<Vec<u8>> {
     match read_length(reader)? {
-        LengthEncoding::Len(len) => {
+        LengthEncoding::Len(len) => read_exact_len(reader, len),
+        LengthEncoding::Byte(value) => Ok(value.to_string().into_bytes()),
+        LengthEncoding::Int(value) => Ok(value.to_string().into_bytes()),
+        LengthEncoding::Short(value) => Ok(value.to_string().into_bytes()),
+        LengthEncoding::Lzf => {
+            let compressed_len = read_len(reader)?;
+            let len = read_len(reader)?;
+            let compressed = read_exact_len(reader, compressed_len as u64)?;
+            lzf_decompress(&compressed, len)
+        }
+    }
+}
+
+/// bytes of a string of the length given in the file. the length is not trusted for the allocation,
+/// the buffer grows with the bytes actually read so that a corrupt length fails as a truncated file
+fn read_exact_len(reader: &mut impl Read, len: u64) -> Result<Vec<u8>> {
+    let mut buf = vec![];
+    reader.take(len).read_to_end(&mut buf)?;
+    if (buf.len() as u64) < len {
+        bail!("string of {} bytes truncated after {} bytes", len, buf.len());
+    }
+    Ok(buf)
+}
+
+/// decompresses LZF compressed bytes into the length they had.
+/// the length is not trusted for the allocation, the output fails as soon as it is longer
+fn lzf_decompress(input: &[u8], len: usize) -> Result<Vec<u8>> {
+    let mut output = vec![];
+    let mut i = 0;
+    while i < input.len() {
+        if output.len() > len {
+            break;
+        }
+        let control = input[i] as usize;
+        i += 1;
+        if control < 1 << 5 {
+            // a run of literal bytes
+            let literal = input.get(i..i + control + 1).ok_or_else(|| format_err!("truncated LZF literal"))?;
+            output.extend_from_slice(literal);
+            i += control + 1;
+        } else {
+            // a back reference to bytes already decompressed
+            let mut ref_len = control >> 5;
+            if ref_len == 7 {
+                ref_len += *input.get(i).ok_or_else(|| format_err!("truncated LZF reference"))? as usize;
+                i += 1;
+            }
+            let offset = ((control & 0x1f) << 8) + *input.get(i).ok_or_else(|| format_err!("truncated LZF reference"))? as usize + 1;
+            i += 1;
+            if offset > output.len() {
+                bail!("invalid LZF reference");
+            }
+            let start = output.len() - offset;
+            // the reference may overlap the bytes it produces
+            for position in start..start + ref_len + 2 {
+                output.push(output[position]);
+            }
+        }
+    }
+    if output.len() != len {
+        bail!("LZF decompressed {} bytes instead of {}", output.len(), len);
+    }
+    Ok(output)
+}
+
+/// binary double of the scores of sorted sets, in little endian
+pub(crate) fn read_f64(reader: &mut impl Read) -> Result<f64> {
+    Ok(f64::from_bits(read_u64(reader)?))
+}
+
+/// double as text preceded by its length, with special lengths for infinities and not a number
+pub(crate) fn read_text_f64(reader: &mut impl Read) -> Result<f64> {
+    Ok(match read_byte(reader)? {
+        253 => f64::NAN,
+        254 => f64::INFINITY,
+        255 => f64::NEG_INFINITY,
+        len => {
             let mut buf = vec![0; len as usize];
             reader.read_exact(&mut buf)?;
-            Ok(String::from_utf8(buf)?)
+            String::from_utf8(buf)?.parse::<f64>()?
+        }
+    })
+}
+
+/// cursor over a blob of a compact encoding, failing when the blob ends too soon
+struct Blob<'a> {
+    bytes: &'a [u8],
+    position: usize,
+}
+
+impl<'a> Blob<'a> {
+    fn new(bytes: &'a [u8]) -> Self {
+        Blob { bytes, position: 0 }
+    }
+
+    fn take(&mut self, len: usize) -> Result<&'a [u8]> {
+        let taken = self.bytes.get(self.position..self.position + len)
+            .ok_or_else(|| format_err!("truncated encoding at byte {}", self.position))?;
+        self.position += len;
+        Ok(taken)
+    }
+
+    fn byte(&mut self) -> Result<u8> {
+        Ok(self.take(1)?[0])
+    }
+
+    fn peek(&self) -> Result<u8> {
+        self.bytes.get(self.position).copied().ok_or_else(|| format_err!("truncated encoding at byte {}", self.position))
+    }
+
+    /// little endian integer of the number of bytes, sign extended
+    fn int(&mut self, len: usize) -> Result<i64> {
+        let bytes = self.take(len)?;
+        let value = bytes.iter().rev().fold(0_i64, |value, &byte| (value << 8) | byte as i64);
+        let unused = 64 - 8 * len as u32;
+        Ok(if unused == 0 { value } else { (value << unused) >> unused })
+    }
+
+    fn u32(&mut self) -> Result<u32> {
+        Ok(u32::from_le_bytes(self.take(4)?.try_into()?))
+    }
+}
+
+/// elements of a ziplist, integers as their decimal text
+pub(crate) fn ziplist_entries(bytes: &[u8]) -> Result<Vec<Vec<u8>>> {
+    let mut blob = Blob::new(bytes);
+    // total bytes and offset of the last entry
+    blob.take(8)?;
+    let len = blob.int(2)? as u16;
+    let mut entries = Vec::with_capacity(len as usize);
+    while blob.peek()? != 0xFF {
+        // length of the previous entry
+        if blob.byte()? == 0xFE {
+            blob.take(4)?;
+        }
+        let encoding = blob.byte()?;
+        let entry = match encoding >> 6 {
+            0b00 => blob.take((encoding & 0x3f) as usize)?.to_vec(),
+            0b01 => {
+                let len = ((encoding as usize & 0x3f) << 8) | blob.byte()? as usize;
+                blob.take(len)?.to_vec()
+            }
+            0b10 => {
+                let len = u32::from_be_bytes(blob.take(4)?.try_into()?);
+                blob.take(len as usize)?.to_vec()
+            }
+            _ => {
+                let value = match encoding {
+                    0xC0 => blob.int(2)?,
+                    0xD0 => blob.int(4)?,
+                    0xE0 => blob.int(8)?,
+                    0xF0 => blob.int(3)?,
+                    0xFE => blob.int(1)?,
+                    0xF1..=0xFD => (encoding & 0x0f) as i64 - 1,
+                    _ => bail!("invalid ziplist entry encoding 0x{:02X}", encoding),
+                };
+                value.to_string().into_bytes()
+            }
+        };
+        entries.push(entry);
+    }
+    Ok(entries)
+}
+
+/// elements of a listpack, integers as their decimal text
+pub(crate) fn listpack_entries(bytes: &[u8]) -> Result<Vec<Vec<u8>>> {
+    let mut blob = Blob::new(bytes);
+    // total bytes and number of elements
+    blob.take(6)?;
+    let mut entries = vec![];
+    loop {
+        let start = blob.position;
+        let encoding = blob.byte()?;
+        let entry = match encoding {
+            0xFF => return Ok(entries),
+            0x00..=0x7F => encoding.to_string().into_bytes(),
+            0x80..=0xBF => blob.take((encoding & 0x3f) as usize)?.to_vec(),
+            0xC0..=0xDF => {
+                let value = ((encoding as i64 & 0x1f) << 8) | blob.byte()? as i64;
+                let value = if value >= 1 << 12 { value - (1 << 13) } else { value };
+                value.to_string().into_bytes()
+            }
+            0xE0..=0xEF => {
+                let len = ((encoding as usize & 0x0f) << 8) | blob.byte()? as usize;
+                blob.take(len)?.to_vec()
+            }
+            0xF0 => {
+                let len = blob.u32()?;
+                blob.take(len as usize)?.to_vec()
+            }
+            0xF1 => blob.int(2)?.to_string().into_bytes(),
+            0xF2 => blob.int(3)?.to_string().into_bytes(),
+            0xF3 => blob.int(4)?.to_string().into_bytes(),
+            0xF4 => blob.int(8)?.to_string().into_bytes(),
+            _ => bail!("invalid listpack entry encoding 0x{:02X}", encoding),
+        };
+        // the entry ends with its length, for walking the listpack backwards
+        blob.take(backlen(blob.position - start).len())?;
+        entries.push(entry);
+    }
+}
+
+/// listpack of the elements, integers in their smallest encoding as redis encodes them
+pub(crate) fn listpack(elements: &[&[u8]]) -> Vec<u8> {
+    let mut body = vec![];
+    for element in elements {
+        let start = body.len();
+        match std::str::from_utf8(element).map(str::parse::<i64>) {
+            // only integers which format back to the same text are stored as integers
+            Ok(Ok(value)) if value.to_string().as_bytes() == *element => match value {
+                0..=127 => body.push(value as u8),
+                -4096..=4095 => body.extend_from_slice(&[0xC0 | ((value >> 8) as u8 & 0x1f), value as u8]),
+                -32768..=32767 => {
+                    body.push(0xF1);
+                    body.extend_from_slice(&(value as i16).to_le_bytes());
+                }
+                -8388608..=8388607 => {
+                    body.push(0xF2);
+                    body.extend_from_slice(&(value as i32).to_le_bytes()[..3]);
+                }
+                -2147483648..=2147483647 => {
+                    body.push(0xF3);
+                    body.extend_from_slice(&(value as i32).to_le_bytes());
+                }
+                _ => {
+                    body.push(0xF4);
+                    body.extend_from_slice(&value.to_le_bytes());
+                }
+            },
+            _ => {
+                match element.len() {
+                    len @ 0..=63 => body.push(0x80 | len as u8),
+                    len @ 64..=4095 => body.extend_from_slice(&[0xE0 | (len >> 8) as u8, len as u8]),
+                    len => {
+                        body.push(0xF0);
+                        body.extend_from_slice(&(len as u32).to_le_bytes());
+                    }
+                }
+                body.extend_from_slice(element);
+            }
         }
-        LengthEncoding::Byte(value) => Ok(value.to_string()),
-        LengthEncoding::Int(value) => Ok(value.to_string()),
-        LengthEncoding::Short(value) => Ok(value.to_string()),
+        let entry_len = body.len() - start;
+        body.extend(backlen(entry_len));
+    }
+    // total bytes, number of elements unless it is too big to tell, the entries and the end marker
+    let mut listpack = Vec::with_capacity(body.len() + 7);
+    listpack.extend_from_slice(&((body.len() + 7) as u32).to_le_bytes());
+    listpack.extend_from_slice(&(elements.len().min(u16::MAX as usize) as u16).to_le_bytes());
+    listpack.extend(body);
+    listpack.push(0xFF);
+    listpack
+}
+
+/// length of a listpack entry which ends it, 7 bits per byte with the most significant byte first
+fn backlen(len: usize) -> Vec<u8> {
+    let size = match len {
+        0..=127 => 1,
+        128..=16382 => 2,
+        16383..=2097150 => 3,
+        2097151..=268435454 => 4,
+        _ => 5,
+    };
+    (0..size).map(|i| {
+        let byte = (len >> (7 * (size - 1 - i))) as u8 & 0x7f;
+        if i == 0 { byte } else { byte | 0x80 }
+    }).collect()
+}
+
+/// members of an intset, a sorted array of integers of the same size
+pub(crate) fn intset_members(bytes: &[u8]) -> Result<Vec<i64>> {
+    let mut blob = Blob::new(bytes);
+    let size = blob.u32()? as usize;
+    if !matches!(size, 2 | 4 | 8) {
+        bail!("invalid intset encoding {}", size);
     }
+    let len = blob.u32()?;
+    (0..len).map(|_| blob.int(size)).collect()
 }
 
-pub(crate) fn read_byte(reader: &mut BufReader<File>) -> Result<u8> {
+/// fields and values of a zipmap
+pub(crate) fn zipmap_entries(bytes: &[u8]) -> Result<Vec<(Vec<u8>, Vec<u8>)>> {
+    let mut blob = Blob::new(bytes);
+    // number of entries, unknown when over 253
+    blob.byte()?;
+    let mut entries = vec![];
+    let read_len = |blob: &mut Blob| -> Result<Option<usize>> {
+        Ok(match blob.byte()? {
+            0xFF => None,
+            0xFE => Some(blob.u32()? as usize),
+            len => Some(len as usize),
+        })
+    };
+    while let Some(field_len) = read_len(&mut blob)? {
+        let field = blob.take(field_len)?.to_vec();
+        let value_len = read_len(&mut blob)?.ok_or_else(|| format_err!("zipmap field without value"))?;
+        // unused bytes after the value
+        let free = blob.byte()? as usize;
+        let value = blob.take(value_len)?.to_vec();
+        blob.take(free)?;
+        entries.push((field, value));
+    }
+    Ok(entries)
+}
+
+pub(crate) fn read_byte(reader: &mut impl Read) -> Result<u8> {
     let mut buf = [0; 1];
     reader.read_exact(&mut buf)?;
     Ok(buf[0])
 }
 
-pub(crate) fn read_u32(reader: &mut BufReader<File>) -> Result<u32> {
+/// bytes of the length stored without a length, like the ids of pending stream entries
+pub(crate) fn read_raw(reader: &mut impl Read, len: usize) -> Result<Vec<u8>> {
+    let mut buf = vec![0; len];
+    reader.read_exact(&mut buf)?;
+    Ok(buf)
+}
+
+pub(crate) fn read_u32(reader: &mut impl Read) -> Result<u32> {
     let mut buf = [0; 4];
     reader.read_exact(&mut buf)?;
     Ok(u32::from_le_bytes(buf))
 }
 
-pub(crate) fn read_u64(reader: &mut BufReader<File>) -> Result<u64> {
+pub(crate) fn read_u64(reader: &mut impl Read) -> Result<u64> {
     let mut buf = [0; 8];
     reader.read_exact(&mut buf)?;
     Ok(u64::from_le_bytes(buf))
 }
 
-const RDB_EMPTY_HEX: &str = "524544495330303131fa0972656469732d76657205372e322e30fa0a72656469732d62697473c040fa056374696d65c26d08bc65fa08757365642d6d656dc2b0c41000fa08616f662d62617365c000fff06e3bfec0ff5aa2";
+pub(crate) fn write_length(writer: &mut impl Write, len: usize) -> Result<()> {
+    write_uint(writer, len as u64)
+}
+
+/// unsigned integer stored as a length, like the parts of the ids of stream entries
+pub(crate) fn write_uint(writer: &mut impl Write, value: u64) -> Result<()> {
+    // lengths are stored in big endian, with the two most significant bits of the first byte describing the size
+    if value < 1 << 6 {
+        writer.write_all(&[value as u8])?;
+    } else if value < 1 << 14 {
+        writer.write_all(&[0b01000000 | (value >> 8) as u8, value as u8])?;
+    } else if value <= u32::MAX as u64 {
+        writer.write_all(&[0x80])?;
+        writer.write_all(&(value as u32).to_be_bytes())?;
+    } else {
+        writer.write_all(&[0x81])?;
+        writer.write_all(&value.to_be_bytes())?;
+    }
+    Ok(())
+}
+
+pub(crate) fn write_string(writer: &mut impl Write, value: &str) -> Result<()> {
+    write_bytes(writer, value.as_bytes())
+}
+
+pub(crate) fn write_bytes(writer: &mut impl Write, value: &[u8]) -> Result<()> {
+    write_length(writer, value.len())?;
+    writer.write_all(value)?;
+    Ok(())
+}
+
+pub(crate) fn write_byte(writer: &mut impl Write, byte: u8) -> Result<()> {
+    writer.write_all(&[byte])?;
+    Ok(())
+}
+
+pub(crate) fn write_u64(writer: &mut impl Write, value: u64) -> Result<()> {
+    writer.write_all(&value.to_le_bytes())?;
+    Ok(())
+}
+
+/// double in binary little endian, as the scores of sorted sets
+pub(crate) fn write_f64(writer: &mut impl Write, value: f64) -> Result<()> {
+    writer.write_all(&value.to_le_bytes())?;
+    Ok(())
+}
+
+/// reflected polynomial of the CRC-64/Jones variant redis checksums RDB files with
+const CRC64_POLY: u64 = 0x95ac9329ac4bc9b5;
+
+const CRC64_TABLE: [u64; 256] = crc64_table();
+
+const fn crc64_table() -> [u64; 256] {
+    let mut table = [0; 256];
+    let mut i = 0;
+    while i < 256 {
+        let mut crc = i as u64;
+        let mut bit = 0;
+        while bit < 8 {
+            crc = if crc & 1 != 0 { (crc >> 1) ^ CRC64_POLY } else { crc >> 1 };
+            bit += 1;
+        }
+        table[i] = crc;
+        i += 1;
+    }
+    table
+}
+
+/// continues the CRC64 checksum with the bytes
+pub(crate) fn crc64(crc: u64, bytes: &[u8]) -> u64 {
+    bytes.iter().fold(crc, |crc, &byte| CRC64_TABLE[((crc ^ byte as u64) & 0xff) as usize] ^ (crc >> 8))
+}
+
+/// writer computing the CRC64 checksum of the bytes written through it, which ends an RDB file
+pub(crate) struct ChecksumWriter<W: Write> {
+    inner: W,
+    crc: u64,
+}
+
+impl<W: Write> ChecksumWriter<W> {
+    pub(crate) fn new(inner: W) -> Self {
+        ChecksumWriter { inner, crc: 0 }
+    }
+
+    /// writes the checksum of the bytes written so far, or zero when checksums are disabled,
+    /// after which the file is complete
+    pub(crate) fn write_checksum(mut self, enabled: bool) -> Result<()> {
+        let crc = if enabled { self.crc } else { 0 };
+        self.inner.write_all(&crc.to_le_bytes())?;
+        self.inner.flush()?;
+        Ok(())
+    }
+}
+
+impl<W: Write> Write for ChecksumWriter<W> {
+    fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+        let written = self.inner.write(buf)?;
+        self.crc = crc64(self.crc, &buf[..written]);
+        Ok(written)
+    }
+
+    fn flush(&mut self) -> std::io::Result<()> {
+        self.inner.flush()
+    }
+}
+
+/// reader computing the CRC64 checksum of the bytes read through it, to compare with the one ending an RDB file
+pub(crate) struct ChecksumReader<R: Read> {
+    inner: R,
+    crc: u64,
+    offset: u64,
+}
+
+impl<R: Read> ChecksumReader<R> {
+    pub(crate) fn new(inner: R) -> Self {
+        ChecksumReader { inner, crc: 0, offset: 0 }
+    }
+
+    /// checksum of the bytes read so far
+    pub(crate) fn checksum(&self) -> u64 {
+        self.crc
+    }
+
+    /// number of bytes read so far
+    pub(crate) fn offset(&self) -> u64 {
+        self.offset
+    }
+}
 
-fn hex_to_bytes(hex: &str) -> Vec<u8> {
-    // hex string is of 2 chars per byte
-    assert_eq!(hex.len() % 2, 0);
-    let mut result = vec![];
-    let mut iter = hex.chars();
-    while let Some(c1) = iter.next() {
-        let c2 = iter.next().unwrap();
-        let byte = u8::from_str_radix(&format!("{}{}", c1, c2), 16).unwrap();
-        result.push(byte);
+impl<R: Read> Read for ChecksumReader<R> {
+    fn read(&mut self, buf: &mut [u8]) -> std::io::Result<usize> {
+        let read = self.inner.read(buf)?;
+        self.crc = crc64(self.crc, &buf[..read]);
+        self.offset += read as u64;
+        Ok(read)
     }
-    result
 }
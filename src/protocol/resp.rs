@@ -1,27 +1,62 @@
 use std::fmt::Display;
-use std::io::{BufRead, BufReader, BufWriter, Read, Write};
+use std::io::{self, BufRead, BufReader, BufWriter, Read, Write};
 use std::net::TcpStream;
+use std::sync::{Arc, Mutex};
 use std::time::Duration;
 
 use anyhow::bail;
 use anyhow::Result;
 
 use crate::io::writer::CountingWriter;
+use crate::log::debug;
 
+#[allow(clippy::upper_case_acronyms)]
 #[derive(Debug, Clone, PartialEq)]
 pub enum RESP {
     String(String),
     Error(String),
     Int(i64),
-    Bulk(String),
+    /// bytes of a string, which are not always text
+    Bulk(Vec<u8>),
     Array(Vec<RESP>),
     Null,
     File(Vec<u8>),
+    // types of RESP3, sent to RESP2 clients as their closest RESP2 type
+    Map(Vec<(RESP, RESP)>),
+    Set(Vec<RESP>),
+    Double(f64),
+    Boolean(bool),
+    BigNumber(String),
+    /// text with its format, like txt or mkd
+    Verbatim(String, String),
+    /// out of band message, like a published message
+    Push(Vec<RESP>),
+}
+
+/// version of the protocol the replies of a connection are encoded with.
+/// replication and persistence always use RESP2
+#[derive(Debug, Clone, Copy, PartialEq, Default)]
+pub enum Protocol {
+    #[default]
+    Resp2,
+    Resp3,
 }
 
 impl RESP {
     pub fn bulk(string: &str) -> Self {
-        RESP::Bulk(string.to_string())
+        RESP::Bulk(string.as_bytes().to_vec())
+    }
+
+    /// number of bytes of the message on the wire
+    pub fn encoded_len(&self) -> Result<usize> {
+        let mut writer = CountingWriter::new(io::sink());
+        encode_message(&mut writer, self, Protocol::Resp2)?;
+        Ok(writer.bytes_written())
+    }
+
+    /// writes the message as sent on the wire
+    pub fn write_to(&self, writer: &mut impl Write) -> Result<()> {
+        encode_message(&mut CountingWriter::new(writer), self, Protocol::Resp2)
     }
 }
 
@@ -31,24 +66,125 @@ impl Display for RESP {
             RESP::String(s) => write!(f, "{}", s),
             RESP::Error(s) => write!(f, "!{}", s),
             RESP::Int(i) => write!(f, "{}", i),
-            RESP::Bulk(s) => write!(f, "{}", s),
-            RESP::Array(array) => {
+            // bytes which are not text are replaced, for logging
+            RESP::Bulk(s) => write!(f, "{}", String::from_utf8_lossy(s)),
+            RESP::Array(array) | RESP::Set(array) | RESP::Push(array) => {
                 for item in array {
                     write!(f, "{} ", item)?;
                 }
                 Ok(())
             }
+            RESP::Map(entries) => {
+                for (key, value) in entries {
+                    write!(f, "{} {} ", key, value)?;
+                }
+                Ok(())
+            }
             RESP::Null => write!(f, "null"),
             RESP::File(file) => write!(f, "File{}", file.len()),
+            RESP::Double(d) => write!(f, "{}", format_double(*d)),
+            RESP::Boolean(b) => write!(f, "{}", b),
+            RESP::BigNumber(n) => write!(f, "{}", n),
+            RESP::Verbatim(_, text) => write!(f, "{}", text),
         }
     }
 }
 
 
+/// bulk strings up to this length are allocated at once, longer ones as their bytes arrive
+const MAX_PREALLOCATED_BULK_LEN: usize = 64 * 1024;
+
+/// malformed or oversized input, the connection can't be used further after it
+#[derive(Debug, thiserror::Error)]
+pub enum ProtocolError {
+    #[error("Protocol error: invalid bulk length")]
+    InvalidBulkLength,
+    #[error("Protocol error: invalid multibulk length")]
+    InvalidMultibulkLength,
+    #[error("Protocol error: invalid number")]
+    InvalidNumber,
+    #[error("Protocol error: bulk string not terminated by CRLF")]
+    UnterminatedBulk,
+    #[error("Protocol error: arrays nested deeper than {0}")]
+    NestingDepth(usize),
+    #[error("Protocol error: unexpected line '{0}'")]
+    UnexpectedLine(String),
+    #[error("Protocol error: query buffer limit of {0} bytes exceeded")]
+    QueryBufferLimit(u64),
+    #[error("Protocol error: unbalanced quotes in request")]
+    UnbalancedQuotes,
+}
+
+/// limits applied to the messages read from a connection
+#[derive(Debug, Clone, Copy)]
+pub struct RequestLimits {
+    /// maximal size of a single bulk string
+    pub max_bulk_len: u64,
+    /// maximal size of a whole message
+    pub max_request_len: u64,
+    /// maximal number of elements of an array
+    pub max_multibulk_len: u64,
+    /// maximal number of arrays nested in each other
+    pub max_depth: usize,
+}
+
+impl Default for RequestLimits {
+    fn default() -> Self {
+        RequestLimits {
+            max_bulk_len: u64::MAX,
+            max_request_len: u64::MAX,
+            max_multibulk_len: u64::MAX,
+            // the messages of the master and of the cluster bus nest a few arrays at most
+            max_depth: 32,
+        }
+    }
+}
+
+/// writing side of a connection, shared with the threads pushing messages to the client, e.g. published messages.
+/// messages are written whole, so they never interleave with the responses of the connection
+#[derive(Clone)]
+pub struct MessageWriter(Arc<Mutex<(BufWriter<TcpStream>, Protocol)>>);
+
+impl MessageWriter {
+    pub fn send_messages(&self, messages: &[&RESP]) -> Result<usize> {
+        let len = self.write_messages(messages)?;
+        self.flush()?;
+        Ok(len)
+    }
+
+    /// writes the messages into the buffer of the connection, which sends them when it is full or flushed
+    pub fn write_messages(&self, messages: &[&RESP]) -> Result<usize> {
+        let mut output = self.0.lock().unwrap();
+        let (buf_writer, protocol) = &mut *output;
+        let mut len = 0_usize;
+        for response in messages {
+            len += write_message(buf_writer, response, *protocol)?;
+        }
+        Ok(len)
+    }
+
+    pub fn flush(&self) -> Result<()> {
+        Ok(self.0.lock().unwrap().0.flush()?)
+    }
+
+    /// sends bytes which are encoded messages already, like the replication stream kept in the backlog
+    pub fn send_raw(&self, bytes: &[u8]) -> Result<()> {
+        let mut output = self.0.lock().unwrap();
+        output.0.write_all(bytes)?;
+        Ok(output.0.flush()?)
+    }
+
+    /// protocol of the following messages, also of those pushed by other threads
+    pub fn set_protocol(&self, protocol: Protocol) {
+        self.0.lock().unwrap().1 = protocol;
+    }
+}
+
 pub struct RESPConnection {
     stream: TcpStream,
     buf_reader: BufReader<TcpStream>,
-    buf_writer: BufWriter<TcpStream>,
+    writer: MessageWriter,
+    limits: RequestLimits,
 }
 
 impl RESPConnection {
@@ -56,16 +192,23 @@ impl RESPConnection {
         Self {
             stream: stream.try_clone().unwrap(),
             buf_reader: BufReader::new(stream.try_clone().unwrap()),
-            buf_writer: BufWriter::new(stream),
+            writer: MessageWriter(Arc::new(Mutex::new((BufWriter::new(stream), Protocol::default())))),
+            limits: RequestLimits::default(),
         }
     }
 
+    pub fn set_limits(&mut self, limits: RequestLimits) {
+        self.limits = limits;
+    }
+
+    /// protocol of the replies and of the messages pushed to the client
+    pub fn set_protocol(&self, protocol: Protocol) {
+        self.writer.set_protocol(protocol);
+    }
+
     pub fn set_read_timeout(&self, timeout: Option<Duration>) -> Result<()> {
         Ok(self.stream.set_read_timeout(timeout)?)
     }
-    pub fn read_timeout(&self) -> Result<Option<Duration>> {
-        Ok(self.stream.read_timeout()?)
-    }
 
     pub fn _send_command(&mut self, command_line: &str) -> Result<usize> {
         let command_message = RESP::Array(command_line.split(" ").map(RESP::bulk).collect::<Vec<RESP>>());
@@ -77,17 +220,45 @@ impl RESPConnection {
     }
 
     pub fn send_messages(&mut self, messages: &[&RESP]) -> Result<usize> {
-        let mut len = 0_usize;
-        for response in messages {
-            len += write_message(&mut self.buf_writer, response)?;
-            self.buf_writer.flush()?;
-        }
-        Ok(len)
+        self.writer.send_messages(messages)
+    }
+
+    pub fn send_raw(&mut self, bytes: &[u8]) -> Result<()> {
+        self.writer.send_raw(bytes)
+    }
+
+    /// writes a reply without sending it yet, so that the replies of pipelined requests are sent together
+    pub fn write_message(&mut self, message: &RESP) -> Result<usize> {
+        self.write_messages(&[message])
+    }
+
+    pub fn write_messages(&mut self, messages: &[&RESP]) -> Result<usize> {
+        self.writer.write_messages(messages)
+    }
+
+    /// sends the written replies
+    pub fn flush(&mut self) -> Result<()> {
+        self.writer.flush()
+    }
+
+    /// a pipelined request was already received, which is read without waiting
+    pub fn has_buffered_input(&self) -> bool {
+        !self.buf_reader.buffer().is_empty()
+    }
+
+    /// connection reading from the same stream on another thread, the input buffered by this one is not shared
+    pub fn try_clone(&self) -> Result<RESPConnection> {
+        Ok(RESPConnection::new(self.stream.try_clone()?))
+    }
+
+    /// writer which can send messages to the client from other threads
+    pub fn writer(&self) -> MessageWriter {
+        self.writer.clone()
     }
 
 
     pub fn read_message(&mut self) -> Result<(usize, Option<RESP>)> {
-        decode_message(&mut self.buf_reader)
+        decode_message(&mut self.buf_reader, &self.limits, 0, 0)
     }
 
     // expects the following format:
@@ -104,7 +275,7 @@ impl RESPConnection {
                 if line.is_empty() {
                     bail!("empty line");
                 } else {
-                    let type_char = line.chars().nth(0);
+                    let type_char = line.chars().next();
                     match type_char {
                         Some('$') => {
                             let len: usize = line[1..].parse().unwrap();
@@ -131,16 +302,15 @@ impl RESPConnection {
 }
 
 
-fn write_message(writer: &mut BufWriter<TcpStream>, message: &RESP) -> Result<usize> {
+fn write_message(writer: &mut BufWriter<TcpStream>, message: &RESP, protocol: Protocol) -> Result<usize> {
     let mut writer = CountingWriter::new(writer);
-    encode_message(&mut writer, message)?;
+    encode_message(&mut writer, message, protocol)?;
     let bytes = writer.bytes_written();
-    println!("written {} bytes", bytes);
-    writer.flush()?;
+    debug!("written {} bytes", bytes);
     Ok(bytes)
 }
 
-fn encode_message(writer: &mut CountingWriter<&mut BufWriter<TcpStream>>, message: &RESP) -> Result<()> {
+fn encode_message<W: Write>(writer: &mut CountingWriter<W>, message: &RESP, protocol: Protocol) -> Result<()> {
     match message {
         RESP::String(s) => {
             write!(writer, "+{}\r\n", s)?;
@@ -152,7 +322,12 @@ fn encode_message(writer: &mut CountingWriter<&mut BufWriter<TcpStream>>, messag
             write!(writer, ":{}\r\n", n)?;
         }
         RESP::Bulk(s) => {
-            write!(writer, "${}\r\n{}\r\n", s.len(), s)?;
+            write!(writer, "${}\r\n", s.len())?;
+            writer.write_all(s)?;
+            write!(writer, "\r\n")?;
+        }
+        RESP::Null if protocol == Protocol::Resp3 => {
+            write!(writer, "_\r\n")?;
         }
         RESP::Null => {
             write!(writer, "$-1\r\n")?;
@@ -160,82 +335,322 @@ fn encode_message(writer: &mut CountingWriter<&mut BufWriter<TcpStream>>, messag
         RESP::Array(array) => {
             // println!("write array of {} items", array.len());
             write!(writer, "*{}\r\n", array.len())?;
-            if array.len() > 0 {
+            if !array.is_empty() {
                 for item in array {
-                    encode_message(writer, item)?;
+                    encode_message(writer, item, protocol)?;
                 }
             }
         }
         RESP::File(array) => {
             // println!("write {} binary: {:?}", array.len(), array);
             write!(writer, "${}\r\n", array.len())?;
-            writer.flush()?;
             writer.write_all(array)?;
         }
+        RESP::Map(entries) => {
+            // RESP2 has the keys and values one after another in an array
+            match protocol {
+                Protocol::Resp2 => write!(writer, "*{}\r\n", entries.len() * 2)?,
+                Protocol::Resp3 => write!(writer, "%{}\r\n", entries.len())?,
+            }
+            for (key, value) in entries {
+                encode_message(writer, key, protocol)?;
+                encode_message(writer, value, protocol)?;
+            }
+        }
+        RESP::Set(items) | RESP::Push(items) => {
+            let type_char = match (protocol, message) {
+                (Protocol::Resp2, _) => '*',
+                (Protocol::Resp3, RESP::Set(_)) => '~',
+                (Protocol::Resp3, _) => '>',
+            };
+            write!(writer, "{}{}\r\n", type_char, items.len())?;
+            for item in items {
+                encode_message(writer, item, protocol)?;
+            }
+        }
+        RESP::Double(d) => match protocol {
+            Protocol::Resp2 => encode_message(writer, &RESP::bulk(&format_double(*d)), protocol)?,
+            Protocol::Resp3 => write!(writer, ",{}\r\n", format_double(*d))?,
+        },
+        RESP::Boolean(b) => match protocol {
+            Protocol::Resp2 => write!(writer, ":{}\r\n", *b as i64)?,
+            Protocol::Resp3 => write!(writer, "#{}\r\n", if *b { 't' } else { 'f' })?,
+        },
+        RESP::BigNumber(n) => match protocol {
+            Protocol::Resp2 => encode_message(writer, &RESP::bulk(n), protocol)?,
+            Protocol::Resp3 => write!(writer, "({}\r\n", n)?,
+        },
+        RESP::Verbatim(format, text) => match protocol {
+            Protocol::Resp2 => encode_message(writer, &RESP::bulk(text), protocol)?,
+            // the format takes 3 characters and a colon
+            Protocol::Resp3 => write!(writer, "={}\r\n{}:{}\r\n", text.len() + 4, format, text)?,
+        },
     }
-    writer.flush()?;
     Ok(())
 }
 
-fn decode_message(reader: &mut BufReader<TcpStream>) -> Result<(usize, Option<RESP>)> {
+/// double as written on the wire, like 1.5, inf, -inf or nan
+fn format_double(d: f64) -> String {
+    if d.is_nan() { "nan".to_string() } else { d.to_string() }
+}
+
+/// decodes the next message of a stream read from a file, like an append only file, with the bytes it took.
+/// input ending within a message fails like a connection closed by the peer
+pub(crate) fn read_message(reader: &mut impl BufRead, limits: &RequestLimits) -> Result<(usize, Option<RESP>)> {
+    decode_message(reader, limits, 0, 0)
+}
+
+/// decodes a message, `request_len` bytes of the enclosing message are already read and it is nested
+/// `depth` arrays deep. malformed input fails with a [ProtocolError], input ending within a message
+/// fails as a closed connection
+fn decode_message(reader: &mut impl BufRead, limits: &RequestLimits, request_len: usize, depth: usize) -> Result<(usize, Option<RESP>)> {
+    // empty lines between inline commands are skipped, counting into the length of the message after them
+    let mut skipped_len = 0;
+    loop {
+        let (len, message) = decode_line(reader, limits, request_len, depth)?;
+        if message.is_some() {
+            return Ok((skipped_len + len, message));
+        }
+        skipped_len += len;
+    }
+}
+
+/// decodes the message starting with the next line, none for an empty line between top level messages
+fn decode_line(reader: &mut impl BufRead, limits: &RequestLimits, request_len: usize, depth: usize) -> Result<(usize, Option<RESP>)> {
     let buf = &mut String::new();
-    match reader.read_line(buf) {
+    // the line itself must fit into the limit
+    let remaining = limits.max_request_len.saturating_sub(request_len as u64);
+    match reader.by_ref().take(remaining.saturating_add(1)).read_line(buf) {
         Ok(0) => {
             bail!("connection closed by peer");
         }
+        Ok(len) if len as u64 > remaining => {
+            bail!(ProtocolError::QueryBufferLimit(limits.max_request_len));
+        }
+        Ok(len) if !buf.ends_with('\n') => {
+            bail!("connection closed by peer after {} bytes of a line", len);
+        }
         Ok(len) => {
             let mut full_len = len;
             let line = buf.trim();
             // println!("read line: {}", line);
-            if line.is_empty() {
-                bail!("empty line");
+            if line.is_empty() && depth == 0 {
+                Ok((len, None))
+            } else if line.is_empty() {
+                bail!(ProtocolError::UnexpectedLine(String::new()));
             } else {
-                let type_char = line.chars().nth(0);
+                let type_char = line.chars().next();
                 let response = match type_char {
                     Some('+') => Ok(Some(RESP::String(line[1..].to_string()))),
                     Some('-') => Ok(Some(RESP::Error(line[1..].to_string()))),
-                    Some(':') => Ok(Some(RESP::Int(line[1..].parse().unwrap()))),
-                    Some('$') => {
-                        let len: i64 = line[1..].parse().unwrap();
-                        if len < 0 {
-                            Ok(Some(RESP::Null))
-                        } else {
-                            let mut buf: Vec<u8> = vec![0; len as usize + 2]; // read also the 2 bytes /r/n after the string which are used as delimiters
-                            if reader.read_exact(&mut buf).is_ok() {
-                                assert_eq!(buf[buf.len() - 2], b'\r');
-                                assert_eq!(buf[buf.len() - 1], b'\n');
-                                full_len += buf.capacity();
-                                buf.truncate(len as usize); // drop the 2 bytes at the end since they are only delimiters
-                                let bulk_string = String::from_utf8(buf)?;
-                                Ok(Some(RESP::bulk(&bulk_string)))
-                            } else {
-                                Ok(None)
-                            }
+                    Some(':') => match line[1..].parse() {
+                        Ok(n) => Ok(Some(RESP::Int(n))),
+                        Err(_) => bail!(ProtocolError::InvalidNumber),
+                    },
+                    Some('_') => Ok(Some(RESP::Null)),
+                    Some(',') => match line[1..].parse() {
+                        Ok(d) => Ok(Some(RESP::Double(d))),
+                        Err(_) => bail!(ProtocolError::InvalidNumber),
+                    },
+                    Some('#') => Ok(Some(RESP::Boolean(&line[1..] == "t"))),
+                    Some('(') => Ok(Some(RESP::BigNumber(line[1..].to_string()))),
+                    Some('$' | '!' | '=') => {
+                        let len = match line[1..].parse::<i64>() {
+                            Ok(len) if len < 0 => return Ok((full_len, Some(RESP::Null))),
+                            Ok(len) if len as u64 <= limits.max_bulk_len => len as usize,
+                            _ => bail!(ProtocolError::InvalidBulkLength),
+                        };
+                        if (request_len + full_len).saturating_add(len + 2) as u64 > limits.max_request_len {
+                            bail!(ProtocolError::QueryBufferLimit(limits.max_request_len));
                         }
-                    }
-                    Some('*') => {
-                        let len: u64 = line[1..].parse().unwrap();
-                        if len == 0 {
-                            Ok(Some(RESP::Array(vec![])))
-                        } else {
-                            let mut array = Vec::with_capacity(len as usize);
-                            for _ in 0..len {
-                                let (item_len, item) = decode_message(reader)?;
-                                full_len += item_len;
-                                array.push(item.unwrap());
+                        // read also the 2 bytes \r\n after the string which are used as delimiters.
+                        // the buffer grows as the bytes arrive, the announced length is not trusted for the allocation
+                        let mut buf: Vec<u8> = Vec::with_capacity((len + 2).min(MAX_PREALLOCATED_BULK_LEN));
+                        reader.by_ref().take(len as u64 + 2).read_to_end(&mut buf)?;
+                        if buf.len() < len + 2 {
+                            bail!("connection closed by peer after {} of {} bytes of a bulk string", buf.len(), len);
+                        }
+                        if !buf.ends_with(b"\r\n") {
+                            bail!(ProtocolError::UnterminatedBulk);
+                        }
+                        full_len += buf.len();
+                        buf.truncate(len); // drop the 2 bytes at the end since they are only delimiters
+                        Ok(Some(match type_char {
+                            Some('!') => RESP::Error(String::from_utf8_lossy(&buf).into_owned()),
+                            Some('=') => {
+                                let text = String::from_utf8_lossy(&buf).into_owned();
+                                match text.split_once(':') {
+                                    Some((format, text)) => RESP::Verbatim(format.to_string(), text.to_string()),
+                                    None => RESP::Verbatim("txt".to_string(), text),
+                                }
                             }
-                            Ok(Some(RESP::Array(array)))
+                            _ => RESP::Bulk(buf),
+                        }))
+                    }
+                    Some(type_char @ ('*' | '~' | '>' | '%')) => {
+                        let len = match line[1..].parse::<i64>() {
+                            // null array of RESP2
+                            Ok(len) if len < 0 && type_char == '*' => return Ok((full_len, Some(RESP::Null))),
+                            Ok(len) if len >= 0 && len as u64 <= limits.max_multibulk_len => len as u64,
+                            _ => bail!(ProtocolError::InvalidMultibulkLength),
+                        };
+                        if depth >= limits.max_depth {
+                            bail!(ProtocolError::NestingDepth(limits.max_depth));
+                        }
+                        // maps have a key and a value for each entry
+                        let items_len = if type_char == '%' { len.saturating_mul(2) } else { len };
+                        // the announced length is not trusted for the allocation
+                        let mut items = Vec::with_capacity(items_len.min(1024) as usize);
+                        for _ in 0..items_len {
+                            let (item_len, item) = decode_message(reader, limits, request_len + full_len, depth + 1)?;
+                            full_len += item_len;
+                            items.push(item.expect("item decoded"));
                         }
+                        Ok(Some(match type_char {
+                            '~' => RESP::Set(items),
+                            '>' => RESP::Push(items),
+                            '%' => {
+                                let mut items = items.into_iter();
+                                let mut entries = vec![];
+                                while let (Some(key), Some(value)) = (items.next(), items.next()) {
+                                    entries.push((key, value));
+                                }
+                                RESP::Map(entries)
+                            }
+                            _ => RESP::Array(items),
+                        }))
                     }
+                    // a line which is not a typed message is an inline command, like typed with telnet
+                    _ if depth == 0 => match split_inline_args(line) {
+                        Some(args) => Ok(Some(RESP::Array(args.into_iter().map(RESP::Bulk).collect()))),
+                        None => bail!(ProtocolError::UnbalancedQuotes),
+                    },
                     _ => {
-                        bail!("unknown command {}", line);
+                        bail!(ProtocolError::UnexpectedLine(line.chars().take(32).collect()));
                     }
                 };
                 response.map(|r| (full_len, r))
             }
         }
-        _ => {
-            bail!("read error");
+        Err(err) if err.kind() == io::ErrorKind::InvalidData => {
+            // a line which is not text can't be a type or an inline command
+            bail!(ProtocolError::UnexpectedLine(String::new()));
+        }
+        Err(err) => {
+            bail!("read error: {}", err);
         }
     }
 }
+
+/// splits the line of an inline command into its arguments like redis-cli: separated by spaces, "double quoted" with
+/// escapes like \n or \x41, or 'single quoted' with only \' escaped. \xHH is the byte, which may not be text.
+/// None if a quote is not closed, or is not followed by a space
+fn split_inline_args(line: &str) -> Option<Vec<Vec<u8>>> {
+    let push = |arg: &mut Vec<u8>, c: char| arg.extend_from_slice(c.encode_utf8(&mut [0; 4]).as_bytes());
+    let mut args = vec![];
+    let mut chars = line.chars().peekable();
+    loop {
+        while chars.next_if(|c| c.is_whitespace()).is_some() {}
+        let Some(&first) = chars.peek() else {
+            return Some(args);
+        };
+        let mut arg = vec![];
+        match first {
+            '"' => {
+                chars.next();
+                loop {
+                    match chars.next()? {
+                        '"' => break,
+                        '\\' => match chars.next()? {
+                            'n' => arg.push(b'\n'),
+                            'r' => arg.push(b'\r'),
+                            't' => arg.push(b'\t'),
+                            'b' => arg.push(0x8),
+                            'a' => arg.push(0x7),
+                            'x' => {
+                                let hex = chars.clone().take(2).collect::<String>();
+                                match u8::from_str_radix(&hex, 16) {
+                                    Ok(byte) if hex.len() == 2 && hex.chars().all(|c| c.is_ascii_hexdigit()) => {
+                                        arg.push(byte);
+                                        chars.nth(1);
+                                    }
+                                    _ => arg.push(b'x'),
+                                }
+                            }
+                            c => push(&mut arg, c),
+                        },
+                        c => push(&mut arg, c),
+                    }
+                }
+            }
+            '\'' => {
+                chars.next();
+                loop {
+                    match chars.next()? {
+                        '\'' => break,
+                        '\\' if chars.peek() == Some(&'\'') => push(&mut arg, chars.next()?),
+                        c => push(&mut arg, c),
+                    }
+                }
+            }
+            _ => {
+                while let Some(c) = chars.next_if(|c| !c.is_whitespace()) {
+                    push(&mut arg, c);
+                }
+            }
+        }
+        if chars.peek().is_some_and(|c| !c.is_whitespace()) {
+            return None;
+        }
+        args.push(arg);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn decode(input: &[u8]) -> Result<(usize, Option<RESP>)> {
+        decode_message(&mut &input[..], &RequestLimits::default(), 0, 0)
+    }
+
+    #[test]
+    fn long_run_of_blank_lines_is_skipped_without_recursion() {
+        let mut input = "\r\n".repeat(1_000_000).into_bytes();
+        input.extend_from_slice(b"PING\r\n");
+        let (len, message) = decode(&input).unwrap();
+        assert_eq!(len, input.len());
+        assert_eq!(message, Some(RESP::Array(vec![RESP::bulk("PING")])));
+    }
+
+    #[test]
+    fn blank_lines_before_typed_message_count_into_its_length() {
+        let (len, message) = decode(b"\n\r\n*1\r\n$4\r\nPING\r\n").unwrap();
+        assert_eq!(len, 17);
+        assert_eq!(message, Some(RESP::Array(vec![RESP::bulk("PING")])));
+    }
+
+    #[test]
+    fn blank_line_inside_array_is_refused() {
+        let err = decode(b"*1\r\n\r\n").unwrap_err();
+        assert!(err.is::<ProtocolError>());
+    }
+
+    #[test]
+    fn only_blank_lines_end_as_closed_connection() {
+        let err = decode(&"\r\n".repeat(1000).into_bytes()).unwrap_err();
+        assert!(!err.is::<ProtocolError>());
+    }
+
+    #[test]
+    fn inline_hex_escapes_are_raw_bytes() {
+        let (_, message) = decode(b"SET \"\\xfe\" \"\\xff\\x41\\xzz\"\r\n").unwrap();
+        assert_eq!(message, Some(RESP::Array(vec![RESP::bulk("SET"), RESP::Bulk(vec![0xfe]), RESP::Bulk(b"\xffAxzz".to_vec())])));
+    }
+
+    #[test]
+    fn inline_args_keep_their_text() {
+        assert_eq!(split_inline_args("a 'b c' \"\u{e9}\\n\""), Some(vec![b"a".to_vec(), b"b c".to_vec(), "\u{e9}\n".as_bytes().to_vec()]));
+        assert_eq!(split_inline_args("\"unclosed"), None);
+    }
+}
@@ -0,0 +1,297 @@
+use std::collections::HashMap;
+use std::sync::{Arc, RwLock};
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::mpsc;
+use std::sync::mpsc::Sender;
+use std::thread;
+
+use crate::glob::glob_match;
+use crate::log::warning;
+use crate::protocol::command::{Command, CommandRequest};
+use crate::protocol::resp::{MessageWriter, Protocol, RESP};
+
+static NEXT_SUBSCRIBER_ID: AtomicU64 = AtomicU64::new(1);
+
+/// outgoing queues of the subscribers of a channel by subscriber id
+type Subscribers = HashMap<u64, Sender<RESP>>;
+
+/// kind of subscription, to a channel by its name, to the channels matching a glob pattern
+/// or to a shard channel, which in cluster mode lives on the node serving its slot like a key
+#[derive(Clone, Copy, Debug, PartialEq)]
+enum Kind {
+    Channel,
+    Pattern,
+    Shard,
+}
+
+impl Kind {
+    fn subscribe_reply(self) -> &'static str {
+        match self {
+            Kind::Channel => "subscribe",
+            Kind::Pattern => "psubscribe",
+            Kind::Shard => "ssubscribe",
+        }
+    }
+
+    fn unsubscribe_reply(self) -> &'static str {
+        match self {
+            Kind::Channel => "unsubscribe",
+            Kind::Pattern => "punsubscribe",
+            Kind::Shard => "sunsubscribe",
+        }
+    }
+}
+
+/// subscribers of the channels, of the patterns and of the shard channels
+#[derive(Default)]
+struct Registry {
+    channels: HashMap<Vec<u8>, Subscribers>,
+    patterns: HashMap<Vec<u8>, Subscribers>,
+    shard_channels: HashMap<Vec<u8>, Subscribers>,
+}
+
+impl Registry {
+    fn of_kind(&mut self, kind: Kind) -> &mut HashMap<Vec<u8>, Subscribers> {
+        match kind {
+            Kind::Channel => &mut self.channels,
+            Kind::Pattern => &mut self.patterns,
+            Kind::Shard => &mut self.shard_channels,
+        }
+    }
+}
+
+/// channels, patterns and shard channels with the outgoing queues of the connections subscribed to them.
+///
+/// messages are delivered on the node they are published on: a master delivers the messages of its clients and
+/// replicates them, a replica delivers the messages arriving over the replication stream, in order with the data
+/// commands around them, and the messages published by its own clients, which stay local to the replica.
+#[derive(Clone, Default)]
+pub struct PubSub(Arc<RwLock<Registry>>);
+
+impl PubSub {
+    fn subscribe(&self, kind: Kind, name: &[u8], subscriber_id: u64, outgoing: &Sender<RESP>) {
+        self.0.write().unwrap()
+            .of_kind(kind)
+            .entry(name.to_vec())
+            .or_default()
+            .insert(subscriber_id, outgoing.clone());
+    }
+
+    fn unsubscribe(&self, kind: Kind, name: &[u8], subscriber_id: u64) {
+        let mut registry = self.0.write().unwrap();
+        let names = registry.of_kind(kind);
+        if let Some(subscribers) = names.get_mut(name) {
+            subscribers.remove(&subscriber_id);
+            if subscribers.is_empty() {
+                names.remove(name);
+            }
+        }
+    }
+
+    /// queues the message to the subscribers of the channel and of the patterns matching it, with the glob matching
+    /// of KEYS, returning the number of messages queued: a client subscribed to the channel and to matching patterns
+    /// receives it once for each of them.
+    /// the messages are written by the connections of the subscribers, so a slow subscriber doesn't hold up the publisher
+    pub fn publish(&self, channel: &[u8], message: &[u8]) -> usize {
+        let registry = self.0.read().unwrap();
+        let mut receivers = 0;
+        if let Some(subscribers) = registry.channels.get(channel) {
+            let push = RESP::Push(vec![RESP::bulk("message"), RESP::Bulk(channel.to_vec()), RESP::Bulk(message.to_vec())]);
+            receivers += deliver(subscribers, &push);
+        }
+        for (pattern, subscribers) in registry.patterns.iter().filter(|(pattern, _)| glob_match(pattern, channel)) {
+            let push = RESP::Push(vec![
+                RESP::bulk("pmessage"), RESP::Bulk(pattern.clone()), RESP::Bulk(channel.to_vec()), RESP::Bulk(message.to_vec()),
+            ]);
+            receivers += deliver(subscribers, &push);
+        }
+        receivers
+    }
+
+    /// queues the message to the subscribers of the shard channel, returning the number of subscribers.
+    /// shard channels are not matched by patterns
+    pub fn publish_shard(&self, shard_channel: &[u8], message: &[u8]) -> usize {
+        let registry = self.0.read().unwrap();
+        let Some(subscribers) = registry.shard_channels.get(shard_channel) else {
+            return 0;
+        };
+        let push = RESP::Push(vec![RESP::bulk("smessage"), RESP::Bulk(shard_channel.to_vec()), RESP::Bulk(message.to_vec())]);
+        deliver(subscribers, &push)
+    }
+}
+
+fn deliver(subscribers: &Subscribers, push: &RESP) -> usize {
+    for outgoing in subscribers.values() {
+        // the queue of a closed connection is gone, the connection unsubscribes as it ends
+        let _ = outgoing.send(push.clone());
+    }
+    subscribers.len()
+}
+
+/// channels, patterns and shard channels a client connection is subscribed to, which are unsubscribed when the connection closes
+pub struct Subscriptions {
+    id: u64,
+    pubsub: PubSub,
+    writer: MessageWriter,
+    /// queue of the messages published to the connection while it is subscribed,
+    /// written to the client by a thread of the connection which ends with the subscriptions
+    outgoing: Option<Sender<RESP>>,
+    channels: Vec<Vec<u8>>,
+    patterns: Vec<Vec<u8>>,
+    shard_channels: Vec<Vec<u8>>,
+}
+
+impl Subscriptions {
+    pub fn new(pubsub: PubSub, writer: MessageWriter) -> Self {
+        Self {
+            id: NEXT_SUBSCRIBER_ID.fetch_add(1, Ordering::Relaxed),
+            pubsub,
+            writer,
+            outgoing: None,
+            channels: vec![],
+            patterns: vec![],
+            shard_channels: vec![],
+        }
+    }
+
+    /// queue of the published messages, with the thread writing them started on the first subscription
+    fn outgoing(&mut self) -> Sender<RESP> {
+        if let Some(outgoing) = &self.outgoing {
+            return outgoing.clone();
+        }
+        let (tx, rx) = mpsc::channel::<RESP>();
+        let writer = self.writer.clone();
+        let spawned = thread::Builder::new()
+            .name(format!("subscriber-{}", self.id))
+            .spawn(move || {
+                // ends once all subscriptions end, dropping the queue
+                for message in rx {
+                    if let Err(err) = writer.send_messages(&[&message]) {
+                        warning!("writing published message failed: {}", err);
+                        break;
+                    }
+                }
+            });
+        if let Err(err) = spawned {
+            warning!("starting subscriber writer failed: {}", err);
+        }
+        self.outgoing = Some(tx.clone());
+        tx
+    }
+
+    /// handles the commands of the subscription context, returning None for commands executed by the server.
+    /// while subscribed to a channel, a pattern or a shard channel, a RESP2 client can only manage its subscriptions.
+    /// RESP3 tells the published messages from replies by their push type, so RESP3 clients can run any command
+    pub fn handle_command(&mut self, cmd: &CommandRequest, protocol: Protocol) -> Option<Vec<RESP>> {
+        match cmd.as_ref() {
+            (Command::SUBSCRIBE, channels) if !channels.is_empty() => {
+                // minimal implementation of https://redis.io/docs/latest/commands/subscribe/
+                // SUBSCRIBE channel [channel ...]
+                Some(self.subscribe(Kind::Channel, channels))
+            }
+            (Command::UNSUBSCRIBE, channels) => {
+                // minimal implementation of https://redis.io/docs/latest/commands/unsubscribe/
+                // UNSUBSCRIBE [channel [channel ...]]
+                Some(self.unsubscribe(Kind::Channel, channels))
+            }
+            (Command::PSUBSCRIBE, patterns) if !patterns.is_empty() => {
+                // minimal implementation of https://redis.io/docs/latest/commands/psubscribe/
+                // PSUBSCRIBE pattern [pattern ...]
+                Some(self.subscribe(Kind::Pattern, patterns))
+            }
+            (Command::PUNSUBSCRIBE, patterns) => {
+                // minimal implementation of https://redis.io/docs/latest/commands/punsubscribe/
+                // PUNSUBSCRIBE [pattern [pattern ...]]
+                Some(self.unsubscribe(Kind::Pattern, patterns))
+            }
+            (Command::SSUBSCRIBE, shard_channels) if !shard_channels.is_empty() => {
+                // minimal implementation of https://redis.io/docs/latest/commands/ssubscribe/
+                // SSUBSCRIBE shardchannel [shardchannel ...]
+                Some(self.subscribe(Kind::Shard, shard_channels))
+            }
+            (Command::SUNSUBSCRIBE, shard_channels) => {
+                // minimal implementation of https://redis.io/docs/latest/commands/sunsubscribe/
+                // SUNSUBSCRIBE [shardchannel [shardchannel ...]]
+                Some(self.unsubscribe(Kind::Shard, shard_channels))
+            }
+            _ if !self.is_subscribed() || protocol == Protocol::Resp3 => None,
+            (Command::PING, params) => Some(vec![RESP::Array(vec![
+                RESP::bulk("pong"),
+                RESP::Bulk(params.first().cloned().unwrap_or_default()),
+            ])]),
+            _ => Some(vec![RESP::Error(format!(
+                "ERR Can't execute '{}': only (P|S)SUBSCRIBE / (P|S)UNSUBSCRIBE / PING / QUIT / RESET are allowed in this context",
+                cmd.0.to_string().to_lowercase()
+            ))]),
+        }
+    }
+
+    fn subscribe(&mut self, kind: Kind, names: &[Vec<u8>]) -> Vec<RESP> {
+        let outgoing = self.outgoing();
+        names.iter().map(|name| {
+            if !self.of_kind(kind).contains(name) {
+                self.pubsub.subscribe(kind, name, self.id, &outgoing);
+                self.of_kind(kind).push(name.clone());
+            }
+            self.confirmation(kind, kind.subscribe_reply(), RESP::Bulk(name.clone()))
+        }).collect()
+    }
+
+    /// unsubscribes the channels or patterns, all of their kind if none is given
+    fn unsubscribe(&mut self, kind: Kind, names: &[Vec<u8>]) -> Vec<RESP> {
+        let names = if names.is_empty() { self.of_kind(kind).clone() } else { names.to_vec() };
+        if names.is_empty() {
+            return vec![self.confirmation(kind, kind.unsubscribe_reply(), RESP::Null)];
+        }
+        let confirmations = names.iter().map(|name| {
+            self.pubsub.unsubscribe(kind, name, self.id);
+            self.of_kind(kind).retain(|n| n != name);
+            self.confirmation(kind, kind.unsubscribe_reply(), RESP::Bulk(name.clone()))
+        }).collect();
+        if !self.is_subscribed() {
+            self.outgoing = None;
+        }
+        confirmations
+    }
+
+    fn of_kind(&mut self, kind: Kind) -> &mut Vec<Vec<u8>> {
+        match kind {
+            Kind::Channel => &mut self.channels,
+            Kind::Pattern => &mut self.patterns,
+            Kind::Shard => &mut self.shard_channels,
+        }
+    }
+
+    /// number of subscribed channels, patterns and shard channels
+    pub(crate) fn counts(&self) -> (usize, usize, usize) {
+        (self.channels.len(), self.patterns.len(), self.shard_channels.len())
+    }
+
+    fn is_subscribed(&self) -> bool {
+        !self.channels.is_empty() || !self.patterns.is_empty() || !self.shard_channels.is_empty()
+    }
+
+    /// confirms a change of subscription with the number of subscriptions: shard channels are counted apart
+    /// from the channels and patterns
+    fn confirmation(&self, kind: Kind, reply: &str, name: RESP) -> RESP {
+        let count = match kind {
+            Kind::Channel | Kind::Pattern => self.channels.len() + self.patterns.len(),
+            Kind::Shard => self.shard_channels.len(),
+        };
+        RESP::Push(vec![RESP::bulk(reply), name, RESP::Int(count as i64)])
+    }
+}
+
+impl Drop for Subscriptions {
+    fn drop(&mut self) {
+        for channel in &self.channels {
+            self.pubsub.unsubscribe(Kind::Channel, channel, self.id);
+        }
+        for pattern in &self.patterns {
+            self.pubsub.unsubscribe(Kind::Pattern, pattern, self.id);
+        }
+        for shard_channel in &self.shard_channels {
+            self.pubsub.unsubscribe(Kind::Shard, shard_channel, self.id);
+        }
+    }
+}
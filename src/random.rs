@@ -0,0 +1,34 @@
+/// random number from the v4 uuid generator, which is backed by the os random source
+pub fn random_u64() -> u64 {
+    uuid::Uuid::new_v4().as_u64_pair().0
+}
+
+/// random 40 characters hex id, as the ids of cluster nodes and the replication ids
+pub fn random_id() -> String {
+    let id = format!("{}{}", uuid::Uuid::new_v4().simple(), uuid::Uuid::new_v4().simple());
+    id[..40].to_string()
+}
+
+/// random index into a collection of the given non zero length
+pub fn random_index(len: usize) -> usize {
+    (random_u64() % len as u64) as usize
+}
+
+/// random elements of the items: a positive count picks distinct items, up to all of them,
+/// a negative count picks its absolute value of items, which may repeat, the caller bounds it
+pub fn random_sample<T: Clone>(mut items: Vec<T>, count: i64) -> Vec<T> {
+    if items.is_empty() {
+        return items;
+    }
+    if count < 0 {
+        return (0..count.unsigned_abs()).map(|_| items[random_index(items.len())].clone()).collect();
+    }
+    // partial shuffle, the first count items are a random sample
+    let count = (count as usize).min(items.len());
+    for i in 0..count {
+        let j = i + random_index(items.len() - i);
+        items.swap(i, j);
+    }
+    items.truncate(count);
+    items
+}
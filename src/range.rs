@@ -0,0 +1,61 @@
+use std::ops::Range;
+
+/// positions between the start and end indexes inclusive in a sequence of the given length,
+/// as taken by GETRANGE, SETRANGE, LRANGE, LTRIM and BITCOUNT.
+///
+/// negative indexes count from the end of the sequence, -1 being the last element.
+/// the start is clamped to the beginning and the end to the last element of the sequence.
+/// returns None if the range is empty: the start is after the end or past the sequence.
+pub fn normalize_range(len: usize, start: i64, end: i64) -> Option<Range<usize>> {
+    let len = len as i64;
+    let start = if start < 0 { (len + start).max(0) } else { start };
+    let end = if end < 0 { len + end } else { end.min(len - 1) };
+    (start <= end).then(|| start as usize..end as usize + 1)
+}
+
+/// position of an index in a sequence of the given length, negative indexes count from the end.
+/// returns None if the index is outside of the sequence
+pub fn normalize_index(len: usize, index: i64) -> Option<usize> {
+    let position = if index < 0 { len as i64 + index } else { index };
+    (0..len as i64).contains(&position).then_some(position as usize)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn negative_indexes_count_from_the_end() {
+        assert_eq!(normalize_range(5, 0, -1), Some(0..5));
+        assert_eq!(normalize_range(5, -2, -1), Some(3..5));
+        assert_eq!(normalize_range(5, -5, 1), Some(0..2));
+        assert_eq!(normalize_index(5, -1), Some(4));
+        assert_eq!(normalize_index(5, -5), Some(0));
+    }
+
+    #[test]
+    fn out_of_range_bounds_are_clamped() {
+        assert_eq!(normalize_range(5, -100, 100), Some(0..5));
+        assert_eq!(normalize_range(5, 2, i64::MAX), Some(2..5));
+        assert_eq!(normalize_range(5, i64::MIN, 0), Some(0..1));
+        assert_eq!(normalize_range(5, 5, 10), None);
+        assert_eq!(normalize_range(5, 0, -6), None);
+        assert_eq!(normalize_index(5, 5), None);
+        assert_eq!(normalize_index(5, -6), None);
+    }
+
+    #[test]
+    fn start_after_end_is_empty() {
+        assert_eq!(normalize_range(5, 3, 1), None);
+        assert_eq!(normalize_range(5, -1, -2), None);
+        assert_eq!(normalize_range(5, 3, 3), Some(3..4));
+    }
+
+    #[test]
+    fn empty_sequence_has_no_range() {
+        assert_eq!(normalize_range(0, 0, -1), None);
+        assert_eq!(normalize_range(0, 0, 0), None);
+        assert_eq!(normalize_index(0, 0), None);
+        assert_eq!(normalize_index(0, -1), None);
+    }
+}
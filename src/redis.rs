@@ -1,73 +1,344 @@
-use std::collections::HashMap;
-use std::fs::File;
-use std::io::BufReader;
+use std::collections::{BTreeMap, HashMap, HashSet, VecDeque};
+use std::fs;
+use std::fs::{File, OpenOptions};
+use std::io::{BufReader, BufWriter};
+use std::net::SocketAddr;
 use std::ops::Deref;
 use std::path::Path;
 use std::sync::{Arc, Condvar, Mutex, RwLock};
-use std::time::Duration;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::thread;
+use std::time::{Duration, Instant, SystemTime};
 
-use anyhow::{bail, Result};
+use anyhow::{bail, format_err, Result};
 
+use crate::aof::{self, AppendOnlyFile, FsyncPolicy};
 use crate::args;
-use crate::args::named_option;
-use crate::io::net::Binding;
+use crate::args::{named_option, Arg};
+use crate::auditlog::AuditLog;
+use crate::backlog::ReplicationBacklog;
+use crate::bitmap::{parse_bitfield, BitOp};
+use crate::budget::TimeBudget;
+use crate::clients::Clients;
+use crate::cluster;
+use crate::cluster::Cluster;
+use crate::config;
+use crate::config::Config;
+use crate::cron::Cron;
+use crate::events::{KeyEvent, KeyEventKind, KeyWatch};
+use crate::eviction::EvictionPolicy;
+use crate::io::net::{Binding, Cidr, Port};
+use crate::lazyfree::LazyFree;
+use crate::log;
+use crate::log::{debug, notice, verbose, warning};
+use crate::memory;
+use crate::notify::NotifyFlags;
 use crate::protocol::command::{Command, CommandRequest};
-use crate::protocol::resp::RESP;
-use crate::store::Store;
-use crate::stream::{StreamEvent, StreamRecordId};
+use crate::protocol::command_table::{self, CommandSpec, COMMAND_TABLE};
+use crate::protocol::resp::{RequestLimits, RESP};
+use crate::pubsub::PubSub;
+use crate::random::{random_id, random_sample};
+use crate::scan::ScanCursors;
+use crate::session::REDIS_VERSION;
+use crate::slots::{key_slot, SLOTS};
+use crate::stats::Stats;
+use crate::store::{update_lru_clock, ExpireCondition, FieldValues, ListEnd, SetSnapshot, Store, StoreEntry, StoreLock, WatchRegistration, WRONGTYPE};
+use crate::stream::{Claim, Delivery, StreamRecord, StreamRecordId, Trim};
+use crate::transaction::ExecLock;
+use crate::zset::{format_score, parse_lex_bound, parse_score, parse_score_bound, AddOptions, ZRange};
 
-#[derive(Default)]
-pub struct LogStore {
-    pub(crate) log: Vec<RESP>,
-    pub(crate) log_bytes: usize,
+/// number of keys with an expiration sampled in a round of active expiry
+const ACTIVE_EXPIRE_SAMPLES: usize = 20;
+/// interval between samples of the command rate
+const METRICS_SAMPLE_INTERVAL: Duration = Duration::from_millis(1000);
+/// interval between checks for clients idle for longer than the timeout
+const CLIENTS_TIMEOUT_INTERVAL: Duration = Duration::from_millis(1000);
+/// interval of checking the save rules
+const SAVE_RULES_INTERVAL: Duration = Duration::from_millis(1000);
+/// delay before a failed background save is tried again by the save rules
+const SAVE_RETRY_DELAY: Duration = Duration::from_secs(5);
+/// attempts to store the result of a set operation computed without holding the store, before it is computed under the write lock
+const SET_OPERATION_ATTEMPTS: usize = 3;
+/// sections of INFO in the order they are reported
+const INFO_SECTIONS: &[&str] = &["server", "clients", "memory", "persistence", "stats", "replication", "cluster", "keyspace"];
+
+
+/// background jobs writing a snapshot of the store to disk
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub(crate) enum PersistenceJob {
+    /// BGSAVE writing the RDB file
+    Save,
+    /// BGREWRITEAOF writing the append only file
+    RewriteAof,
+}
+
+/// outcome of requesting a background job
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub(crate) enum JobRequest {
+    Started,
+    /// another job is running, the job starts once it completes
+    Scheduled,
+    /// the same job is already running
+    InProgress,
+}
+
+/// state of saving the store to disk.
+///
+/// a single background job runs at a time, so that saving the RDB and rewriting the AOF never write
+/// snapshots of the store concurrently. a job requested while another one runs is scheduled and started
+/// when the running job completes
+pub struct PersistenceState {
+    running: Option<PersistenceJob>,
+    /// start of the running job
+    started_at: Option<Instant>,
+    scheduled: VecDeque<PersistenceJob>,
+    pub(crate) last_bgsave_ok: bool,
+    pub(crate) last_aof_rewrite_ok: bool,
+    /// seconds the last job of each kind ran, None before it completed once
+    last_bgsave_secs: Option<u64>,
+    last_aof_rewrite_secs: Option<u64>,
+    /// number of writes to the store included in the last saved RDB file, see [Store::write_count]
+    saved_writes: u64,
+    /// time of the last successful save, or of the start of the server
+    last_save: SystemTime,
+    /// start of the last background save, successful or not
+    last_bgsave_try: Option<Instant>,
+}
+
+impl Default for PersistenceState {
+    fn default() -> Self {
+        PersistenceState {
+            running: None,
+            started_at: None,
+            scheduled: VecDeque::new(),
+            // nothing failed yet
+            last_bgsave_ok: true,
+            last_aof_rewrite_ok: true,
+            last_bgsave_secs: None,
+            last_aof_rewrite_secs: None,
+            saved_writes: 0,
+            last_save: SystemTime::now(),
+            last_bgsave_try: None,
+        }
+    }
+}
+
+impl PersistenceState {
+    /// starts the job unless another one runs, in which case it is scheduled once
+    fn request(&mut self, job: PersistenceJob) -> JobRequest {
+        match self.running {
+            Some(running) if running == job => JobRequest::InProgress,
+            Some(_) => {
+                if !self.scheduled.contains(&job) {
+                    self.scheduled.push_back(job);
+                }
+                JobRequest::Scheduled
+            }
+            None => {
+                self.start(job);
+                JobRequest::Started
+            }
+        }
+    }
+
+    fn start(&mut self, job: PersistenceJob) {
+        self.running = Some(job);
+        self.started_at = Some(Instant::now());
+        if job == PersistenceJob::Save {
+            self.last_bgsave_try = self.started_at;
+        }
+    }
+
+    /// records the result of the running job, which wrote the store after the number of writes,
+    /// returns the scheduled job which starts next
+    fn complete(&mut self, job: PersistenceJob, ok: bool, writes: u64) -> Option<PersistenceJob> {
+        let secs = self.started_at.map(|started_at| started_at.elapsed().as_secs());
+        match job {
+            PersistenceJob::Save => (self.last_bgsave_ok, self.last_bgsave_secs) = (ok, secs),
+            PersistenceJob::RewriteAof => (self.last_aof_rewrite_ok, self.last_aof_rewrite_secs) = (ok, secs),
+        }
+        if job == PersistenceJob::Save && ok {
+            self.saved(writes);
+        }
+        self.running = None;
+        if let Some(next) = self.scheduled.pop_front() {
+            self.start(next);
+        }
+        self.running
+    }
+
+    /// records a successful save of the store after the number of writes
+    fn saved(&mut self, writes: u64) {
+        self.saved_writes = writes;
+        self.last_save = SystemTime::now();
+    }
+
+    fn is_running(&self, job: PersistenceJob) -> bool {
+        self.running == Some(job)
+    }
+
+    fn is_scheduled(&self, job: PersistenceJob) -> bool {
+        self.scheduled.contains(&job)
+    }
+
+    /// seconds the job has been running as reported by INFO persistence, -1 when it does not run
+    fn running_secs(&self, job: PersistenceJob) -> i64 {
+        match self.started_at {
+            Some(started_at) if self.is_running(job) => started_at.elapsed().as_secs() as i64,
+            _ => -1,
+        }
+    }
+}
+
+/// commands held back by CLIENT PAUSE
+#[derive(Debug, Clone, Copy, PartialEq, PartialOrd)]
+pub(crate) enum PauseMode {
+    /// commands which write or are replicated, reads keep flowing
+    Write,
+    /// all commands
+    All,
 }
 
+/// pause of the client commands until the deadline
+#[derive(Debug, Clone, Copy)]
+pub(crate) struct ClientPause {
+    until: Instant,
+    mode: PauseMode,
+}
+
+impl ClientPause {
+    fn holds(&self, cmd: &CommandRequest) -> bool {
+        self.until > Instant::now() && (self.mode == PauseMode::All || cmd.0.is_replicated())
+    }
+}
 
 #[derive(Clone)]
 pub struct RedisServer {
     pub(crate) binding: Binding,
-    store: Arc<RwLock<Store>>,
-    pub(crate) log_store: Arc<RwLock<LogStore>>,
+    store: Arc<StoreLock>,
+    /// most recent part of the replication stream, which replicas catch up from
+    pub(crate) backlog: Arc<RwLock<ReplicationBacklog>>,
+    pub(crate) config: Arc<RwLock<Config>>,
+    pub(crate) persistence: Arc<RwLock<PersistenceState>>,
+    pub(crate) cluster: Option<Cluster>,
+    lazyfree: LazyFree,
+    /// append only file of the replicated commands, open while appendonly is enabled
+    pub(crate) aof: AppendOnlyFile,
+    audit_log: AuditLog,
+    pub(crate) pubsub: PubSub,
+    /// active client pause, with the condition signalled when it is lifted
+    client_pause: Arc<(Mutex<Option<ClientPause>>, Condvar)>,
+    /// number of commands aborted after spending their time budget
+    aborted_commands: Arc<AtomicU64>,
+    /// counters of commands, connections and keys reported by INFO stats
+    stats: Arc<Stats>,
+    /// rate of the received commands, sampled by the cron
+    ops_per_sec: Arc<AtomicU64>,
+    /// number of clients currently connected
+    connected_clients: Arc<AtomicU64>,
+    /// connections of the clients, by client id
+    pub(crate) clients: Clients,
+    scan_cursors: ScanCursors,
+    /// bytes of the commands queued by the transactions of all clients
+    pub(crate) client_memory: Arc<AtomicU64>,
+    /// held exclusively while a transaction executes, and shared by the other commands,
+    /// so that no command runs in the middle of a transaction
+    pub(crate) exec_lock: Arc<ExecLock>,
     pub(crate) master_replid: String,
+    /// time the server started, for its uptime
+    started_at: Instant,
     pub is_master: bool,
     pub db_dir: String,
     pub db_filename: String,
 }
 
+/// connection accepted from a client, counted as connected until it is dropped with the end of the connection
+pub(crate) struct ConnectedClient {
+    pub(crate) peer: SocketAddr,
+    pub(crate) accepted_at: SystemTime,
+    connected_clients: Arc<AtomicU64>,
+}
+
+impl Drop for ConnectedClient {
+    fn drop(&mut self) {
+        self.connected_clients.fetch_sub(1, Ordering::Relaxed);
+    }
+}
+
 impl RedisServer {
-    pub fn new(binding: Binding, is_master: bool, dir: String, dbfilename: String) -> Result<Self> {
-        let master_replid = "8371b4fb1155b71f4a04d3e1bc3e18c4a990deep".to_string();
+    pub fn new(binding: Binding, is_master: bool, dir: String, dbfilename: String, config: Config) -> Result<Self> {
+        // a new id for every start, the offsets of the replication stream start over with it
+        let master_replid = random_id();
 
         let path_dir = Path::new(&dir);
         if !path_dir.exists() {
             bail!("dir {} must exist", dir);
         }
 
+        let cluster = if config.get_bool("cluster-enabled") { Some(Cluster::new(&binding)) } else { None };
+        let backlog = ReplicationBacklog::new(config.get_bytes("repl-backlog-size").unwrap_or_default() as usize);
+
         let server = RedisServer {
             binding,
-            store: Arc::new(RwLock::new(Store::new())),
+            store: Arc::new(StoreLock::new(Store::new())),
             master_replid,
+            started_at: Instant::now(),
             is_master,
-            log_store: Arc::new(RwLock::new(LogStore::default())),
+            backlog: Arc::new(RwLock::new(backlog)),
+            config: Arc::new(RwLock::new(config)),
+            persistence: Arc::new(RwLock::new(PersistenceState::default())),
+            cluster,
+            lazyfree: LazyFree::start()?,
+            aof: AppendOnlyFile::start()?,
+            audit_log: AuditLog::default(),
+            pubsub: PubSub::default(),
+            client_pause: Arc::new((Mutex::new(None), Condvar::new())),
+            aborted_commands: Arc::new(AtomicU64::new(0)),
+            stats: Arc::default(),
+            ops_per_sec: Arc::new(AtomicU64::new(0)),
+            connected_clients: Arc::new(AtomicU64::new(0)),
+            clients: Clients::default(),
+            scan_cursors: ScanCursors::default(),
+            client_memory: Arc::new(AtomicU64::new(0)),
+            exec_lock: Arc::new(ExecLock::default()),
             db_dir: dir.clone(),
             db_filename: dbfilename.clone(),
         };
 
-        server.load_rds()?;
+        server.store.write().unwrap().set_stats(server.stats.clone());
+        server.load_data()?;
+        // the loaded keys are already saved
+        server.persistence.write().unwrap().saved(server.store.read().unwrap().write_count());
+        server.configure_aof()?;
+        // the loaded keys are not notified
+        let flags = server.notify_flags();
+        server.store.write().unwrap().set_notify_flags(flags);
 
         Ok(server)
     }
 
+    /// runs the command, then publishes the keyspace events of the changes it made
     pub(crate) fn handle_command(&self, cmd: &CommandRequest) -> Result<Vec<RESP>> {
+        let responses = self.run_command(cmd);
+        self.publish_keyspace_events();
+        responses
+    }
+
+    fn run_command(&self, cmd: &CommandRequest) -> Result<Vec<RESP>> {
         match cmd.as_ref() {
             (Command::PING, []) => Ok(vec![RESP::String("PONG".to_string())]),
-            (Command::ECHO, [param1]) => {
-                Ok(vec![RESP::bulk(param1)])
+            (Command::ECHO, [message]) => {
+                Ok(vec![RESP::Bulk(message.clone())])
+            }
+            (Command::UNWATCH, []) => {
+                // minimal implementation of https://redis.io/docs/latest/commands/unwatch/
+                // the watches are cleared by the connection, queued in a transaction it does nothing as EXEC clears them
+                Ok(vec![RESP::String("OK".to_string())])
             }
             (Command::SET, [key, value, options @ ..]) => {
                 // minimal implementation of https://redis.io/docs/latest/commands/set/
                 let px_expiration = named_option::<u64>(options, "PX")?.map(Duration::from_millis);
-                self.store.write().unwrap().insert_value(&key, &value, px_expiration);
+                self.store.write().unwrap().insert_value(key, value, px_expiration);
                 Ok(vec![RESP::String("OK".to_string())])
             }
             (Command::GET, [key]) => {
@@ -81,6 +352,980 @@ impl RedisServer {
                         .map_or(RESP::Null, RESP::Bulk)
                 ])
             }
+            (Command::GETDEL, [key]) => {
+                // minimal implementation of https://redis.io/docs/latest/commands/getdel/
+                // GETDEL key
+                Ok(vec![
+                    self.store.write().unwrap().remove_value(key)
+                        .map_or_else(|err| RESP::Error(err.to_string()), |value| value.map_or(RESP::Null, RESP::Bulk))
+                ])
+            }
+            (Command::GETEX, [key, options @ ..]) => {
+                // minimal implementation of https://redis.io/docs/latest/commands/getex/
+                // GETEX key [EX seconds | PX milliseconds | EXAT unix-time-seconds | PXAT unix-time-milliseconds | PERSIST]
+                let expiration = match parse_expiration(options) {
+                    Ok(expiration) => expiration,
+                    Err(err) => return Ok(vec![RESP::Error(err.to_string())]),
+                };
+                let mut store = self.store.write().unwrap();
+                if !matches!(store.get_type(key), "string" | "none") {
+                    return Ok(vec![RESP::Error(WRONGTYPE.to_string())]);
+                }
+                let value = store.get_value(key);
+                if let (Some(_), Some(valid_until)) = (&value, expiration) {
+                    store.set_expiration(key, valid_until);
+                }
+                Ok(vec![value.map_or(RESP::Null, RESP::Bulk)])
+            }
+            (Command::EXPIRE | Command::PEXPIRE | Command::EXPIREAT | Command::PEXPIREAT, [key, amount, options @ ..]) => {
+                // minimal implementation of https://redis.io/docs/latest/commands/expire/
+                // EXPIRE key seconds [NX | XX | GT | LT]
+                let conditions = match parse_expire_conditions(options) {
+                    Ok(conditions) => conditions,
+                    Err(err) => return Ok(vec![RESP::Error(err.to_string())]),
+                };
+                let valid_until_ms = match amount.parse::<i64>() {
+                    Ok(amount) => cmd.0.expiration_ms(amount),
+                    Err(_) => return Ok(vec![RESP::Error("ERR value is not an integer or out of range".to_string())]),
+                };
+                let Some(valid_until_ms) = valid_until_ms else {
+                    return Ok(vec![RESP::Error(format!("ERR invalid expire time in '{}' command", cmd.0.to_string().to_lowercase()))]);
+                };
+                // times before the epoch are in the past as well
+                let valid_until = SystemTime::UNIX_EPOCH + Duration::from_millis(valid_until_ms.max(0) as u64);
+                let updated = self.store.write().unwrap().expire(key, valid_until, &conditions);
+                Ok(vec![RESP::Int(updated as i64)])
+            }
+            (Command::TTL | Command::PTTL | Command::EXPIRETIME | Command::PEXPIRETIME, [key]) => {
+                // minimal implementation of https://redis.io/docs/latest/commands/ttl/
+                // TTL key
+                let expiration = self.store.read().unwrap().expiration(key);
+                Ok(vec![RESP::Int(match expiration {
+                    None => -2,
+                    Some(None) => -1,
+                    Some(Some(valid_until)) => {
+                        let ttl_ms = valid_until.duration_since(SystemTime::now()).unwrap_or_default().as_millis() as i64;
+                        let unix_ms = valid_until.duration_since(SystemTime::UNIX_EPOCH).unwrap_or_default().as_millis() as i64;
+                        match cmd.0 {
+                            Command::TTL => (ttl_ms + 500) / 1000,
+                            Command::PTTL => ttl_ms,
+                            Command::EXPIRETIME => unix_ms / 1000,
+                            _ => unix_ms,
+                        }
+                    }
+                })])
+            }
+            (Command::PERSIST, [key]) => {
+                // minimal implementation of https://redis.io/docs/latest/commands/persist/
+                // PERSIST key
+                let persisted = self.store.write().unwrap().persist(key);
+                Ok(vec![RESP::Int(persisted as i64)])
+            }
+            (Command::DEL | Command::UNLINK, keys) if !keys.is_empty() => {
+                // minimal implementation of https://redis.io/docs/latest/commands/del/
+                // DEL key [key ...]
+                // UNLINK key [key ...]
+                let removed = self.store.write().unwrap().remove_keys(keys);
+                let count = removed.len();
+                if cmd.0 == Command::UNLINK || self.config.read().unwrap().get_bool("lazyfree-lazy-user-del") {
+                    // values are reclaimed in the background after the keys are already gone
+                    self.lazyfree.free(removed);
+                }
+                Ok(vec![RESP::Int(count as i64)])
+            }
+            (Command::TOUCH, keys) if !keys.is_empty() => {
+                // minimal implementation of https://redis.io/docs/latest/commands/touch/
+                // TOUCH key [key ...]
+                let store = self.store.read().unwrap();
+                Ok(vec![RESP::Int(keys.iter().filter(|key| store.contains_key(key)).count() as i64)])
+            }
+            (Command::RANDOMKEY, []) => {
+                // minimal implementation of https://redis.io/docs/latest/commands/randomkey/
+                // RANDOMKEY
+                Ok(vec![self.store.read().unwrap().random_key().map_or(RESP::Null, |key| RESP::Bulk(key.to_vec()))])
+            }
+            (Command::PUBLISH, [channel, message]) => {
+                // minimal implementation of https://redis.io/docs/latest/commands/publish/
+                // PUBLISH channel message
+                Ok(vec![RESP::Int(self.pubsub.publish(channel, message) as i64)])
+            }
+            (Command::SPUBLISH, [shard_channel, message]) => {
+                // minimal implementation of https://redis.io/docs/latest/commands/spublish/
+                // SPUBLISH shardchannel message
+                Ok(vec![RESP::Int(self.pubsub.publish_shard(shard_channel, message) as i64)])
+            }
+            (Command::CLIENT, [sub_command, params @ ..]) => {
+                // minimal implementation of https://redis.io/docs/latest/commands/client-pause/
+                // CLIENT PAUSE timeout [WRITE | ALL]
+                // CLIENT UNPAUSE
+                match (sub_command.to_uppercase().as_str(), params) {
+                    ("PAUSE", [timeout, mode @ ..]) if mode.len() <= 1 => {
+                        let Ok(timeout_ms) = timeout.parse::<u64>() else {
+                            return Ok(vec![RESP::Error("ERR timeout is not an integer or out of range".to_string())]);
+                        };
+                        let mode = match mode.first().map(|mode| mode.to_uppercase()).as_deref() {
+                            None | Some("ALL") => PauseMode::All,
+                            Some("WRITE") => PauseMode::Write,
+                            Some(_) => return Ok(vec![RESP::Error("ERR syntax error".to_string())]),
+                        };
+                        self.pause_clients(Duration::from_millis(timeout_ms), mode);
+                        Ok(vec![RESP::String("OK".to_string())])
+                    }
+                    ("UNPAUSE", []) => {
+                        self.unpause_clients();
+                        Ok(vec![RESP::String("OK".to_string())])
+                    }
+                    _ => Ok(vec![RESP::Error(format!("ERR unknown subcommand '{}'. Try CLIENT HELP.", sub_command.text()))]),
+                }
+            }
+            (Command::COMMAND, params) => {
+                // minimal implementation of https://redis.io/docs/latest/commands/command/
+                // COMMAND
+                // COMMAND COUNT
+                // COMMAND INFO [command-name [command-name ...]]
+                // COMMAND DOCS [command-name [command-name ...]]
+                // COMMAND GETKEYS command [arg [arg ...]]
+                let Some((sub_command, names)) = params.split_first() else {
+                    return Ok(vec![RESP::Array(COMMAND_TABLE.iter().map(CommandSpec::info).collect())]);
+                };
+                match (sub_command.to_uppercase().as_str(), names) {
+                    ("COUNT", []) => Ok(vec![RESP::Int(COMMAND_TABLE.len() as i64)]),
+                    ("INFO", []) => Ok(vec![RESP::Array(COMMAND_TABLE.iter().map(CommandSpec::info).collect())]),
+                    ("INFO", names) => Ok(vec![RESP::Array(
+                        names.iter().map(|name| command_table::lookup(&name.text()).map_or(RESP::Null, CommandSpec::info)).collect()
+                    )]),
+                    ("DOCS", []) => Ok(vec![RESP::Map(COMMAND_TABLE.iter().map(CommandSpec::docs).collect())]),
+                    // unknown commands are left out
+                    ("DOCS", names) => Ok(vec![RESP::Map(
+                        names.iter().filter_map(|name| command_table::lookup(&name.text())).map(CommandSpec::docs).collect()
+                    )]),
+                    ("GETKEYS", command_line) if !command_line.is_empty() => Ok(vec![command_table::command_keys(command_line)]),
+                    _ => Ok(vec![RESP::Error(format!("ERR unknown subcommand or wrong number of arguments for '{}'", sub_command.text()))]),
+                }
+            }
+            (Command::DBSIZE, []) => {
+                // minimal implementation of https://redis.io/docs/latest/commands/dbsize/
+                // DBSIZE
+                Ok(vec![RESP::Int(self.store.read().unwrap().key_count() as i64)])
+            }
+            (Command::FLUSHDB | Command::FLUSHALL, params) if params.len() <= 1 => {
+                // minimal implementation of https://redis.io/docs/latest/commands/flushall/
+                // FLUSHDB [ASYNC | SYNC]
+                // FLUSHALL [ASYNC | SYNC]
+                let lazy = match params.first().map(|mode| mode.to_uppercase()).as_deref() {
+                    None => self.config.read().unwrap().get_bool("lazyfree-lazy-user-flush"),
+                    Some("SYNC") => false,
+                    Some("ASYNC") => true,
+                    Some(_) => return Ok(vec![RESP::Error("ERR syntax error".to_string())]),
+                };
+                let removed = self.store.write().unwrap().flush();
+                if lazy {
+                    self.lazyfree.free_all(removed);
+                }
+                if cmd.0 == Command::FLUSHALL {
+                    // the db file is replaced as well, so that a restart does not bring the flushed keys back.
+                    // the function libraries are not flushed
+                    let db_file = Path::new(&self.db_dir).join(&self.db_filename);
+                    if db_file.exists() {
+                        let flushed = self.store.read().unwrap().snapshot();
+                        if let Err(err) = self.save_rdb(&flushed) {
+                            warning!("saving the flushed dataset failed: {}", err);
+                        }
+                    }
+                }
+                Ok(vec![RESP::String("OK".to_string())])
+            }
+            (Command::RENAME, [key, new_key]) => {
+                // minimal implementation of https://redis.io/docs/latest/commands/rename/
+                // RENAME key newkey
+                Ok(vec![
+                    if self.store.write().unwrap().rename(key, new_key) {
+                        RESP::String("OK".to_string())
+                    } else {
+                        RESP::Error("ERR no such key".to_string())
+                    }
+                ])
+            }
+            (Command::RENAMENX, [key, new_key]) => {
+                // minimal implementation of https://redis.io/docs/latest/commands/renamenx/
+                // RENAMENX key newkey
+                let mut store = self.store.write().unwrap();
+                Ok(vec![
+                    if !store.contains_key(key) {
+                        RESP::Error("ERR no such key".to_string())
+                    } else if store.contains_key(new_key) {
+                        RESP::Int(0)
+                    } else {
+                        store.rename(key, new_key);
+                        RESP::Int(1)
+                    }
+                ])
+            }
+            (Command::COPY, [source, destination, options @ ..]) => {
+                // minimal implementation of https://redis.io/docs/latest/commands/copy/
+                // COPY source destination [DB destination-db] [REPLACE]
+                let mut replace = false;
+                let mut iter = options.iter();
+                while let Some(option) = iter.next() {
+                    match option.to_uppercase().as_str() {
+                        "REPLACE" => replace = true,
+                        // only the default database exists
+                        "DB" => match iter.next().map(|db| db.parse::<i64>()) {
+                            Some(Ok(0)) => {}
+                            Some(Ok(_)) => return Ok(vec![RESP::Error("ERR DB index is out of range".to_string())]),
+                            Some(Err(_)) => return Ok(vec![RESP::Error("ERR value is not an integer or out of range".to_string())]),
+                            None => return Ok(vec![RESP::Error("ERR syntax error".to_string())]),
+                        },
+                        _ => return Ok(vec![RESP::Error("ERR syntax error".to_string())]),
+                    }
+                }
+                Ok(vec![
+                    self.store.write().unwrap().copy(source, destination, replace)
+                        .map_or_else(|err| RESP::Error(err.to_string()), |copied| RESP::Int(copied as i64))
+                ])
+            }
+            (Command::SADD, [key, members @ ..]) if !members.is_empty() => {
+                // minimal implementation of https://redis.io/docs/latest/commands/sadd/
+                // SADD key member [member ...]
+                Ok(vec![
+                    self.store.write().unwrap().add_to_set(key, members)
+                        .map_or_else(|err| RESP::Error(err.to_string()), |added| RESP::Int(added as i64))
+                ])
+            }
+            (Command::SREM, [key, members @ ..]) if !members.is_empty() => {
+                // minimal implementation of https://redis.io/docs/latest/commands/srem/
+                // SREM key member [member ...]
+                Ok(vec![
+                    self.store.write().unwrap().remove_from_set(key, members)
+                        .map_or_else(|err| RESP::Error(err.to_string()), |removed| RESP::Int(removed as i64))
+                ])
+            }
+            (Command::SMEMBERS | Command::SCARD | Command::SISMEMBER, [key, params @ ..]) => {
+                // minimal implementation of https://redis.io/docs/latest/commands/smembers/
+                // SMEMBERS key
+                // SCARD key
+                // SISMEMBER key member
+                let set = match self.set_snapshots(std::slice::from_ref(key)) {
+                    Ok(mut sets) => sets.remove(0).unwrap_or_default(),
+                    Err(err) => return Ok(vec![RESP::Error(err.to_string())]),
+                };
+                match (cmd.0, params) {
+                    (Command::SMEMBERS, []) => Ok(vec![RESP::Set(set.iter().map(|m| RESP::Bulk(m.clone())).collect())]),
+                    (Command::SCARD, []) => Ok(vec![RESP::Int(set.len() as i64)]),
+                    (Command::SISMEMBER, [member]) => Ok(vec![RESP::Int(set.contains(member) as i64)]),
+                    _ => bail!("invalid {} command", cmd.0),
+                }
+            }
+            (Command::SMISMEMBER, [key, members @ ..]) if !members.is_empty() => {
+                // minimal implementation of https://redis.io/docs/latest/commands/smismember/
+                // SMISMEMBER key member [member ...]
+                let set = match self.set_snapshots(std::slice::from_ref(key)) {
+                    Ok(mut sets) => sets.remove(0).unwrap_or_default(),
+                    Err(err) => return Ok(vec![RESP::Error(err.to_string())]),
+                };
+                Ok(vec![RESP::Array(members.iter().map(|member| RESP::Int(set.contains(member) as i64)).collect())])
+            }
+            (Command::SPOP | Command::SRANDMEMBER, [key, params @ ..]) if params.len() <= 1 => {
+                // minimal implementation of https://redis.io/docs/latest/commands/spop/
+                // SPOP key [count]
+                // SRANDMEMBER key [count]
+                let count = match params.first() {
+                    None => None,
+                    Some(count) if cmd.0 == Command::SRANDMEMBER => match self.random_count(count) {
+                        Ok(count) => Some(count),
+                        Err(err) => return Ok(vec![RESP::Error(err.to_string())]),
+                    },
+                    Some(count) => match count.parse::<i64>() {
+                        Ok(count) if count >= 0 => Some(count),
+                        _ => return Ok(vec![RESP::Error("ERR value is out of range, must be positive".to_string())]),
+                    },
+                };
+                let picked = if cmd.0 == Command::SPOP {
+                    self.store.write().unwrap().pop_from_set(key, count.unwrap_or(1) as usize)
+                } else {
+                    // members are sampled from a snapshot without holding the store
+                    self.set_snapshots(std::slice::from_ref(key))
+                        .map(|mut sets| sets.remove(0).map(|set| random_sample(set.iter().cloned().collect(), count.unwrap_or(1))))
+                };
+                Ok(vec![match (picked, count) {
+                    (Err(err), _) => RESP::Error(err.to_string()),
+                    // without a count a single member is returned
+                    (Ok(picked), None) => picked.and_then(|picked| picked.first().cloned().map(RESP::Bulk)).unwrap_or(RESP::Null),
+                    (Ok(picked), Some(_)) => RESP::Array(picked.unwrap_or_default().into_iter().map(RESP::Bulk).collect()),
+                }])
+            }
+            (Command::SMOVE, [source, destination, member]) => {
+                // minimal implementation of https://redis.io/docs/latest/commands/smove/
+                // SMOVE source destination member
+                Ok(vec![
+                    self.store.write().unwrap().move_set_member(source, destination, member)
+                        .map_or_else(|err| RESP::Error(err.to_string()), |moved| RESP::Int(moved as i64))
+                ])
+            }
+            (Command::HSET, [key, field_value_pairs @ ..]) if !field_value_pairs.is_empty() && field_value_pairs.len() % 2 == 0 => {
+                // minimal implementation of https://redis.io/docs/latest/commands/hset/
+                // HSET key field value [field value ...]
+                let fields = field_value_pairs.chunks(2)
+                    .map(|pair| (pair[0].clone(), pair[1].clone()))
+                    .collect::<Vec<(Vec<u8>, Vec<u8>)>>();
+                Ok(vec![
+                    self.store.write().unwrap().set_hash_fields(key, &fields)
+                        .map_or_else(|err| RESP::Error(err.to_string()), |added| RESP::Int(added as i64))
+                ])
+            }
+            (Command::HSETNX, [key, field, value]) => {
+                // minimal implementation of https://redis.io/docs/latest/commands/hsetnx/
+                // HSETNX key field value
+                Ok(vec![
+                    self.store.write().unwrap().set_hash_field_if_missing(key, field, value)
+                        .map_or_else(|err| RESP::Error(err.to_string()), |set| RESP::Int(set as i64))
+                ])
+            }
+            (Command::HINCRBY, [key, field, increment]) => {
+                // minimal implementation of https://redis.io/docs/latest/commands/hincrby/
+                // HINCRBY key field increment
+                let Ok(increment) = increment.parse::<i64>() else {
+                    return Ok(vec![RESP::Error("ERR value is not an integer or out of range".to_string())]);
+                };
+                let result = self.store.write().unwrap().update_hash_field(key, field, "hincrby", |current| {
+                    let Ok(current) = current.unwrap_or(b"0").parse::<i64>() else {
+                        bail!("ERR hash value is not an integer");
+                    };
+                    match current.checked_add(increment) {
+                        Some(value) => Ok(value.to_string().into_bytes()),
+                        None => bail!("ERR increment or decrement would overflow"),
+                    }
+                });
+                Ok(vec![result.map_or_else(|err| RESP::Error(err.to_string()), |value| RESP::Int(value.parse().expect("integer value")))])
+            }
+            (Command::HINCRBYFLOAT, [key, field, increment]) => {
+                // minimal implementation of https://redis.io/docs/latest/commands/hincrbyfloat/
+                // HINCRBYFLOAT key field increment
+                let increment = match increment.parse::<f64>() {
+                    Ok(increment) if increment.is_finite() => increment,
+                    _ => return Ok(vec![RESP::Error("ERR value is not a valid float".to_string())]),
+                };
+                let result = self.store.write().unwrap().update_hash_field(key, field, "hincrbyfloat", |current| {
+                    let current = match current.unwrap_or(b"0").parse::<f64>() {
+                        Ok(current) if current.is_finite() => current,
+                        _ => bail!("ERR hash value is not a float"),
+                    };
+                    let value = current + increment;
+                    if !value.is_finite() {
+                        bail!("ERR increment would produce NaN or Infinity");
+                    }
+                    Ok(value.to_string().into_bytes())
+                });
+                Ok(vec![result.map_or_else(|err| RESP::Error(err.to_string()), RESP::Bulk)])
+            }
+            (Command::HRANDFIELD, [key, params @ ..]) if params.len() <= 2 => {
+                // minimal implementation of https://redis.io/docs/latest/commands/hrandfield/
+                // HRANDFIELD key [count [WITHVALUES]]
+                let with_values = match params {
+                    [_, option] if option.eq_ignore_ascii_case(b"WITHVALUES") => true,
+                    [_, _] => return Ok(vec![RESP::Error("ERR syntax error".to_string())]),
+                    _ => false,
+                };
+                let count = match params.first().map(|count| self.random_count(count)) {
+                    None => None,
+                    Some(Ok(count)) => Some(count),
+                    Some(Err(err)) => return Ok(vec![RESP::Error(err.to_string())]),
+                };
+                let picked = match self.store.read().unwrap().random_hash_fields(key, count.unwrap_or(1)) {
+                    Ok(picked) => picked,
+                    Err(err) => return Ok(vec![RESP::Error(err.to_string())]),
+                };
+                Ok(vec![match (picked, count) {
+                    // without a count a single field is returned
+                    (None, None) => RESP::Null,
+                    (Some(picked), None) => picked.first().map_or(RESP::Null, |(field, _)| RESP::Bulk(field.clone())),
+                    (picked, Some(_)) => RESP::Array(picked.unwrap_or_default().iter()
+                        .flat_map(|(field, value)| {
+                            let mut reply = vec![RESP::Bulk(field.clone())];
+                            if with_values {
+                                reply.push(RESP::Bulk(value.clone()));
+                            }
+                            reply
+                        })
+                        .collect()),
+                }])
+            }
+            (Command::HDEL, [key, fields @ ..]) if !fields.is_empty() => {
+                // minimal implementation of https://redis.io/docs/latest/commands/hdel/
+                // HDEL key field [field ...]
+                Ok(vec![
+                    self.store.write().unwrap().delete_hash_fields(key, fields)
+                        .map_or_else(|err| RESP::Error(err.to_string()), |removed| RESP::Int(removed as i64))
+                ])
+            }
+            (Command::HGET | Command::HEXISTS, [key, field]) => {
+                // minimal implementation of https://redis.io/docs/latest/commands/hget/
+                // HGET key field
+                // HEXISTS key field
+                let value = match self.store.read().unwrap().hash_values(key, std::slice::from_ref(field)) {
+                    Ok(mut values) => values.remove(0),
+                    Err(err) => return Ok(vec![RESP::Error(err.to_string())]),
+                };
+                Ok(vec![match cmd.0 {
+                    Command::HGET => value.map_or(RESP::Null, RESP::Bulk),
+                    _ => RESP::Int(value.is_some() as i64),
+                }])
+            }
+            (Command::HMGET, [key, fields @ ..]) if !fields.is_empty() => {
+                // minimal implementation of https://redis.io/docs/latest/commands/hmget/
+                // HMGET key field [field ...]
+                Ok(vec![
+                    self.store.read().unwrap().hash_values(key, fields)
+                        .map_or_else(
+                            |err| RESP::Error(err.to_string()),
+                            |values| RESP::Array(values.iter().map(|value| value.as_ref().map_or(RESP::Null, |v| RESP::Bulk(v.clone()))).collect()),
+                        )
+                ])
+            }
+            (Command::HGETALL | Command::HKEYS | Command::HVALS, [key]) => {
+                // minimal implementation of https://redis.io/docs/latest/commands/hgetall/
+                // HGETALL key
+                // HKEYS key
+                // HVALS key
+                let entries = match self.store.read().unwrap().hash_entries(key) {
+                    Ok(entries) => entries,
+                    Err(err) => return Ok(vec![RESP::Error(err.to_string())]),
+                };
+                Ok(vec![match cmd.0 {
+                    Command::HKEYS => RESP::Array(entries.iter().map(|(field, _)| RESP::Bulk(field.clone())).collect()),
+                    Command::HVALS => RESP::Array(entries.iter().map(|(_, value)| RESP::Bulk(value.clone())).collect()),
+                    _ => RESP::Map(entries.iter().map(|(field, value)| (RESP::Bulk(field.clone()), RESP::Bulk(value.clone()))).collect()),
+                }])
+            }
+            (Command::HLEN, [key]) => {
+                // minimal implementation of https://redis.io/docs/latest/commands/hlen/
+                // HLEN key
+                Ok(vec![
+                    self.store.read().unwrap().hash_len(key)
+                        .map_or_else(|err| RESP::Error(err.to_string()), |len| RESP::Int(len as i64))
+                ])
+            }
+            (Command::ZADD, [key, params @ ..]) => {
+                // minimal implementation of https://redis.io/docs/latest/commands/zadd/
+                // ZADD key [NX | XX] [GT | LT] [CH] [INCR] score member [score member ...]
+                Ok(vec![self.zadd(key, params).unwrap_or_else(|err| RESP::Error(err.to_string()))])
+            }
+            (Command::ZSCORE, [key, member]) => {
+                // minimal implementation of https://redis.io/docs/latest/commands/zscore/
+                // ZSCORE key member
+                Ok(vec![
+                    self.store.read().unwrap().zset_score(key, member)
+                        .map_or_else(|err| RESP::Error(err.to_string()), |score| score.map_or(RESP::Null, RESP::Double))
+                ])
+            }
+            (Command::ZCARD, [key]) => {
+                // minimal implementation of https://redis.io/docs/latest/commands/zcard/
+                // ZCARD key
+                Ok(vec![
+                    self.store.read().unwrap().zset_len(key)
+                        .map_or_else(|err| RESP::Error(err.to_string()), |len| RESP::Int(len as i64))
+                ])
+            }
+            (Command::ZINCRBY, [key, increment, member]) => {
+                // minimal implementation of https://redis.io/docs/latest/commands/zincrby/
+                // ZINCRBY key increment member
+                let Some(increment) = parse_score(increment) else {
+                    return Ok(vec![RESP::Error("ERR value is not a valid float".to_string())]);
+                };
+                let options = AddOptions { incr: true, ..AddOptions::default() };
+                Ok(vec![
+                    self.store.write().unwrap().add_to_zset(key, &[(increment, member.clone())], &options)
+                        .map_or_else(|err| RESP::Error(err.to_string()), |(_, _, score)| score.map_or(RESP::Null, RESP::Double))
+                ])
+            }
+            (Command::ZRANK | Command::ZREVRANK, [key, member, params @ ..]) if params.len() <= 1 => {
+                // minimal implementation of https://redis.io/docs/latest/commands/zrank/
+                // ZRANK key member [WITHSCORE]
+                // ZREVRANK key member [WITHSCORE]
+                let with_score = match params {
+                    [option] if option.eq_ignore_ascii_case(b"WITHSCORE") => true,
+                    [_] => return Ok(vec![RESP::Error("ERR syntax error".to_string())]),
+                    _ => false,
+                };
+                let rank = match self.store.read().unwrap().zset_rank(key, member, cmd.0 == Command::ZREVRANK) {
+                    Ok(rank) => rank,
+                    Err(err) => return Ok(vec![RESP::Error(err.to_string())]),
+                };
+                Ok(vec![match rank {
+                    None => RESP::Null,
+                    Some((rank, score)) if with_score => RESP::Array(vec![RESP::Int(rank as i64), RESP::bulk(&format_score(score))]),
+                    Some((rank, _)) => RESP::Int(rank as i64),
+                }])
+            }
+            (Command::ZCOUNT, [key, min, max]) => {
+                // minimal implementation of https://redis.io/docs/latest/commands/zcount/
+                // ZCOUNT key min max
+                let (Some(min), Some(max)) = (parse_score_bound(min), parse_score_bound(max)) else {
+                    return Ok(vec![RESP::Error("ERR min or max is not a float".to_string())]);
+                };
+                Ok(vec![
+                    self.store.read().unwrap().zset_count(key, &ZRange::Score(min, max))
+                        .map_or_else(|err| RESP::Error(err.to_string()), |count| RESP::Int(count as i64))
+                ])
+            }
+            (Command::ZRANGESTORE, [destination, key, start, stop, params @ ..]) => {
+                // minimal implementation of https://redis.io/docs/latest/commands/zrangestore/
+                // ZRANGESTORE dst src min max [BYSCORE | BYLEX] [REV] [LIMIT offset count]
+                Ok(vec![self.zrangestore(destination, key, start, stop, params).unwrap_or_else(|err| RESP::Error(err.to_string()))])
+            }
+            (Command::ZREMRANGEBYRANK | Command::ZREMRANGEBYSCORE | Command::ZREMRANGEBYLEX, [key, min, max]) => {
+                // minimal implementation of https://redis.io/docs/latest/commands/zremrangebyrank/
+                // ZREMRANGEBYRANK key start stop
+                // ZREMRANGEBYSCORE key min max
+                // ZREMRANGEBYLEX key min max
+                let range = match cmd.0 {
+                    Command::ZREMRANGEBYRANK => match (min.parse::<i64>(), max.parse::<i64>()) {
+                        (Ok(start), Ok(stop)) => Ok(Some(ZRange::Rank(start, stop))),
+                        _ => Err(format_err!("ERR value is not an integer or out of range")),
+                    },
+                    Command::ZREMRANGEBYSCORE => parse_zrange_by_score(min, max).map(Some),
+                    _ => parse_zrange_by_lex(min, max),
+                };
+                Ok(vec![match range {
+                    Err(err) => RESP::Error(err.to_string()),
+                    Ok(range) => self.store.write().unwrap().remove_zset_range(key, range.as_ref())
+                        .map_or_else(|err| RESP::Error(err.to_string()), |removed| RESP::Int(removed as i64)),
+                }])
+            }
+            (Command::ZMPOP, params) => {
+                // minimal implementation of https://redis.io/docs/latest/commands/zmpop/
+                // ZMPOP numkeys key [key ...] <MIN | MAX> [COUNT count]
+                let (keys, max, count) = match parse_mpop(params, parse_zset_end) {
+                    Ok(parsed) => parsed,
+                    Err(err) => return Ok(vec![err]),
+                };
+                let mut store = self.store.write().unwrap();
+                let popped = keys.iter()
+                    .find_map(|key| pop_zset_members(&mut store, key, max, count).transpose())
+                    .transpose();
+                Ok(vec![popped.map_or_else(|err| RESP::Error(err.to_string()), |popped| popped.unwrap_or(RESP::Null))])
+            }
+            (Command::BZMPOP, [timeout, params @ ..]) => {
+                // minimal implementation of https://redis.io/docs/latest/commands/bzmpop/
+                // BZMPOP timeout numkeys key [key ...] <MIN | MAX> [COUNT count]
+                let (keys, max, count) = match parse_mpop(params, parse_zset_end) {
+                    Ok(parsed) => parsed,
+                    Err(err) => return Ok(vec![err]),
+                };
+                let timeout = match parse_block_timeout(timeout) {
+                    Ok(timeout) => timeout,
+                    Err(err) => return Ok(vec![err]),
+                };
+                let popped = self.block_to_pop(keys, "zset", timeout, |store, key| pop_zset_members(store, key, max, count));
+                Ok(vec![popped.map_or_else(|err| RESP::Error(err.to_string()), |popped| popped.unwrap_or(RESP::Null))])
+            }
+            (Command::ZRANGE, [key, start, stop, params @ ..]) => {
+                // minimal implementation of https://redis.io/docs/latest/commands/zrange/
+                // ZRANGE key start stop [BYSCORE | BYLEX] [REV] [LIMIT offset count] [WITHSCORES]
+                Ok(vec![self.zrange(key, start, stop, params).unwrap_or_else(|err| RESP::Error(err.to_string()))])
+            }
+            (Command::GETRANGE, [key, start, end]) => {
+                // minimal implementation of https://redis.io/docs/latest/commands/getrange/
+                // GETRANGE key start end
+                let (Ok(start), Ok(end)) = (start.parse::<i64>(), end.parse::<i64>()) else {
+                    return Ok(vec![RESP::Error("ERR value is not an integer or out of range".to_string())]);
+                };
+                Ok(vec![
+                    self.store.read().unwrap().get_range(key, start, end)
+                        .map_or_else(|err| RESP::Error(err.to_string()), RESP::Bulk)
+                ])
+            }
+            (Command::SETRANGE, [key, offset, value]) => {
+                // minimal implementation of https://redis.io/docs/latest/commands/setrange/
+                // SETRANGE key offset value
+                let Ok(offset) = offset.parse::<usize>() else {
+                    return Ok(vec![RESP::Error("ERR offset is out of range".to_string())]);
+                };
+                let max_len = self.request_limits().max_bulk_len;
+                let too_long = match offset.checked_add(value.len()) {
+                    Some(end) => end as u64 > max_len,
+                    None => true,
+                };
+                if !value.is_empty() && too_long {
+                    return Ok(vec![RESP::Error("ERR string exceeds maximum allowed size (proto-max-bulk-len)".to_string())]);
+                }
+                Ok(vec![
+                    self.store.write().unwrap().set_range(key, offset, value)
+                        .map_or_else(|err| RESP::Error(err.to_string()), |len| RESP::Int(len as i64))
+                ])
+            }
+            (Command::SETBIT, [key, offset, value]) => {
+                // minimal implementation of https://redis.io/docs/latest/commands/setbit/
+                // SETBIT key offset value
+                let max_bits = self.request_limits().max_bulk_len.saturating_mul(8);
+                let Some(offset) = offset.parse::<usize>().ok().filter(|offset| (*offset as u64) < max_bits) else {
+                    return Ok(vec![RESP::Error("ERR bit offset is not an integer or out of range".to_string())]);
+                };
+                let value = match value.as_slice() {
+                    b"0" => false,
+                    b"1" => true,
+                    _ => return Ok(vec![RESP::Error("ERR bit is not an integer or out of range".to_string())]),
+                };
+                Ok(vec![
+                    self.store.write().unwrap().set_bit(key, offset, value)
+                        .map_or_else(|err| RESP::Error(err.to_string()), |previous| RESP::Int(previous as i64))
+                ])
+            }
+            (Command::GETBIT, [key, offset]) => {
+                // minimal implementation of https://redis.io/docs/latest/commands/getbit/
+                // GETBIT key offset
+                let Ok(offset) = offset.parse::<usize>() else {
+                    return Ok(vec![RESP::Error("ERR bit offset is not an integer or out of range".to_string())]);
+                };
+                Ok(vec![
+                    self.store.read().unwrap().get_bit(key, offset)
+                        .map_or_else(|err| RESP::Error(err.to_string()), |bit| RESP::Int(bit as i64))
+                ])
+            }
+            (Command::BITOP, [op, destination, keys @ ..]) if !keys.is_empty() => {
+                // minimal implementation of https://redis.io/docs/latest/commands/bitop/
+                // BITOP <AND | OR | XOR | NOT> destkey key [key ...]
+                let op = match op.parse::<BitOp>() {
+                    Ok(op) => op,
+                    Err(err) => return Ok(vec![RESP::Error(err.to_string())]),
+                };
+                if op == BitOp::Not && keys.len() > 1 {
+                    return Ok(vec![RESP::Error("ERR BITOP NOT must be called with a single source key.".to_string())]);
+                }
+                Ok(vec![
+                    self.store.write().unwrap().bit_op(op, destination, keys)
+                        .map_or_else(|err| RESP::Error(err.to_string()), |len| RESP::Int(len as i64))
+                ])
+            }
+            (Command::BITPOS, [key, bit, params @ ..]) if params.len() <= 3 => {
+                // minimal implementation of https://redis.io/docs/latest/commands/bitpos/
+                // BITPOS key bit [start [end [BYTE | BIT]]]
+                let bit = match bit.as_slice() {
+                    b"0" => false,
+                    b"1" => true,
+                    _ => return Ok(vec![RESP::Error("ERR The bit argument must be 1 or 0.".to_string())]),
+                };
+                let (start, end, unit) = match params {
+                    [] => (Ok(0), None, None),
+                    [start] => (start.parse::<i64>(), None, None),
+                    [start, end, unit @ ..] => (start.parse::<i64>(), Some(end.parse::<i64>()), unit.first()),
+                };
+                let (Ok(start), Ok(end)) = (start, end.transpose()) else {
+                    return Ok(vec![RESP::Error("ERR value is not an integer or out of range".to_string())]);
+                };
+                let bits = match unit.map(|unit| unit.to_uppercase()).as_deref() {
+                    None | Some("BYTE") => false,
+                    Some("BIT") => true,
+                    Some(_) => return Ok(vec![RESP::Error("ERR syntax error".to_string())]),
+                };
+                Ok(vec![
+                    self.store.read().unwrap().bit_pos(key, bit, start, end, bits)
+                        .map_or_else(|err| RESP::Error(err.to_string()), RESP::Int)
+                ])
+            }
+            (Command::BITFIELD, [key, params @ ..]) => {
+                // minimal implementation of https://redis.io/docs/latest/commands/bitfield/
+                // BITFIELD key [GET encoding offset | [OVERFLOW <WRAP | SAT | FAIL>]
+                //   <SET encoding offset value | INCRBY encoding offset increment> [GET encoding offset | ...]]
+                let max_bits = self.request_limits().max_bulk_len.saturating_mul(8);
+                let result = parse_bitfield(params, max_bits)
+                    .and_then(|ops| self.store.write().unwrap().bitfield(key, &ops));
+                Ok(vec![result.map_or_else(
+                    |err| RESP::Error(err.to_string()),
+                    |results| RESP::Array(results.into_iter().map(|result| result.map_or(RESP::Null, RESP::Int)).collect()),
+                )])
+            }
+            (Command::PFADD, [key, elements @ ..]) => {
+                // minimal implementation of https://redis.io/docs/latest/commands/pfadd/
+                // PFADD key [element [element ...]]
+                Ok(vec![
+                    self.store.write().unwrap().pf_add(key, elements)
+                        .map_or_else(|err| RESP::Error(err.to_string()), |changed| RESP::Int(changed as i64))
+                ])
+            }
+            (Command::PFCOUNT, keys) if !keys.is_empty() => {
+                // minimal implementation of https://redis.io/docs/latest/commands/pfcount/
+                // PFCOUNT key [key ...]
+                Ok(vec![
+                    self.store.read().unwrap().pf_count(keys)
+                        .map_or_else(|err| RESP::Error(err.to_string()), |count| RESP::Int(count as i64))
+                ])
+            }
+            (Command::PFMERGE, [destination, keys @ ..]) => {
+                // minimal implementation of https://redis.io/docs/latest/commands/pfmerge/
+                // PFMERGE destkey [sourcekey [sourcekey ...]]
+                Ok(vec![
+                    self.store.write().unwrap().pf_merge(destination, keys)
+                        .map_or_else(|err| RESP::Error(err.to_string()), |_| RESP::String("OK".to_string()))
+                ])
+            }
+            (Command::BITCOUNT, [key, params @ ..]) => {
+                // minimal implementation of https://redis.io/docs/latest/commands/bitcount/
+                // BITCOUNT key [start end [BYTE | BIT]]
+                let range = match params {
+                    [] => None,
+                    [start, end, unit @ ..] if unit.len() <= 1 => {
+                        let (Ok(start), Ok(end)) = (start.parse::<i64>(), end.parse::<i64>()) else {
+                            return Ok(vec![RESP::Error("ERR value is not an integer or out of range".to_string())]);
+                        };
+                        let bits = match unit.first().map(|unit| unit.to_uppercase()).as_deref() {
+                            None | Some("BYTE") => false,
+                            Some("BIT") => true,
+                            Some(_) => return Ok(vec![RESP::Error("ERR syntax error".to_string())]),
+                        };
+                        Some((start, end, bits))
+                    }
+                    _ => return Ok(vec![RESP::Error("ERR syntax error".to_string())]),
+                };
+                Ok(vec![
+                    self.store.read().unwrap().bit_count(key, range)
+                        .map_or_else(|err| RESP::Error(err.to_string()), |count| RESP::Int(count as i64))
+                ])
+            }
+            (Command::LPUSH | Command::RPUSH, [key, elements @ ..]) if !elements.is_empty() => {
+                // minimal implementation of https://redis.io/docs/latest/commands/lpush/
+                // LPUSH key element [element ...]
+                // RPUSH key element [element ...]
+                let end = if cmd.0 == Command::LPUSH { ListEnd::Left } else { ListEnd::Right };
+                Ok(vec![
+                    self.store.write().unwrap().push_to_list(key, end, elements)
+                        .map_or_else(|err| RESP::Error(err.to_string()), |len| RESP::Int(len as i64))
+                ])
+            }
+            (Command::LPOP | Command::RPOP, [key, params @ ..]) if params.len() <= 1 => {
+                // minimal implementation of https://redis.io/docs/latest/commands/lpop/
+                // LPOP key [count]
+                // RPOP key [count]
+                let end = if cmd.0 == Command::LPOP { ListEnd::Left } else { ListEnd::Right };
+                let count = match params.first().map(|count| count.parse::<i64>()) {
+                    None => None,
+                    Some(Ok(count)) if count >= 0 => Some(count as usize),
+                    Some(Ok(_)) => return Ok(vec![RESP::Error("ERR value is out of range, must be positive".to_string())]),
+                    Some(Err(_)) => return Ok(vec![RESP::Error("ERR value is not an integer or out of range".to_string())]),
+                };
+                let popped = match self.store.write().unwrap().pop_from_list(key, end, count.unwrap_or(1)) {
+                    Ok(popped) => popped,
+                    Err(err) => return Ok(vec![RESP::Error(err.to_string())]),
+                };
+                Ok(vec![match (popped, count) {
+                    (None, _) => RESP::Null,
+                    // without a count a single element is returned
+                    (Some(mut popped), None) => popped.pop().map_or(RESP::Null, RESP::Bulk),
+                    (Some(popped), Some(_)) => RESP::Array(popped.into_iter().map(RESP::Bulk).collect()),
+                }])
+            }
+            (Command::LLEN, [key]) => {
+                // minimal implementation of https://redis.io/docs/latest/commands/llen/
+                // LLEN key
+                Ok(vec![
+                    self.store.read().unwrap().list_len(key)
+                        .map_or_else(|err| RESP::Error(err.to_string()), |len| RESP::Int(len as i64))
+                ])
+            }
+            (Command::LRANGE, [key, start, stop]) => {
+                // minimal implementation of https://redis.io/docs/latest/commands/lrange/
+                // LRANGE key start stop
+                let (Ok(start), Ok(stop)) = (start.parse::<i64>(), stop.parse::<i64>()) else {
+                    return Ok(vec![RESP::Error("ERR value is not an integer or out of range".to_string())]);
+                };
+                Ok(vec![
+                    self.store.read().unwrap().list_range(key, start, stop)
+                        .map_or_else(|err| RESP::Error(err.to_string()), |elements| RESP::Array(elements.into_iter().map(RESP::Bulk).collect()))
+                ])
+            }
+            (Command::LINDEX, [key, index]) => {
+                // minimal implementation of https://redis.io/docs/latest/commands/lindex/
+                // LINDEX key index
+                let Ok(index) = index.parse::<i64>() else {
+                    return Ok(vec![RESP::Error("ERR value is not an integer or out of range".to_string())]);
+                };
+                Ok(vec![
+                    self.store.read().unwrap().list_index(key, index)
+                        .map_or_else(|err| RESP::Error(err.to_string()), |element| element.map_or(RESP::Null, RESP::Bulk))
+                ])
+            }
+            (Command::LINSERT, [key, position, pivot, element]) => {
+                // minimal implementation of https://redis.io/docs/latest/commands/linsert/
+                // LINSERT key <BEFORE | AFTER> pivot element
+                let before = match position.to_uppercase().as_str() {
+                    "BEFORE" => true,
+                    "AFTER" => false,
+                    _ => return Ok(vec![RESP::Error("ERR syntax error".to_string())]),
+                };
+                Ok(vec![
+                    self.store.write().unwrap().list_insert(key, before, pivot, element)
+                        .map_or_else(|err| RESP::Error(err.to_string()), RESP::Int)
+                ])
+            }
+            (Command::LSET, [key, index, element]) => {
+                // minimal implementation of https://redis.io/docs/latest/commands/lset/
+                // LSET key index element
+                let Ok(index) = index.parse::<i64>() else {
+                    return Ok(vec![RESP::Error("ERR value is not an integer or out of range".to_string())]);
+                };
+                Ok(vec![
+                    self.store.write().unwrap().list_set(key, index, element)
+                        .map_or_else(|err| RESP::Error(err.to_string()), |_| RESP::String("OK".to_string()))
+                ])
+            }
+            (Command::LREM, [key, count, element]) => {
+                // minimal implementation of https://redis.io/docs/latest/commands/lrem/
+                // LREM key count element
+                let Ok(count) = count.parse::<i64>() else {
+                    return Ok(vec![RESP::Error("ERR value is not an integer or out of range".to_string())]);
+                };
+                Ok(vec![
+                    self.store.write().unwrap().list_remove(key, count, element)
+                        .map_or_else(|err| RESP::Error(err.to_string()), |removed| RESP::Int(removed as i64))
+                ])
+            }
+            (Command::LTRIM, [key, start, stop]) => {
+                // minimal implementation of https://redis.io/docs/latest/commands/ltrim/
+                // LTRIM key start stop
+                let (Ok(start), Ok(stop)) = (start.parse::<i64>(), stop.parse::<i64>()) else {
+                    return Ok(vec![RESP::Error("ERR value is not an integer or out of range".to_string())]);
+                };
+                Ok(vec![
+                    self.store.write().unwrap().list_trim(key, start, stop)
+                        .map_or_else(|err| RESP::Error(err.to_string()), |_| RESP::String("OK".to_string()))
+                ])
+            }
+            (Command::LMOVE, [source, destination, from, to]) => {
+                // minimal implementation of https://redis.io/docs/latest/commands/lmove/
+                // LMOVE source destination <LEFT | RIGHT> <LEFT | RIGHT>
+                let (Some(from), Some(to)) = (parse_list_end(from), parse_list_end(to)) else {
+                    return Ok(vec![RESP::Error("ERR syntax error".to_string())]);
+                };
+                Ok(vec![
+                    self.store.write().unwrap().move_list_element(source, destination, from, to)
+                        .map_or_else(|err| RESP::Error(err.to_string()), |moved| moved.map_or(RESP::Null, RESP::Bulk))
+                ])
+            }
+            (Command::BLPOP | Command::BRPOP, [keys @ .., timeout]) if !keys.is_empty() => {
+                // minimal implementation of https://redis.io/docs/latest/commands/blpop/
+                // BLPOP key [key ...] timeout
+                // BRPOP key [key ...] timeout
+                let timeout = match parse_block_timeout(timeout) {
+                    Ok(timeout) => timeout,
+                    Err(err) => return Ok(vec![err]),
+                };
+                let end = if cmd.0 == Command::BLPOP { ListEnd::Left } else { ListEnd::Right };
+                let popped = self.block_to_pop(keys, "list", timeout, |store, key| {
+                    Ok(store.pop_from_list(key, end, 1)?.and_then(|mut popped| popped.pop())
+                        .map(|element| RESP::Array(vec![RESP::Bulk(key.to_vec()), RESP::Bulk(element)])))
+                });
+                Ok(vec![popped.map_or_else(|err| RESP::Error(err.to_string()), |popped| popped.unwrap_or(RESP::Null))])
+            }
+            (Command::BLMOVE, [source, destination, from, to, timeout]) => {
+                // minimal implementation of https://redis.io/docs/latest/commands/blmove/
+                // BLMOVE source destination <LEFT | RIGHT> <LEFT | RIGHT> timeout
+                let (Some(from), Some(to)) = (parse_list_end(from), parse_list_end(to)) else {
+                    return Ok(vec![RESP::Error("ERR syntax error".to_string())]);
+                };
+                let timeout = match parse_block_timeout(timeout) {
+                    Ok(timeout) => timeout,
+                    Err(err) => return Ok(vec![err]),
+                };
+                let moved = self.block_to_pop(std::slice::from_ref(source), "list", timeout, |store, _| {
+                    Ok(store.move_list_element(source, destination, from, to)?.map(RESP::Bulk))
+                });
+                Ok(vec![moved.map_or_else(|err| RESP::Error(err.to_string()), |moved| moved.unwrap_or(RESP::Null))])
+            }
+            (Command::LMPOP, params) => {
+                // minimal implementation of https://redis.io/docs/latest/commands/lmpop/
+                // LMPOP numkeys key [key ...] <LEFT | RIGHT> [COUNT count]
+                let (keys, end, count) = match parse_mpop(params, parse_list_end) {
+                    Ok(parsed) => parsed,
+                    Err(err) => return Ok(vec![err]),
+                };
+                let mut store = self.store.write().unwrap();
+                let popped = keys.iter()
+                    .find_map(|key| pop_list_elements(&mut store, key, end, count).transpose())
+                    .transpose();
+                Ok(vec![popped.map_or_else(|err| RESP::Error(err.to_string()), |popped| popped.unwrap_or(RESP::Null))])
+            }
+            (Command::BLMPOP, [timeout, params @ ..]) => {
+                // minimal implementation of https://redis.io/docs/latest/commands/blmpop/
+                // BLMPOP timeout numkeys key [key ...] <LEFT | RIGHT> [COUNT count]
+                let (keys, end, count) = match parse_mpop(params, parse_list_end) {
+                    Ok(parsed) => parsed,
+                    Err(err) => return Ok(vec![err]),
+                };
+                let timeout = match parse_block_timeout(timeout) {
+                    Ok(timeout) => timeout,
+                    Err(err) => return Ok(vec![err]),
+                };
+                let popped = self.block_to_pop(keys, "list", timeout, |store, key| pop_list_elements(store, key, end, count));
+                Ok(vec![popped.map_or_else(|err| RESP::Error(err.to_string()), |popped| popped.unwrap_or(RESP::Null))])
+            }
+            (Command::SINTERCARD, [numkeys, params @ ..]) => {
+                // minimal implementation of https://redis.io/docs/latest/commands/sintercard/
+                // SINTERCARD numkeys key [key ...] [LIMIT limit]
+                let numkeys = match numkeys.parse::<usize>() {
+                    Ok(numkeys) if numkeys > 0 => numkeys,
+                    _ => return Ok(vec![RESP::Error("ERR numkeys should be greater than 0".to_string())]),
+                };
+                if numkeys > params.len() {
+                    return Ok(vec![RESP::Error("ERR Number of keys can't be greater than number of args".to_string())]);
+                }
+                let (keys, options) = params.split_at(numkeys);
+                let limit = match options {
+                    [] => 0,
+                    [option, limit] if option.eq_ignore_ascii_case(b"LIMIT") => match limit.parse::<usize>() {
+                        Ok(limit) => limit,
+                        Err(_) => return Ok(vec![RESP::Error("ERR LIMIT can't be negative".to_string())]),
+                    },
+                    _ => return Ok(vec![RESP::Error("ERR syntax error".to_string())]),
+                };
+                let sets = match self.set_snapshots(keys) {
+                    Ok(sets) => sets,
+                    Err(err) => return Ok(vec![RESP::Error(err.to_string())]),
+                };
+                // the intersection is computed on the snapshots without holding the store
+                let Some(mut sets) = sets.into_iter().collect::<Option<Vec<SetSnapshot>>>() else {
+                    // a missing key is an empty set
+                    return Ok(vec![RESP::Int(0)]);
+                };
+                sets.sort_by_key(|set| set.len());
+                let (smallest, others) = sets.split_first().expect("at least one key");
+                let mut budget = self.time_budget();
+                let mut cardinality = 0;
+                for member in smallest.iter() {
+                    if let Err(err) = budget.step() {
+                        return Ok(vec![RESP::Error(err.to_string())]);
+                    }
+                    if others.iter().all(|set| set.contains(member)) {
+                        cardinality += 1;
+                        if cardinality == limit {
+                            break;
+                        }
+                    }
+                }
+                Ok(vec![RESP::Int(cardinality as i64)])
+            }
+            (Command::SUNION | Command::SINTER | Command::SDIFF, keys) if !keys.is_empty() => {
+                // minimal implementation of https://redis.io/docs/latest/commands/sunion/
+                // SUNION key [key ...]
+                // SINTER key [key ...]
+                // SDIFF key [key ...]
+                let operation = SetOperation::of(&cmd.0).expect("set operation");
+                // the result is computed on the snapshots without holding the store
+                let result = self.set_snapshots(keys)
+                    .and_then(|sets| combine_sets(sets, operation, &mut self.time_budget()));
+                Ok(vec![result.map_or_else(
+                    |err| RESP::Error(err.to_string()),
+                    |members| RESP::Set(members.into_iter().map(RESP::Bulk).collect()),
+                )])
+            }
+            (Command::SUNIONSTORE | Command::SINTERSTORE | Command::SDIFFSTORE, [destination, keys @ ..]) if !keys.is_empty() => {
+                // minimal implementation of https://redis.io/docs/latest/commands/sunionstore/
+                // SUNIONSTORE destination key [key ...]
+                // SINTERSTORE destination key [key ...]
+                // SDIFFSTORE destination key [key ...]
+                let operation = SetOperation::of(&cmd.0).expect("set operation");
+                let result = self.store_combined_sets(destination, keys, operation);
+                Ok(vec![result.map_or_else(|err| RESP::Error(err.to_string()), |len| RESP::Int(len as i64))])
+            }
             (Command::TYPE, [key]) => {
                 // minimal implementation of https://redis.io/docs/latest/commands/type/
                 Ok(vec![
@@ -89,19 +1334,78 @@ impl RedisServer {
                     )
                 ])
             }
-            (Command::XADD, [key, id, key_value_pairs @ ..]) => {
+            (Command::OBJECT, [sub_command, key]) if sub_command.eq_ignore_ascii_case(b"ENCODING") => {
+                // minimal implementation of https://redis.io/docs/latest/commands/object-encoding/
+                // OBJECT ENCODING key
+                Ok(vec![self.store.read().unwrap().object_encoding(key).map_or(RESP::Null, RESP::bulk)])
+            }
+            (Command::MEMORY, [sub_command, params @ ..]) => {
+                // minimal implementation of https://redis.io/docs/latest/commands/memory-usage/
+                // MEMORY USAGE key [SAMPLES count]
+                // MEMORY DOCTOR
+                // MEMORY PURGE
+                match (sub_command.to_uppercase().as_str(), params) {
+                    ("USAGE", [key]) => {
+                        Ok(vec![self.store.read().unwrap().key_memory_usage(key).map_or(RESP::Null, |usage| RESP::Int(usage as i64))])
+                    }
+                    // all elements are accounted, the usage is not sampled
+                    ("USAGE", [key, samples, count]) if samples.eq_ignore_ascii_case(b"SAMPLES") && count.parse::<u64>().is_ok() => {
+                        Ok(vec![self.store.read().unwrap().key_memory_usage(key).map_or(RESP::Null, |usage| RESP::Int(usage as i64))])
+                    }
+                    ("DOCTOR", []) => {
+                        let stats = self.store.read().unwrap().memory_stats();
+                        let max_memory = self.config.read().unwrap().get_bytes("maxmemory").unwrap_or(0);
+                        let client_memory = self.client_memory.load(Ordering::Relaxed) as usize;
+                        let report = memory::doctor(&stats, client_memory, max_memory as usize, memory::rss_bytes());
+                        Ok(vec![RESP::bulk(&report)])
+                    }
+                    ("PURGE", []) => {
+                        // the values still queued for lazy freeing are freed first, so that their memory is returned too
+                        self.lazyfree.drain();
+                        memory::purge();
+                        Ok(vec![RESP::String("OK".to_string())])
+                    }
+                    _ => Ok(vec![RESP::Error(format!("ERR unknown subcommand or wrong number of arguments for '{}'", sub_command.text()))]),
+                }
+            }
+            (Command::DEBUG, [sub_command, params @ ..]) => {
+                // minimal implementation of https://redis.io/docs/latest/commands/debug/
+                // DEBUG OBJECT key
+                // DEBUG SLEEP seconds
+                match (sub_command.to_uppercase().as_str(), params) {
+                    ("OBJECT", [key]) => {
+                        Ok(vec![self.store.read().unwrap().debug_object(key)
+                            .map_or(RESP::Error("ERR no such key".to_string()), RESP::String)])
+                    }
+                    ("SLEEP", [seconds]) => {
+                        let Some(duration) = seconds.parse::<f64>().ok().and_then(|seconds| Duration::try_from_secs_f64(seconds).ok()) else {
+                            return Ok(vec![RESP::Error("ERR value is not a valid float".to_string())]);
+                        };
+                        // the store is held meanwhile, so that the other clients wait as they do for the single threaded redis
+                        let _store = self.store.write().unwrap();
+                        thread::sleep(duration);
+                        Ok(vec![RESP::String("OK".to_string())])
+                    }
+                    _ => Ok(vec![RESP::Error(format!("ERR unknown subcommand or wrong number of arguments for '{}'", sub_command.text()))]),
+                }
+            }
+            (Command::XADD, [key, params @ ..]) => {
                 // minimal implementation of https://redis.io/docs/latest/commands/xadd/
-                // XADD key id field value [field value ...]
+                // XADD key [NOMKSTREAM] [<MAXLEN | MINID> [= | ~] threshold [LIMIT count]] <* | id> field value [field value ...]
+                let (no_mkstream, trim, id, key_value_pairs) = match parse_xadd(params) {
+                    Ok(parsed) => parsed,
+                    Err(err) => return Ok(vec![RESP::Error(err.to_string())]),
+                };
 
                 let mut stream_data = vec![];
                 let mut iter = key_value_pairs.iter();
                 while let Some((key, value)) = iter.next().zip(iter.next()) {
-                    stream_data.push((key.to_string(), value.to_string()));
+                    stream_data.push((key.clone(), value.clone()));
                 }
                 Ok(vec![
                     self.store.write().unwrap()
-                        .insert_stream(key, id, stream_data)
-                        .map_or_else(|err| RESP::Error(err.to_string()), |new_id| RESP::bulk(&new_id))
+                        .insert_stream(key, &id.text(), stream_data, no_mkstream, trim.as_ref())
+                        .map_or_else(|err| RESP::Error(err.to_string()), |new_id| new_id.map_or(RESP::Null, |new_id| RESP::bulk(&new_id)))
                 ])
             }
             (Command::XRANGE, [key, from_id, to_id]) => {
@@ -109,13 +1413,13 @@ impl RedisServer {
                 // XRANGE key id-from id-to
 
                 let from_id =
-                    if from_id == "-" {
+                    if from_id == b"-" {
                         StreamRecordId::MIN
                     } else {
                         from_id.parse::<StreamRecordId>().or(from_id.parse::<u64>().map(|v| StreamRecordId::new(v, 0)))?
                     };
                 let to_id =
-                    if to_id == "+" {
+                    if to_id == b"+" {
                         StreamRecordId::MAX
                     } else {
                         to_id.parse::<StreamRecordId>().or(to_id.parse::<u64>().map(|v| StreamRecordId::new(v, u64::MAX)))?
@@ -138,12 +1442,12 @@ impl RedisServer {
                     Some(sub_params) => {
                         // XREAD stream key1 key2 id1 id2
                         let (keys, ids) = sub_params.split_at(sub_params.len() / 2);
-                        let key_id_pairs: HashMap<String, StreamRecordId> = {
-                            let mut pairs: HashMap<String, StreamRecordId> = HashMap::new();
+                        let mut key_id_pairs: HashMap<Vec<u8>, StreamRecordId> = {
+                            let mut pairs: HashMap<Vec<u8>, StreamRecordId> = HashMap::new();
                             let store = self.store.read().unwrap();
                             for (key, id) in keys.iter().zip(ids.iter()) {
-                                let key = key.to_string();
-                                let from_id = id.to_string();
+                                let key = key.clone();
+                                let from_id = id.text();
                                 let from_id = if from_id == "$" {
                                     store.latest_stream(&key)?
                                 } else {
@@ -163,7 +1467,8 @@ impl RedisServer {
                                     return Ok(vec![existing_values]);
                                 }
                                 // block until some data arrives
-                                if self.block_xread(block_ms, &key_id_pairs)? {
+                                let timeout = if block_ms == 0 { Duration::MAX } else { Duration::from_millis(block_ms) };
+                                if self.block_xread(timeout, &mut key_id_pairs)? {
                                     Ok(vec![RESP::Null])
                                 } else {
                                     Ok(vec![self.xread_values(keys, &key_id_pairs)?])
@@ -179,61 +1484,921 @@ impl RedisServer {
                 }
             }
 
-            (Command::KEYS, _) => {
+            (Command::XGROUP, [sub_command, key, group, params @ ..]) => {
+                // minimal implementation of https://redis.io/docs/latest/commands/xgroup/
+                // XGROUP CREATE key group id|$ [MKSTREAM]
+                // XGROUP DESTROY key group
+                // XGROUP SETID key group id|$
+                // XGROUP CREATECONSUMER key group consumer
+                // XGROUP DELCONSUMER key group consumer
+                let mut store = self.store.write().unwrap();
+                let result = match (sub_command.to_uppercase().as_str(), params) {
+                    ("CREATE", [id, options @ ..]) => match options {
+                        [] => parse_group_id(id).and_then(|id| store.create_stream_group(key, group, id, false)),
+                        [mkstream] if mkstream.eq_ignore_ascii_case(b"MKSTREAM") => {
+                            parse_group_id(id).and_then(|id| store.create_stream_group(key, group, id, true))
+                        }
+                        _ => Err(format_err!("ERR syntax error")),
+                    }.map(|_| RESP::String("OK".to_string())),
+                    ("DESTROY", []) => store.destroy_stream_group(key, group).map(|destroyed| RESP::Int(destroyed as i64)),
+                    ("SETID", [id]) => parse_group_id(id)
+                        .and_then(|id| store.set_stream_group_id(key, group, id))
+                        .map(|_| RESP::String("OK".to_string())),
+                    ("CREATECONSUMER", [consumer]) => store.create_stream_consumer(key, group, consumer)
+                        .map(|created| RESP::Int(created as i64)),
+                    ("DELCONSUMER", [consumer]) => store.delete_stream_consumer(key, group, consumer)
+                        .map(|pending| RESP::Int(pending as i64)),
+                    _ => Err(format_err!("ERR unknown subcommand or wrong number of arguments for '{}'", sub_command.text())),
+                };
+                Ok(vec![result.unwrap_or_else(|err| RESP::Error(err.to_string()))])
+            }
+            (Command::XREADGROUP, [group_option, group, consumer, options @ ..]) if group_option.eq_ignore_ascii_case(b"GROUP") => {
+                // minimal implementation of https://redis.io/docs/latest/commands/xreadgroup/
+                // XREADGROUP GROUP group consumer [COUNT count] [NOACK] STREAMS key [key ...] id [id ...]
+                Ok(vec![self.xreadgroup(group, consumer, options).unwrap_or_else(|err| RESP::Error(err.to_string()))])
+            }
+            (Command::XACK, [key, group, ids @ ..]) if !ids.is_empty() => {
+                // minimal implementation of https://redis.io/docs/latest/commands/xack/
+                // XACK key group id [id ...]
+                let result = ids.iter().map(|id| parse_stream_id(id)).collect::<Result<Vec<StreamRecordId>>>()
+                    .and_then(|ids| self.store.write().unwrap().ack_stream(key, group, &ids));
+                Ok(vec![result.map_or_else(|err| RESP::Error(err.to_string()), |acked| RESP::Int(acked as i64))])
+            }
+            (Command::XCLAIM, [key, group, consumer, min_idle_time, params @ ..]) if !params.is_empty() => {
+                // minimal implementation of https://redis.io/docs/latest/commands/xclaim/
+                // XCLAIM key group consumer min-idle-time id [id ...] [IDLE ms] [TIME unix-time-milliseconds]
+                //   [RETRYCOUNT count] [FORCE] [JUSTID] [LASTID lastid]
+                Ok(vec![self.xclaim(key, group, consumer, min_idle_time, params).unwrap_or_else(|err| RESP::Error(err.to_string()))])
+            }
+            (Command::XPENDING, [key, group, params @ ..]) => {
+                // minimal implementation of https://redis.io/docs/latest/commands/xpending/
+                // XPENDING key group [[IDLE min-idle-time] start end count [consumer]]
+                Ok(vec![self.xpending(key, group, params).unwrap_or_else(|err| RESP::Error(err.to_string()))])
+            }
+            (Command::XDEL, [key, ids @ ..]) if !ids.is_empty() => {
+                // minimal implementation of https://redis.io/docs/latest/commands/xdel/
+                // XDEL key id [id ...]
+                let result = ids.iter().map(|id| parse_stream_id(id)).collect::<Result<Vec<StreamRecordId>>>()
+                    .and_then(|ids| self.store.write().unwrap().delete_from_stream(key, &ids));
+                Ok(vec![result.map_or_else(|err| RESP::Error(err.to_string()), |deleted| RESP::Int(deleted as i64))])
+            }
+            (Command::XTRIM, [key, params @ ..]) => {
+                // minimal implementation of https://redis.io/docs/latest/commands/xtrim/
+                // XTRIM key <MAXLEN | MINID> [= | ~] threshold [LIMIT count]
+                let result = match Trim::parse(params) {
+                    Ok((trim, read)) if read == params.len() => self.store.write().unwrap().trim_stream(key, &trim),
+                    Ok(_) => Err(format_err!("ERR syntax error")),
+                    Err(err) => Err(err),
+                };
+                Ok(vec![result.map_or_else(|err| RESP::Error(err.to_string()), |trimmed| RESP::Int(trimmed as i64))])
+            }
+            (Command::XINFO, [sub_command, key, params @ ..]) => {
+                // minimal implementation of https://redis.io/docs/latest/commands/xinfo/
+                // XINFO STREAM key [FULL [COUNT count]]
+                // XINFO GROUPS key
+                // XINFO CONSUMERS key group
+                Ok(vec![self.xinfo(sub_command, key, params).unwrap_or_else(|err| RESP::Error(err.to_string()))])
+            }
+            (Command::XSETID, [key, last_id, options @ ..]) => {
+                // minimal implementation of https://redis.io/docs/latest/commands/xsetid/
+                // XSETID key last-id [ENTRIESADDED entries-added] [MAXDELETEDID max-deleted-id]
+                Ok(vec![self.xsetid(key, last_id, options).map_or_else(|err| RESP::Error(err.to_string()), |_| RESP::String("OK".to_string()))])
+            }
+
+            (Command::KEYS, [pattern]) => {
                 // minimal implementation of https://redis.io/docs/latest/commands/keys/
+                // KEYS pattern
                 Ok(vec![
-                    RESP::Array(
-                        self.store.read().unwrap().keys()
+                    self.store.read().unwrap().keys(pattern, &mut self.time_budget())
+                        .map_or_else(
+                            |err| RESP::Error(err.to_string()),
                             // wrap it in bulk
-                            .iter()
-                            .map(|v| RESP::bulk(v))
-                            .collect()
-                    )
+                            |keys| RESP::Array(keys.iter().map(|v| RESP::Bulk(v.to_vec())).collect()),
+                        )
                 ])
             }
 
-            (Command::INFO, [sub_command]) => {
-                // minimal implementation of https://redis.io/docs/latest/commands/info/
-                // INFO replication
-
-                match sub_command.to_ascii_uppercase().as_str() {
-                    "REPLICATION" => {
-                        let role = if !self.is_master { "slave" } else { "master" };
-                        let pairs = [
-                            ("role", role),
-                            ("master_replid", &self.master_replid),
-                            ("master_repl_offset", &format!("{}", self.log_store.read().unwrap().log_bytes))
-                        ];
-                        let info = pairs
-                            .map(|(k, v)| format!("{}:{}", k, v))
-                            .join("\r\n");
-                        Ok(vec![RESP::bulk(&info)])
-                    }
-                    // TODO implement other sub commands
-                    _ => bail!("unknown info command {:?}", sub_command),
-                }
-            }
-            (Command::CONFIG, [sub_command, key]) => {
-                // minimal implementation of https://redis.io/docs/latest/commands/info/
-                // INFO replication
+            (Command::SCAN, [cursor, options @ ..]) => {
+                // minimal implementation of https://redis.io/docs/latest/commands/scan/
+                // SCAN cursor [MATCH pattern] [COUNT count] [TYPE type]
+                let after = match self.scan_cursors.resume(cursor) {
+                    Ok(after) => after,
+                    Err(err) => return Ok(vec![RESP::Error(err.to_string())]),
+                };
+                let (mut pattern, mut count, mut value_type) = (None, 10, None);
+                for option in options.chunks(2) {
+                    match (option[0].to_uppercase().as_str(), option.get(1)) {
+                        ("MATCH", Some(value)) => pattern = Some(value.as_slice()),
+                        ("COUNT", Some(value)) => match value.parse::<usize>() {
+                            Ok(value) if value > 0 => count = value,
+                            Ok(_) => return Ok(vec![RESP::Error("ERR syntax error".to_string())]),
+                            Err(_) => return Ok(vec![RESP::Error("ERR value is not an integer or out of range".to_string())]),
+                        },
+                        ("TYPE", Some(value)) => value_type = Some(value.text()),
+                        _ => return Ok(vec![RESP::Error("ERR syntax error".to_string())]),
+                    }
+                }
+                let (keys, resume) = self.store.read().unwrap().scan(after.as_deref(), pattern, value_type.as_deref(), count);
+                Ok(vec![RESP::Array(vec![
+                    RESP::bulk(&self.scan_cursors.suspend(resume).to_string()),
+                    RESP::Array(keys.into_iter().map(RESP::Bulk).collect()),
+                ])])
+            }
+            (Command::INFO, sections) => {
+                // minimal implementation of https://redis.io/docs/latest/commands/info/
+                // INFO [section [section ...]]
+                Ok(vec![RESP::bulk(&self.info(sections))])
+            }
+            (Command::CONFIG, [sub_command]) if sub_command.eq_ignore_ascii_case(b"REWRITE") => {
+                // minimal implementation of https://redis.io/docs/latest/commands/config-rewrite/
+                // CONFIG REWRITE
+                let result = self.config.read().unwrap().rewrite();
+                Ok(vec![result.map_or_else(|err| RESP::Error(err.to_string()), |_| RESP::String("OK".to_string()))])
+            }
+            (Command::CONFIG, [sub_command, key, params @ ..]) => {
+                // minimal implementation of https://redis.io/docs/latest/commands/config-get/
+                // CONFIG GET parameter
+                // CONFIG SET parameter value
 
-                match (sub_command.to_uppercase().as_str(), key.to_lowercase().as_str()) {
-                    ("GET", "dir") => {
-                        Ok(vec![RESP::Array(vec![RESP::bulk(key), RESP::bulk(&self.db_dir)])])
+                match (sub_command.to_uppercase().as_str(), key.text().to_lowercase().as_str(), params) {
+                    ("GET", "dir", []) => {
+                        Ok(vec![RESP::Map(vec![(RESP::Bulk(key.clone()), RESP::bulk(&self.db_dir))])])
                     }
-                    ("GET", "dbfilename") => {
-                        Ok(vec![RESP::Array(vec![RESP::bulk(key), RESP::bulk(&self.db_filename)])])
+                    ("GET", "dbfilename", []) => {
+                        Ok(vec![RESP::Map(vec![(RESP::Bulk(key.clone()), RESP::bulk(&self.db_filename))])])
+                    }
+                    ("GET", name, []) => {
+                        Ok(vec![RESP::Map(
+                            self.config.read().unwrap().get(name)
+                                .map_or(vec![], |value| vec![(RESP::Bulk(key.clone()), RESP::bulk(value))])
+                        )])
+                    }
+                    ("SET", name, [value]) => {
+                        let previous = self.config.read().unwrap().get(name).map(|previous| previous.to_string());
+                        let result = self.config.write().unwrap().set(name, &value.text());
+                        if result.is_ok() && name == "notify-keyspace-events" {
+                            let flags = self.notify_flags();
+                            self.store.write().unwrap().set_notify_flags(flags);
+                        }
+                        if result.is_ok() && name == "repl-backlog-size" {
+                            let size = self.config.read().unwrap().get_bytes(name).unwrap_or_default();
+                            self.backlog.write().unwrap().resize(size as usize);
+                        }
+                        // setting the log file opens it again, also to continue a rotated log file
+                        if result.is_ok() && matches!(name, "loglevel" | "logfile" | "appendonly" | "appendfsync") {
+                            let configured = if matches!(name, "loglevel" | "logfile") { self.configure_log() } else { self.configure_aof() };
+                            if let Err(err) = configured {
+                                // the log and the AOF keep their file, the file which can't be opened is not configured
+                                self.config.write().unwrap().set(name, previous.as_deref().unwrap_or_default())?;
+                                return Ok(vec![RESP::Error(format!("ERR CONFIG SET failed (possibly related to argument '{}') - {}", name, err))]);
+                            }
+                        }
+                        Ok(vec![result.map_or_else(|err| RESP::Error(err.to_string()), |_| RESP::String("OK".to_string()))])
                     }
                     _ => bail!("unknown config command {:?}", sub_command),
                 }
             }
+            (Command::CLUSTER, [sub_command, params @ ..]) => {
+                // minimal implementation of https://redis.io/docs/latest/commands/cluster/
+                // CLUSTER COUNTKEYSINSLOT slot
+                // CLUSTER GETKEYSINSLOT slot count
+                // CLUSTER KEYSLOT key
+                // CLUSTER MEET ip port
+                // CLUSTER ADDSLOTS slot [slot ...]
+                // CLUSTER ADDSLOTSRANGE start-slot end-slot [start-slot end-slot ...]
+                // CLUSTER NODES | SLOTS | INFO | MYID
+                let sub_command = sub_command.to_uppercase();
+                let parse_slot = |slot: &Vec<u8>| match slot.parse::<u16>() {
+                    Ok(slot) if slot < SLOTS => Ok(slot),
+                    _ => Err(RESP::Error("ERR Invalid or out of range slot".to_string())),
+                };
+                match (sub_command.as_str(), params) {
+                    ("COUNTKEYSINSLOT", [slot]) => {
+                        let slot = match parse_slot(slot) { Ok(slot) => slot, Err(err) => return Ok(vec![err]) };
+                        return Ok(vec![RESP::Int(self.store.read().unwrap().count_keys_in_slot(slot) as i64)]);
+                    }
+                    ("GETKEYSINSLOT", [slot, count]) => {
+                        let slot = match parse_slot(slot) { Ok(slot) => slot, Err(err) => return Ok(vec![err]) };
+                        let Ok(count) = count.parse::<usize>() else {
+                            return Ok(vec![RESP::Error("ERR Invalid number of keys".to_string())]);
+                        };
+                        return Ok(vec![RESP::Array(
+                            self.store.read().unwrap().keys_in_slot(slot, count)
+                                .iter()
+                                .map(|k| RESP::Bulk(k.to_vec()))
+                                .collect()
+                        )]);
+                    }
+                    ("KEYSLOT", [key]) => return Ok(vec![RESP::Int(key_slot(key) as i64)]),
+                    _ => {}
+                }
+
+                let Some(cluster) = &self.cluster else {
+                    return Ok(vec![RESP::Error("ERR This instance has cluster support disabled".to_string())]);
+                };
+                match (sub_command.as_str(), params) {
+                    ("MEET", [host, port]) => {
+                        // the cluster bus of the node listens on a port above its client port
+                        let Some(port) = port.parse::<Port>().ok().filter(|port| cluster::bus_port(*port).is_some()) else {
+                            return Ok(vec![RESP::Error(format!("ERR Invalid base port specified: {}", port.text()))]);
+                        };
+                        cluster.meet(&host.text(), port);
+                        Ok(vec![RESP::String("OK".to_string())])
+                    }
+                    ("ADDSLOTS" | "ADDSLOTSRANGE", slots) if !slots.is_empty() => {
+                        let slots = match slots.iter().map(parse_slot).collect::<Result<Vec<u16>, RESP>>() {
+                            Ok(slots) => slots,
+                            Err(err) => return Ok(vec![err]),
+                        };
+                        let slots = if sub_command == "ADDSLOTS" {
+                            slots
+                        } else if slots.len() % 2 == 0 {
+                            slots.chunks(2).flat_map(|range| range[0]..=range[1]).collect()
+                        } else {
+                            return Ok(vec![RESP::Error("ERR wrong number of arguments for 'cluster|addslotsrange' command".to_string())]);
+                        };
+                        Ok(vec![
+                            cluster.add_slots(&slots)
+                                .map_or_else(|err| RESP::Error(err.to_string()), |_| RESP::String("OK".to_string()))
+                        ])
+                    }
+                    ("NODES", []) => Ok(vec![RESP::bulk(&cluster.nodes_description())]),
+                    ("SLOTS", []) => Ok(vec![cluster.slots_description()]),
+                    ("INFO", []) => Ok(vec![RESP::bulk(&cluster.info())]),
+                    ("MYID", []) => Ok(vec![RESP::bulk(&cluster.myself_id())]),
+                    _ => bail!("unknown cluster command {:?}", sub_command),
+                }
+            }
+            (Command::SAVE, []) => {
+                // minimal implementation of https://redis.io/docs/latest/commands/save/
+                // SAVE
+                Ok(vec![self.save()])
+            }
+            (Command::LASTSAVE, []) => {
+                // minimal implementation of https://redis.io/docs/latest/commands/lastsave/
+                // LASTSAVE
+                let last_save = self.persistence.read().unwrap().last_save;
+                Ok(vec![RESP::Int(last_save.duration_since(SystemTime::UNIX_EPOCH)?.as_secs() as i64)])
+            }
+            (Command::BGSAVE, params) if params.len() <= 1 => {
+                // minimal implementation of https://redis.io/docs/latest/commands/bgsave/
+                // BGSAVE [SCHEDULE]
+                // a save requested while the AOF is rewritten is refused, or scheduled with SCHEDULE
+                let schedule = match params.first() {
+                    None => false,
+                    Some(option) if option.eq_ignore_ascii_case(b"SCHEDULE") => true,
+                    Some(_) => return Ok(vec![RESP::Error("ERR syntax error".to_string())]),
+                };
+                Ok(vec![self.bgsave(schedule)?])
+            }
+            (Command::BGREWRITEAOF, []) => {
+                // minimal implementation of https://redis.io/docs/latest/commands/bgrewriteaof/
+                // BGREWRITEAOF
+                Ok(vec![self.bgrewriteaof()?])
+            }
 
             _ => bail!("Unknown or invalid command {:?}", cmd),
         }
     }
 
+    /// snapshots of the sets stored under the keys taken at the same time.
+    /// the store is only locked while taking the snapshots, so the sets can be read while it is modified
+    fn set_snapshots(&self, keys: &[Vec<u8>]) -> Result<Vec<Option<SetSnapshot>>> {
+        let store = self.store.read().unwrap();
+        keys.iter().map(|key| store.set_snapshot(key)).collect()
+    }
+
+    /// combines the sets and stores the result under the destination, returning its size.
+    ///
+    /// the result is computed on snapshots of the sets without holding the store, so commands on other keys
+    /// are not held up by big sets. the write lock is only taken to store the result, if none of the sets changed
+    /// since they were taken: a snapshot is shared, so any write to a set replaces it with a copy. that way replicas
+    /// computing the result from the same sets store the same members. when the sets keep changing, the result is
+    /// computed under the write lock after a few attempts
+    fn store_combined_sets(&self, destination: &[u8], keys: &[Vec<u8>], operation: SetOperation) -> Result<usize> {
+        for _ in 0..SET_OPERATION_ATTEMPTS {
+            let sets = self.set_snapshots(keys)?;
+            let members = combine_sets(sets.clone(), operation, &mut self.time_budget())?;
+            let mut store = self.store.write().unwrap();
+            let current = keys.iter().map(|key| store.set_snapshot(key)).collect::<Result<Vec<Option<SetSnapshot>>>>()?;
+            let unchanged = sets.iter().zip(&current).all(|(set, current)| match (set, current) {
+                (Some(set), Some(current)) => Arc::ptr_eq(set, current),
+                (None, None) => true,
+                _ => false,
+            });
+            if unchanged {
+                return Ok(store.replace_set(destination, members, operation.store_event()));
+            }
+        }
+        let mut store = self.store.write().unwrap();
+        let sets = keys.iter().map(|key| store.set_snapshot(key)).collect::<Result<Vec<Option<SetSnapshot>>>>()?;
+        let members = combine_sets(sets, operation, &mut self.time_budget())?;
+        Ok(store.replace_set(destination, members, operation.store_event()))
+    }
+
+    /// pauses client commands for the duration. an already active pause is only extended,
+    /// keeping the later deadline and the more restrictive mode
+    fn pause_clients(&self, timeout: Duration, mode: PauseMode) {
+        let (lock, condvar) = &*self.client_pause;
+        let mut pause = lock.lock().unwrap();
+        let mut new_pause = ClientPause { until: Instant::now() + timeout, mode };
+        if let Some(current) = pause.filter(|current| current.until > Instant::now()) {
+            new_pause.until = new_pause.until.max(current.until);
+            if current.mode > new_pause.mode {
+                new_pause.mode = current.mode;
+            }
+        }
+        *pause = Some(new_pause);
+        // waiting clients re-evaluate the new pause
+        condvar.notify_all();
+    }
+
+    fn unpause_clients(&self) {
+        let (lock, condvar) = &*self.client_pause;
+        *lock.lock().unwrap() = None;
+        condvar.notify_all();
+    }
+
+    /// whether the command would wait for a CLIENT PAUSE to end
+    pub(crate) fn is_client_paused(&self, cmd: &CommandRequest) -> bool {
+        self.client_pause.0.lock().unwrap().is_some_and(|current| current.holds(cmd))
+    }
+
+    /// blocks while the command is held back by a client pause
+    pub(crate) fn wait_client_pause(&self, cmd: &CommandRequest) {
+        let (lock, condvar) = &*self.client_pause;
+        let mut pause = lock.lock().unwrap();
+        while let Some(current) = pause.filter(|current| current.holds(cmd)) {
+            let remaining = current.until.saturating_duration_since(Instant::now());
+            pause = condvar.wait_timeout(pause, remaining).unwrap().0;
+        }
+    }
+
+    /// counts a connection accepted by the listener, which is served as a connected client
+    /// or rejected with the error to close it with when over maxclients or its source is not allowed to connect
+    pub(crate) fn accept_connection(&self, peer: &SocketAddr) -> Result<ConnectedClient, RESP> {
+        Stats::count(&self.stats.connections_received);
+        let max_clients = self.config.read().unwrap().get_bytes("maxclients").unwrap_or(0);
+        let denied = if self.connected_clients.load(Ordering::Relaxed) >= max_clients {
+            Some(RESP::Error("ERR max number of clients reached".to_string()))
+        } else {
+            self.check_connection_source(peer)
+        };
+        if let Some(denied) = denied {
+            Stats::count(&self.stats.rejected_connections);
+            return Err(denied);
+        }
+        self.connected_clients.fetch_add(1, Ordering::Relaxed);
+        Ok(ConnectedClient {
+            peer: *peer,
+            accepted_at: SystemTime::now(),
+            connected_clients: self.connected_clients.clone(),
+        })
+    }
+
+    /// error to close a new connection with if its source is not allowed to connect
+    fn check_connection_source(&self, peer: &SocketAddr) -> Option<RESP> {
+        let address = peer.ip();
+        // local clients can always connect, e.g. to fix the configuration
+        if address.is_loopback() || address.to_canonical().is_loopback() {
+            return None;
+        }
+        let config = self.config.read().unwrap();
+        let allowlist = config.get("client-allowlist").unwrap_or_default()
+            .split_whitespace()
+            .filter_map(|cidr| cidr.parse::<Cidr>().ok())
+            .collect::<Vec<Cidr>>();
+        if allowlist.is_empty() {
+            let all_interfaces = matches!(config.get("bind"), Some("0.0.0.0" | "::" | "*"));
+            if config.get_bool("protected-mode") && all_interfaces {
+                return Some(RESP::Error("DENIED Redis is running in protected mode because protected mode is enabled and no client allowlist is set. \
+In this mode connections are only accepted from the loopback interface. \
+Set client-allowlist to the networks allowed to connect, or disable protected mode with CONFIG SET protected-mode no".to_string()));
+            }
+            return None;
+        }
+        if allowlist.iter().any(|cidr| cidr.contains(&address)) {
+            None
+        } else {
+            Some(RESP::Error(format!("DENIED connections from {} are not in the client allowlist", address)))
+        }
+    }
+
+    /// records an administrative command received from a client in the audit log if it is enabled
+    pub(crate) fn audit(&self, client: &SocketAddr, cmd: &CommandRequest) {
+        let path = self.config.read().unwrap().get("audit-log").unwrap_or_default().to_string();
+        if let Err(err) = self.audit_log.log(&path, client, cmd) {
+            warning!("writing audit log {} failed: {}", path, err);
+        }
+    }
+
+    /// frames of replication connections are logged, as configured by repl-debug-frames
+    pub(crate) fn logs_replication_frames(&self) -> bool {
+        self.config.read().unwrap().get_bool("repl-debug-frames")
+    }
+
+    /// limits for reading client requests
+    pub(crate) fn request_limits(&self) -> RequestLimits {
+        let config = self.config.read().unwrap();
+        let defaults = RequestLimits::default();
+        RequestLimits {
+            max_bulk_len: config.get_bytes("proto-max-bulk-len").unwrap_or(defaults.max_bulk_len),
+            max_request_len: config.get_bytes("client-query-buffer-limit").unwrap_or(defaults.max_request_len),
+            max_multibulk_len: config.get_bytes("proto-max-multibulk-len").unwrap_or(defaults.max_multibulk_len),
+            max_depth: config.get_bytes("proto-max-multibulk-depth").map_or(defaults.max_depth, |depth| depth as usize),
+        }
+    }
+
+    /// count of random elements picked by SRANDMEMBER and HRANDFIELD. the elements repeat for a negative count,
+    /// so the reply is as long as its absolute value, which is limited as the number of elements of a request
+    fn random_count(&self, count: &[u8]) -> Result<i64> {
+        let Ok(count) = count.parse::<i64>() else {
+            bail!("ERR value is not an integer or out of range");
+        };
+        if count < 0 && count.unsigned_abs() > self.request_limits().max_multibulk_len {
+            bail!("ERR value is out of range");
+        }
+        Ok(count)
+    }
+
+    /// in cluster mode the keys of a command must hash to a single slot served by this node.
+    /// returns the redirection or error to respond with otherwise
+    pub(crate) fn check_cluster_slot(&self, cmd: &CommandRequest) -> Option<RESP> {
+        let cluster = self.cluster.as_ref()?;
+        let mut slots = cmd.keys().into_iter().map(key_slot);
+        let slot = slots.next()?;
+        if slots.any(|other| other != slot) {
+            return Some(RESP::Error("CROSSSLOT Keys in request don't hash to the same slot".to_string()));
+        }
+        match cluster.slot_owner(slot) {
+            None => Some(RESP::Error("CLUSTERDOWN Hash slot not served".to_string())),
+            Some(owner) if owner.id == cluster.myself_id() => None,
+            Some(owner) => Some(RESP::Error(format!("MOVED {} {}:{}", slot, owner.host, owner.port))),
+        }
+    }
+
+    /// background tasks of every server: the clock of key accesses and the sampling of metrics
+    pub(crate) fn schedule(&self, cron: &mut Cron) {
+        cron.every("lru-clock", Duration::ZERO, |_| update_lru_clock());
+
+        let server = self.clone();
+        let mut last_sample = (Instant::now(), 0);
+        cron.every("metrics", METRICS_SAMPLE_INTERVAL, move |_| {
+            let (sampled_at, commands) = last_sample;
+            let processed = server.stats.commands_processed.load(Ordering::Relaxed);
+            let elapsed_ms = sampled_at.elapsed().as_millis().max(1) as u64;
+            server.ops_per_sec.store((processed - commands) * 1000 / elapsed_ms, Ordering::Relaxed);
+            last_sample = (Instant::now(), processed);
+        });
+
+        let server = self.clone();
+        cron.every("save-rules", SAVE_RULES_INTERVAL, move |_| server.save_by_rules());
+
+        let server = self.clone();
+        cron.every("aof-flush", Duration::ZERO, move |_| server.aof.flush());
+
+        let server = self.clone();
+        cron.every("clients-timeout", CLIENTS_TIMEOUT_INTERVAL, move |_| {
+            let timeout_secs = server.config.read().unwrap().get("timeout")
+                .and_then(|timeout| timeout.parse::<u64>().ok())
+                .unwrap_or(0);
+            if timeout_secs > 0 {
+                let closed = server.clients.close_idle(Duration::from_secs(timeout_secs));
+                if closed > 0 {
+                    verbose!("closing {} idle clients", closed);
+                }
+            }
+        });
+    }
+
+    /// text of INFO with the sections requested, in the order of the sections of redis.
+    /// all sections without a section or with all, default or everything. unknown sections are left out
+    fn info(&self, sections: &[Vec<u8>]) -> String {
+        let requested = sections.iter().map(|section| section.text().to_lowercase()).collect::<Vec<String>>();
+        let all = requested.is_empty() || requested.iter().any(|section| matches!(section.as_str(), "all" | "default" | "everything"));
+        INFO_SECTIONS.iter()
+            .filter(|section| all || requested.iter().any(|requested| requested == *section))
+            .map(|section| {
+                let fields = self.info_section(section).iter()
+                    .map(|(name, value)| format!("{}:{}\r\n", name, value))
+                    .collect::<String>();
+                let mut title = section.to_string();
+                title[..1].make_ascii_uppercase();
+                format!("# {}\r\n{}", title, fields)
+            })
+            .collect::<Vec<String>>()
+            .join("\r\n")
+    }
+
+    /// fields of a section of INFO
+    fn info_section(&self, section: &str) -> Vec<(&'static str, String)> {
+        match section {
+            "server" => {
+                let uptime = self.started_at.elapsed().as_secs();
+                let now_us = SystemTime::now().duration_since(SystemTime::UNIX_EPOCH).unwrap_or_default().as_micros();
+                let executable = std::env::current_exe().map(|path| path.display().to_string()).unwrap_or_default();
+                vec![
+                    ("redis_version", REDIS_VERSION.to_string()),
+                    ("redis_mode", if self.cluster.is_some() { "cluster" } else { "standalone" }.to_string()),
+                    ("os", format!("{} {}", std::env::consts::OS, std::env::consts::ARCH)),
+                    ("arch_bits", usize::BITS.to_string()),
+                    ("process_id", std::process::id().to_string()),
+                    ("tcp_port", self.binding.1.to_string()),
+                    ("server_time_usec", now_us.to_string()),
+                    ("uptime_in_seconds", uptime.to_string()),
+                    ("uptime_in_days", (uptime / 86400).to_string()),
+                    ("hz", self.config.read().unwrap().get("hz").unwrap_or_default().to_string()),
+                    ("executable", executable),
+                ]
+            }
+            "clients" => {
+                let counts = self.clients.counts();
+                vec![
+                    ("connected_clients", self.connected_clients.load(Ordering::Relaxed).to_string()),
+                    ("maxclients", self.config.read().unwrap().get("maxclients").unwrap_or_default().to_string()),
+                    ("blocked_clients", counts.blocked.to_string()),
+                    ("pubsub_clients", counts.pubsub.to_string()),
+                ]
+            }
+            "memory" => {
+                let used_memory = self.store.read().unwrap().used_memory() as u64;
+                let rss = memory::rss_bytes();
+                let config = self.config.read().unwrap();
+                let max_memory = config.get_bytes("maxmemory").unwrap_or(0);
+                vec![
+                    ("used_memory", used_memory.to_string()),
+                    ("used_memory_human", memory::human_bytes(used_memory)),
+                    ("used_memory_clients", self.client_memory.load(Ordering::Relaxed).to_string()),
+                    ("used_memory_rss", rss.unwrap_or(0).to_string()),
+                    ("mem_fragmentation_ratio", format!("{:.2}", rss.map_or(0.0, |rss| memory::fragmentation_ratio(rss, used_memory as usize)))),
+                    ("maxmemory", max_memory.to_string()),
+                    ("maxmemory_human", memory::human_bytes(max_memory)),
+                    ("maxmemory_policy", config.get("maxmemory-policy").unwrap_or_default().to_string()),
+                    ("lazyfree_pending_objects", self.lazyfree.pending().to_string()),
+                ]
+            }
+            "persistence" => {
+                let writes = self.store.read().unwrap().write_count();
+                let persistence = self.persistence.read().unwrap();
+                let last_save = persistence.last_save.duration_since(SystemTime::UNIX_EPOCH).unwrap_or_default();
+                let status = |ok: bool| if ok { "ok" } else { "err" };
+                let last_secs = |secs: Option<u64>| secs.map_or("-1".to_string(), |secs| secs.to_string());
+                let mut fields = vec![
+                    ("loading", "0".to_string()),
+                    ("rdb_changes_since_last_save", writes.saturating_sub(persistence.saved_writes).to_string()),
+                    ("rdb_bgsave_in_progress", (persistence.is_running(PersistenceJob::Save) as u8).to_string()),
+                    ("rdb_last_save_time", last_save.as_secs().to_string()),
+                    ("rdb_bgsave_scheduled", (persistence.is_scheduled(PersistenceJob::Save) as u8).to_string()),
+                    ("rdb_last_bgsave_status", status(persistence.last_bgsave_ok).to_string()),
+                    ("rdb_last_bgsave_time_sec", last_secs(persistence.last_bgsave_secs)),
+                    ("rdb_current_bgsave_time_sec", persistence.running_secs(PersistenceJob::Save).to_string()),
+                    ("aof_rewrite_in_progress", (persistence.is_running(PersistenceJob::RewriteAof) as u8).to_string()),
+                    ("aof_rewrite_scheduled", (persistence.is_scheduled(PersistenceJob::RewriteAof) as u8).to_string()),
+                    ("aof_last_rewrite_time_sec", last_secs(persistence.last_aof_rewrite_secs)),
+                    ("aof_current_rewrite_time_sec", persistence.running_secs(PersistenceJob::RewriteAof).to_string()),
+                    ("aof_last_bgrewrite_status", status(persistence.last_aof_rewrite_ok).to_string()),
+                ];
+                fields.extend(self.aof.info());
+                fields
+            }
+            "stats" => {
+                let mut fields = self.stats.info();
+                fields.insert(2, ("instantaneous_ops_per_sec", self.ops_per_sec.load(Ordering::Relaxed).to_string()));
+                fields.push(("aborted_commands", self.aborted_commands.load(Ordering::Relaxed).to_string()));
+                fields.push(("lazyfreed_objects", self.lazyfree.freed().to_string()));
+                fields
+            }
+            "replication" => {
+                let backlog = self.backlog.read().unwrap();
+                vec![
+                    ("role", if self.is_master { "master" } else { "slave" }.to_string()),
+                    ("connected_slaves", self.clients.counts().replicas.to_string()),
+                    ("master_replid", self.master_replid.clone()),
+                    ("master_repl_offset", backlog.offset().to_string()),
+                    ("repl_backlog_active", "1".to_string()),
+                    ("repl_backlog_size", backlog.size().to_string()),
+                    // offsets of the bytes count from 1 in the INFO of redis
+                    ("repl_backlog_first_byte_offset", (backlog.first_offset() + 1).to_string()),
+                    ("repl_backlog_histlen", backlog.histlen().to_string()),
+                ]
+            }
+            "cluster" => vec![("cluster_enabled", (self.cluster.is_some() as u8).to_string())],
+            "keyspace" => {
+                let store = self.store.read().unwrap();
+                match store.key_count() {
+                    0 => vec![],
+                    keys => {
+                        let expires = store.volatile_key_count();
+                        vec![("db0", format!("keys={},expires={},avg_ttl={}", keys, expires, store.average_ttl_ms()))]
+                    }
+                }
+            }
+            _ => vec![],
+        }
+    }
+
+    /// applies loglevel and logfile to the log
+    fn configure_log(&self) -> Result<()> {
+        let config = self.config.read().unwrap();
+        let level = config.get("loglevel").unwrap_or_default().parse::<log::Level>()?;
+        log::configure(level, config.get("logfile").unwrap_or_default())?;
+        Ok(())
+    }
+
+    /// opens or closes the append only file as configured by appendonly, with the policy of appendfsync
+    fn configure_aof(&self) -> Result<()> {
+        let config = self.config.read().unwrap();
+        if !config.get_bool("appendonly") {
+            self.aof.close();
+            return Ok(());
+        }
+        let policy = config.get("appendfsync").unwrap_or_default().parse::<FsyncPolicy>()?;
+        let path = Path::new(&self.db_dir).join(config.get("appendfilename").unwrap_or_default());
+        self.aof.open(&path, policy)
+    }
+
+    /// classes of keyspace events and channels they are published to, as configured by notify-keyspace-events
+    fn notify_flags(&self) -> NotifyFlags {
+        self.config.read().unwrap().get("notify-keyspace-events")
+            .and_then(|flags| flags.parse::<NotifyFlags>().ok())
+            .unwrap_or_default()
+    }
+
+    /// publishes the keyspace events recorded by the store since the last call,
+    /// each event to the keyspace channel of the key and the key to the keyevent channel of the event
+    pub(crate) fn publish_keyspace_events(&self) {
+        if !self.store.read().unwrap().has_notifications() {
+            return;
+        }
+        let (flags, notifications) = self.store.write().unwrap().take_notifications();
+        for (event, key) in notifications {
+            if flags.keyspace {
+                self.pubsub.publish(&[b"__keyspace@0__:", &key[..]].concat(), event.as_bytes());
+            }
+            if flags.keyevent {
+                self.pubsub.publish(format!("__keyevent@0__:{}", event).as_bytes(), &key);
+            }
+        }
+    }
+
+    /// counts a command received from a client
+    /// versions of the keys, None for missing keys, read at once so that they are consistent
+    pub(crate) fn key_versions(&self, keys: &[Vec<u8>]) -> Vec<Option<u64>> {
+        let store = self.store.read().unwrap();
+        keys.iter().map(|key| store.version(key)).collect()
+    }
+
+    pub(crate) fn command_received(&self) {
+        Stats::count(&self.stats.commands_processed);
+    }
+
+    /// removes a sample of the expired keys within the time limit, returning the removed keys
+    pub(crate) fn active_expire(&self, time_limit: Duration) -> Vec<Vec<u8>> {
+        let expired = self.store.write().unwrap().expire_sampled(ACTIVE_EXPIRE_SAMPLES, time_limit);
+        let (keys, entries): (Vec<Vec<u8>>, Vec<StoreEntry>) = expired.into_iter().unzip();
+        if !keys.is_empty() {
+            debug!("expired {} keys", keys.len());
+        }
+        self.publish_keyspace_events();
+        if self.config.read().unwrap().get_bool("lazyfree-lazy-expire") {
+            self.lazyfree.free(entries);
+        }
+        keys
+    }
+
+    /// time budget of a command starting now, as configured by command-time-budget
+    fn time_budget(&self) -> TimeBudget {
+        let limit_ms = self.config.read().unwrap().get("command-time-budget")
+            .and_then(|limit| limit.parse::<u64>().ok())
+            .unwrap_or(0);
+        TimeBudget::new(Duration::from_millis(limit_ms), self.aborted_commands.clone())
+    }
+
+    /// evicts keys according to maxmemory-policy once the used memory exceeds maxmemory.
+    /// returns the evicted keys and whether the used memory is still over the limit
+    pub(crate) fn perform_evictions(&self) -> (Vec<Vec<u8>>, bool) {
+        let config = self.config.read().unwrap();
+        let max_memory = config.get_bytes("maxmemory").unwrap_or(0) as usize;
+        if max_memory == 0 || self.store.read().unwrap().used_memory() <= max_memory {
+            return (vec![], false);
+        }
+        let policy = config.get("maxmemory-policy")
+            .and_then(|policy| policy.parse::<EvictionPolicy>().ok())
+            .unwrap_or(EvictionPolicy::NoEviction);
+        let samples = config.get("maxmemory-samples")
+            .and_then(|samples| samples.parse::<usize>().ok())
+            .unwrap_or(5);
+        let lazy = config.get_bool("lazyfree-lazy-eviction");
+        drop(config);
+
+        let mut store = self.store.write().unwrap();
+        let evicted = store.evict(max_memory, policy, samples);
+        let over_limit = store.used_memory() > max_memory;
+        drop(store);
+        if !evicted.is_empty() {
+            verbose!("evicted {} keys with {:?}", evicted.len(), policy);
+        }
+        self.publish_keyspace_events();
+        let (keys, entries): (Vec<Vec<u8>>, Vec<StoreEntry>) = evicted.into_iter().unzip();
+        if lazy {
+            self.lazyfree.free(entries);
+        }
+        (keys, over_limit)
+    }
+
+    /// writes are refused while the last background save failed and stop-writes-on-bgsave-error is set.
+    /// returns the error to respond with if writes are not allowed
+    pub(crate) fn check_writes_allowed(&self) -> Option<RESP> {
+        if self.config.read().unwrap().get_bool("stop-writes-on-bgsave-error")
+            && !self.persistence.read().unwrap().last_bgsave_ok {
+            return Some(RESP::Error("MISCONF Redis is configured to save RDB snapshots, but it's currently unable to persist to disk. \
+Commands that may modify the data set are disabled, because this instance is configured to report errors during writes if RDB snapshotting fails \
+(stop-writes-on-bgsave-error option). Please check the Redis logs for details about the RDB error.".to_string()));
+        }
+        None
+    }
+
+    /// starts a background save once any rule of the save parameter is met: the number of writes
+    /// since the last save reached the changes of the rule, and the seconds of the rule passed since the last save.
+    /// after a failed save, the next one is tried once the retry delay passed
+    fn save_by_rules(&self) {
+        let rules = config::save_rules(self.config.read().unwrap().get("save").unwrap_or_default());
+        if rules.is_empty() {
+            return;
+        }
+        let writes = self.store.read().unwrap().write_count();
+        let persistence = self.persistence.read().unwrap();
+        if persistence.running.is_some() || persistence.is_scheduled(PersistenceJob::Save) {
+            return;
+        }
+        let changes = writes.saturating_sub(persistence.saved_writes);
+        let since_save = persistence.last_save.elapsed().unwrap_or_default();
+        let retry_delayed = !persistence.last_bgsave_ok
+            && persistence.last_bgsave_try.is_some_and(|tried| tried.elapsed() < SAVE_RETRY_DELAY);
+        drop(persistence);
+        if retry_delayed {
+            return;
+        }
+        let rule = rules.iter().find(|(secs, min_changes)| changes >= *min_changes && since_save.as_secs() >= *secs);
+        if let Some((secs, min_changes)) = rule {
+            notice!("{} changes in {} seconds. Saving...", min_changes, secs);
+            if let Err(err) = self.bgsave(false) {
+                warning!("starting the background save failed: {}", err);
+            }
+        }
+    }
+
+    /// saves the store in the foreground, holding off writes until the RDB file is written
+    fn save(&self) -> RESP {
+        if self.persistence.read().unwrap().is_running(PersistenceJob::Save) {
+            return RESP::Error("ERR Background save already in progress".to_string());
+        }
+        let store = self.store.read().unwrap();
+        let result = self.save_rdb(&store);
+        let writes = store.write_count();
+        drop(store);
+        match result {
+            Ok(()) => {
+                notice!("DB saved on disk");
+                let mut persistence = self.persistence.write().unwrap();
+                persistence.last_bgsave_ok = true;
+                persistence.saved(writes);
+                RESP::String("OK".to_string())
+            }
+            Err(err) => {
+                warning!("saving the DB failed: {}", err);
+                RESP::Error(format!("ERR {}", err))
+            }
+        }
+    }
+
+    /// saves a snapshot of the store in a background thread. while the AOF is rewritten the save is scheduled
+    /// if requested, otherwise it is refused
+    fn bgsave(&self, schedule: bool) -> Result<RESP> {
+        let mut persistence = self.persistence.write().unwrap();
+        if !schedule && persistence.is_running(PersistenceJob::RewriteAof) {
+            return Ok(RESP::Error("ERR Another child process is active (AOF?): can't BGSAVE right now. \
+Use BGSAVE SCHEDULE in order to schedule a BGSAVE whenever possible.".to_string()));
+        }
+        let request = persistence.request(PersistenceJob::Save);
+        drop(persistence);
+        match request {
+            JobRequest::InProgress => return Ok(RESP::Error("ERR Background save already in progress".to_string())),
+            JobRequest::Scheduled => return Ok(RESP::String("Background saving scheduled".to_string())),
+            JobRequest::Started => {}
+        }
+        self.start_persistence_job(PersistenceJob::Save)?;
+        Ok(RESP::String("Background saving started".to_string()))
+    }
+
+    /// rewrites the append only file from a snapshot of the store in a background thread,
+    /// or schedules it while the RDB is saved
+    fn bgrewriteaof(&self) -> Result<RESP> {
+        match self.persistence.write().unwrap().request(PersistenceJob::RewriteAof) {
+            JobRequest::InProgress => return Ok(RESP::Error("ERR Background append only file rewriting already in progress".to_string())),
+            JobRequest::Scheduled => return Ok(RESP::String("Background append only file rewriting scheduled".to_string())),
+            JobRequest::Started => {}
+        }
+        self.start_persistence_job(PersistenceJob::RewriteAof)?;
+        Ok(RESP::String("Background append only file rewriting started".to_string()))
+    }
+
+    /// runs a job marked as running on a snapshot of the store taken now, then starts the job scheduled after it
+    fn start_persistence_job(&self, job: PersistenceJob) -> Result<()> {
+        let store = self.store.read().unwrap();
+        let (snapshot, writes) = (store.snapshot(), store.write_count());
+        if job == PersistenceJob::RewriteAof {
+            self.aof.start_rewrite();
+        }
+        drop(store);
+        let server = self.clone();
+        let name = match job {
+            PersistenceJob::Save => "bgsave",
+            PersistenceJob::RewriteAof => "bgrewriteaof",
+        };
+        thread::Builder::new()
+            .name(name.to_string())
+            .spawn(move || {
+                let result = match job {
+                    PersistenceJob::Save => server.save_rdb(&snapshot),
+                    PersistenceJob::RewriteAof => server.rewrite_aof(&snapshot),
+                };
+                match &result {
+                    Err(err) => warning!("{} failed: {}", name, err),
+                    Ok(_) => notice!("{} completed", name),
+                }
+                drop(snapshot);
+                let next = server.persistence.write().unwrap().complete(job, result.is_ok(), writes);
+                if let Some(next) = next {
+                    server.start_persistence_job(next).unwrap_or_else(|err| {
+                        warning!("starting scheduled {:?} failed: {}", next, err);
+                        server.persistence.write().unwrap().complete(next, false, 0);
+                    });
+                }
+            })?;
+        Ok(())
+    }
+
+    /// RDB snapshot of the store sent to a replica with a full resync, with the offset of the replication stream
+    /// it was taken at. no other command runs meanwhile, so the snapshot has the changes of the stream up to the
+    /// offset and none after it
+    pub(crate) fn replication_snapshot(&self) -> Result<(usize, Vec<u8>)> {
+        let _exclusive = self.exec_lock.exclusive();
+        let store = self.store.read().unwrap();
+        let offset = self.backlog.read().unwrap().offset();
+        let mut rdb = vec![];
+        store.save_rdb(&mut rdb, self.config.read().unwrap().get_bool("rdbchecksum"))?;
+        Ok((offset, rdb))
+    }
+
+    /// replaces the keys of the store with the snapshot received from the master with a full resync
+    pub(crate) fn load_replication_snapshot(&self, rdb: &[u8]) -> Result<()> {
+        let mut store = self.store.write().unwrap();
+        let removed = store.flush();
+        self.lazyfree.free_all(removed);
+        store.load_rdb(rdb, false, true)
+    }
+
+    /// writes the store into a temporary file which replaces the db file once complete
+    fn save_rdb(&self, store: &Store) -> Result<()> {
+        let db_file = Path::new(&self.db_dir).join(&self.db_filename);
+        let temp_file = Path::new(&self.db_dir).join(format!("temp-{}.rdb", std::process::id()));
+        let result = File::create(&temp_file)
+            .map_err(anyhow::Error::from)
+            .and_then(|file| {
+                let mut writer = BufWriter::new(file);
+                store.save_rdb(&mut writer, self.config.read().unwrap().get_bool("rdbchecksum"))?;
+                writer.into_inner()?.sync_all()?;
+                Ok(fs::rename(&temp_file, &db_file)?)
+            });
+        if result.is_err() {
+            let _ = fs::remove_file(&temp_file);
+        }
+        result
+    }
+
+    /// writes the commands recreating the store into a temporary file which replaces the append only file once complete
+    fn rewrite_aof(&self, store: &Store) -> Result<()> {
+        let appendfilename = self.config.read().unwrap().get("appendfilename").unwrap_or_default().to_string();
+        let aof_file = Path::new(&self.db_dir).join(appendfilename);
+        let temp_file = Path::new(&self.db_dir).join(format!("temp-rewriteaof-bg-{}.aof", std::process::id()));
+        let result = File::create(&temp_file)
+            .map_err(anyhow::Error::from)
+            .and_then(|file| {
+                let mut writer = BufWriter::new(file);
+                store.write_aof(&mut writer)?;
+                writer.into_inner()?.sync_all()?;
+                self.aof.finish_rewrite(&temp_file, &aof_file)
+            });
+        if result.is_err() {
+            self.aof.abort_rewrite();
+            let _ = fs::remove_file(&temp_file);
+        }
+        result
+    }
+
     /// read all stream values for the keys and minimal ids
-    fn xread_values(&self, keys: &[String], key_id_pairs: &HashMap<String, StreamRecordId>) -> Result<RESP> {
+    fn xread_values(&self, keys: &[Vec<u8>], key_id_pairs: &HashMap<Vec<u8>, StreamRecordId>) -> Result<RESP> {
         let mut all_results = vec![];
         let store = self.store.read().unwrap();
 
@@ -252,7 +2417,7 @@ impl RedisServer {
 
             all_results.push(
                 RESP::Array(vec![
-                    RESP::bulk(key),
+                    RESP::Bulk(key.clone()),
                     RESP::Array(results),
                 ])
             )
@@ -263,62 +2428,851 @@ impl RedisServer {
         )
     }
 
+    /// delivers entries of the streams to the consumer of the group
+    fn xreadgroup(&self, group: &[u8], consumer: &[u8], options: &[Vec<u8>]) -> Result<RESP> {
+        let Some(streams_position) = options.iter().position(|option| option.eq_ignore_ascii_case(b"STREAMS")) else {
+            bail!("ERR syntax error");
+        };
+        let (options, streams) = (&options[..streams_position], &options[streams_position + 1..]);
+        let mut count = usize::MAX;
+        let mut no_ack = false;
+        let mut timeout = None;
+        let mut options = options.iter();
+        while let Some(option) = options.next() {
+            match option.to_uppercase().as_str() {
+                "COUNT" => match options.next().map(|value| value.parse::<usize>()) {
+                    // a count of 0 is no limit
+                    Some(Ok(value)) if value > 0 => count = value,
+                    Some(Ok(_)) => {}
+                    Some(Err(_)) => bail!("ERR value is not an integer or out of range"),
+                    None => bail!("ERR syntax error"),
+                },
+                "BLOCK" => timeout = match options.next().map(|value| value.parse::<u64>()) {
+                    // a timeout of 0 blocks forever
+                    Some(Ok(0)) => Some(Duration::MAX),
+                    Some(Ok(block_ms)) => Some(Duration::from_millis(block_ms)),
+                    Some(Err(_)) => bail!("ERR timeout is not an integer or out of range"),
+                    None => bail!("ERR syntax error"),
+                },
+                "NOACK" => no_ack = true,
+                _ => bail!("ERR syntax error"),
+            }
+        }
+        if streams.is_empty() || streams.len() % 2 != 0 {
+            bail!("ERR Unbalanced 'xreadgroup' list of streams: for each stream key an ID or '>' must be specified.");
+        }
+        let (keys, ids) = streams.split_at(streams.len() / 2);
+        let ids = ids.iter()
+            .map(|id| if id == b">" { Ok(None) } else { parse_stream_id(id).map(Some) })
+            .collect::<Result<Vec<Option<StreamRecordId>>>>()?;
+
+        let started_at = Instant::now();
+        loop {
+            let mut last_delivered = {
+                let mut store = self.store.write().unwrap();
+                // all groups are checked first, so that a failed read delivers nothing
+                for key in keys {
+                    store.stream_group(key, group)?;
+                }
+                let mut results = vec![];
+                for (key, after) in keys.iter().zip(ids.iter()) {
+                    let delivery = store.read_stream_group(key, group, consumer, after.as_ref(), count, no_ack)?;
+                    // reading the history of the consumer replies even when nothing is pending
+                    if after.is_none() && delivery.is_empty() {
+                        continue;
+                    }
+                    results.push(RESP::Array(vec![RESP::Bulk(key.clone()), encode_delivery(&delivery, false)]));
+                }
+                // only reads of new entries block, reads of the history are served right away
+                if !results.is_empty() || timeout.is_none() || ids.iter().any(|id| id.is_some()) {
+                    return Ok(if results.is_empty() { RESP::Null } else { RESP::Array(results) });
+                }
+                keys.iter()
+                    .map(|key| Ok((key.clone(), store.stream_group(key, group)?.last_delivered.clone())))
+                    .collect::<Result<HashMap<Vec<u8>, StreamRecordId>>>()?
+            };
+            // entries added after the last delivered ones may be read by another consumer of the group first,
+            // then the read waits again for the rest of the timeout
+            let remaining = timeout.unwrap_or_default().saturating_sub(started_at.elapsed());
+            if remaining.is_zero() || self.block_xread(remaining, &mut last_delivered)? {
+                return Ok(RESP::Null);
+            }
+        }
+    }
+
+    /// changes the owner of pending entries of the group to the consumer
+    fn xclaim(&self, key: &[u8], group: &[u8], consumer: &[u8], min_idle_time: &[u8], params: &[Vec<u8>]) -> Result<RESP> {
+        let min_idle_ms = min_idle_time.parse::<u64>().map_err(|_| format_err!("ERR Invalid min-idle-time argument for XCLAIM"))?;
+        let mut ids = vec![];
+        let mut params = params.iter().peekable();
+        while let Some(id) = params.next_if(|param| param.parse::<StreamRecordId>().is_ok()) {
+            ids.push(parse_stream_id(id)?);
+        }
+        let now_ms = SystemTime::now().duration_since(SystemTime::UNIX_EPOCH)?.as_millis() as u64;
+        let mut claim = Claim { min_idle_ms, delivered_ms: now_ms, retry_count: None, force: false, just_id: false, last_id: None };
+        let integer = |value: Option<&Vec<u8>>| value
+            .and_then(|value| value.parse::<u64>().ok())
+            .ok_or_else(|| format_err!("ERR Invalid {} option argument for XCLAIM", value.map_or_else(Default::default, |v| v.text())));
+        while let Some(option) = params.next() {
+            match option.to_uppercase().as_str() {
+                "IDLE" => claim.delivered_ms = now_ms.saturating_sub(integer(params.next())?),
+                "TIME" => claim.delivered_ms = integer(params.next())?,
+                "RETRYCOUNT" => claim.retry_count = Some(integer(params.next())?),
+                "FORCE" => claim.force = true,
+                "JUSTID" => claim.just_id = true,
+                "LASTID" => claim.last_id = Some(parse_stream_id(params.next().map_or(b"", |id| id.as_slice()))?),
+                _ => bail!("ERR Unrecognized XCLAIM option '{}'", option.text()),
+            }
+        }
+        if ids.is_empty() {
+            bail!("ERR Invalid stream ID specified as stream command argument");
+        }
+        let claimed = self.store.write().unwrap().claim_stream(key, group, consumer, &ids, &claim)?;
+        Ok(encode_delivery(&claimed, claim.just_id))
+    }
+
+    /// information on a stream, its consumer groups or the consumers of a group
+    fn xinfo(&self, sub_command: &[u8], key: &[u8], params: &[Vec<u8>]) -> Result<RESP> {
+        let store = self.store.read().unwrap();
+        let stream = store.stream(key)?;
+        let now_ms = SystemTime::now().duration_since(SystemTime::UNIX_EPOCH)?.as_millis() as u64;
+        let entry = |record: Option<&StreamRecord>| record.map_or(RESP::Null, |record| {
+            encode_stream_entries(&(record.id.to_string(), &record.attributes))
+        });
+        let id = |id: &StreamRecordId| RESP::bulk(&id.to_string());
+        let last_id = stream.last_id()?;
+        let metadata = || vec![
+            RESP::bulk("length"), RESP::Int(stream.len() as i64),
+            RESP::bulk("last-generated-id"), id(&last_id),
+            RESP::bulk("max-deleted-entry-id"), id(stream.max_deleted_id()),
+            RESP::bulk("entries-added"), RESP::Int(stream.entries_added() as i64),
+            RESP::bulk("recorded-first-entry-id"), id(stream.first().map_or(&StreamRecordId::MIN, |record| &record.id)),
+        ];
+        // entries read by the group and the entries still to deliver to it, nil when it can't be told
+        let read_and_lag = |last_delivered: &StreamRecordId| {
+            let entries_read = stream.entries_read(last_delivered);
+            [
+                RESP::bulk("entries-read"), entries_read.map_or(RESP::Null, |read| RESP::Int(read as i64)),
+                RESP::bulk("lag"), entries_read.map_or(RESP::Null, |read| RESP::Int((stream.entries_added() - read) as i64)),
+            ]
+        };
+        match (sub_command.to_uppercase().as_str(), params) {
+            ("STREAM", []) => {
+                let mut info = metadata();
+                info.extend([
+                    RESP::bulk("groups"), RESP::Int(stream.groups().count() as i64),
+                    RESP::bulk("first-entry"), entry(stream.first()),
+                    RESP::bulk("last-entry"), entry(stream.last()),
+                ]);
+                Ok(RESP::Array(info))
+            }
+            ("STREAM", [full, options @ ..]) if full.eq_ignore_ascii_case(b"FULL") => {
+                let count = match options {
+                    [] => 10,
+                    [option, count] if option.eq_ignore_ascii_case(b"COUNT") => {
+                        count.parse::<usize>().map_err(|_| format_err!("ERR value is not an integer or out of range"))?
+                    }
+                    _ => bail!("ERR syntax error"),
+                };
+                // a count of 0 returns all entries
+                let count = if count == 0 { usize::MAX } else { count };
+                let mut info = metadata();
+                info.extend([
+                    RESP::bulk("entries"),
+                    RESP::Array(stream.records().iter().take(count).map(|record| entry(Some(record))).collect()),
+                ]);
+                let groups = stream.groups().map(|(name, group)| {
+                    let pending = group.pending.iter().take(count)
+                        .map(|(pending_id, pending)| RESP::Array(vec![
+                            id(pending_id),
+                            RESP::Bulk(pending.consumer.clone()),
+                            RESP::Int(pending.delivered_ms as i64),
+                            RESP::Int(pending.delivery_count as i64),
+                        ]))
+                        .collect();
+                    let consumers = group.consumers.iter().map(|(consumer_name, consumer)| {
+                        let pending = group.pending.iter()
+                            .filter(|(_, pending)| pending.consumer == *consumer_name)
+                            .take(count)
+                            .map(|(pending_id, pending)| RESP::Array(vec![
+                                id(pending_id),
+                                RESP::Int(pending.delivered_ms as i64),
+                                RESP::Int(pending.delivery_count as i64),
+                            ]))
+                            .collect();
+                        RESP::Array(vec![
+                            RESP::bulk("name"), RESP::Bulk(consumer_name.clone()),
+                            RESP::bulk("seen-time"), RESP::Int(consumer.seen_ms as i64),
+                            RESP::bulk("active-time"), RESP::Int(consumer.active_ms.map_or(-1, |active_ms| active_ms as i64)),
+                            RESP::bulk("pel-count"), RESP::Int(group.consumer_pending(consumer_name) as i64),
+                            RESP::bulk("pending"), RESP::Array(pending),
+                        ])
+                    }).collect();
+                    let mut group_info = vec![
+                        RESP::bulk("name"), RESP::Bulk(name.clone()),
+                        RESP::bulk("last-delivered-id"), id(&group.last_delivered),
+                    ];
+                    group_info.extend(read_and_lag(&group.last_delivered));
+                    group_info.extend([
+                        RESP::bulk("pel-count"), RESP::Int(group.pending.len() as i64),
+                        RESP::bulk("pending"), RESP::Array(pending),
+                        RESP::bulk("consumers"), RESP::Array(consumers),
+                    ]);
+                    RESP::Array(group_info)
+                }).collect();
+                info.extend([RESP::bulk("groups"), RESP::Array(groups)]);
+                Ok(RESP::Array(info))
+            }
+            ("GROUPS", []) => Ok(RESP::Array(stream.groups().map(|(name, group)| {
+                let mut info = vec![
+                    RESP::bulk("name"), RESP::Bulk(name.clone()),
+                    RESP::bulk("consumers"), RESP::Int(group.consumers.len() as i64),
+                    RESP::bulk("pending"), RESP::Int(group.pending.len() as i64),
+                    RESP::bulk("last-delivered-id"), id(&group.last_delivered),
+                ];
+                info.extend(read_and_lag(&group.last_delivered));
+                RESP::Array(info)
+            }).collect())),
+            ("CONSUMERS", [group_name]) => {
+                let group = store.stream_group(key, group_name)?;
+                Ok(RESP::Array(group.consumers.iter().map(|(name, consumer)| RESP::Array(vec![
+                    RESP::bulk("name"), RESP::Bulk(name.clone()),
+                    RESP::bulk("pending"), RESP::Int(group.consumer_pending(name) as i64),
+                    RESP::bulk("idle"), RESP::Int(now_ms.saturating_sub(consumer.seen_ms) as i64),
+                    RESP::bulk("inactive"), RESP::Int(consumer.active_ms.map_or(-1, |active_ms| now_ms.saturating_sub(active_ms) as i64)),
+                ])).collect()))
+            }
+            ("STREAM", _) => bail!("ERR syntax error"),
+            _ => bail!("ERR unknown subcommand or wrong number of arguments for '{}'", sub_command.text()),
+        }
+    }
+
+    /// sets the last id of the stream with its number of added entries and highest deleted id
+    fn xsetid(&self, key: &[u8], last_id: &[u8], options: &[Vec<u8>]) -> Result<()> {
+        let last_id = parse_stream_id(last_id)?;
+        let (mut entries_added, mut max_deleted_id) = (None, None);
+        for option in options.chunks(2) {
+            match (option[0].to_uppercase().as_str(), option.get(1)) {
+                ("ENTRIESADDED", Some(value)) => entries_added = Some(
+                    value.parse::<u64>().map_err(|_| format_err!("ERR entries_added must be positive"))?
+                ),
+                ("MAXDELETEDID", Some(id)) => max_deleted_id = Some(parse_stream_id(id)?),
+                _ => bail!("ERR syntax error"),
+            }
+        }
+        self.store.write().unwrap().set_stream_id(key, last_id, entries_added, max_deleted_id)
+    }
+
+    /// summary of the pending entries of the group, or the pending entries in a range of ids
+    fn xpending(&self, key: &[u8], group: &[u8], params: &[Vec<u8>]) -> Result<RESP> {
+        let store = self.store.read().unwrap();
+        let group = store.stream_group(key, group)?;
+        let (min_idle_ms, params) = match params {
+            [idle, min_idle_time, params @ ..] if idle.eq_ignore_ascii_case(b"IDLE") => {
+                (min_idle_time.parse::<u64>().map_err(|_| format_err!("ERR value is not an integer or out of range"))?, params)
+            }
+            params => (0, params),
+        };
+        let (start, end, count, consumer) = match params {
+            [] if min_idle_ms == 0 => {
+                let (Some((first, _)), Some((last, _))) = (group.pending.first_key_value(), group.pending.last_key_value()) else {
+                    return Ok(RESP::Array(vec![RESP::Int(0), RESP::Null, RESP::Null, RESP::Null]));
+                };
+                let mut consumers = BTreeMap::<&[u8], usize>::new();
+                for entry in group.pending.values() {
+                    *consumers.entry(&entry.consumer).or_default() += 1;
+                }
+                return Ok(RESP::Array(vec![
+                    RESP::Int(group.pending.len() as i64),
+                    RESP::bulk(&first.to_string()),
+                    RESP::bulk(&last.to_string()),
+                    RESP::Array(consumers.iter()
+                        .map(|(consumer, count)| RESP::Array(vec![RESP::Bulk(consumer.to_vec()), RESP::bulk(&count.to_string())]))
+                        .collect()),
+                ]));
+            }
+            [start, end, count] => (start, end, count, None),
+            [start, end, count, consumer] => (start, end, count, Some(consumer)),
+            _ => bail!("ERR syntax error"),
+        };
+        let start = if start == b"-" { StreamRecordId::MIN } else { parse_stream_id(start)? };
+        let end = if end == b"+" { StreamRecordId::MAX } else { parse_stream_id(end)? };
+        let count = count.parse::<usize>().map_err(|_| format_err!("ERR value is not an integer or out of range"))?;
+        if start > end {
+            return Ok(RESP::Array(vec![]));
+        }
+        let now_ms = SystemTime::now().duration_since(SystemTime::UNIX_EPOCH)?.as_millis() as u64;
+        Ok(RESP::Array(group.pending.range(start..=end)
+            .filter(|(_, entry)| match consumer {
+                Some(consumer) => entry.consumer == *consumer,
+                None => true,
+            })
+            .filter(|(_, entry)| now_ms.saturating_sub(entry.delivered_ms) >= min_idle_ms)
+            .take(count)
+            .map(|(id, entry)| RESP::Array(vec![
+                RESP::bulk(&id.to_string()),
+                RESP::Bulk(entry.consumer.clone()),
+                RESP::Int(now_ms.saturating_sub(entry.delivered_ms) as i64),
+                RESP::Int(entry.delivery_count as i64),
+            ]))
+            .collect()))
+    }
+
+    /// adds members to a sorted set, replies the number of new members, of new and updated members with CH,
+    /// or the score of the member with INCR
+    fn zadd(&self, key: &[u8], params: &[Vec<u8>]) -> Result<RESP> {
+        let mut options = AddOptions::default();
+        let mut changed = false;
+        let mut params = params;
+        while let [option, rest @ ..] = params {
+            match option.to_uppercase().as_str() {
+                "NX" => options.nx = true,
+                "XX" => options.xx = true,
+                "GT" => options.gt = true,
+                "LT" => options.lt = true,
+                "CH" => changed = true,
+                "INCR" => options.incr = true,
+                _ => break,
+            }
+            params = rest;
+        }
+        if params.is_empty() || !params.len().is_multiple_of(2) {
+            bail!("ERR syntax error");
+        }
+        if options.nx && options.xx {
+            bail!("ERR XX and NX options at the same time are not compatible");
+        }
+        if (options.gt && options.lt) || (options.nx && (options.gt || options.lt)) {
+            bail!("ERR GT, LT, and/or NX options at the same time are not compatible");
+        }
+        if options.incr && params.len() > 2 {
+            bail!("ERR INCR option supports a single increment-element pair");
+        }
+        let members = params.chunks(2)
+            .map(|pair| parse_score(&pair[0]).map(|score| (score, pair[1].clone())).ok_or_else(|| format_err!("ERR value is not a valid float")))
+            .collect::<Result<Vec<(f64, Vec<u8>)>>>()?;
+        let (added, updated, score) = self.store.write().unwrap().add_to_zset(key, &members, &options)?;
+        Ok(match score {
+            // INCR replies nil when the conditions prevented the update
+            _ if options.incr => score.map_or(RESP::Null, RESP::Double),
+            _ if changed => RESP::Int((added + updated) as i64),
+            _ => RESP::Int(added as i64),
+        })
+    }
+
+    /// members of a sorted set between ranks, scores or members, optionally with their scores
+    fn zrange(&self, key: &[u8], start: &[u8], stop: &[u8], params: &[Vec<u8>]) -> Result<RESP> {
+        let Some(selection) = parse_zrange(start, stop, params, true)? else {
+            return Ok(RESP::Array(vec![]));
+        };
+        let members = self.store.read().unwrap().zset_range(key, &selection.range, selection.rev, selection.limit)?;
+        Ok(RESP::Array(members.iter()
+            .flat_map(|(member, score)| {
+                let mut reply = vec![RESP::Bulk(member.clone())];
+                if selection.with_scores {
+                    reply.push(RESP::bulk(&format_score(*score)));
+                }
+                reply
+            })
+            .collect()))
+    }
+
+    /// stores the members of a sorted set between ranks, scores or members as the destination sorted set,
+    /// replies the number of stored members
+    fn zrangestore(&self, destination: &[u8], key: &[u8], start: &[u8], stop: &[u8], params: &[Vec<u8>]) -> Result<RESP> {
+        let selection = parse_zrange(start, stop, params, false)?;
+        let mut store = self.store.write().unwrap();
+        let members = match selection {
+            Some(selection) => store.zset_range(key, &selection.range, selection.rev, selection.limit)?,
+            None => {
+                // the source still has to be a sorted set
+                store.zset_len(key)?;
+                vec![]
+            }
+        };
+        Ok(RESP::Int(store.replace_zset(destination, members, "zrangestore") as i64))
+    }
+
     /**
     blocks for until either timeout or new records were added.
     returns true if it timed out.
+    the ids of the streams which expired while waiting are reset, so that the entries of a recreated stream are read.
      */
-    fn block_xread(&self, block_ms: u64, key_id_pairs: &HashMap<String, StreamRecordId>) -> Result<bool> {
-// wait for any of the keys to be added
-        let timeout = if block_ms == 0 { Duration::MAX } else { Duration::from_millis(block_ms) };
-        println!("will block for {:?}", timeout);
+    fn block_xread(&self, timeout: Duration, key_id_pairs: &mut HashMap<Vec<u8>, StreamRecordId>) -> Result<bool> {
+        // wait for any of the keys to be added
+        debug!("will block for {:?}", timeout);
 
-        let keys = key_id_pairs.keys().collect::<Vec<&String>>();
+        let keys = key_id_pairs.keys().cloned().collect::<Vec<Vec<u8>>>();
 
-        let is_acceptable = |stream_event: StreamEvent| {
-            key_id_pairs.get(&stream_event.key).map_or(false, |id| stream_event.id > *id)
-        };
+        let watch: Arc<KeyWatch> = Arc::new((Mutex::new(VecDeque::new()), Condvar::new()));
+        let _registration = WatchRegistration::new(&self.store, &keys, &watch);
+
+        {
+            let mut store = self.store.write().unwrap();
+            store.watch(&keys.iter().collect::<Vec<&Vec<u8>>>(), Arc::downgrade(&watch));
+            // entries added before the watch was registered
+            for (key, from_id) in key_id_pairs.iter() {
+                if !store.read_stream(key, from_id.clone(), StreamRecordId::MAX)?.is_empty() {
+                    return Ok(false);
+                }
+            }
+        }
 
-        let this_listener = Arc::new((Mutex::new(None), Condvar::new()));
-
-        // listeners will be removed passively
-        self.store.write().unwrap().add_listener(&keys, Arc::downgrade(&this_listener))?;
-
-        let (lock, cvar) = this_listener.deref();
-        let mut event_guard = lock.lock().unwrap();
-        while !event_guard.clone().map_or(false, is_acceptable) {
-            let result = cvar.wait_timeout_while(
-                event_guard, timeout,
-                |event| !event.clone().map_or(false, is_acceptable),
-            ).unwrap();
-            event_guard = result.0;
-            if result.1.timed_out() {
-                println!("timeout of the blocked xread");
+        let started_at = Instant::now();
+        let (lock, cvar) = watch.deref();
+        let mut events: VecDeque<KeyEvent> = VecDeque::new();
+        loop {
+            // events are queued in the order of the changes, so an expiration precedes the recreation of the key
+            while let Some(event) = events.pop_front() {
+                debug!("blocked xread received event #{} {:?} of {}", event.seq, event.kind, event.key.text());
+                match event.kind {
+                    KeyEventKind::Expired => {
+                        key_id_pairs.insert(event.key, StreamRecordId::MIN);
+                    }
+                    KeyEventKind::StreamAdded(id) => {
+                        if key_id_pairs.get(&event.key).is_some_and(|from_id| id > *from_id) {
+                            // continue by running the normal non-blocking op
+                            return Ok(false);
+                        }
+                    }
+                    KeyEventKind::ListPushed | KeyEventKind::ListPopped | KeyEventKind::SetAdded | KeyEventKind::SetRemoved
+                    | KeyEventKind::ZSetAdded | KeyEventKind::ZSetRemoved | KeyEventKind::RenamedFrom | KeyEventKind::RenamedTo | KeyEventKind::CopiedTo => {}
+                }
+            }
+            // transactions run while the client waits, the events are taken before waiting for them to complete
+            let timed_out;
+            (events, timed_out) = self.exec_lock.released_while(|| {
+                let remaining = timeout.saturating_sub(started_at.elapsed());
+                let (mut events, result) = cvar.wait_timeout_while(lock.lock().unwrap(), remaining, |events| events.is_empty()).unwrap();
+                (std::mem::take(&mut *events), result.timed_out())
+            });
+            if timed_out {
+                debug!("timeout of the blocked xread");
                 // timed-out, meaning no new values are added
                 return Ok(true);
             }
         }
-        // continue by running the normal non-blocking op
-        Ok(false)
+    }
+
+    /// takes a value from the first of the lists or sorted sets, as told by `value_type`, `pop` finds one in,
+    /// blocking until one is added or the timeout passes.
+    /// clients blocked on a key are served in the order they blocked, returns None on timeout
+    fn block_to_pop(
+        &self,
+        keys: &[Vec<u8>],
+        value_type: &str,
+        timeout: Duration,
+        pop: impl Fn(&mut Store, &[u8]) -> Result<Option<RESP>>,
+    ) -> Result<Option<RESP>> {
+        let watch: Arc<KeyWatch> = Arc::new((Mutex::new(VecDeque::new()), Condvar::new()));
+
+        let waiter_id = {
+            let mut store = self.store.write().unwrap();
+            for key in keys {
+                if let Some(popped) = pop(&mut store, key)? {
+                    return Ok(Some(popped));
+                }
+            }
+            store.wait_to_pop(keys, Arc::downgrade(&watch))
+        };
+        let _registration = WatchRegistration::new(&self.store, keys, &watch);
+
+        let started_at = Instant::now();
+        let (lock, cvar) = watch.deref();
+        let served = loop {
+            // transactions run while the client waits, the events are taken before waiting for them to complete
+            let pushed = self.exec_lock.released_while(|| {
+                let remaining = timeout.saturating_sub(started_at.elapsed());
+                let (mut events, result) = cvar.wait_timeout_while(lock.lock().unwrap(), remaining, |events| events.is_empty()).unwrap();
+                (!result.timed_out()).then(|| events.drain(..)
+                    .filter(|event| event.kind.may_fill())
+                    .map(|event| event.key)
+                    .collect::<Vec<Vec<u8>>>())
+            });
+            let Some(pushed) = pushed else {
+                break Ok(None);
+            };
+            // the events lock is released before taking the store lock, which is held while emitting events
+            let mut store = self.store.write().unwrap();
+            for key in pushed.iter().filter(|key| keys.contains(key)) {
+                // a value of another type renamed or copied to the key keeps the client waiting
+                if !store.is_next_pop_waiter(key, waiter_id) || store.get_type(key) != value_type {
+                    continue;
+                }
+                if let Some(popped) = pop(&mut store, key).transpose() {
+                    store.stop_waiting_to_pop(keys, waiter_id);
+                    return popped.map(Some);
+                }
+            }
+        };
+        self.store.write().unwrap().stop_waiting_to_pop(keys, waiter_id);
+        served
+    }
+
+    /// loads the append only file when appendonly is on and the file exists, as it has the most recent writes,
+    /// else the RDB file
+    fn load_data(&self) -> Result<()> {
+        let (aof_path, load_truncated) = {
+            let config = self.config.read().unwrap();
+            let aof_path = config.get_bool("appendonly")
+                .then(|| Path::new(&self.db_dir).join(config.get("appendfilename").unwrap_or_default()));
+            (aof_path, config.get_bool("aof-load-truncated"))
+        };
+        match aof_path {
+            Some(aof_path) if aof_path.exists() => self.load_aof(&aof_path, load_truncated),
+            _ => self.load_rds(),
+        }
+    }
+
+    /// replays the commands of the append only file. a command cut off at the end of the file is removed from it,
+    /// so that the commands appended from now on follow the last complete one
+    fn load_aof(&self, path: &Path, load_truncated: bool) -> Result<()> {
+        let file = File::open(path)?;
+        let contents = aof::read_commands(BufReader::new(file), load_truncated, |command| {
+            // commands failing with an error reply failed the same way when they were appended
+            self.handle_command(&command).map(|_| ())
+        })?;
+        if contents.truncated {
+            warning!("AOF file {:?} ends with a truncated command, truncating it to the {} bytes of the last complete command", path, contents.valid_len);
+            OpenOptions::new().write(true).open(path)?.set_len(contents.valid_len)?;
+        }
+        notice!("loaded AOF file: {:?}, {} commands", path, contents.commands);
+        Ok(())
     }
 
     fn load_rds(&self) -> Result<()> {
         let db_file = Path::new(&self.db_dir).join(&self.db_filename);
         if db_file.exists() {
             let file = File::open(&db_file)?;
-            self.store.write().unwrap().load_rdb(BufReader::new(file))?;
-            println!("loaded RDB file: {:?}", db_file);
+            let (skip_unknown, verify_checksum) = {
+                let config = self.config.read().unwrap();
+                (config.get_bool("rdb-skip-unknown-opcodes"), config.get_bool("rdbchecksum"))
+            };
+            self.store.write().unwrap().load_rdb(BufReader::new(file), skip_unknown, verify_checksum)?;
+            notice!("loaded RDB file: {:?}", db_file);
         } else {
-            println!("no db file found to load: {:?}", db_file);
+            notice!("no db file found to load: {:?}", db_file);
         }
         Ok(())
     }
 }
 
-fn encode_stream_entries(entries: &(String, &Vec<(String, String)>)) -> RESP {
+/// parses the end of a list given as LEFT or RIGHT
+fn parse_list_end(end: &[u8]) -> Option<ListEnd> {
+    match end.to_uppercase().as_str() {
+        "LEFT" => Some(ListEnd::Left),
+        "RIGHT" => Some(ListEnd::Right),
+        _ => None,
+    }
+}
+
+/// parses the end of a sorted set given as MIN or MAX, true for the highest scores
+fn parse_zset_end(end: &[u8]) -> Option<bool> {
+    match end.to_uppercase().as_str() {
+        "MIN" => Some(false),
+        "MAX" => Some(true),
+        _ => None,
+    }
+}
+
+/// parses the keys, the end and the count of the multi-key pops: numkeys key [key ...] end [COUNT count].
+/// a single element is popped without a count
+fn parse_mpop<E>(params: &[Vec<u8>], parse_end: impl Fn(&[u8]) -> Option<E>) -> Result<(&[Vec<u8>], E, usize), RESP> {
+    let syntax_error = || RESP::Error("ERR syntax error".to_string());
+    let numkeys = match params.first().map(|numkeys| numkeys.parse::<usize>()) {
+        Some(Ok(numkeys)) if numkeys > 0 => numkeys,
+        Some(_) => return Err(RESP::Error("ERR numkeys should be greater than 0".to_string())),
+        None => return Err(syntax_error()),
+    };
+    let Some((keys, [end, options @ ..])) = params.get(1..).filter(|rest| rest.len() > numkeys).map(|rest| rest.split_at(numkeys)) else {
+        return Err(syntax_error());
+    };
+    let end = parse_end(end).ok_or_else(syntax_error)?;
+    let count = match options {
+        [] => 1,
+        [option, count] if option.eq_ignore_ascii_case(b"COUNT") => match count.parse::<usize>() {
+            Ok(count) if count > 0 => count,
+            _ => return Err(RESP::Error("ERR count should be greater than 0".to_string())),
+        },
+        _ => return Err(syntax_error()),
+    };
+    Ok((keys, end, count))
+}
+
+/// pops up to count elements from the end of the list, as the key followed by the elements. None if the list is missing
+fn pop_list_elements(store: &mut Store, key: &[u8], end: ListEnd, count: usize) -> Result<Option<RESP>> {
+    Ok(store.pop_from_list(key, end, count)?
+        .filter(|popped| !popped.is_empty())
+        .map(|popped| RESP::Array(vec![
+            RESP::Bulk(key.to_vec()),
+            RESP::Array(popped.into_iter().map(RESP::Bulk).collect()),
+        ])))
+}
+
+/// pops up to count members from the end of the sorted set, as the key followed by the members with their scores.
+/// None if the sorted set is missing
+fn pop_zset_members(store: &mut Store, key: &[u8], max: bool, count: usize) -> Result<Option<RESP>> {
+    Ok(store.pop_from_zset(key, max, count)?
+        .filter(|popped| !popped.is_empty())
+        .map(|popped| RESP::Array(vec![
+            RESP::Bulk(key.to_vec()),
+            RESP::Array(popped.into_iter()
+                .map(|(member, score)| RESP::Array(vec![RESP::Bulk(member), RESP::bulk(&format_score(score))]))
+                .collect()),
+        ])))
+}
+
+/// parses the timeout of a blocking command in seconds with decimals, 0 blocks indefinitely
+fn parse_block_timeout(timeout: &[u8]) -> Result<Duration, RESP> {
+    match timeout.parse::<f64>() {
+        Ok(seconds) if seconds < 0.0 => Err(RESP::Error("ERR timeout is negative".to_string())),
+        Ok(0.0) => Ok(Duration::MAX),
+        Ok(seconds) => Duration::try_from_secs_f64(seconds)
+            .map_err(|_| RESP::Error("ERR timeout is out of range".to_string())),
+        Err(_) => Err(RESP::Error("ERR timeout is not a float or out of range".to_string())),
+    }
+}
+
+/// parses the expiration options of GETEX:
+/// EX seconds | PX milliseconds | EXAT unix-time-seconds | PXAT unix-time-milliseconds | PERSIST.
+/// returns None when the expiration is not changed and Some(None) when it is removed
+fn parse_expiration(options: &[Vec<u8>]) -> Result<Option<Option<SystemTime>>> {
+    let amount = |value: &Vec<u8>| match value.parse::<u64>() {
+        Ok(amount) if amount > 0 => Ok(amount),
+        _ => bail!("ERR invalid expire time in 'getex' command"),
+    };
+    let valid_until = match options {
+        [] => return Ok(None),
+        [option] if option.eq_ignore_ascii_case(b"PERSIST") => return Ok(Some(None)),
+        [option, value] => match option.to_uppercase().as_str() {
+            "EX" => SystemTime::now() + Duration::from_secs(amount(value)?),
+            "PX" => SystemTime::now() + Duration::from_millis(amount(value)?),
+            "EXAT" => SystemTime::UNIX_EPOCH + Duration::from_secs(amount(value)?),
+            "PXAT" => SystemTime::UNIX_EPOCH + Duration::from_millis(amount(value)?),
+            _ => bail!("ERR syntax error"),
+        },
+        _ => bail!("ERR syntax error"),
+    };
+    Ok(Some(Some(valid_until)))
+}
+
+/// parses the NX | XX | GT | LT options of the EXPIRE family of commands
+fn parse_expire_conditions(options: &[Vec<u8>]) -> Result<Vec<ExpireCondition>> {
+    let mut conditions = vec![];
+    for option in options {
+        conditions.push(match option.to_uppercase().as_str() {
+            "NX" => ExpireCondition::NX,
+            "XX" => ExpireCondition::XX,
+            "GT" => ExpireCondition::GT,
+            "LT" => ExpireCondition::LT,
+            other => bail!("ERR Unsupported option {}", other),
+        });
+    }
+    if conditions.contains(&ExpireCondition::NX) && conditions.iter().any(|c| *c != ExpireCondition::NX) {
+        bail!("ERR NX and XX, GT or LT options at the same time are not compatible");
+    }
+    if conditions.contains(&ExpireCondition::GT) && conditions.contains(&ExpireCondition::LT) {
+        bail!("ERR GT and LT options at the same time are not compatible");
+    }
+    Ok(conditions)
+}
+
+/// members selected by ZRANGE and ZRANGESTORE
+struct ZRangeSelection {
+    range: ZRange,
+    rev: bool,
+    /// offset of the first selected member and the number of members, all remaining members without a count
+    limit: Option<(usize, Option<usize>)>,
+    with_scores: bool,
+}
+
+/// parses the range and options of ZRANGE, None if the range is known to select nothing
+fn parse_zrange(start: &[u8], stop: &[u8], params: &[Vec<u8>], allow_with_scores: bool) -> Result<Option<ZRangeSelection>> {
+    let (mut by, mut rev, mut limit, mut with_scores) = ("RANK".to_string(), false, None, false);
+    let mut params = params.iter();
+    while let Some(option) = params.next() {
+        match option.to_uppercase().as_str() {
+            option @ ("BYSCORE" | "BYLEX") => by = option.to_string(),
+            "REV" => rev = true,
+            "WITHSCORES" if allow_with_scores => with_scores = true,
+            "LIMIT" => {
+                let (Some(Ok(offset)), Some(Ok(count))) = (params.next().map(|offset| offset.parse::<i64>()), params.next().map(|count| count.parse::<i64>())) else {
+                    bail!("ERR value is not an integer or out of range");
+                };
+                limit = Some((offset, count));
+            }
+            _ => bail!("ERR syntax error"),
+        }
+    }
+    // REV takes the bounds of scores and members from the highest to the lowest
+    let (min, max) = if rev { (stop, start) } else { (start, stop) };
+    let range = match by.as_str() {
+        "BYSCORE" => parse_zrange_by_score(min, max)?,
+        "BYLEX" => match parse_zrange_by_lex(min, max)? {
+            Some(range) => range,
+            None => return Ok(None),
+        },
+        _ if limit.is_some() => bail!("ERR syntax error, LIMIT is only supported in combination with either BYSCORE or BYLEX"),
+        _ => {
+            let (Ok(start), Ok(stop)) = (start.parse::<i64>(), stop.parse::<i64>()) else {
+                bail!("ERR value is not an integer or out of range");
+            };
+            ZRange::Rank(start, stop)
+        }
+    };
+    let limit = match limit {
+        // a negative offset selects nothing, a negative count selects all the remaining members
+        Some((offset, _)) if offset < 0 => return Ok(None),
+        Some((offset, count)) => Some((offset as usize, usize::try_from(count).ok())),
+        None => None,
+    };
+    Ok(Some(ZRangeSelection { range, rev, limit, with_scores }))
+}
+
+fn parse_zrange_by_score(min: &[u8], max: &[u8]) -> Result<ZRange> {
+    match (parse_score_bound(min), parse_score_bound(max)) {
+        (Some(min), Some(max)) => Ok(ZRange::Score(min, max)),
+        _ => bail!("ERR min or max is not a float"),
+    }
+}
+
+/// members between the bounds, None if the bounds select nothing
+fn parse_zrange_by_lex(min: &[u8], max: &[u8]) -> Result<Option<ZRange>> {
+    match (parse_lex_bound(min), parse_lex_bound(max)) {
+        // nothing is above + or below -
+        (Some(_), Some(_)) if min == b"+" || max == b"-" => Ok(None),
+        (Some(min), Some(max)) => Ok(Some(ZRange::Lex(min, max))),
+        _ => bail!("ERR min or max not valid string range item"),
+    }
+}
+
+#[derive(Clone, Copy)]
+enum SetOperation {
+    Union,
+    Intersection,
+    Difference,
+}
+
+impl SetOperation {
+    fn of(command: &Command) -> Option<Self> {
+        match command {
+            Command::SUNION | Command::SUNIONSTORE => Some(SetOperation::Union),
+            Command::SINTER | Command::SINTERSTORE => Some(SetOperation::Intersection),
+            Command::SDIFF | Command::SDIFFSTORE => Some(SetOperation::Difference),
+            _ => None,
+        }
+    }
+
+    /// keyspace event of storing the result
+    fn store_event(&self) -> &'static str {
+        match self {
+            SetOperation::Union => "sunionstore",
+            SetOperation::Intersection => "sinterstore",
+            SetOperation::Difference => "sdiffstore",
+        }
+    }
+}
+
+/// members of the union, intersection or difference of the sets, missing sets are empty.
+/// the difference is the members of the first set which are in none of the others
+fn combine_sets(sets: Vec<Option<SetSnapshot>>, operation: SetOperation, budget: &mut TimeBudget) -> Result<HashSet<Vec<u8>>> {
+    let mut result = HashSet::new();
+    match operation {
+        SetOperation::Union => {
+            for set in sets.iter().flatten() {
+                for member in set.iter() {
+                    budget.step()?;
+                    result.insert(member.clone());
+                }
+            }
+        }
+        SetOperation::Intersection => {
+            let Some(mut sets) = sets.into_iter().collect::<Option<Vec<SetSnapshot>>>() else {
+                return Ok(result);
+            };
+            sets.sort_by_key(|set| set.len());
+            let (smallest, others) = sets.split_first().expect("at least one key");
+            for member in smallest.iter() {
+                budget.step()?;
+                if others.iter().all(|set| set.contains(member)) {
+                    result.insert(member.clone());
+                }
+            }
+        }
+        SetOperation::Difference => {
+            let (Some(Some(first)), others) = (sets.first(), &sets[1..]) else {
+                return Ok(result);
+            };
+            for member in first.iter() {
+                budget.step()?;
+                if !others.iter().flatten().any(|set| set.contains(member)) {
+                    result.insert(member.clone());
+                }
+            }
+        }
+    }
+    Ok(result)
+}
+
+/// stream id given to a command, where the sequence number may be omitted
+fn parse_stream_id(id: &[u8]) -> Result<StreamRecordId> {
+    id.parse::<StreamRecordId>().map_err(|_| format_err!("ERR Invalid stream ID specified as stream command argument"))
+}
+
+/// arguments of XADD: whether a missing stream is not created, the trim applied after the append,
+/// the id and the fields with their values
+type XAddArgs<'a> = (bool, Option<Trim>, &'a [u8], &'a [Vec<u8>]);
+
+/// options of XADD before the id: whether a missing stream is not created and the trim applied after the append,
+/// followed by the id and the fields with their values
+fn parse_xadd(params: &[Vec<u8>]) -> Result<XAddArgs<'_>> {
+    let (mut no_mkstream, mut trim) = (false, None);
+    let mut position = 0;
+    while let Some(param) = params.get(position) {
+        match param.to_uppercase().as_str() {
+            "NOMKSTREAM" => {
+                no_mkstream = true;
+                position += 1;
+            }
+            "MAXLEN" | "MINID" => {
+                let (parsed, read) = Trim::parse(&params[position..])?;
+                trim = Some(parsed);
+                position += read;
+            }
+            _ => break,
+        }
+    }
+    match &params[position..] {
+        [id, key_value_pairs @ ..] if !key_value_pairs.is_empty() && key_value_pairs.len().is_multiple_of(2) => {
+            Ok((no_mkstream, trim, id, key_value_pairs))
+        }
+        _ => bail!("ERR wrong number of arguments for 'xadd' command"),
+    }
+}
+
+/// id a consumer group starts delivering after, None for `$`, the last entry of the stream
+fn parse_group_id(id: &[u8]) -> Result<Option<StreamRecordId>> {
+    if id == b"$" { Ok(None) } else { parse_stream_id(id).map(Some) }
+}
+
+/// entries delivered to a consumer, entries deleted from the stream have no fields
+fn encode_delivery(delivery: &Delivery, just_id: bool) -> RESP {
+    RESP::Array(delivery.iter().map(|(id, attributes)| {
+        if just_id {
+            return RESP::bulk(&id.to_string());
+        }
+        let fields = attributes.as_ref().map_or(RESP::Null, |attributes| {
+            RESP::Array(attributes.iter().flat_map(|(k, v)| [RESP::Bulk(k.clone()), RESP::Bulk(v.clone())]).collect())
+        });
+        RESP::Array(vec![RESP::bulk(&id.to_string()), fields])
+    }).collect())
+}
+
+fn encode_stream_entries(entries: &(String, &FieldValues)) -> RESP {
     let mut array = vec![];
     for (k, v) in entries.1 {
-        array.push(RESP::bulk(k));
-        array.push(RESP::bulk(v));
+        array.push(RESP::Bulk(k.clone()));
+        array.push(RESP::Bulk(v.clone()));
     }
     RESP::Array(vec![
         RESP::bulk(&entries.0),
@@ -1,20 +1,78 @@
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::mpsc::{self, RecvTimeoutError};
+use std::sync::{Arc, RwLock};
 use std::thread;
 use std::time::Duration;
 
-use anyhow::{bail, Result};
+use anyhow::Result;
 
-use crate::client::ReplicaClient;
+use crate::client::{PsyncReply, ReplicaClient};
 use crate::connection::ClientConnectionHandler;
 use crate::io::net::Binding;
+use crate::log::{debug, notice, warning};
 use crate::protocol::command::{Command, CommandRequest};
-use crate::protocol::resp::{RESP, RESPConnection};
+use crate::protocol::resp::RESP;
 use crate::redis::RedisServer;
 
+/// interval of the REPLCONF ACK the replica sends on its own to the master
+const REPLICA_ACK_INTERVAL: Duration = Duration::from_secs(1);
+
+/// offset in the replication stream received from the master.
+/// every command sent over the replication connection counts once it was processed, whether it was applied,
+/// failed or was ignored, as the master counts the bytes it sent. the RDB payload of a full resync is not part of the stream.
+/// clones share the offset, so that it is acknowledged by another thread than the one processing the stream
+#[derive(Clone, Default)]
+pub(crate) struct ReplicationOffset(Arc<AtomicUsize>);
+
+impl ReplicationOffset {
+    /// continues from the offset announced by the master in the FULLRESYNC response
+    pub(crate) fn full_resync(&self, offset: usize) {
+        self.0.store(offset, Ordering::Relaxed);
+    }
+
+    /// processes a command of the stream, then accounts its bytes whether it succeeded or not.
+    /// the reply to a GETACK is made while processing it, so that the acknowledged offset excludes the GETACK
+    pub(crate) fn process<R>(&self, message_bytes: usize, process: impl FnOnce() -> R) -> R {
+        let result = process();
+        self.0.fetch_add(message_bytes, Ordering::Relaxed);
+        result
+    }
+
+    /// offset reported to the master with REPLCONF ACK, which excludes the GETACK being answered
+    pub(crate) fn ack(&self) -> usize {
+        self.0.load(Ordering::Relaxed)
+    }
+
+    /// offset sent with PSYNC to continue the stream: of the byte following the last processed one, counting from 1 as in redis
+    pub(crate) fn psync_offset(&self) -> usize {
+        self.ack() + 1
+    }
+
+    /// REPLCONF ACK with the offset
+    fn ack_message(&self) -> RESP {
+        RESP::Array(vec![RESP::bulk("REPLCONF"), RESP::bulk("ACK"), RESP::bulk(&self.ack().to_string())])
+    }
+}
+
+/// state of the link to the master, shared by the replication connection with the client connections
+#[derive(Clone, Copy, Debug, PartialEq)]
+enum MasterLink {
+    /// not connected to the master, or the connection failed
+    Down,
+    /// connected, waiting for the snapshot or for the master to continue the stream
+    Syncing,
+    /// following the replication stream
+    Up,
+}
+
 #[derive(Clone)]
 pub struct ReplicaConnection {
     redis: RedisServer,
     replica_of: Binding,
-    replicated_offset: usize,
+    link: Arc<RwLock<MasterLink>>,
+    /// id of the replication stream the store follows, none until a snapshot of the master was loaded
+    master_replid: Option<String>,
+    replicated_offset: ReplicationOffset,
 }
 
 impl ReplicaConnection {
@@ -22,19 +80,37 @@ impl ReplicaConnection {
         Self {
             replica_of: replica_of.clone(),
             redis,
-            replicated_offset: 0,
+            link: Arc::new(RwLock::new(MasterLink::Down)),
+            master_replid: None,
+            replicated_offset: ReplicationOffset::default(),
         }
     }
 
     pub(crate) fn handle_client_command(
         &self,
-        cmd: CommandRequest,
+        cmd: &CommandRequest,
     ) -> Result<Vec<RESP>> {
-        if cmd.0.is_mutating() {
-            bail!("replica can't handle mutating command: {:?}", cmd)
+        // the master changes the store through the replication connection, not through this one
+        if cmd.0.is_mutating() && self.redis.config.read().unwrap().get_bool("replica-read-only") {
+            return Ok(vec![RESP::Error("READONLY You can't write against a read only replica.".to_string())]);
         }
-        match cmd {
-            _ => self.redis.handle_command(&cmd),
+        let link = *self.link.read().unwrap();
+        if link != MasterLink::Up && !cmd.0.is_stale_allowed() && !self.redis.config.read().unwrap().get_bool("replica-serve-stale-data") {
+            return Ok(vec![RESP::Error("MASTERDOWN Link with MASTER is down and replica-serve-stale-data is set to 'no'.".to_string())]);
+        }
+        match cmd.as_ref() {
+            (Command::REPLDEBUG, []) => {
+                // REPLDEBUG
+                // the replication stream is followed by the connection to the master, only the master is known here
+                Ok(vec![RESP::Array(vec![
+                    RESP::bulk("role"), RESP::bulk("slave"),
+                    RESP::bulk("master_host"), RESP::bulk(&self.replica_of.0),
+                    RESP::bulk("master_port"), RESP::Int(self.replica_of.1 as i64),
+                    RESP::bulk("master_link_status"), RESP::bulk(if link == MasterLink::Up { "up" } else { "down" }),
+                    RESP::bulk("master_sync_in_progress"), RESP::Int((link == MasterLink::Syncing) as i64),
+                ])])
+            }
+            _ => self.redis.handle_command(cmd),
         }
     }
 
@@ -42,16 +118,14 @@ impl ReplicaConnection {
         &self,
         cmd: &CommandRequest,
     ) -> Result<Vec<RESP>> {
-        match cmd {
-            CommandRequest(Command::REPLCONF, _) => {
+        match cmd.as_ref() {
+            (Command::REPLCONF, [sub_command, ..]) if sub_command.eq_ignore_ascii_case(b"GETACK") => {
                 // minimal implementation of https://redis.io/docs/latest/commands/replconf/
-                // REPLCONF ...
-                Ok(vec![RESP::Array(vec![
-                    RESP::bulk("REPLCONF"),
-                    RESP::bulk("ACK"),
-                    RESP::bulk(&format!("{}", self.replicated_offset)),
-                ])])
+                // REPLCONF GETACK *
+                Ok(vec![self.replicated_offset.ack_message()])
             }
+            // keep alive of the replication connection
+            (Command::PING | Command::REPLCONF, _) => Ok(vec![]),
             _ => {
                 let _response = self.redis.handle_command(cmd)?;
                 // when replicating responses are ignored
@@ -65,82 +139,173 @@ impl ReplicaConnection {
         let thread_name = current.name().unwrap();
 
         let mut master_client = ReplicaClient::new(&self.replica_of)?;
+        *self.link.write().unwrap() = MasterLink::Syncing;
         let this_port = self.redis.binding.1;
 
+        let masterauth = self.redis.config.read().unwrap().get("masterauth").unwrap_or_default().to_string();
+        if !masterauth.is_empty() {
+            master_client.auth(&masterauth)?;
+        }
         master_client.ping_pong()?;
-        master_client.replconf(&vec!["listening-port", &format!("{}", this_port)])?;
-        master_client.replconf(&vec!["capa", "psync2"])?;
-        let _rds = master_client.psync("?", -1)?;
-        // reset offset after snapshot reset
-        self.replicated_offset = 0;
+        master_client.replconf(&["listening-port", &format!("{}", this_port)])?;
+        master_client.replconf(&["capa", "psync2"])?;
+        // after a reconnect the stream is continued from the byte following the last processed one
+        let (replid, offset) = match &self.master_replid {
+            Some(replid) => (replid.clone(), self.replicated_offset.psync_offset() as i64),
+            None => ("?".to_string(), -1),
+        };
+        match master_client.psync(&replid, offset)? {
+            PsyncReply::FullResync(replid, offset, rdb) => {
+                // a store loaded only partly does not follow any stream
+                self.master_replid = None;
+                self.redis.load_replication_snapshot(&rdb)?;
+                self.master_replid = Some(replid);
+                // the stream continues from the offset of the snapshot
+                self.replicated_offset.full_resync(offset);
+            }
+            PsyncReply::Continue(replid) => {
+                notice!("@{}: partial resynchronization with master {} continues from offset {}", thread_name, self.replica_of, self.replicated_offset.ack());
+                if replid.is_some() {
+                    self.master_replid = replid;
+                }
+            }
+        }
 
-        println!(
+        *self.link.write().unwrap() = MasterLink::Up;
+        notice!(
             "@{}: replication connection initialised with master: {}",
             thread_name, self.replica_of
         );
 
+        // the offset is acknowledged every second also without GETACK, so that the master knows the lag of the replica.
+        // the acknowledgements stop once this connection ends and drops the sender
+        let (_connected, disconnected) = mpsc::channel::<()>();
+        let writer = master_client.stream.writer();
+        let offset = self.replicated_offset.clone();
+        thread::Builder::new()
+            .name(format!("{}-ack", thread_name))
+            .spawn(move || {
+                while let Err(RecvTimeoutError::Timeout) = disconnected.recv_timeout(REPLICA_ACK_INTERVAL) {
+                    if writer.send_messages(&[&offset.ack_message()]).is_err() {
+                        break;
+                    }
+                }
+            })?;
+
         // accumulating data sent from master to replica
 
         loop {
             let (len, message) = master_client.read_replication_command()?;
-            println!(
-                "@{}: master sent message over replication connection: {:?}",
-                thread_name, message
-            );
-
-            let command: CommandRequest = message.try_into()?;
-
-            let responses = self.handle_internal_command(&command)?;
-            master_client
-                .stream
-                .send_messages(&responses.iter().map(|r| r).collect::<Vec<&RESP>>())?;
-            println!(
-                "@{}: replica connection handled {:?} and responded to master: {:?}",
-                thread_name, command, responses
-            );
-
-            self.replicated_offset += len;
-            println!(
-                "@{}: replica after command {:?} offset is {}",
-                thread_name, command, self.replicated_offset
-            );
+            let logs_frames = self.redis.logs_replication_frames();
+            if logs_frames {
+                notice!(
+                    "@{}: master sent message over replication connection: {:?}",
+                    thread_name, message
+                );
+            }
+
+            // a command which can't be applied is skipped, the master counted its bytes anyway
+            let result = self.replicated_offset.process(len, || CommandRequest::try_from(message)
+                .and_then(|command| self.handle_internal_command(&command).map(|responses| (command, responses))));
+            match result {
+                Ok((command, responses)) => {
+                    master_client
+                        .stream
+                        .send_messages(&responses.iter().collect::<Vec<&RESP>>())?;
+                    if logs_frames {
+                        notice!(
+                            "@{}: replica connection handled {:?} and responded to master: {:?}",
+                            thread_name, command, responses
+                        );
+                    }
+                }
+                Err(err) => warning!("@{}: replicated command failed: {}", thread_name, err),
+            }
+
+            if logs_frames {
+                notice!(
+                    "@{}: replica offset is {}",
+                    thread_name, self.replicated_offset.ack()
+                );
+            }
         }
     }
 }
 
 impl ClientConnectionHandler for ReplicaConnection {
-    fn handle_request(
-        &mut self,
-        _message_bytes: usize,
-        _message: RESP,
-        command: CommandRequest,
-        connection: &mut RESPConnection,
-    ) -> anyhow::Result<()> {
-        println!("handled {:?} ", command);
-        let responses = self.handle_client_command(command)?;
+    fn server(&self) -> &RedisServer {
+        &self.redis
+    }
 
-        println!("responded with: {:?}", responses);
-        connection.send_messages(&responses.iter().map(|r| r).collect::<Vec<&RESP>>())?;
+    fn execute_request(&mut self, _message_bytes: usize, _message: RESP, command: &CommandRequest) -> anyhow::Result<Vec<RESP>> {
+        debug!("handled {:?} ", command);
+        let responses = self.handle_client_command(command)?;
 
-        Ok(())
+        debug!("responded with: {:?}", responses);
+        Ok(responses)
     }
 }
 
-pub fn start_replication(redis: RedisServer, replica_of: Binding) -> Result<()> {
-    let thread_name = format!("replica-master-{}", replica_of);
+/// follows the master in a background thread, connecting again whenever the connection fails.
+/// the client connections are clones of the replica, sharing the state of the link to the master
+pub fn start_replication(mut replica: ReplicaConnection) -> Result<()> {
+    let thread_name = format!("replica-master-{}", replica.replica_of);
     thread::Builder::new()
         .name(thread_name.clone())
         .spawn(move || {
-            let mut replica = ReplicaConnection::new(redis.clone(), replica_of.clone());
             loop {
                 replica.replica_master_connection().unwrap_or_else(|err| {
-                    println!(
+                    warning!(
                         "replication failed: {:?}. will restart replication connection",
                         err
                     )
                 });
+                *replica.link.write().unwrap() = MasterLink::Down;
                 thread::sleep(Duration::from_secs(2));
             }
         })?;
     Ok(())
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn full_resync_continues_from_the_offset_of_the_snapshot() {
+        let offset = ReplicationOffset::default();
+        offset.process(100, || ());
+        offset.full_resync(42);
+        assert_eq!(offset.ack(), 42);
+        offset.process(14, || ());
+        assert_eq!(offset.ack(), 56);
+    }
+
+    #[test]
+    fn getack_is_excluded_from_its_own_ack() {
+        let offset = ReplicationOffset::default();
+        offset.full_resync(10);
+        let getack = RESP::Array(vec![RESP::bulk("REPLCONF"), RESP::bulk("GETACK"), RESP::bulk("*")]).encoded_len().unwrap();
+        let reply = offset.process(getack, || offset.ack_message());
+        assert_eq!(reply, RESP::Array(vec![RESP::bulk("REPLCONF"), RESP::bulk("ACK"), RESP::bulk("10")]));
+        // the next ACK includes the GETACK
+        assert_eq!(offset.ack(), 10 + getack);
+    }
+
+    #[test]
+    fn failed_commands_count_into_the_offset() {
+        let offset = ReplicationOffset::default();
+        let result: Result<()> = offset.process(20, || Err(anyhow::anyhow!("ERR failed")));
+        assert!(result.is_err());
+        assert_eq!(offset.ack(), 20);
+    }
+
+    #[test]
+    fn clones_share_the_offset() {
+        let offset = ReplicationOffset::default();
+        let acknowledging = offset.clone();
+        offset.process(5, || ());
+        assert_eq!(acknowledging.ack(), 5);
+        assert_eq!(acknowledging.psync_offset(), 6);
+    }
+}
@@ -0,0 +1,54 @@
+use std::collections::{HashMap, VecDeque};
+use std::sync::{Arc, Mutex};
+
+use anyhow::bail;
+
+use crate::args::Arg;
+
+/// number of suspended scans kept, the oldest ones are forgotten first
+const MAX_CURSORS: usize = 10_000;
+
+/// positions of suspended SCAN iterations by cursor.
+/// keys are scanned in order, so a scan continues after the last key it visited and keys which exist for the whole
+/// iteration are returned even when other keys are added or removed in between
+#[derive(Clone, Default)]
+pub(crate) struct ScanCursors(Arc<Mutex<Cursors>>);
+
+#[derive(Default)]
+struct Cursors {
+    last_cursor: u64,
+    positions: HashMap<u64, Vec<u8>>,
+    order: VecDeque<u64>,
+}
+
+impl ScanCursors {
+    /// key the scan continues after, None for the cursor 0 starting a new scan
+    pub(crate) fn resume(&self, cursor: &[u8]) -> anyhow::Result<Option<Vec<u8>>> {
+        match cursor.parse::<u64>() {
+            Ok(0) => Ok(None),
+            Ok(cursor) => match self.0.lock().unwrap().positions.get(&cursor) {
+                Some(position) => Ok(Some(position.clone())),
+                None => bail!("ERR invalid cursor"),
+            },
+            Err(_) => bail!("ERR invalid cursor"),
+        }
+    }
+
+    /// cursor continuing the scan after the key, 0 when the scan is complete
+    pub(crate) fn suspend(&self, position: Option<Vec<u8>>) -> u64 {
+        let Some(position) = position else {
+            return 0;
+        };
+        let mut cursors = self.0.lock().unwrap();
+        cursors.last_cursor += 1;
+        let cursor = cursors.last_cursor;
+        cursors.positions.insert(cursor, position);
+        cursors.order.push_back(cursor);
+        if cursors.order.len() > MAX_CURSORS {
+            if let Some(oldest) = cursors.order.pop_front() {
+                cursors.positions.remove(&oldest);
+            }
+        }
+        cursor
+    }
+}
@@ -0,0 +1,195 @@
+use std::sync::atomic::{AtomicU64, Ordering};
+
+use crate::args::Arg;
+use crate::protocol::command::{Command, CommandRequest};
+use crate::protocol::resp::{Protocol, RESP};
+use crate::redis::RedisServer;
+
+/// version of the server reported to clients
+pub(crate) const REDIS_VERSION: &str = "7.4.0";
+
+/// the only user, authenticated with the requirepass password
+const DEFAULT_USER: &str = "default";
+
+static NEXT_CLIENT_ID: AtomicU64 = AtomicU64::new(1);
+
+/// state of a client connection: its id, whether it authenticated and the protocol it speaks.
+/// the name of the connection is kept with the clients of the server, which CLIENT LIST reports
+pub struct Session {
+    id: u64,
+    authenticated: bool,
+    protocol: Protocol,
+}
+
+impl Session {
+    /// new connection, which has to authenticate when a password is required
+    pub fn new(server: &RedisServer) -> Self {
+        Self {
+            id: NEXT_CLIENT_ID.fetch_add(1, Ordering::Relaxed),
+            authenticated: required_password(server).is_none(),
+            protocol: Protocol::default(),
+        }
+    }
+
+    pub fn id(&self) -> u64 {
+        self.id
+    }
+
+    /// protocol selected with HELLO, RESP2 until then
+    pub fn protocol(&self) -> Protocol {
+        self.protocol
+    }
+
+    /// handles the commands about the connection itself, returning None for commands executed by the server.
+    /// until the client authenticates, only AUTH and HELLO are accepted
+    pub fn handle_command(&mut self, server: &RedisServer, cmd: &CommandRequest) -> Option<Vec<RESP>> {
+        match cmd.as_ref() {
+            (Command::AUTH, [password]) => Some(vec![self.auth(server, None, password)]),
+            (Command::AUTH, [username, password]) => Some(vec![self.auth(server, Some(username), password)]),
+            (Command::HELLO, params) => Some(vec![self.hello(server, params)]),
+            _ if !self.authenticated => Some(vec![RESP::Error("NOAUTH Authentication required.".to_string())]),
+            (Command::CLIENT, [sub_command, params @ ..]) => match (sub_command.to_uppercase().as_str(), params) {
+                // minimal implementation of https://redis.io/docs/latest/commands/client-setname/
+                // CLIENT SETNAME connection-name
+                // CLIENT GETNAME
+                // CLIENT ID
+                // CLIENT INFO
+                // CLIENT LIST [TYPE normal | replica | pubsub] [ID client-id [client-id ...]]
+                ("SETNAME", [name]) => Some(vec![self.set_name(server, name).map_or_else(|err| err, |_| RESP::String("OK".to_string()))]),
+                ("GETNAME", []) => Some(vec![server.clients.name(self.id).map_or(RESP::Null, |name| RESP::bulk(&name))]),
+                ("ID", []) => Some(vec![RESP::Int(self.id as i64)]),
+                ("INFO", []) => Some(vec![client_info(server.clients.info(self.id).into_iter().collect())]),
+                ("LIST", params) => Some(vec![client_list(server, params)]),
+                _ => None,
+            },
+            _ => None,
+        }
+    }
+
+    /// minimal implementation of https://redis.io/docs/latest/commands/auth/
+    /// AUTH [username] password
+    fn auth(&mut self, server: &RedisServer, username: Option<&[u8]>, password: &[u8]) -> RESP {
+        let required = required_password(server);
+        let username = username.map(|username| username.text());
+        match (username.as_deref(), &required) {
+            (None, None) => return RESP::Error("ERR AUTH <password> called without any password configured for the default user. Are you sure your configuration is correct?".to_string()),
+            // the default user accepts any password when none is configured
+            (Some(DEFAULT_USER), None) => {}
+            (Some(DEFAULT_USER) | None, Some(required)) if password == required.as_bytes() => {}
+            _ => return RESP::Error("WRONGPASS invalid username-password pair or user is disabled.".to_string()),
+        }
+        self.authenticated = true;
+        RESP::String("OK".to_string())
+    }
+
+    /// minimal implementation of https://redis.io/docs/latest/commands/hello/
+    /// HELLO [protover [AUTH username password] [SETNAME clientname]]
+    fn hello(&mut self, server: &RedisServer, params: &[Vec<u8>]) -> RESP {
+        let (protocol, options) = match params.split_first() {
+            None => (self.protocol, params),
+            Some((protover, options)) => match protover.parse::<i64>() {
+                Ok(2) => (Protocol::Resp2, options),
+                Ok(3) => (Protocol::Resp3, options),
+                // clients fall back to RESP2 on NOPROTO
+                Ok(_) => return RESP::Error("NOPROTO sorry, this protocol version is not supported.".to_string()),
+                Err(_) => return RESP::Error("ERR Protocol version is not an integer or out of range".to_string()),
+            },
+        };
+
+        let mut auth = None;
+        let mut name = None;
+        let mut options = options.iter();
+        while let Some(option) = options.next() {
+            match option.to_uppercase().as_str() {
+                "AUTH" => match (options.next(), options.next()) {
+                    (Some(username), Some(password)) => auth = Some((username, password)),
+                    _ => return RESP::Error(format!("ERR Syntax error in HELLO option '{}'", option.text())),
+                },
+                "SETNAME" => match options.next() {
+                    Some(client_name) => name = Some(client_name),
+                    None => return RESP::Error(format!("ERR Syntax error in HELLO option '{}'", option.text())),
+                },
+                _ => return RESP::Error(format!("ERR Syntax error in HELLO option '{}'", option.text())),
+            }
+        }
+
+        // authentication comes first, a failed one leaves the name unchanged
+        if let Some((username, password)) = auth {
+            if let error @ RESP::Error(_) = self.auth(server, Some(username), password) {
+                return error;
+            }
+        }
+        if !self.authenticated {
+            return RESP::Error("NOAUTH HELLO must be called with the client already authenticated, otherwise the HELLO <proto> AUTH <user> <pass> option can be used to authenticate the client and select the RESP protocol version at the same time".to_string());
+        }
+        if let Some(name) = name {
+            if let Err(error) = self.set_name(server, name) {
+                return error;
+            }
+        }
+
+        self.protocol = protocol;
+
+        let mode = if server.cluster.is_some() { "cluster" } else { "standalone" };
+        let role = if server.is_master { "master" } else { "replica" };
+        let protover = match protocol {
+            Protocol::Resp2 => 2,
+            Protocol::Resp3 => 3,
+        };
+        RESP::Map(vec![
+            (RESP::bulk("server"), RESP::bulk("redis")),
+            (RESP::bulk("version"), RESP::bulk(REDIS_VERSION)),
+            (RESP::bulk("proto"), RESP::Int(protover)),
+            (RESP::bulk("id"), RESP::Int(self.id as i64)),
+            (RESP::bulk("mode"), RESP::bulk(mode)),
+            (RESP::bulk("role"), RESP::bulk(role)),
+            (RESP::bulk("modules"), RESP::Array(vec![])),
+        ])
+    }
+
+    /// names the connection, an empty name removes it
+    fn set_name(&mut self, server: &RedisServer, name: &[u8]) -> Result<(), RESP> {
+        if name.iter().any(|c| !c.is_ascii_graphic()) {
+            return Err(RESP::Error("ERR Client names cannot contain spaces, newlines or special characters.".to_string()));
+        }
+        server.clients.set_name(self.id, (!name.is_empty()).then(|| name.text().to_string()));
+        Ok(())
+    }
+}
+
+/// minimal implementation of https://redis.io/docs/latest/commands/client-list/
+/// CLIENT LIST [TYPE normal | replica | pubsub] [ID client-id [client-id ...]]
+fn client_list(server: &RedisServer, params: &[Vec<u8>]) -> RESP {
+    let mut client_type = None;
+    let mut ids = None;
+    match params {
+        [] => {}
+        [option, value] if option.eq_ignore_ascii_case(b"TYPE") => match value.text().to_lowercase().as_str() {
+            // redis lists replicas also as the type slave
+            "normal" | "pubsub" => client_type = Some(value.text().to_lowercase()),
+            "replica" | "slave" => client_type = Some("replica".to_string()),
+            _ => return RESP::Error(format!("ERR Unknown client type '{}'", value.text())),
+        },
+        [option, values @ ..] if option.eq_ignore_ascii_case(b"ID") && !values.is_empty() => {
+            match values.iter().map(|id| id.parse::<u64>()).collect::<Result<Vec<u64>, _>>() {
+                Ok(parsed) => ids = Some(parsed),
+                Err(_) => return RESP::Error("ERR Invalid client ID".to_string()),
+            }
+        }
+        _ => return RESP::Error("ERR syntax error".to_string()),
+    }
+    client_info(server.clients.list(client_type.as_deref(), ids.as_deref()))
+}
+
+/// lines describing clients, each terminated by a newline
+fn client_info(lines: Vec<String>) -> RESP {
+    let text = lines.iter().map(|line| format!("{}\n", line)).collect::<String>();
+    RESP::Verbatim("txt".to_string(), text)
+}
+
+/// password of the default user, None when clients don't need to authenticate
+fn required_password(server: &RedisServer) -> Option<String> {
+    server.config.read().unwrap().get("requirepass")
+        .filter(|password| !password.is_empty())
+        .map(|password| password.to_string())
+}
@@ -0,0 +1,29 @@
+/// number of hash slots the keyspace is split into, as in redis cluster
+pub const SLOTS: u16 = 16384;
+
+/// hash slot of a key as defined by https://redis.io/docs/latest/operate/oss_and_stack/reference/cluster-spec/#key-distribution-model
+///
+/// only the hash tag between the first `{` and the following `}` is hashed if it is not empty,
+/// so that related keys can be forced into the same slot.
+pub fn key_slot(key: &[u8]) -> u16 {
+    let hashed = key.iter().position(|&b| b == b'{')
+        .and_then(|start| {
+            key[start + 1..].iter().position(|&b| b == b'}')
+                .filter(|&len| len > 0)
+                .map(|len| &key[start + 1..start + 1 + len])
+        })
+        .unwrap_or(key);
+    crc16(hashed) % SLOTS
+}
+
+/// CRC16 XMODEM variant used by redis cluster
+fn crc16(bytes: &[u8]) -> u16 {
+    let mut crc = 0_u16;
+    for &byte in bytes {
+        crc ^= (byte as u16) << 8;
+        for _ in 0..8 {
+            crc = if crc & 0x8000 != 0 { (crc << 1) ^ 0x1021 } else { crc << 1 };
+        }
+    }
+    crc
+}
@@ -0,0 +1,40 @@
+use std::sync::atomic::{AtomicU64, Ordering};
+
+/// counters of the server reported by INFO stats, updated by the connections, the listener and the store
+#[derive(Default)]
+pub(crate) struct Stats {
+    /// number of commands received from clients
+    pub(crate) commands_processed: AtomicU64,
+    /// number of connections accepted by the listener
+    pub(crate) connections_received: AtomicU64,
+    /// number of accepted connections closed right away, over maxclients or from a source not allowed to connect
+    pub(crate) rejected_connections: AtomicU64,
+    /// number of lookups of keys read by commands which found the key
+    pub(crate) keyspace_hits: AtomicU64,
+    /// number of lookups of keys read by commands which found no key or an expired one
+    pub(crate) keyspace_misses: AtomicU64,
+    /// number of keys removed because they expired, when accessed or by the active expiry
+    pub(crate) expired_keys: AtomicU64,
+    /// number of keys evicted to stay within maxmemory
+    pub(crate) evicted_keys: AtomicU64,
+}
+
+impl Stats {
+    pub(crate) fn count(counter: &AtomicU64) {
+        counter.fetch_add(1, Ordering::Relaxed);
+    }
+
+    /// INFO stats fields of the counters
+    pub(crate) fn info(&self) -> Vec<(&'static str, String)> {
+        let load = |counter: &AtomicU64| counter.load(Ordering::Relaxed).to_string();
+        vec![
+            ("total_connections_received", load(&self.connections_received)),
+            ("total_commands_processed", load(&self.commands_processed)),
+            ("rejected_connections", load(&self.rejected_connections)),
+            ("expired_keys", load(&self.expired_keys)),
+            ("evicted_keys", load(&self.evicted_keys)),
+            ("keyspace_hits", load(&self.keyspace_hits)),
+            ("keyspace_misses", load(&self.keyspace_misses)),
+        ]
+    }
+}
@@ -1,46 +1,327 @@
 use core::time::Duration;
-use std::collections::HashMap;
-use std::fs::File;
-use std::io::BufReader;
-use std::io::Read;
-use std::sync::{Condvar, Mutex, Weak};
-use std::time::SystemTime;
+use std::collections::{BTreeMap, BTreeSet, HashMap, HashSet, VecDeque};
+use std::io::{Read, Write};
+use std::ops::{Bound, Deref, DerefMut};
+use std::sync::{Arc, LockResult, Mutex, PoisonError, RwLock, RwLockReadGuard, RwLockWriteGuard, Weak};
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::time::{Instant, SystemTime};
 
-use anyhow::{bail, format_err};
+use anyhow::{bail, format_err, Context};
 
+use crate::args::Arg;
+use crate::bitmap::{bit_op, bit_pos, BitOp, BitfieldOp, Field};
+use crate::budget::TimeBudget;
+use crate::events::{KeyEvent, KeyEventKind, KeyWatch};
+use crate::eviction::EvictionPolicy;
+use crate::glob::{glob_match, literal_prefix};
+use crate::hyperloglog::HyperLogLog;
+use crate::log::{debug, notice, warning};
+use crate::notify::{EventClass, NotifyFlags};
+use crate::protocol::command::{Command, CommandRequest};
 use crate::protocol::rdb;
-use crate::protocol::rdb::LengthEncoding;
-use crate::stream::{Stream, StreamEvent, StreamRecordId};
+use crate::protocol::resp::RESP;
+use crate::random::{random_index, random_sample};
+use crate::range::{normalize_index, normalize_range};
+use crate::session::REDIS_VERSION;
+use crate::slots::{key_slot, SLOTS};
+use crate::stats::Stats;
+use crate::stream::{Claim, Consumer, ConsumerGroup, Delivery, PendingEntry, Stream, StreamRecord, StreamRecordId, Trim};
+use crate::zset::{format_score, parse_score, AddOptions, Added, SortedSet, ZRange};
 
+type StreamEntries<'a> = Vec<(String, &'a FieldValues)>;
+
+/// fields of a hash or of a stream entry with their values
+pub(crate) type FieldValues = Vec<(Vec<u8>, Vec<u8>)>;
+
+/// members of a sorted set with their scores
+pub(crate) type ScoredMembers = Vec<(Vec<u8>, f64)>;
+
+/// keyspace event with the key it happened to, waiting to be published
+pub(crate) type Notification = (&'static str, Vec<u8>);
+
+/// members of a set shared between the store and readers working on a snapshot of it
+pub type SetSnapshot = Arc<HashSet<Vec<u8>>>;
+
+pub const WRONGTYPE: &str = "WRONGTYPE Operation against a key holding the wrong kind of value";
+
+#[derive(Clone)]
 enum Value {
-    String(String),
+    /// bytes of a string, which are not always text, e.g. after SETBIT
+    String(Vec<u8>),
     Stream(Stream),
+    /// copy on write, so that readers can take a snapshot and release the store
+    Set(SetSnapshot),
+    List(VecDeque<Vec<u8>>),
+    Hash(HashMap<Vec<u8>, Vec<u8>>),
+    ZSet(SortedSet),
+}
+
+/// read-only view of a stored value, borrowed from the store. it lives as long as the guard of the store lock
+/// it was read through, so writers wait until it is dropped, while other readers go on
+pub(crate) enum ValueRef<'a> {
+    String(&'a [u8]),
+    Stream(&'a Stream),
+    Set(&'a HashSet<Vec<u8>>),
+    List(&'a VecDeque<Vec<u8>>),
+    Hash(&'a HashMap<Vec<u8>, Vec<u8>>),
+    ZSet(&'a SortedSet),
+}
+
+impl<'a> From<&'a Value> for ValueRef<'a> {
+    fn from(value: &'a Value) -> Self {
+        match value {
+            Value::String(value) => ValueRef::String(value),
+            Value::Stream(stream) => ValueRef::Stream(stream),
+            Value::Set(set) => ValueRef::Set(set),
+            Value::List(list) => ValueRef::List(list),
+            Value::Hash(hash) => ValueRef::Hash(hash),
+            Value::ZSet(zset) => ValueRef::ZSet(zset),
+        }
+    }
+}
+
+/// approximate bytes of bookkeeping of a key in the store, on top of the key and value bytes
+const ENTRY_OVERHEAD: usize = 64;
+/// approximate bytes of bookkeeping of an element of a collection
+const ELEMENT_OVERHEAD: usize = 32;
+
+/// collections up to this number of elements, each up to the element size, are reported with a compact encoding
+const COMPACT_MAX_ELEMENTS: usize = 128;
+const COMPACT_MAX_ELEMENT_SIZE: usize = 64;
+/// sets of integers up to this number of members are reported as intsets
+const INTSET_MAX_MEMBERS: usize = 512;
+/// strings up to this size are reported as allocated with their header
+const EMBSTR_MAX_SIZE: usize = 44;
+
+impl Value {
+    /// approximate bytes held by the value
+    fn memory_usage(&self) -> usize {
+        match self {
+            Value::String(value) => value.len(),
+            Value::Stream(stream) => stream.memory_usage(),
+            Value::Set(set) => set.iter().map(|member| element_usage(member)).sum(),
+            Value::List(list) => list.iter().map(|element| element_usage(element)).sum(),
+            Value::Hash(hash) => hash.iter().map(|(field, value)| field_usage(field, value)).sum(),
+            Value::ZSet(zset) => zset.members().map(|member| zset_member_usage(member)).sum(),
+        }
+    }
+
+    /// number of elements of a collection, 0 for strings
+    fn len(&self) -> usize {
+        match self {
+            Value::String(_) => 0,
+            Value::Stream(stream) => stream.len(),
+            Value::Set(set) => set.len(),
+            Value::List(list) => list.len(),
+            Value::Hash(hash) => hash.len(),
+            Value::ZSet(zset) => zset.len(),
+        }
+    }
+
+    /// bytes of the value when it is saved in an RDB file
+    fn serialized_len(&self) -> usize {
+        let mut bytes = vec![];
+        write_rdb_value(&mut bytes, self.into()).map_or(0, |_| bytes.len())
+    }
+
+    /// encoding redis would use for the value, as reported by OBJECT ENCODING.
+    /// values are always stored the same way here, small values would be encoded compactly by redis
+    fn encoding(&self) -> &'static str {
+        let compact = |len: usize, mut elements: Box<dyn Iterator<Item=&Vec<u8>> + '_>| {
+            len <= COMPACT_MAX_ELEMENTS && elements.all(|element| element.len() <= COMPACT_MAX_ELEMENT_SIZE)
+        };
+        match self {
+            Value::String(value) if std::str::from_utf8(value).is_ok_and(|value| value.parse::<i64>().is_ok()) => "int",
+            Value::String(value) if value.len() <= EMBSTR_MAX_SIZE => "embstr",
+            Value::String(_) => "raw",
+            Value::Stream(_) => "stream",
+            Value::Set(set) if set.len() <= INTSET_MAX_MEMBERS && set.iter().all(|member| member.parse::<i64>().is_ok()) => "intset",
+            Value::Set(set) if compact(set.len(), Box::new(set.iter())) => "listpack",
+            Value::Set(_) => "hashtable",
+            Value::List(list) if compact(list.len(), Box::new(list.iter())) => "listpack",
+            Value::List(_) => "quicklist",
+            Value::Hash(hash) if compact(hash.len(), Box::new(hash.iter().flat_map(|(field, value)| [field, value]))) => "listpack",
+            Value::Hash(_) => "hashtable",
+            Value::ZSet(zset) if compact(zset.len(), Box::new(zset.members())) => "listpack",
+            Value::ZSet(_) => "skiplist",
+        }
+    }
+}
+
+/// breakdown of the used memory, analyzed by MEMORY DOCTOR
+pub struct MemoryStats {
+    pub keys: usize,
+    pub used_memory: usize,
+    /// bytes of bookkeeping of the keys and of the elements of collections rather than of data
+    pub overhead: usize,
+    /// the collection with the most elements and its number of elements
+    pub biggest: Option<(Vec<u8>, usize)>,
+}
+
+fn element_usage(element: &[u8]) -> usize {
+    element.len() + ELEMENT_OVERHEAD
+}
+
+fn field_usage(field: &[u8], value: &[u8]) -> usize {
+    element_usage(field) + value.len()
+}
+
+/// a member of a sorted set is kept in both the member map and the score order, with its score
+fn zset_member_usage(member: &[u8]) -> usize {
+    2 * element_usage(member) + size_of::<f64>()
+}
+
+/// number of elements of a collection written by a single command of an append only file rewrite
+const AOF_REWRITE_ITEMS_PER_COMMAND: usize = 64;
+
+/// commands recreating the value under the key
+fn rewrite_value(key: &[u8], value: ValueRef) -> Vec<CommandRequest> {
+    // the elements of a collection are added by a command per batch
+    let batched = |command: Command, items: Vec<Vec<u8>>, item_len: usize| items
+        .chunks(AOF_REWRITE_ITEMS_PER_COMMAND * item_len)
+        .map(|batch| CommandRequest::new(command, [&[key.to_vec()], batch].concat()))
+        .collect::<Vec<CommandRequest>>();
+    match value {
+        ValueRef::String(value) => vec![CommandRequest::new(Command::SET, vec![key.to_vec(), value.to_vec()])],
+        ValueRef::List(list) => batched(Command::RPUSH, list.iter().cloned().collect(), 1),
+        ValueRef::Set(set) => batched(Command::SADD, set.iter().cloned().collect(), 1),
+        ValueRef::Hash(hash) => batched(Command::HSET, hash.iter().flat_map(|(field, value)| [field.clone(), value.clone()]).collect(), 2),
+        ValueRef::ZSet(zset) => batched(
+            Command::ZADD,
+            zset.range(&ZRange::Rank(0, -1), false, None).into_iter().flat_map(|(member, score)| [format_score(score).into_bytes(), member]).collect(),
+            2,
+        ),
+        ValueRef::Stream(stream) => rewrite_stream(key, stream),
+    }
+}
+
+/// commands recreating a stream: its records and its metadata, then its consumer groups with their consumers
+/// and pending entries
+fn rewrite_stream(key: &[u8], stream: &Stream) -> Vec<CommandRequest> {
+    let mut commands = stream.records().iter()
+        .map(|record| {
+            let fields = record.attributes.iter().flat_map(|(field, value)| [field.clone(), value.clone()]);
+            CommandRequest::new(Command::XADD, [key.to_vec(), record.id.to_string().into_bytes()].into_iter().chain(fields).collect())
+        })
+        .collect::<Vec<CommandRequest>>();
+    if !commands.is_empty() {
+        let params = vec![
+            key.to_vec(), stream.last_id().unwrap_or(StreamRecordId::MIN).to_string().into_bytes(),
+            b"ENTRIESADDED".to_vec(), stream.entries_added().to_string().into_bytes(),
+            b"MAXDELETEDID".to_vec(), stream.max_deleted_id().to_string().into_bytes(),
+        ];
+        commands.push(CommandRequest::new(Command::XSETID, params));
+    }
+    for (name, group) in stream.groups() {
+        let params = vec![b"CREATE".to_vec(), key.to_vec(), name.clone(), group.last_delivered.to_string().into_bytes(), b"MKSTREAM".to_vec()];
+        commands.push(CommandRequest::new(Command::XGROUP, params));
+        for consumer in group.consumers.keys() {
+            let params = vec![b"CREATECONSUMER".to_vec(), key.to_vec(), name.clone(), consumer.clone()];
+            commands.push(CommandRequest::new(Command::XGROUP, params));
+        }
+        for (id, entry) in &group.pending {
+            let params = vec![
+                key.to_vec(), name.clone(), entry.consumer.clone(), b"0".to_vec(), id.to_string().into_bytes(),
+                b"TIME".to_vec(), entry.delivered_ms.to_string().into_bytes(),
+                b"RETRYCOUNT".to_vec(), entry.delivery_count.to_string().into_bytes(), b"FORCE".to_vec(), b"JUSTID".to_vec(),
+            ];
+            commands.push(CommandRequest::new(Command::XCLAIM, params));
+        }
+    }
+    commands
+}
+
+/// milliseconds since unix epoch
+fn now_ms() -> u64 {
+    SystemTime::now().duration_since(SystemTime::UNIX_EPOCH).map_or(0, |d| d.as_millis() as u64)
+}
+
+/// clock of the key accesses in milliseconds since unix epoch, updated by the cron on every tick
+/// so that accesses don't read the system time
+static LRU_CLOCK_MS: AtomicU64 = AtomicU64::new(0);
+
+/// the LRU clock of redis counts seconds in 24 bits and wraps around, as reported by DEBUG OBJECT
+const LRU_CLOCK_WRAP: u64 = 1 << 24;
+
+pub(crate) fn update_lru_clock() {
+    LRU_CLOCK_MS.store(now_ms(), Ordering::Relaxed);
+}
+
+fn lru_clock_ms() -> u64 {
+    match LRU_CLOCK_MS.load(Ordering::Relaxed) {
+        // the cron is not running yet
+        0 => now_ms(),
+        clock => clock,
+    }
 }
 
-struct StoreEntry {
+pub(crate) struct StoreEntry {
     value: Value,
     valid_until: Option<SystemTime>,
+    /// time of the last access, updated by readers holding a shared reference
+    last_access_ms: AtomicU64,
+    /// version of the store when the entry was last written, see [Store::version]
+    version: u64,
 }
 
 impl StoreEntry {
-    fn from_string(value: &str, valid_until: Option<SystemTime>) -> Self {
+    fn new(value: Value, valid_until: Option<SystemTime>) -> Self {
         StoreEntry {
-            value: Value::String(value.to_string()),
+            value,
             valid_until,
+            last_access_ms: AtomicU64::new(lru_clock_ms()),
+            version: 0,
         }
     }
-    fn empty_stream(key: &str) -> Self {
-        StoreEntry {
-            value: Value::Stream(Stream::new(key.to_string())),
-            valid_until: None,
-        }
+
+    fn from_bytes(value: Vec<u8>, valid_until: Option<SystemTime>) -> Self {
+        StoreEntry::new(Value::String(value), valid_until)
     }
 
-    fn value(&self) -> Option<String> {
-        if let Some(valid_until) = self.valid_until {
-            if valid_until < SystemTime::now() {
-                return None;
-            }
+    fn empty_stream() -> Self {
+        StoreEntry::new(Value::Stream(Stream::new()), None)
+    }
+
+    /// copy of the entry with the value duplicated and the given expiration.
+    /// key moving operations pass the source `valid_until` so that the TTL is carried along
+    fn clone_with_ttl(&self, valid_until: Option<SystemTime>) -> Self {
+        StoreEntry::new(self.value.clone(), valid_until)
+    }
+
+    fn touch(&self) {
+        self.last_access_ms.store(lru_clock_ms(), Ordering::Relaxed);
+    }
+
+    fn last_access_ms(&self) -> u64 {
+        self.last_access_ms.load(Ordering::Relaxed)
+    }
+
+    /// number of elements of the value, which is the effort of freeing it
+    pub(crate) fn len(&self) -> usize {
+        self.value.len()
+    }
+
+    /// approximate bytes held by the entry stored under the key
+    fn memory_usage(&self, key: &[u8]) -> usize {
+        ENTRY_OVERHEAD + key.len() + self.value.memory_usage()
+    }
+
+    fn valid_until(&self) -> Option<SystemTime> {
+        self.valid_until
+    }
+
+    fn set_valid_until(&mut self, valid_until: Option<SystemTime>) {
+        self.valid_until = valid_until;
+    }
+
+    fn is_expired(&self) -> bool {
+        self.valid_until.is_some_and(|valid_until| valid_until < SystemTime::now())
+    }
+
+    /// bytes of a string value
+    fn value(&self) -> Option<Vec<u8>> {
+        if self.is_expired() {
+            return None;
         }
         match self.value {
             Value::String(ref value) => Some(value.clone()),
@@ -50,15 +331,16 @@ impl StoreEntry {
 
 
     fn value_type(&self) -> &str {
+        if self.is_expired() {
+            return "none";
+        }
         match &self.value {
-            Value::String(_) => {
-                if self.value().is_none() {
-                    "none"
-                } else {
-                    "string"
-                }
-            }
+            Value::String(_) => "string",
             Value::Stream(_) => "stream",
+            Value::Set(_) => "set",
+            Value::List(_) => "list",
+            Value::Hash(_) => "hash",
+            Value::ZSet(_) => "zset",
         }
     }
 
@@ -78,183 +360,2633 @@ impl StoreEntry {
     }
 }
 
-pub(crate) struct Store(HashMap<String, StoreEntry>);
+/// condition for setting an expiration with the EXPIRE family of commands
+#[allow(clippy::upper_case_acronyms)]
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum ExpireCondition {
+    /// only when the key has no expiry
+    NX,
+    /// only when the key has an existing expiry
+    XX,
+    /// only when the new expiry is greater than the current one
+    GT,
+    /// only when the new expiry is less than the current one
+    LT,
+}
+
+/// end of a list which elements are pushed to or popped from
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum ListEnd {
+    Left,
+    Right,
+}
+
+impl ListEnd {
+    /// keyspace event of pushing to the end
+    fn push_event(self) -> &'static str {
+        match self {
+            ListEnd::Left => "lpush",
+            ListEnd::Right => "rpush",
+        }
+    }
+
+    /// keyspace event of popping from the end
+    fn pop_event(self) -> &'static str {
+        match self {
+            ListEnd::Left => "lpop",
+            ListEnd::Right => "rpop",
+        }
+    }
+}
+
+/// number of shards of the store, each covering a contiguous range of hash slots
+const SHARDS: usize = 16;
+const SLOTS_PER_SHARD: u16 = SLOTS / SHARDS as u16;
+
+/// keys of a contiguous range of hash slots, grouped by slot.
+/// a shard can be handed over as a whole when its slots move to another node
+/// and keys of a slot are available without hashing the whole keyspace.
+#[derive(Default)]
+struct Shard(HashMap<u16, HashMap<Vec<u8>, StoreEntry>>);
+
+/// the keyspace partitioned by hash slot ranges
+pub(crate) struct Store {
+    shards: Vec<Shard>,
+    /// number of keys, including expired ones not removed yet
+    len: usize,
+    /// approximate bytes held by the keys and values
+    used_memory: usize,
+    /// all keys in order, so that keys sharing a prefix can be scanned without walking the keyspace
+    key_index: BTreeSet<Vec<u8>>,
+    /// watches of blocked clients by key
+    watchers: HashMap<Vec<u8>, Vec<Weak<KeyWatch>>>,
+    /// sequence number of the last emitted event
+    event_seq: u64,
+    /// events emitted with the watches they go to, delivered once the store is unlocked
+    pending_events: Vec<(Arc<KeyWatch>, KeyEvent)>,
+    /// clients blocked on popping from a list or a sorted set by key, in the order they blocked
+    pop_waiters: HashMap<Vec<u8>, VecDeque<u64>>,
+    /// id of the last client blocked on popping
+    pop_waiter_seq: u64,
+    /// incremented on every write, giving the written entry its version
+    write_seq: u64,
+    /// classes of the keyspace events which are recorded, as configured by notify-keyspace-events
+    notify_flags: NotifyFlags,
+    /// keyspace events recorded in the order of the changes, until they are published
+    notifications: Vec<Notification>,
+    /// counters of the server, of the lookups, expirations and evictions of keys
+    stats: Arc<Stats>,
+    /// code of the function libraries of the loaded RDB file. scripting is not supported, so they can't be run,
+    /// they are kept as they are to be saved again and not lost by a load and save
+    function_libraries: Vec<Vec<u8>>,
+}
+
+/// lock of the store shared by the server, which delivers the events of the changes made under a write lock
+/// to the watches of blocked clients once it is released, so that waking them doesn't hold up the store
+pub(crate) struct StoreLock {
+    store: RwLock<Store>,
+    /// held from the release of a write lock until its events are delivered, so that they are delivered in order
+    delivery: Mutex<()>,
+}
+
+impl StoreLock {
+    pub(crate) fn new(store: Store) -> Self {
+        StoreLock { store: RwLock::new(store), delivery: Mutex::new(()) }
+    }
+
+    pub(crate) fn read(&self) -> LockResult<RwLockReadGuard<'_, Store>> {
+        self.store.read()
+    }
+
+    pub(crate) fn write(&self) -> LockResult<StoreWriteGuard<'_>> {
+        let guard = |store| StoreWriteGuard { store: Some(store), delivery: &self.delivery };
+        self.store.write().map(guard).map_err(|poisoned| PoisonError::new(guard(poisoned.into_inner())))
+    }
+}
+
+/// watch of a blocked client on keys, removed from the store once the client stops waiting and drops it
+pub(crate) struct WatchRegistration<'a> {
+    store: &'a StoreLock,
+    keys: Vec<Vec<u8>>,
+    watch: Weak<KeyWatch>,
+}
+
+impl<'a> WatchRegistration<'a> {
+    /// the watch is registered by [Store::watch] or [Store::wait_to_pop], together with checking the keys
+    pub(crate) fn new(store: &'a StoreLock, keys: &[Vec<u8>], watch: &Arc<KeyWatch>) -> Self {
+        WatchRegistration { store, keys: keys.to_vec(), watch: Arc::downgrade(watch) }
+    }
+}
+
+impl Drop for WatchRegistration<'_> {
+    fn drop(&mut self) {
+        self.store.write().unwrap_or_else(PoisonError::into_inner).unwatch(&self.keys, &self.watch);
+    }
+}
+
+/// write lock of the store, delivering the events emitted meanwhile once released
+pub(crate) struct StoreWriteGuard<'a> {
+    store: Option<RwLockWriteGuard<'a, Store>>,
+    delivery: &'a Mutex<()>,
+}
+
+impl Deref for StoreWriteGuard<'_> {
+    type Target = Store;
+
+    fn deref(&self) -> &Store {
+        self.store.as_ref().expect("store locked")
+    }
+}
+
+impl DerefMut for StoreWriteGuard<'_> {
+    fn deref_mut(&mut self) -> &mut Store {
+        self.store.as_mut().expect("store locked")
+    }
+}
+
+impl Drop for StoreWriteGuard<'_> {
+    fn drop(&mut self) {
+        let Some(mut store) = self.store.take() else {
+            return;
+        };
+        let events = std::mem::take(&mut store.pending_events);
+        if events.is_empty() {
+            return;
+        }
+        let _delivery = self.delivery.lock().unwrap_or_else(PoisonError::into_inner);
+        drop(store);
+        for (watch, event) in events {
+            let (lock, cvar) = watch.deref();
+            lock.lock().unwrap().push_back(event);
+            cvar.notify_all();
+        }
+    }
+}
 
 impl Store {
     pub(crate) fn new() -> Self {
-        Self(HashMap::new())
+        Self {
+            shards: (0..SHARDS).map(|_| Shard::default()).collect(),
+            len: 0,
+            used_memory: 0,
+            key_index: BTreeSet::new(),
+            watchers: HashMap::new(),
+            event_seq: 0,
+            pending_events: vec![],
+            pop_waiters: HashMap::new(),
+            pop_waiter_seq: 0,
+            write_seq: 0,
+            notify_flags: NotifyFlags::default(),
+            notifications: vec![],
+            stats: Arc::default(),
+            function_libraries: vec![],
+        }
     }
 
-    pub fn get_value(&self, key: &str) -> Option<String> {
-        self.0.get(key).and_then(|v| v.value())
+    /// counts the lookups, expirations and evictions of keys in the counters of the server
+    pub(crate) fn set_stats(&mut self, stats: Arc<Stats>) {
+        self.stats = stats;
     }
 
-    pub fn get_type(&self, key: &str) -> &str {
-        self.0.get(key).map_or("none", |v| v.value_type())
+    fn shard_mut(&mut self, slot: u16) -> &mut Shard {
+        &mut self.shards[(slot / SLOTS_PER_SHARD) as usize]
     }
 
-    pub fn keys(&self) -> Vec<&str> {
-        self.0.keys().map(|k| k.as_str()).collect()
+    fn slot_entries(&self, key: &[u8]) -> Option<&HashMap<Vec<u8>, StoreEntry>> {
+        let slot = key_slot(key);
+        self.shards[(slot / SLOTS_PER_SHARD) as usize].0.get(&slot)
     }
 
-    pub fn insert_value(&mut self, key: &str, value: &str, expiration: Option<Duration>) -> () {
-        let valid_until = expiration
-            .and_then(|d| SystemTime::now().checked_add(d));
-        self.0.insert(key.to_string(), StoreEntry::from_string(value, valid_until));
+    fn get(&self, key: &[u8]) -> Option<&StoreEntry> {
+        let entry = self.slot_entries(key).and_then(|entries| entries.get(key));
+        if let Some(entry) = entry {
+            entry.touch();
+        }
+        entry
     }
 
-    pub fn insert_stream(
-        &mut self,
-        key: &str,
-        id_pattern: &str,
-        stream_data: Vec<(String, String)>,
-    ) -> anyhow::Result<String> {
-        if !self.0.contains_key(key) {
-            self.0
-                .insert(key.to_string(), StoreEntry::empty_stream(key));
+    /// entry of a key read by a command, None if it is missing or expired. counted as a keyspace hit or miss
+    fn lookup(&self, key: &[u8]) -> Option<&StoreEntry> {
+        let entry = self.get(key).filter(|entry| !entry.is_expired());
+        Stats::count(if entry.is_some() { &self.stats.keyspace_hits } else { &self.stats.keyspace_misses });
+        entry
+    }
+
+    /// entry for modification, which counts as written whether it is changed or not
+    fn get_mut(&mut self, key: &[u8]) -> Option<&mut StoreEntry> {
+        let slot = key_slot(key);
+        self.write_seq += 1;
+        let version = self.write_seq;
+        let entry = self.shard_mut(slot).0.get_mut(&slot)
+            .and_then(|entries| entries.get_mut(key));
+        if let Some(entry) = entry {
+            entry.touch();
+            entry.version = version;
+            return Some(entry);
         }
+        None
+    }
 
-        let value = self.0.get_mut(key).and_then(|v| v.stream_mut()).ok_or_else(
-            || format_err!("stream not found {}", key))?;
+    /// version of the value of the key, which changes with every write of the key.
+    /// None if the key is missing or expired. a key removed and created again gets a new version
+    pub(crate) fn version(&self, key: &[u8]) -> Option<u64> {
+        self.slot_entries(key)
+            .and_then(|entries| entries.get(key))
+            .filter(|entry| !entry.is_expired())
+            .map(|entry| entry.version)
+    }
 
-        return value.add_entry(id_pattern.to_string(), stream_data);
+    fn insert(&mut self, key: Vec<u8>, mut entry: StoreEntry) {
+        let slot = key_slot(&key);
+        self.write_seq += 1;
+        entry.version = self.write_seq;
+        self.used_memory += entry.memory_usage(&key);
+        let replaced = self.shard_mut(slot).0.entry(slot)
+            .or_default()
+            .insert(key.clone(), entry);
+        match replaced {
+            Some(replaced) => self.used_memory -= replaced.memory_usage(&key),
+            None => {
+                self.len += 1;
+                self.notify(EventClass::New, "new", &key);
+                self.key_index.insert(key);
+            }
+        }
     }
 
-    pub fn range_stream(
-        &self,
-        key: &str,
-        from_id: StreamRecordId,
-        to_id: StreamRecordId,
-    ) -> anyhow::Result<Vec<(String, &Vec<(String, String)>)>> {
-        self.0.get(key).and_then(|v| v.stream()).map_or_else(
-            || bail!("stream not found {}", key),
-            |value| {
-                Ok(value
-                    .range(&from_id, &to_id, true)?
-                    .iter()
-                    .map(|&entry| (entry.id.to_string(), &entry.attributes))
-                    .collect())
-            },
-        )
+    fn remove(&mut self, key: &[u8]) -> Option<StoreEntry> {
+        let slot = key_slot(key);
+        let shard = self.shard_mut(slot);
+        let entries = shard.0.get_mut(&slot)?;
+        let removed = entries.remove(key);
+        // empty slots are dropped so that only populated slots are kept
+        if entries.is_empty() {
+            shard.0.remove(&slot);
+        }
+        if let Some(removed) = &removed {
+            self.len -= 1;
+            self.used_memory -= removed.memory_usage(key);
+            self.key_index.remove(key);
+        }
+        removed
     }
 
-    pub fn read_stream(
-        &self,
-        key: &str,
-        from_id: StreamRecordId,
-        to_id: StreamRecordId,
-    ) -> anyhow::Result<Vec<(String, &Vec<(String, String)>)>> {
-        self.0.get(key).and_then(|v| v.stream()).map_or_else(
-            || bail!("stream not found {}", key),
-            |value| {
-                Ok(value
-                    .range(&from_id, &to_id, false)?
-                    .iter()
-                    .map(|&entry| (entry.id.to_string(), &entry.attributes))
-                    .collect())
-            },
-        )
+    fn slot_keys(&self, slot: u16) -> impl Iterator<Item=&Vec<u8>> {
+        self.shards[(slot / SLOTS_PER_SHARD) as usize].0.get(&slot)
+            .into_iter()
+            .flat_map(|entries| entries.iter())
+            .filter(|(_, entry)| !entry.is_expired())
+            .map(|(key, _)| key)
     }
 
-    pub fn latest_stream(&self, key: &str) -> anyhow::Result<StreamRecordId> {
-        self.0.get(key).and_then(|v| v.stream()).map_or_else(
-            || bail!("stream not found {}", key),
-            |value| value.last_id(),
-        )
+    fn entries(&self) -> impl Iterator<Item=(&Vec<u8>, &StoreEntry)> {
+        self.shards.iter()
+            .flat_map(|shard| shard.0.values())
+            .flat_map(|entries| entries.iter())
     }
 
-    pub fn add_listener(
-        &mut self,
-        keys: &Vec<&String>,
-        listener: Weak<(Mutex<Option<StreamEvent>>, Condvar)>,
-    ) -> anyhow::Result<()> {
-        for &key in keys {
-            let value = self.0.get_mut(key).and_then(|v| v.stream_mut()).ok_or_else(
-                || format_err!("stream not found {}", key))?;
-            value.add_listener(listener.clone())?;
+    /// keys which are not expired with their values and expirations, in no particular order.
+    /// the values are borrowed, so the store lock is held for the whole iteration: a read lock lets
+    /// other readers through while writers wait until the iteration is done
+    pub(crate) fn iter(&self) -> impl Iterator<Item=(&[u8], ValueRef<'_>, Option<SystemTime>)> {
+        self.entries()
+            .filter(|(_, entry)| !entry.is_expired())
+            .map(|(key, entry)| (key.as_slice(), ValueRef::from(&entry.value), entry.valid_until))
+    }
+
+    pub fn get_value(&self, key: &[u8]) -> Option<Vec<u8>> {
+        self.lookup(key).and_then(|v| v.value())
+    }
+
+    pub fn get_type(&self, key: &[u8]) -> &str {
+        self.lookup(key).map_or("none", |v| v.value_type())
+    }
+
+    /// keys which are not expired matching the glob pattern, failing once the time budget is spent
+    pub fn keys(&self, pattern: &[u8], budget: &mut TimeBudget) -> anyhow::Result<Vec<&[u8]>> {
+        let mut keys = vec![];
+        for (k, entry) in self.entries() {
+            budget.step()?;
+            if !entry.is_expired() && glob_match(pattern, k) {
+                keys.push(k.as_slice());
+            }
         }
-        Ok(())
+        Ok(keys)
     }
 
-    /**
-    load rdb file into the store.
-     */
-    pub fn load_rdb(&mut self, mut reader: BufReader<File>) -> anyhow::Result<()> {
-        // Loading of the RDB file is based on the https://rdb.fnordig.de/file_format.html
-        let mut header = [0x00; 9];
-        reader.read_exact(&mut header)?;
-        let header = String::from_utf8_lossy(&header);
-        if !header.starts_with("REDIS") {
-            bail!("invalid header: {}", header);
+    /// visits up to count keys in order after the given key, returning the ones which are not expired and match
+    /// the glob pattern and type, and the last visited key to continue from, None once all keys were visited.
+    /// only keys starting with the literal prefix of the pattern are visited
+    pub fn scan(&self, after: Option<&[u8]>, pattern: Option<&[u8]>, value_type: Option<&str>, count: usize) -> (Vec<Vec<u8>>, Option<Vec<u8>>) {
+        let prefix = pattern.map_or(&b""[..], literal_prefix);
+        let start = match after {
+            Some(after) if after >= prefix => Bound::Excluded(after),
+            _ => Bound::Included(prefix),
+        };
+        let mut visited = self.key_index.range::<[u8], _>((start, Bound::Unbounded))
+            .take_while(|key| key.starts_with(prefix))
+            .peekable();
+        let mut keys = vec![];
+        let mut last = None;
+        for key in visited.by_ref().take(count.max(1)) {
+            // the entry is looked up without touching it, scanning is not an access of the key
+            let entry = self.slot_entries(key).and_then(|entries| entries.get(key.as_slice()));
+            let matches = match (entry, pattern, value_type) {
+                (Some(entry), _, _) if entry.is_expired() => false,
+                (None, _, _) => false,
+                (Some(_), Some(pattern), _) if !glob_match(pattern, key) => false,
+                (Some(entry), _, Some(value_type)) => entry.value_type().eq_ignore_ascii_case(value_type),
+                _ => true,
+            };
+            if matches {
+                keys.push(key.clone());
+            }
+            last = Some(key);
         }
+        let resume = visited.peek().and(last).cloned();
+        (keys, resume)
+    }
 
-        let version = header["REDIS".len()..header.len()].to_string();
-        println!("rdb version: {}", version);
-        let mut valid_until_ms = None;
+    /// random key which is not expired, None for an empty store
+    pub fn random_key(&self) -> Option<&[u8]> {
+        // expired keys are skipped, giving up if most of the keys are expired
+        for _ in 0..100 {
+            match self.random_entry() {
+                Some((key, entry)) if !entry.is_expired() => return Some(key),
+                Some(_) => continue,
+                None => return None,
+            }
+        }
+        None
+    }
 
-        while let Ok(op) = rdb::read_byte(&mut reader) {
-            match op {
-                0xFA => {
-                    // AUX fields
-                    let key = rdb::read_string(&mut reader)?;
-                    let value = rdb::read_string(&mut reader)?;
-                    println!("aux: {} {}", key, value);
-                    // TODO
-                }
-                0xFE => {
-                    // Database selector
-                    let db_number = match rdb::read_length(&mut reader)? {
-                        LengthEncoding::Len(len) => len,
-                        LengthEncoding::Short(len) => len as u32,
-                        LengthEncoding::Byte(len) => len as u32,
-                        LengthEncoding::Int(len) => len,
-                    };
-                    println!("database selector {}", db_number);
-                    // TODO
-                }
-                0xFB => {
-                    // resize db field
-                    let hash_size = rdb::read_int(&mut reader)?;
-                    let expire_size = rdb::read_int(&mut reader)?;
-                    println!("sizes {} {}", hash_size, expire_size);
-                    // TODO
-                }
-                0xFD => {
-                    // The following expire value is specified in seconds. The following 4 bytes represent the Unix timestamp as an unsigned integer.
-                    valid_until_ms = Some((rdb::read_u32(&mut reader)? as u64) * 1000);
-                }
-                0xFC => {
-                    // The following expire value is specified in milliseconds. The following 8 bytes represent the Unix timestamp as an unsigned long.
-                    valid_until_ms = Some(rdb::read_u64(&mut reader)?);
-                }
-                0xFF => {
-                    // rdb load finished
-                    rdb::read_crc64(&mut reader)?;
-                    return Ok(());
-                }
-                0..=14 => {
-                    let key = rdb::read_string(&mut reader)?;
-                    let value = rdb::read_string(&mut reader)?;
-                    let valid_until = valid_until_ms.map(|epoch_ms| {
-                        SystemTime::UNIX_EPOCH + Duration::from_millis(epoch_ms)
-                    });
-                    self.0.insert(
-                        key.clone(),
-                        StoreEntry::from_string(
-                            &value,
-                            valid_until,
-                        ),
-                    );
-                    // reset
-                    valid_until_ms = None;
+    /// random entry, possibly expired. None for an empty store
+    fn random_entry(&self) -> Option<(&Vec<u8>, &StoreEntry)> {
+        if self.len == 0 {
+            return None;
+        }
+        // the slots are walked by their sizes so that only the key's slot is iterated
+        let mut index = random_index(self.len);
+        let slot_entries = self.shards.iter()
+            .flat_map(|shard| shard.0.values())
+            .find(|entries| {
+                if index < entries.len() {
+                    true
+                } else {
+                    index -= entries.len();
+                    false
                 }
+            })?;
+        slot_entries.iter().nth(index)
+    }
 
-                _ => {
-                    bail!("invalid rdb op: {}", op);
-                }
+    pub fn count_keys_in_slot(&self, slot: u16) -> usize {
+        self.slot_keys(slot).count()
+    }
+
+    pub fn keys_in_slot(&self, slot: u16, count: usize) -> Vec<&[u8]> {
+        self.slot_keys(slot).take(count).map(|k| k.as_slice()).collect()
+    }
+
+    /// removes the key if it is expired, so that it can be written as a new value.
+    /// every write calls it first, so the expiration is emitted before the event of the write
+    fn drop_expired(&mut self, key: &[u8]) {
+        if self.get(key).is_some_and(|entry| entry.is_expired()) {
+            self.remove(key);
+            Stats::count(&self.stats.expired_keys);
+            self.emit(key, KeyEventKind::Expired);
+            self.notify(EventClass::Expired, "expired", key);
+        }
+    }
+
+    /// set stored under the key for modification, created if requested and missing
+    fn set_mut(&mut self, key: &[u8], create: bool) -> anyhow::Result<Option<&mut HashSet<Vec<u8>>>> {
+        self.drop_expired(key);
+        if create && self.get(key).is_none() {
+            self.insert(key.to_vec(), StoreEntry::new(Value::Set(Arc::new(HashSet::new())), None));
+        }
+        match self.get_mut(key) {
+            // readers holding a snapshot keep the previous members
+            Some(StoreEntry { value: Value::Set(set), .. }) => Ok(Some(Arc::make_mut(set))),
+            Some(_) => bail!(WRONGTYPE),
+            None => Ok(None),
+        }
+    }
+
+    /// adds members to a set, returns the number of new members
+    pub fn add_to_set(&mut self, key: &[u8], members: &[Vec<u8>]) -> anyhow::Result<usize> {
+        let set = self.set_mut(key, true)?.expect("set is created");
+        let added = members.iter().filter(|&member| set.insert(member.clone())).collect::<Vec<&Vec<u8>>>();
+        self.used_memory += added.iter().map(|member| element_usage(member)).sum::<usize>();
+        let added = added.len();
+        if added > 0 {
+            self.emit(key, KeyEventKind::SetAdded);
+            self.notify(EventClass::Set, "sadd", key);
+        }
+        Ok(added)
+    }
+
+    /// removes members from a set, returns the number of removed members.
+    /// the key is removed with the last member
+    pub fn remove_from_set(&mut self, key: &[u8], members: &[Vec<u8>]) -> anyhow::Result<usize> {
+        self.remove_set_members(key, members, "srem")
+    }
+
+    /// removes members from a set notifying the event, returns the number of removed members
+    fn remove_set_members(&mut self, key: &[u8], members: &[Vec<u8>], event: &'static str) -> anyhow::Result<usize> {
+        let Some(set) = self.set_mut(key, false)? else {
+            return Ok(0);
+        };
+        let removed = members.iter().filter(|&member| set.remove(member)).collect::<Vec<&Vec<u8>>>();
+        let is_empty = set.is_empty();
+        self.used_memory -= removed.iter().map(|member| element_usage(member)).sum::<usize>();
+        let removed = removed.len();
+        if is_empty {
+            self.remove(key);
+        }
+        if removed > 0 {
+            self.emit(key, KeyEventKind::SetRemoved);
+            self.notify(EventClass::Set, event, key);
+        }
+        if is_empty {
+            self.notify(EventClass::Generic, "del", key);
+        }
+        Ok(removed)
+    }
+
+    /// removes up to count random members from a set, None if the set does not exist
+    pub fn pop_from_set(&mut self, key: &[u8], count: usize) -> anyhow::Result<Option<Vec<Vec<u8>>>> {
+        let Some(set) = self.set_mut(key, false)? else {
+            return Ok(None);
+        };
+        let popped = random_sample(set.iter().cloned().collect(), count as i64);
+        self.remove_set_members(key, &popped, "spop")?;
+        Ok(Some(popped))
+    }
+
+    /// moves a member from the source set to the destination set, which is created if missing.
+    /// returns false if the member is not in the source
+    pub fn move_set_member(&mut self, source: &[u8], destination: &[u8], member: &[u8]) -> anyhow::Result<bool> {
+        // a destination of another type fails the move before the member is taken
+        self.drop_expired(destination);
+        self.set_snapshot(destination)?;
+        let member = [member.to_vec()];
+        if self.remove_from_set(source, &member)? == 0 {
+            return Ok(false);
+        }
+        self.add_to_set(destination, &member)?;
+        Ok(true)
+    }
+
+    /// stores the members as a set, replacing the value of the key whatever its type, notifying the event.
+    /// the key is removed when there are no members. returns the number of members
+    pub fn replace_set(&mut self, key: &[u8], members: HashSet<Vec<u8>>, event: &'static str) -> usize {
+        let len = members.len();
+        self.drop_expired(key);
+        if members.is_empty() {
+            if self.remove(key).is_some() {
+                self.notify(EventClass::Generic, "del", key);
             }
+        } else {
+            self.insert(key.to_vec(), StoreEntry::new(Value::Set(Arc::new(members)), None));
+            self.notify(EventClass::Set, event, key);
         }
-        Ok(())
+        len
+    }
+
+    /// string stored under the key for modification, created empty if requested and missing
+    fn string_mut(&mut self, key: &[u8], create: bool) -> anyhow::Result<Option<&mut Vec<u8>>> {
+        self.drop_expired(key);
+        if create && self.get(key).is_none() {
+            self.insert(key.to_vec(), StoreEntry::from_bytes(vec![], None));
+        }
+        match self.get_mut(key) {
+            Some(StoreEntry { value: Value::String(value), .. }) => Ok(Some(value)),
+            Some(_) => bail!(WRONGTYPE),
+            None => Ok(None),
+        }
+    }
+
+    fn string(&self, key: &[u8]) -> anyhow::Result<Option<&Vec<u8>>> {
+        match self.lookup(key) {
+            Some(StoreEntry { value: Value::String(value), .. }) => Ok(Some(value)),
+            Some(_) => bail!(WRONGTYPE),
+            None => Ok(None),
+        }
+    }
+
+    /// bytes of a string between the start and end offsets inclusive, negative offsets count from the end of the string
+    pub fn get_range(&self, key: &[u8], start: i64, end: i64) -> anyhow::Result<Vec<u8>> {
+        let Some(value) = self.string(key)? else {
+            return Ok(vec![]);
+        };
+        Ok(normalize_range(value.len(), start, end).map_or_else(Vec::new, |range| value[range].to_vec()))
+    }
+
+    /// overwrites a string from the offset on, padding it with zero bytes up to the offset.
+    /// returns the length of the string
+    pub fn set_range(&mut self, key: &[u8], offset: usize, value: &[u8]) -> anyhow::Result<usize> {
+        // with nothing to write, a missing key is not created
+        let Some(bytes) = self.string_mut(key, !value.is_empty())? else {
+            return Ok(0);
+        };
+        if value.is_empty() {
+            return Ok(bytes.len());
+        }
+        let previous_len = bytes.len();
+        let end = offset + value.len();
+        if bytes.len() < end {
+            bytes.resize(end, 0);
+        }
+        bytes[offset..end].copy_from_slice(value);
+        let len = bytes.len();
+        self.used_memory = self.used_memory + len - previous_len;
+        self.notify(EventClass::String, "setrange", key);
+        Ok(len)
+    }
+
+    /// sets or clears a bit of a string, padding it with zero bytes up to the bit. returns the previous value of the bit.
+    /// bits are numbered from the most significant bit of the first byte
+    pub fn set_bit(&mut self, key: &[u8], offset: usize, value: bool) -> anyhow::Result<bool> {
+        let bytes = self.string_mut(key, true)?.expect("string is created");
+        let previous_len = bytes.len();
+        let (index, mask) = (offset / 8, 0x80 >> (offset % 8));
+        if bytes.len() <= index {
+            bytes.resize(index + 1, 0);
+        }
+        let previous = bytes[index] & mask != 0;
+        if value {
+            bytes[index] |= mask;
+        } else {
+            bytes[index] &= !mask;
+        }
+        let len = bytes.len();
+        self.used_memory = self.used_memory + len - previous_len;
+        self.notify(EventClass::String, "setbit", key);
+        Ok(previous)
     }
-}
 
+    /// value of a bit of a string, unset past the end of the string or for a missing key
+    pub fn get_bit(&self, key: &[u8], offset: usize) -> anyhow::Result<bool> {
+        Ok(self.string(key)?
+            .and_then(|bytes| bytes.get(offset / 8))
+            .is_some_and(|byte| byte & (0x80 >> (offset % 8)) != 0))
+    }
+
+    /// number of set bits of a string, within the start and end offsets inclusive if given.
+    /// the offsets are of bytes, or of bits when `bits` is set
+    pub fn bit_count(&self, key: &[u8], range: Option<(i64, i64, bool)>) -> anyhow::Result<usize> {
+        let Some(bytes) = self.string(key)? else {
+            return Ok(0);
+        };
+        Ok(match range {
+            None => bytes.iter().map(|b| b.count_ones() as usize).sum(),
+            Some((start, end, false)) => normalize_range(bytes.len(), start, end)
+                .map_or(0, |range| bytes[range].iter().map(|b| b.count_ones() as usize).sum()),
+            // bits are numbered from the most significant bit of the first byte
+            Some((start, end, true)) => normalize_range(bytes.len() * 8, start, end)
+                .map_or(0, |range| range.filter(|bit| bytes[bit / 8] & (0x80 >> (bit % 8)) != 0).count()),
+        })
+    }
+
+    /// combines the strings with the bitwise operation and stores the result under the destination,
+    /// replacing its value whatever its type. missing keys count as empty strings and the destination is removed
+    /// when the result is empty. returns the length of the result
+    pub fn bit_op(&mut self, op: BitOp, destination: &[u8], keys: &[Vec<u8>]) -> anyhow::Result<usize> {
+        let values = keys.iter()
+            .map(|key| self.string(key).map(|value| value.map_or(&[][..], |value| value.as_slice())))
+            .collect::<anyhow::Result<Vec<&[u8]>>>()?;
+        let result = bit_op(op, &values);
+        let len = result.len();
+        self.drop_expired(destination);
+        if result.is_empty() {
+            if self.remove(destination).is_some() {
+                self.notify(EventClass::Generic, "del", destination);
+            }
+        } else {
+            self.insert(destination.to_vec(), StoreEntry::from_bytes(result, None));
+            self.notify(EventClass::String, "set", destination);
+        }
+        Ok(len)
+    }
+
+    /// position of the first bit of a string set to `bit` within the range, as told by [bit_pos].
+    /// a missing key counts as a string of clear bits
+    pub fn bit_pos(&self, key: &[u8], bit: bool, start: i64, end: Option<i64>, bits: bool) -> anyhow::Result<i64> {
+        Ok(match self.string(key)? {
+            Some(bytes) => bit_pos(bytes, bit, start, end, bits),
+            None if bit => -1,
+            None => 0,
+        })
+    }
+
+    /// runs the operations of BITFIELD on a string, returning their results in order.
+    /// a missing key counts as a string of clear bits and is only created by operations writing to it
+    pub fn bitfield(&mut self, key: &[u8], ops: &[BitfieldOp]) -> anyhow::Result<Vec<Option<i64>>> {
+        if let Some(fields) = ops.iter().map(|op| op.read_field()).collect::<Option<Vec<Field>>>() {
+            let bytes = self.string(key)?.map_or(&[][..], |bytes| bytes.as_slice());
+            return Ok(fields.iter().map(|field| Some(field.read(bytes))).collect());
+        }
+        let bytes = self.string_mut(key, true)?.expect("string is created");
+        let previous_len = bytes.len();
+        let results = ops.iter().map(|op| op.apply(bytes)).collect();
+        let len = bytes.len();
+        self.used_memory = self.used_memory + len - previous_len;
+        self.notify(EventClass::String, "setbit", key);
+        Ok(results)
+    }
+
+    /// HyperLogLog stored as a string under the key, None if the key is missing
+    fn hyperloglog(&self, key: &[u8]) -> anyhow::Result<Option<HyperLogLog>> {
+        self.string(key)?.map(|bytes| HyperLogLog::from_bytes(bytes)).transpose()
+    }
+
+    /// stores the HyperLogLog as the string under the key, keeping the expiration of an existing key
+    fn store_hyperloglog(&mut self, key: &[u8], hll: &HyperLogLog) -> anyhow::Result<()> {
+        let bytes = self.string_mut(key, true)?.expect("string is created");
+        let previous_len = bytes.len();
+        *bytes = hll.to_bytes();
+        let len = bytes.len();
+        self.used_memory = self.used_memory + len - previous_len;
+        self.notify(EventClass::String, "pfadd", key);
+        Ok(())
+    }
+
+    /// adds the elements to the HyperLogLog under the key, created if missing.
+    /// returns whether the estimated cardinality may have changed
+    pub fn pf_add(&mut self, key: &[u8], elements: &[Vec<u8>]) -> anyhow::Result<bool> {
+        self.drop_expired(key);
+        let (mut hll, mut changed) = match self.hyperloglog(key)? {
+            Some(hll) => (hll, false),
+            None => (HyperLogLog::default(), true),
+        };
+        for element in elements {
+            changed |= hll.add(element);
+        }
+        if changed {
+            self.store_hyperloglog(key, &hll)?;
+        }
+        Ok(changed)
+    }
+
+    /// estimated number of distinct elements added to any of the HyperLogLogs, missing keys count as empty
+    pub fn pf_count(&self, keys: &[Vec<u8>]) -> anyhow::Result<u64> {
+        let mut union = HyperLogLog::default();
+        for key in keys {
+            if let Some(hll) = self.hyperloglog(key)? {
+                union.merge(&hll);
+            }
+        }
+        Ok(union.count())
+    }
+
+    /// merges the HyperLogLogs into the one under the destination, which is created if missing
+    pub fn pf_merge(&mut self, destination: &[u8], keys: &[Vec<u8>]) -> anyhow::Result<()> {
+        self.drop_expired(destination);
+        let mut merged = self.hyperloglog(destination)?.unwrap_or_default();
+        for key in keys {
+            if let Some(hll) = self.hyperloglog(key)? {
+                merged.merge(&hll);
+            }
+        }
+        self.store_hyperloglog(destination, &merged)
+    }
+
+    /// list stored under the key for modification, created if requested and missing
+    fn list_mut(&mut self, key: &[u8], create: bool) -> anyhow::Result<Option<&mut VecDeque<Vec<u8>>>> {
+        self.drop_expired(key);
+        if create && self.get(key).is_none() {
+            self.insert(key.to_vec(), StoreEntry::new(Value::List(VecDeque::new()), None));
+        }
+        match self.get_mut(key) {
+            Some(StoreEntry { value: Value::List(list), .. }) => Ok(Some(list)),
+            Some(_) => bail!(WRONGTYPE),
+            None => Ok(None),
+        }
+    }
+
+    fn list(&self, key: &[u8]) -> anyhow::Result<Option<&VecDeque<Vec<u8>>>> {
+        match self.lookup(key) {
+            Some(StoreEntry { value: Value::List(list), .. }) => Ok(Some(list)),
+            Some(_) => bail!(WRONGTYPE),
+            None => Ok(None),
+        }
+    }
+
+    /// pushes the elements one after another to the end of a list, returns the length of the list
+    pub fn push_to_list(&mut self, key: &[u8], end: ListEnd, elements: &[Vec<u8>]) -> anyhow::Result<usize> {
+        let list = self.list_mut(key, true)?.expect("list is created");
+        for element in elements {
+            match end {
+                ListEnd::Left => list.push_front(element.clone()),
+                ListEnd::Right => list.push_back(element.clone()),
+            }
+        }
+        let len = list.len();
+        self.used_memory += elements.iter().map(|element| element_usage(element)).sum::<usize>();
+        self.emit(key, KeyEventKind::ListPushed);
+        self.notify(EventClass::List, end.push_event(), key);
+        Ok(len)
+    }
+
+    /// pops up to count elements from the end of a list, None if the list does not exist.
+    /// the key is removed with the last element
+    pub fn pop_from_list(&mut self, key: &[u8], end: ListEnd, count: usize) -> anyhow::Result<Option<Vec<Vec<u8>>>> {
+        let Some(list) = self.list_mut(key, false)? else {
+            return Ok(None);
+        };
+        let count = count.min(list.len());
+        let popped: Vec<Vec<u8>> = match end {
+            ListEnd::Left => list.drain(..count).collect(),
+            ListEnd::Right => list.drain(list.len() - count..).rev().collect(),
+        };
+        let is_empty = list.is_empty();
+        self.used_memory -= popped.iter().map(|element| element_usage(element)).sum::<usize>();
+        if is_empty {
+            self.remove(key);
+        }
+        if !popped.is_empty() {
+            self.emit(key, KeyEventKind::ListPopped);
+            self.notify(EventClass::List, end.pop_event(), key);
+        }
+        if is_empty {
+            self.notify(EventClass::Generic, "del", key);
+        }
+        Ok(Some(popped))
+    }
+
+    /// moves an element from the end of the source list to the end of the destination list, returns the moved element
+    /// or None if the source does not exist. the destination is created if missing and can be the source itself
+    pub fn move_list_element(&mut self, source: &[u8], destination: &[u8], from: ListEnd, to: ListEnd) -> anyhow::Result<Option<Vec<u8>>> {
+        // a destination of another type fails the move before the element is taken
+        self.drop_expired(destination);
+        self.list(destination)?;
+        let Some(element) = self.pop_from_list(source, from, 1)?.and_then(|mut popped| popped.pop()) else {
+            return Ok(None);
+        };
+        self.push_to_list(destination, to, std::slice::from_ref(&element))?;
+        Ok(Some(element))
+    }
+
+    /// number of elements of a list, 0 if the list does not exist
+    pub fn list_len(&self, key: &[u8]) -> anyhow::Result<usize> {
+        Ok(self.list(key)?.map_or(0, |list| list.len()))
+    }
+
+    /// elements between the start and stop indexes inclusive, negative indexes count from the end of the list
+    pub fn list_range(&self, key: &[u8], start: i64, stop: i64) -> anyhow::Result<Vec<Vec<u8>>> {
+        let Some(list) = self.list(key)? else {
+            return Ok(vec![]);
+        };
+        Ok(normalize_range(list.len(), start, stop)
+            .map_or_else(Vec::new, |range| list.range(range).cloned().collect()))
+    }
+
+    /// element at the index, negative indexes count from the end of the list
+    pub fn list_index(&self, key: &[u8], index: i64) -> anyhow::Result<Option<Vec<u8>>> {
+        let Some(list) = self.list(key)? else {
+            return Ok(None);
+        };
+        Ok(normalize_index(list.len(), index).map(|position| list[position].clone()))
+    }
+
+    /// inserts the element before or after the first occurrence of the pivot.
+    /// returns the length of the list, 0 if the list does not exist and -1 if the pivot is not found
+    pub fn list_insert(&mut self, key: &[u8], before: bool, pivot: &[u8], element: &[u8]) -> anyhow::Result<i64> {
+        let Some(list) = self.list_mut(key, false)? else {
+            return Ok(0);
+        };
+        let Some(position) = list.iter().position(|e| e == pivot) else {
+            return Ok(-1);
+        };
+        list.insert(if before { position } else { position + 1 }, element.to_vec());
+        let len = list.len();
+        self.used_memory += element_usage(element);
+        self.notify(EventClass::List, "linsert", key);
+        Ok(len as i64)
+    }
+
+    /// replaces the element at the index, negative indexes count from the end of the list
+    pub fn list_set(&mut self, key: &[u8], index: i64, element: &[u8]) -> anyhow::Result<()> {
+        let Some(list) = self.list_mut(key, false)? else {
+            bail!("ERR no such key");
+        };
+        let Some(position) = normalize_index(list.len(), index) else {
+            bail!("ERR index out of range");
+        };
+        let replaced = std::mem::replace(&mut list[position], element.to_vec());
+        self.used_memory = self.used_memory + element_usage(element) - element_usage(&replaced);
+        self.notify(EventClass::List, "lset", key);
+        Ok(())
+    }
+
+    /// removes count occurrences of the element, from the head for a positive count, from the tail for a negative one
+    /// and all of them for 0. returns the number of removed elements
+    pub fn list_remove(&mut self, key: &[u8], count: i64, element: &[u8]) -> anyhow::Result<usize> {
+        let Some(list) = self.list_mut(key, false)? else {
+            return Ok(0);
+        };
+        let limit = if count == 0 { usize::MAX } else { count.unsigned_abs() as usize };
+        let positions: Vec<usize> = if count < 0 {
+            (0..list.len()).rev().filter(|&i| list[i] == element).take(limit).collect()
+        } else {
+            (0..list.len()).filter(|&i| list[i] == element).take(limit).collect()
+        };
+        let mut removed = vec![false; list.len()];
+        for &position in &positions {
+            removed[position] = true;
+        }
+        let mut index = 0;
+        list.retain(|_| {
+            index += 1;
+            !removed[index - 1]
+        });
+        let is_empty = list.is_empty();
+        self.used_memory -= positions.len() * element_usage(element);
+        if is_empty {
+            self.remove(key);
+        }
+        if !positions.is_empty() {
+            self.notify(EventClass::List, "lrem", key);
+        }
+        if is_empty {
+            self.notify(EventClass::Generic, "del", key);
+        }
+        Ok(positions.len())
+    }
+
+    /// keeps only the elements between the start and stop indexes inclusive,
+    /// negative indexes count from the end of the list
+    pub fn list_trim(&mut self, key: &[u8], start: i64, stop: i64) -> anyhow::Result<()> {
+        let Some(list) = self.list_mut(key, false)? else {
+            return Ok(());
+        };
+        let kept = normalize_range(list.len(), start, stop).unwrap_or(list.len()..list.len());
+        let mut removed: Vec<Vec<u8>> = list.drain(kept.end..).collect();
+        removed.extend(list.drain(..kept.start));
+        let is_empty = list.is_empty();
+        self.used_memory -= removed.iter().map(|element| element_usage(element)).sum::<usize>();
+        if is_empty {
+            self.remove(key);
+        }
+        self.notify(EventClass::List, "ltrim", key);
+        if is_empty {
+            self.notify(EventClass::Generic, "del", key);
+        }
+        Ok(())
+    }
+
+    /// snapshot of the set members, which can be read after releasing the store
+    pub fn set_snapshot(&self, key: &[u8]) -> anyhow::Result<Option<SetSnapshot>> {
+        match self.lookup(key) {
+            Some(StoreEntry { value: Value::Set(set), .. }) => Ok(Some(set.clone())),
+            Some(_) => bail!(WRONGTYPE),
+            None => Ok(None),
+        }
+    }
+
+    /// hash stored under the key for modification, created if requested and missing
+    fn hash_mut(&mut self, key: &[u8], create: bool) -> anyhow::Result<Option<&mut HashMap<Vec<u8>, Vec<u8>>>> {
+        self.drop_expired(key);
+        if create && self.get(key).is_none() {
+            self.insert(key.to_vec(), StoreEntry::new(Value::Hash(HashMap::new()), None));
+        }
+        match self.get_mut(key) {
+            Some(StoreEntry { value: Value::Hash(hash), .. }) => Ok(Some(hash)),
+            Some(_) => bail!(WRONGTYPE),
+            None => Ok(None),
+        }
+    }
+
+    fn hash(&self, key: &[u8]) -> anyhow::Result<Option<&HashMap<Vec<u8>, Vec<u8>>>> {
+        match self.lookup(key) {
+            Some(StoreEntry { value: Value::Hash(hash), .. }) => Ok(Some(hash)),
+            Some(_) => bail!(WRONGTYPE),
+            None => Ok(None),
+        }
+    }
+
+    /// sets the fields of a hash, returns the number of new fields
+    pub fn set_hash_fields(&mut self, key: &[u8], fields: &[(Vec<u8>, Vec<u8>)]) -> anyhow::Result<usize> {
+        let hash = self.hash_mut(key, true)?.expect("hash is created");
+        let (mut added, mut added_usage, mut removed_usage) = (0, 0, 0);
+        for (field, value) in fields {
+            match hash.insert(field.clone(), value.clone()) {
+                Some(previous) => removed_usage += field_usage(field, &previous),
+                None => added += 1,
+            }
+            added_usage += field_usage(field, value);
+        }
+        self.used_memory = self.used_memory + added_usage - removed_usage;
+        self.notify(EventClass::Hash, "hset", key);
+        Ok(added)
+    }
+
+    /// sets the field of a hash unless it exists, returns whether it was set
+    pub fn set_hash_field_if_missing(&mut self, key: &[u8], field: &[u8], value: &[u8]) -> anyhow::Result<bool> {
+        let hash = self.hash_mut(key, true)?.expect("hash is created");
+        if hash.contains_key(field) {
+            return Ok(false);
+        }
+        hash.insert(field.to_vec(), value.to_vec());
+        self.used_memory += field_usage(field, value);
+        self.notify(EventClass::Hash, "hset", key);
+        Ok(true)
+    }
+
+    /// replaces the value of a hash field with the one computed from the current value, notifying the event.
+    /// returns the new value, a failed update leaves the hash unchanged
+    pub fn update_hash_field(
+        &mut self,
+        key: &[u8],
+        field: &[u8],
+        event: &'static str,
+        update: impl FnOnce(Option<&[u8]>) -> anyhow::Result<Vec<u8>>,
+    ) -> anyhow::Result<Vec<u8>> {
+        // a missing hash is not created when the update fails
+        self.drop_expired(key);
+        let current = self.hash(key)?.and_then(|hash| hash.get(field)).cloned();
+        let value = update(current.as_deref())?;
+        let hash = self.hash_mut(key, true)?.expect("hash is created");
+        hash.insert(field.to_vec(), value.clone());
+        self.used_memory = self.used_memory + field_usage(field, &value) - current.map_or(0, |current| field_usage(field, &current));
+        self.notify(EventClass::Hash, event, key);
+        Ok(value)
+    }
+
+    /// removes fields from a hash, returns the number of removed fields.
+    /// the key is removed with the last field
+    pub fn delete_hash_fields(&mut self, key: &[u8], fields: &[Vec<u8>]) -> anyhow::Result<usize> {
+        let Some(hash) = self.hash_mut(key, false)? else {
+            return Ok(0);
+        };
+        let removed = fields.iter()
+            .filter_map(|field| hash.remove(field).map(|value| field_usage(field, &value)))
+            .collect::<Vec<usize>>();
+        let is_empty = hash.is_empty();
+        self.used_memory -= removed.iter().sum::<usize>();
+        if is_empty {
+            self.remove(key);
+        }
+        if !removed.is_empty() {
+            self.notify(EventClass::Hash, "hdel", key);
+        }
+        if is_empty {
+            self.notify(EventClass::Generic, "del", key);
+        }
+        Ok(removed.len())
+    }
+
+    /// values of the fields of a hash, None for missing fields
+    pub fn hash_values(&self, key: &[u8], fields: &[Vec<u8>]) -> anyhow::Result<Vec<Option<Vec<u8>>>> {
+        let hash = self.hash(key)?;
+        Ok(fields.iter().map(|field| hash.and_then(|hash| hash.get(field)).cloned()).collect())
+    }
+
+    /// fields of a hash with their values, empty if the hash does not exist
+    pub fn hash_entries(&self, key: &[u8]) -> anyhow::Result<Vec<(Vec<u8>, Vec<u8>)>> {
+        Ok(self.hash(key)?.map_or_else(Vec::new, |hash| {
+            hash.iter().map(|(field, value)| (field.clone(), value.clone())).collect()
+        }))
+    }
+
+    /// random fields of a hash with their values, None if the hash does not exist.
+    /// a positive count returns distinct fields, up to all of them, a negative count may repeat fields
+    pub fn random_hash_fields(&self, key: &[u8], count: i64) -> anyhow::Result<Option<FieldValues>> {
+        let Some(hash) = self.hash(key)? else {
+            return Ok(None);
+        };
+        let picked = random_sample(hash.iter().collect::<Vec<(&Vec<u8>, &Vec<u8>)>>(), count);
+        Ok(Some(picked.into_iter().map(|(field, value)| (field.clone(), value.clone())).collect()))
+    }
+
+    /// number of fields of a hash, 0 if the hash does not exist
+    pub fn hash_len(&self, key: &[u8]) -> anyhow::Result<usize> {
+        Ok(self.hash(key)?.map_or(0, |hash| hash.len()))
+    }
+
+    /// sorted set stored under the key for modification, created if requested and missing
+    fn zset_mut(&mut self, key: &[u8], create: bool) -> anyhow::Result<Option<&mut SortedSet>> {
+        self.drop_expired(key);
+        if create && self.get(key).is_none() {
+            self.insert(key.to_vec(), StoreEntry::new(Value::ZSet(SortedSet::default()), None));
+        }
+        match self.get_mut(key) {
+            Some(StoreEntry { value: Value::ZSet(zset), .. }) => Ok(Some(zset)),
+            Some(_) => bail!(WRONGTYPE),
+            None => Ok(None),
+        }
+    }
+
+    fn zset(&self, key: &[u8]) -> anyhow::Result<Option<&SortedSet>> {
+        match self.lookup(key) {
+            Some(StoreEntry { value: Value::ZSet(zset), .. }) => Ok(Some(zset)),
+            Some(_) => bail!(WRONGTYPE),
+            None => Ok(None),
+        }
+    }
+
+    /// adds members with their scores to a sorted set or updates their scores, as allowed by the options.
+    /// returns the number of new members, the number of updated members and the score of the last member,
+    /// None if it was not added or updated.
+    /// a missing key is not created when only existing members are updated
+    pub fn add_to_zset(&mut self, key: &[u8], members: &[(f64, Vec<u8>)], options: &AddOptions) -> anyhow::Result<(usize, usize, Option<f64>)> {
+        let create = !options.xx;
+        let Some(zset) = self.zset_mut(key, create)? else {
+            return Ok((0, 0, None));
+        };
+        let (mut added, mut added_usage, mut updated, mut last_score, mut failure) = (0, 0, 0, None, None);
+        for (score, member) in members {
+            match zset.add(member, *score, options) {
+                Ok((Added::New, score)) => {
+                    added += 1;
+                    added_usage += zset_member_usage(member);
+                    last_score = Some(score);
+                }
+                Ok((Added::Updated, score)) => {
+                    updated += 1;
+                    last_score = Some(score);
+                }
+                Ok((Added::Unchanged, score)) => last_score = Some(score),
+                Ok((Added::Skipped, _)) => last_score = None,
+                Err(err) => {
+                    failure = Some(err);
+                    break;
+                }
+            }
+        }
+        let is_empty = zset.is_empty();
+        self.used_memory += added_usage;
+        if is_empty {
+            self.remove(key);
+        }
+        if added > 0 {
+            self.emit(key, KeyEventKind::ZSetAdded);
+        }
+        if added + updated > 0 {
+            self.notify(EventClass::ZSet, if options.incr { "zincr" } else { "zadd" }, key);
+        }
+        if let Some(err) = failure {
+            return Err(err);
+        }
+        Ok((added, updated, last_score))
+    }
+
+    /// stores the members with their scores as a sorted set, replacing the value of the key whatever its type,
+    /// notifying the event. the key is removed when there are no members. returns the number of members
+    pub fn replace_zset(&mut self, key: &[u8], members: Vec<(Vec<u8>, f64)>, event: &'static str) -> usize {
+        let len = members.len();
+        self.drop_expired(key);
+        if members.is_empty() {
+            if self.remove(key).is_some() {
+                self.notify(EventClass::Generic, "del", key);
+            }
+        } else {
+            let mut zset = SortedSet::default();
+            for (member, score) in members {
+                zset.insert(&member, score);
+            }
+            self.insert(key.to_vec(), StoreEntry::new(Value::ZSet(zset), None));
+            self.emit(key, KeyEventKind::ZSetAdded);
+            self.notify(EventClass::ZSet, event, key);
+        }
+        len
+    }
+
+    /// pops up to count members with their scores from a sorted set, the lowest scores first or the highest with `max`.
+    /// None if the set does not exist. the key is removed with the last member
+    pub fn pop_from_zset(&mut self, key: &[u8], max: bool, count: usize) -> anyhow::Result<Option<ScoredMembers>> {
+        let Some(zset) = self.zset_mut(key, false)? else {
+            return Ok(None);
+        };
+        let last = i64::try_from(count).unwrap_or(i64::MAX) - 1;
+        let popped = zset.range(&ZRange::Rank(0, last), max, None);
+        for (member, _) in &popped {
+            zset.remove(member);
+        }
+        let is_empty = zset.is_empty();
+        self.used_memory -= popped.iter().map(|(member, _)| zset_member_usage(member)).sum::<usize>();
+        if is_empty {
+            self.remove(key);
+        }
+        if !popped.is_empty() {
+            self.emit(key, KeyEventKind::ZSetRemoved);
+            self.notify(EventClass::ZSet, if max { "zpopmax" } else { "zpopmin" }, key);
+        }
+        if is_empty {
+            self.notify(EventClass::Generic, "del", key);
+        }
+        Ok(Some(popped))
+    }
+
+    /// removes the members of a sorted set in the range, nothing without a range. returns the number of removed members.
+    /// the key is removed with the last member
+    pub fn remove_zset_range(&mut self, key: &[u8], range: Option<&ZRange>) -> anyhow::Result<usize> {
+        let Some(zset) = self.zset_mut(key, false)? else {
+            return Ok(0);
+        };
+        let Some(range) = range else {
+            return Ok(0);
+        };
+        let removed = zset.range(range, false, None);
+        for (member, _) in &removed {
+            zset.remove(member);
+        }
+        let is_empty = zset.is_empty();
+        self.used_memory -= removed.iter().map(|(member, _)| zset_member_usage(member)).sum::<usize>();
+        if is_empty {
+            self.remove(key);
+        }
+        if !removed.is_empty() {
+            self.emit(key, KeyEventKind::ZSetRemoved);
+            let event = match range {
+                ZRange::Rank(..) => "zremrangebyrank",
+                ZRange::Score(..) => "zremrangebyscore",
+                ZRange::Lex(..) => "zremrangebylex",
+            };
+            self.notify(EventClass::ZSet, event, key);
+        }
+        if is_empty {
+            self.notify(EventClass::Generic, "del", key);
+        }
+        Ok(removed.len())
+    }
+
+    /// score of a member of a sorted set, None if the member or the set do not exist
+    pub fn zset_score(&self, key: &[u8], member: &[u8]) -> anyhow::Result<Option<f64>> {
+        Ok(self.zset(key)?.and_then(|zset| zset.score(member)))
+    }
+
+    /// number of members of a sorted set, 0 if the set does not exist
+    pub fn zset_len(&self, key: &[u8]) -> anyhow::Result<usize> {
+        Ok(self.zset(key)?.map_or(0, |zset| zset.len()))
+    }
+
+    /// rank of a member of a sorted set with its score, None if the member or the set do not exist
+    pub fn zset_rank(&self, key: &[u8], member: &[u8], rev: bool) -> anyhow::Result<Option<(usize, f64)>> {
+        Ok(self.zset(key)?.and_then(|zset| Some((zset.rank(member, rev)?, zset.score(member)?))))
+    }
+
+    /// number of members of a sorted set in the range, 0 if the set does not exist
+    pub fn zset_count(&self, key: &[u8], range: &ZRange) -> anyhow::Result<usize> {
+        Ok(self.zset(key)?.map_or(0, |zset| zset.range(range, false, None).len()))
+    }
+
+    /// members of a sorted set in the range with their scores, empty if the set does not exist
+    pub fn zset_range(&self, key: &[u8], range: &ZRange, rev: bool, limit: Option<(usize, Option<usize>)>) -> anyhow::Result<Vec<(Vec<u8>, f64)>> {
+        Ok(self.zset(key)?.map_or_else(Vec::new, |zset| zset.range(range, rev, limit)))
+    }
+
+    /// removes the keys returning the removed entries, expired keys are not counted as removed
+    pub fn remove_keys(&mut self, keys: &[Vec<u8>]) -> Vec<StoreEntry> {
+        let mut removed = vec![];
+        for key in keys {
+            self.drop_expired(key);
+            if let Some(entry) = self.remove(key) {
+                self.notify(EventClass::Generic, "del", key);
+                removed.push(entry);
+            }
+        }
+        removed
+    }
+
+    /// removes all keys returning the removed entries
+    pub fn flush(&mut self) -> Vec<StoreEntry> {
+        self.write_seq += 1;
+        self.len = 0;
+        self.used_memory = 0;
+        self.key_index.clear();
+        self.shards.iter_mut()
+            .flat_map(|shard| shard.0.drain())
+            .flat_map(|(_, entries)| entries.into_values())
+            .collect()
+    }
+
+    /// number of writes to the store since it was created, which the automatic saves compare with the last save
+    pub fn write_count(&self) -> u64 {
+        self.write_seq
+    }
+
+    /// approximate bytes held by the keys and values
+    pub fn used_memory(&self) -> usize {
+        self.used_memory
+    }
+
+    /// evicts keys chosen by the policy until the used memory is within the limit.
+    /// each eviction samples keys at random and evicts the best candidate among them,
+    /// approximating the policy without keeping the keys ordered.
+    /// returns the evicted keys with their entries, fewer than needed if the policy has no more candidates
+    pub fn evict(&mut self, max_memory: usize, policy: EvictionPolicy, samples: usize) -> Vec<(Vec<u8>, StoreEntry)> {
+        let mut evicted = vec![];
+        while self.used_memory > max_memory && policy != EvictionPolicy::NoEviction {
+            let Some(key) = self.eviction_candidate(policy, samples.max(1)) else {
+                break;
+            };
+            if let Some(entry) = self.remove(&key) {
+                Stats::count(&self.stats.evicted_keys);
+                self.notify(EventClass::Evicted, "evicted", &key);
+                evicted.push((key, entry));
+            }
+        }
+        evicted
+    }
+
+    /// removes expired keys found by sampling keys with an expiration, so that keys which are never accessed again
+    /// don't hold memory. sampling is repeated while a quarter of the sampled keys were expired, within the time limit.
+    /// returns the removed keys with their entries
+    pub fn expire_sampled(&mut self, samples: usize, time_limit: Duration) -> Vec<(Vec<u8>, StoreEntry)> {
+        let started_at = Instant::now();
+        let mut expired = vec![];
+        loop {
+            // keys with an expiration may be rare, so more keys are drawn to find enough of them
+            let sampled = (0..samples * 10)
+                .filter_map(|_| self.random_entry())
+                .filter(|(_, entry)| entry.valid_until.is_some())
+                .take(samples)
+                .map(|(key, entry)| (key.clone(), entry.is_expired()))
+                .collect::<Vec<(Vec<u8>, bool)>>();
+            let expired_keys = sampled.iter().filter(|(_, is_expired)| *is_expired).map(|(key, _)| key).collect::<Vec<_>>();
+            for key in &expired_keys {
+                if let Some(entry) = self.remove(key) {
+                    Stats::count(&self.stats.expired_keys);
+                    self.emit(key, KeyEventKind::Expired);
+                    self.notify(EventClass::Expired, "expired", key);
+                    expired.push((key.to_vec(), entry));
+                }
+            }
+            if expired_keys.len() * 4 < sampled.len().max(1) || started_at.elapsed() >= time_limit {
+                return expired;
+            }
+        }
+    }
+
+    fn eviction_candidate(&self, policy: EvictionPolicy, samples: usize) -> Option<Vec<u8>> {
+        // keys with an expiration may be rare, so more keys are drawn to find enough of them
+        let attempts = if policy.is_volatile() { samples * 10 } else { samples };
+        let candidates = (0..attempts)
+            .filter_map(|_| self.random_entry())
+            .filter(|(_, entry)| !policy.is_volatile() || entry.valid_until.is_some())
+            .take(samples);
+        let best = match policy {
+            EvictionPolicy::AllKeysLru | EvictionPolicy::VolatileLru => candidates.min_by_key(|(_, entry)| entry.last_access_ms()),
+            EvictionPolicy::VolatileTtl => candidates.min_by_key(|(_, entry)| entry.valid_until),
+            _ => candidates.take(1).next(),
+        };
+        best.map(|(key, _)| key.clone())
+    }
+
+    /// number of keys, including expired keys which are not removed yet
+    pub fn key_count(&self) -> usize {
+        self.len
+    }
+
+    /// encoding of the value of the key as reported by OBJECT ENCODING, None if the key does not exist
+    pub fn object_encoding(&self, key: &[u8]) -> Option<&'static str> {
+        self.get(key).filter(|entry| !entry.is_expired()).map(|entry| entry.value.encoding())
+    }
+
+    /// internals of the value of the key as reported by DEBUG OBJECT, None if the key does not exist.
+    /// the address and the LRU clock follow the format of redis, the number of elements is reported for collections
+    pub fn debug_object(&self, key: &[u8]) -> Option<String> {
+        let entry = self.get(key).filter(|entry| !entry.is_expired())?;
+        let idle_secs = lru_clock_ms().saturating_sub(entry.last_access_ms()) / 1000;
+        let mut info = format!(
+            "Value at:{:p} refcount:1 encoding:{} serializedlength:{} lru:{} lru_seconds_idle:{}",
+            &entry.value, entry.value.encoding(), entry.value.serialized_len(), entry.last_access_ms() / 1000 % LRU_CLOCK_WRAP, idle_secs,
+        );
+        if !matches!(entry.value, Value::String(_)) {
+            info.push_str(&format!(" elements:{}", entry.value.len()));
+        }
+        Some(info)
+    }
+
+    /// approximate bytes held by the key and its value, None if the key does not exist
+    pub fn key_memory_usage(&self, key: &[u8]) -> Option<usize> {
+        self.get(key).filter(|entry| !entry.is_expired()).map(|entry| entry.memory_usage(key))
+    }
+
+    /// breakdown of the used memory, walking all keys
+    pub fn memory_stats(&self) -> MemoryStats {
+        let mut stats = MemoryStats { keys: self.key_count(), used_memory: self.used_memory, overhead: 0, biggest: None };
+        for (key, entry) in self.entries() {
+            let len = entry.value.len();
+            stats.overhead += ENTRY_OVERHEAD + len * ELEMENT_OVERHEAD;
+            if len > stats.biggest.as_ref().map_or(0, |(_, biggest)| *biggest) {
+                stats.biggest = Some((key.clone(), len));
+            }
+        }
+        stats
+    }
+
+    /// number of keys with an expiration
+    pub fn volatile_key_count(&self) -> usize {
+        self.entries().filter(|(_, entry)| entry.valid_until.is_some()).count()
+    }
+
+    /// average time to live of the keys with an expiration in milliseconds, 0 without such keys
+    pub fn average_ttl_ms(&self) -> u64 {
+        let now = SystemTime::now();
+        let ttls = self.entries()
+            .filter_map(|(_, entry)| entry.valid_until)
+            .map(|valid_until| valid_until.duration_since(now).unwrap_or_default().as_millis() as u64)
+            .collect::<Vec<u64>>();
+        if ttls.is_empty() { 0 } else { ttls.iter().sum::<u64>() / ttls.len() as u64 }
+    }
+
+    /// checks for a key which is not expired
+    pub fn contains_key(&self, key: &[u8]) -> bool {
+        self.get(key).is_some_and(|v| !v.is_expired())
+    }
+
+    /// moves the value under a new key keeping its expiration.
+    /// returns false if the source key does not exist
+    pub fn rename(&mut self, key: &[u8], new_key: &[u8]) -> bool {
+        self.drop_expired(key);
+        match self.remove(key) {
+            Some(entry) if !entry.is_expired() => {
+                self.drop_expired(new_key);
+                self.insert(new_key.to_vec(), entry);
+                self.emit(key, KeyEventKind::RenamedFrom);
+                self.emit(new_key, KeyEventKind::RenamedTo);
+                self.notify(EventClass::Generic, "rename_from", key);
+                self.notify(EventClass::Generic, "rename_to", new_key);
+                true
+            }
+            _ => false,
+        }
+    }
+
+    /// copies the value into the destination key keeping its expiration.
+    /// returns false if the source does not exist or the destination exists and replace is not requested,
+    /// fails if the source is the destination
+    pub fn copy(&mut self, key: &[u8], destination: &[u8], replace: bool) -> anyhow::Result<bool> {
+        if key == destination {
+            bail!("ERR source and destination objects are the same");
+        }
+        if !replace && self.contains_key(destination) {
+            return Ok(false);
+        }
+        match self.get(key) {
+            Some(entry) if !entry.is_expired() => {
+                let copy = entry.clone_with_ttl(entry.valid_until);
+                self.drop_expired(destination);
+                self.insert(destination.to_vec(), copy);
+                self.emit(destination, KeyEventKind::CopiedTo);
+                self.notify(EventClass::Generic, "copy_to", destination);
+                Ok(true)
+            }
+            _ => Ok(false),
+        }
+    }
+
+    /// removes a string value returning it.
+    /// fails if the key holds a value of another type
+    pub fn remove_value(&mut self, key: &[u8]) -> anyhow::Result<Option<Vec<u8>>> {
+        self.drop_expired(key);
+        match self.get(key) {
+            Some(StoreEntry { value: Value::String(_), .. }) => {
+                self.notify(EventClass::Generic, "del", key);
+                Ok(self.remove(key).and_then(|entry| entry.value()))
+            }
+            Some(_) => bail!(WRONGTYPE),
+            None => Ok(None),
+        }
+    }
+
+    /// sets or clears (with None) the expiration of an existing key.
+    /// returns false if the key does not exist
+    pub fn set_expiration(&mut self, key: &[u8], valid_until: Option<SystemTime>) -> bool {
+        self.drop_expired(key);
+        match self.get_mut(key) {
+            Some(entry) if !entry.is_expired() => {
+                entry.set_valid_until(valid_until);
+                self.notify(EventClass::Generic, if valid_until.is_some() { "expire" } else { "persist" }, key);
+                true
+            }
+            _ => false,
+        }
+    }
+
+    /// expiration time of a key, with the inner None for keys without expiration.
+    /// returns None if the key does not exist
+    pub fn expiration(&self, key: &[u8]) -> Option<Option<SystemTime>> {
+        self.lookup(key).map(|entry| entry.valid_until())
+    }
+
+    /// removes the expiration of a key.
+    /// returns false if the key does not exist or has no expiration
+    pub fn persist(&mut self, key: &[u8]) -> bool {
+        self.drop_expired(key);
+        match self.get_mut(key) {
+            Some(entry) if !entry.is_expired() && entry.valid_until().is_some() => {
+                entry.set_valid_until(None);
+                self.notify(EventClass::Generic, "persist", key);
+                true
+            }
+            _ => false,
+        }
+    }
+
+    /// sets the expiration of an existing key if all conditions hold.
+    /// a time in the past deletes the key.
+    /// returns false if the key does not exist or a condition is not met
+    pub fn expire(&mut self, key: &[u8], valid_until: SystemTime, conditions: &[ExpireCondition]) -> bool {
+        self.drop_expired(key);
+        let entry = match self.get_mut(key) {
+            Some(entry) if !entry.is_expired() => entry,
+            _ => return false,
+        };
+        // keys without expiration have infinite ttl
+        let current = entry.valid_until();
+        let applies = conditions.iter().all(|condition| match condition {
+            ExpireCondition::NX => current.is_none(),
+            ExpireCondition::XX => current.is_some(),
+            ExpireCondition::GT => current.is_some_and(|current| valid_until > current),
+            ExpireCondition::LT => match current {
+                Some(current) => valid_until < current,
+                None => true,
+            },
+        });
+        if !applies {
+            return false;
+        }
+        if valid_until <= SystemTime::now() {
+            self.remove(key);
+            self.notify(EventClass::Generic, "del", key);
+        } else {
+            entry.set_valid_until(Some(valid_until));
+            self.notify(EventClass::Generic, "expire", key);
+        }
+        true
+    }
+
+    pub fn insert_value(&mut self, key: &[u8], value: &[u8], expiration: Option<Duration>) {
+        self.drop_expired(key);
+        let valid_until = expiration
+            .and_then(|d| SystemTime::now().checked_add(d));
+        self.insert(key.to_vec(), StoreEntry::from_bytes(value.to_vec(), valid_until));
+        self.notify(EventClass::String, "set", key);
+    }
+
+    /// adds an entry to the stream, which is created if missing unless `no_mkstream`, then trims the stream.
+    /// returns the id of the entry, None if the stream is missing and not created
+    pub fn insert_stream(
+        &mut self,
+        key: &[u8],
+        id_pattern: &str,
+        stream_data: Vec<(Vec<u8>, Vec<u8>)>,
+        no_mkstream: bool,
+        trim: Option<&Trim>,
+    ) -> anyhow::Result<Option<String>> {
+        self.drop_expired(key);
+        let created = self.get(key).is_none();
+        if created {
+            if no_mkstream {
+                return Ok(None);
+            }
+            self.insert(key.to_vec(), StoreEntry::empty_stream());
+        }
+
+        let value = self.live_stream_mut(key)?.ok_or_else(|| format_err!("stream not found {}", key.text()))?;
+        let id = match value.add_entry(id_pattern.to_string(), stream_data) {
+            Ok(id) => id,
+            Err(err) => {
+                // a stream is not created by a failed XADD
+                if created {
+                    self.remove(key);
+                }
+                return Err(err);
+            }
+        };
+        let added = value.last().map_or(0, |record| record.memory_usage());
+        let trimmed = trim.map_or(vec![], |trim| value.trim(trim));
+        self.used_memory = self.used_memory + added - trimmed.iter().map(|record| record.memory_usage()).sum::<usize>();
+        self.emit(key, KeyEventKind::StreamAdded(id.clone()));
+        self.notify(EventClass::Stream, "xadd", key);
+        if !trimmed.is_empty() {
+            self.notify(EventClass::Stream, "xtrim", key);
+        }
+        Ok(Some(id.to_string()))
+    }
+
+    pub fn range_stream(
+        &self,
+        key: &[u8],
+        from_id: StreamRecordId,
+        to_id: StreamRecordId,
+    ) -> anyhow::Result<StreamEntries<'_>> {
+        self.lookup(key).and_then(|v| v.stream()).map_or_else(
+            || bail!("stream not found {}", key.text()),
+            |value| {
+                Ok(value
+                    .range(&from_id, &to_id, true)?
+                    .iter()
+                    .map(|&entry| (entry.id.to_string(), &entry.attributes))
+                    .collect())
+            },
+        )
+    }
+
+    pub fn read_stream(
+        &self,
+        key: &[u8],
+        from_id: StreamRecordId,
+        to_id: StreamRecordId,
+    ) -> anyhow::Result<StreamEntries<'_>> {
+        // a missing stream has no entries to read yet
+        self.live_stream(key)?.map_or_else(
+            || Ok(vec![]),
+            |value| {
+                Ok(value
+                    .range(&from_id, &to_id, false)?
+                    .iter()
+                    .map(|&entry| (entry.id.to_string(), &entry.attributes))
+                    .collect())
+            },
+        )
+    }
+
+    /// removes the entries with the ids from the stream, returns the number of removed entries
+    pub fn delete_from_stream(&mut self, key: &[u8], ids: &[StreamRecordId]) -> anyhow::Result<usize> {
+        let Some(stream) = self.live_stream_mut(key)? else {
+            return Ok(0);
+        };
+        let deleted = stream.delete(ids);
+        self.used_memory -= deleted.iter().map(|record| record.memory_usage()).sum::<usize>();
+        if !deleted.is_empty() {
+            self.notify(EventClass::Stream, "xdel", key);
+        }
+        Ok(deleted.len())
+    }
+
+    /// trims the entries from the start of the stream, returns the number of trimmed entries
+    pub fn trim_stream(&mut self, key: &[u8], trim: &Trim) -> anyhow::Result<usize> {
+        let Some(stream) = self.live_stream_mut(key)? else {
+            return Ok(0);
+        };
+        let trimmed = stream.trim(trim);
+        self.used_memory -= trimmed.iter().map(|record| record.memory_usage()).sum::<usize>();
+        if !trimmed.is_empty() {
+            self.notify(EventClass::Stream, "xtrim", key);
+        }
+        Ok(trimmed.len())
+    }
+
+    /// sets the last id and the metadata of the stream, see [Stream::set_id]
+    pub fn set_stream_id(
+        &mut self,
+        key: &[u8],
+        last_id: StreamRecordId,
+        entries_added: Option<u64>,
+        max_deleted_id: Option<StreamRecordId>,
+    ) -> anyhow::Result<()> {
+        match self.live_stream_mut(key)? {
+            Some(stream) => stream.set_id(last_id, entries_added, max_deleted_id)?,
+            None => bail!("ERR no such key"),
+        }
+        self.notify(EventClass::Stream, "xsetid", key);
+        Ok(())
+    }
+
+    /// stream stored under the key, None if missing or expired
+    fn live_stream(&self, key: &[u8]) -> anyhow::Result<Option<&Stream>> {
+        match self.lookup(key) {
+            None => Ok(None),
+            Some(entry) => Ok(Some(entry.stream().ok_or_else(|| format_err!(WRONGTYPE))?)),
+        }
+    }
+
+    /// last id of the stream, the minimal id for a missing stream
+    pub fn latest_stream(&self, key: &[u8]) -> anyhow::Result<StreamRecordId> {
+        self.live_stream(key)?.map_or(Ok(StreamRecordId::MIN), |stream| stream.last_id())
+    }
+
+    /// stream stored under the key for modification, None if missing or expired
+    fn live_stream_mut(&mut self, key: &[u8]) -> anyhow::Result<Option<&mut Stream>> {
+        self.drop_expired(key);
+        match self.get_mut(key) {
+            None => Ok(None),
+            Some(entry) => Ok(Some(entry.stream_mut().ok_or_else(|| format_err!(WRONGTYPE))?)),
+        }
+    }
+
+    /// runs the update on the consumer groups of the stream, accounting the memory they gain or release.
+    /// None if the stream is missing or expired
+    fn update_stream_groups<T>(&mut self, key: &[u8], update: impl FnOnce(&mut Stream) -> T) -> anyhow::Result<Option<T>> {
+        let Some(stream) = self.live_stream_mut(key)? else {
+            return Ok(None);
+        };
+        let previous_usage = stream.groups_memory_usage();
+        let result = update(stream);
+        let usage = stream.groups_memory_usage();
+        self.used_memory = self.used_memory + usage - previous_usage;
+        Ok(Some(result))
+    }
+
+    /// runs the update on a consumer group of the stream, which returns None when the group does not exist
+    fn update_stream_group<T>(&mut self, key: &[u8], group: &[u8], update: impl FnOnce(&mut Stream) -> Option<T>) -> anyhow::Result<T> {
+        self.update_stream_groups(key, update)?
+            .flatten()
+            .ok_or_else(|| format_err!("NOGROUP No such key '{}' or consumer group '{}'", key.text(), group.text()))
+    }
+
+    /// creates a consumer group delivering the entries after the id, or after the last entry when the id is None.
+    /// a missing stream is created empty with `mkstream`
+    pub fn create_stream_group(&mut self, key: &[u8], group: &[u8], id: Option<StreamRecordId>, mkstream: bool) -> anyhow::Result<()> {
+        self.drop_expired(key);
+        if mkstream && self.get(key).is_none() {
+            self.insert(key.to_vec(), StoreEntry::empty_stream());
+        }
+        let created = self.update_stream_groups(key, |stream| -> anyhow::Result<bool> {
+            let id = match id {
+                Some(id) => id,
+                None => stream.last_id()?,
+            };
+            Ok(stream.create_group(group, id))
+        })?;
+        let Some(created) = created else {
+            bail!("ERR The XGROUP subcommand requires the key to exist. Note that for CREATE you may want to use the MKSTREAM option to create an empty stream automatically.");
+        };
+        if !created? {
+            bail!("BUSYGROUP Consumer Group name already exists");
+        }
+        self.notify(EventClass::Stream, "xgroup-create", key);
+        Ok(())
+    }
+
+    /// removes a consumer group, returns whether it existed
+    pub fn destroy_stream_group(&mut self, key: &[u8], group: &[u8]) -> anyhow::Result<bool> {
+        let Some(destroyed) = self.update_stream_groups(key, |stream| stream.destroy_group(group))? else {
+            bail!("ERR The XGROUP subcommand requires the key to exist.");
+        };
+        if destroyed {
+            self.notify(EventClass::Stream, "xgroup-destroy", key);
+        }
+        Ok(destroyed)
+    }
+
+    /// sets the last delivered id of a consumer group, the last entry of the stream when the id is None
+    pub fn set_stream_group_id(&mut self, key: &[u8], group: &[u8], id: Option<StreamRecordId>) -> anyhow::Result<()> {
+        self.update_stream_group(key, group, |stream| {
+            let id = match id {
+                Some(id) => id,
+                None => stream.last_id().ok()?,
+            };
+            stream.set_group_id(group, id)
+        })?;
+        self.notify(EventClass::Stream, "xgroup-setid", key);
+        Ok(())
+    }
+
+    /// returns whether the consumer was created
+    pub fn create_stream_consumer(&mut self, key: &[u8], group: &[u8], consumer: &[u8]) -> anyhow::Result<bool> {
+        let created = self.update_stream_group(key, group, |stream| stream.create_consumer(group, consumer, now_ms()))?;
+        if created {
+            self.notify(EventClass::Stream, "xgroup-createconsumer", key);
+        }
+        Ok(created)
+    }
+
+    /// removes the consumer, returns the number of its pending entries which were removed with it
+    pub fn delete_stream_consumer(&mut self, key: &[u8], group: &[u8], consumer: &[u8]) -> anyhow::Result<usize> {
+        let pending = self.update_stream_group(key, group, |stream| stream.delete_consumer(group, consumer))?;
+        self.notify(EventClass::Stream, "xgroup-delconsumer", key);
+        Ok(pending)
+    }
+
+    /// delivers entries of the stream to a consumer of the group, see [Stream::read_group]
+    pub fn read_stream_group(
+        &mut self,
+        key: &[u8],
+        group: &[u8],
+        consumer: &[u8],
+        after: Option<&StreamRecordId>,
+        count: usize,
+        no_ack: bool,
+    ) -> anyhow::Result<Delivery> {
+        self.update_stream_group(key, group, |stream| stream.read_group(group, consumer, after, count, no_ack, now_ms()))
+    }
+
+    /// acknowledges pending entries of the group, returns the number of acknowledged entries.
+    /// nothing is acknowledged for a missing stream or group
+    pub fn ack_stream(&mut self, key: &[u8], group: &[u8], ids: &[StreamRecordId]) -> anyhow::Result<usize> {
+        Ok(self.update_stream_groups(key, |stream| stream.ack(group, ids))?.flatten().unwrap_or(0))
+    }
+
+    /// changes the owner of pending entries of the group, see [Stream::claim]
+    pub fn claim_stream(&mut self, key: &[u8], group: &[u8], consumer: &[u8], ids: &[StreamRecordId], claim: &Claim) -> anyhow::Result<Delivery> {
+        self.update_stream_group(key, group, |stream| stream.claim(group, consumer, ids, claim, now_ms()))
+    }
+
+    /// stream stored under the key, an error if it is missing
+    pub fn stream(&self, key: &[u8]) -> anyhow::Result<&Stream> {
+        self.live_stream(key)?.ok_or_else(|| format_err!("ERR no such key"))
+    }
+
+    /// consumer group of the stream with its pending entries
+    pub fn stream_group(&self, key: &[u8], group: &[u8]) -> anyhow::Result<&ConsumerGroup> {
+        self.live_stream(key)?
+            .and_then(|stream| stream.group(group))
+            .ok_or_else(|| format_err!("NOGROUP No such key '{}' or consumer group '{}'", key.text(), group.text()))
+    }
+
+    /// registers for the events of the keys, whether they exist or not.
+    /// watches are removed by [Store::unwatch], or passively once dropped
+    pub(crate) fn watch(&mut self, keys: &[&Vec<u8>], watch: Weak<KeyWatch>) {
+        for &key in keys {
+            self.watchers.entry(key.clone()).or_default().push(watch.clone());
+        }
+    }
+
+    /// registers a client blocked on popping from the lists or sorted sets, which also watches their keys.
+    /// returns the id of the waiter, waiters of a key are served in the order they blocked
+    pub(crate) fn wait_to_pop(&mut self, keys: &[Vec<u8>], watch: Weak<KeyWatch>) -> u64 {
+        self.pop_waiter_seq += 1;
+        for key in keys {
+            self.pop_waiters.entry(key.clone()).or_default().push_back(self.pop_waiter_seq);
+        }
+        self.watch(&keys.iter().collect::<Vec<&Vec<u8>>>(), watch);
+        self.pop_waiter_seq
+    }
+
+    /// whether the waiter is the first one blocked on the key
+    pub(crate) fn is_next_pop_waiter(&self, key: &[u8], waiter_id: u64) -> bool {
+        self.pop_waiters.get(key).and_then(|waiters| waiters.front()) == Some(&waiter_id)
+    }
+
+    /// unregisters a waiter which was served or timed out. the next waiter of a key which still has elements is woken,
+    /// as the push which woke this waiter may not be consumed yet
+    pub(crate) fn stop_waiting_to_pop(&mut self, keys: &[Vec<u8>], waiter_id: u64) {
+        for key in keys {
+            let Some(waiters) = self.pop_waiters.get_mut(key) else {
+                continue;
+            };
+            waiters.retain(|&id| id != waiter_id);
+            if waiters.is_empty() {
+                self.pop_waiters.remove(key);
+                continue;
+            }
+            // lists and sorted sets are removed with their last element
+            match self.get_type(key) {
+                "list" => self.emit(key, KeyEventKind::ListPushed),
+                "zset" => self.emit(key, KeyEventKind::ZSetAdded),
+                _ => {}
+            }
+        }
+    }
+
+    /// removes a watch registered for the keys, with the dropped watches of other clients
+    fn unwatch(&mut self, keys: &[Vec<u8>], watch: &Weak<KeyWatch>) {
+        for key in keys {
+            let Some(watchers) = self.watchers.get_mut(key) else {
+                continue;
+            };
+            watchers.retain(|watcher| !watcher.ptr_eq(watch) && watcher.strong_count() > 0);
+            if watchers.is_empty() {
+                self.watchers.remove(key);
+            }
+        }
+    }
+
+    /// queues an event for the watchers of the key. commands changing several keys emit an event for each of them,
+    /// e.g. LMOVE emits [KeyEventKind::ListPopped] for the source and [KeyEventKind::ListPushed] for the destination.
+    /// events are numbered in the order of the changes, which are all made under the store write lock,
+    /// and are delivered in that order once the lock is released
+    fn emit(&mut self, key: &[u8], kind: KeyEventKind) {
+        self.event_seq += 1;
+        let event = KeyEvent { seq: self.event_seq, key: key.to_vec(), kind };
+        if let Some(watchers) = self.watchers.get_mut(key) {
+            watchers.retain(|watch| match watch.upgrade() {
+                Some(watch) => {
+                    self.pending_events.push((watch, event.clone()));
+                    true
+                }
+                None => false,
+            });
+            if watchers.is_empty() {
+                self.watchers.remove(key);
+            }
+        }
+    }
+
+    /// records a keyspace event of the key if its class is enabled. every change of the store records its events,
+    /// which the server publishes once the command is done and the store is no longer locked
+    fn notify(&mut self, class: EventClass, event: &'static str, key: &[u8]) {
+        if self.notify_flags.publishes(class) {
+            self.notifications.push((event, key.to_vec()));
+        }
+    }
+
+    /// selects the classes of the keyspace events to record from now on
+    pub(crate) fn set_notify_flags(&mut self, flags: NotifyFlags) {
+        self.notify_flags = flags;
+    }
+
+    pub(crate) fn has_notifications(&self) -> bool {
+        !self.notifications.is_empty()
+    }
+
+    /// takes the keyspace events recorded so far, with the flags telling the channels to publish them to
+    pub(crate) fn take_notifications(&mut self) -> (NotifyFlags, Vec<Notification>) {
+        (self.notify_flags, std::mem::take(&mut self.notifications))
+    }
+
+    /// point in time copy of all valid entries, used for saving in the background
+    pub fn snapshot(&self) -> Store {
+        let mut snapshot = Store::new();
+        snapshot.function_libraries = self.function_libraries.clone();
+        for (key, entry) in self.entries().filter(|(_, entry)| !entry.is_expired()) {
+            snapshot.insert(key.clone(), entry.clone_with_ttl(entry.valid_until));
+        }
+        snapshot
+    }
+
+    /**
+    save the store in rdb format, with the values of all types.
+    the file ends with its CRC64 checksum, or with zero without `checksum_enabled`
+     */
+    pub fn save_rdb(&self, writer: &mut impl Write, checksum_enabled: bool) -> anyhow::Result<()> {
+        let mut checksum = rdb::ChecksumWriter::new(writer);
+        let writer = &mut checksum;
+        writer.write_all(b"REDIS0011")?;
+        let ctime = SystemTime::now().duration_since(SystemTime::UNIX_EPOCH)?.as_secs().to_string();
+        for (name, value) in [("redis-ver", REDIS_VERSION), ("redis-bits", "64"), ("ctime", &ctime)] {
+            rdb::write_byte(writer, 0xFA)?;
+            rdb::write_string(writer, name)?;
+            rdb::write_string(writer, value)?;
+        }
+        for library in &self.function_libraries {
+            rdb::write_byte(writer, 0xF5)?;
+            rdb::write_bytes(writer, library)?;
+        }
+
+        rdb::write_byte(writer, 0xFE)?;
+        rdb::write_length(writer, 0)?;
+        // the resize hint counts the keys serialized, without the expired keys not yet evicted
+        let entries = self.iter().collect::<Vec<_>>();
+        rdb::write_byte(writer, 0xFB)?;
+        rdb::write_length(writer, entries.len())?;
+        rdb::write_length(writer, entries.iter().filter(|(_, _, valid_until)| valid_until.is_some()).count())?;
+
+        for (key, value, valid_until) in entries {
+            if let Some(valid_until) = valid_until {
+                rdb::write_byte(writer, 0xFC)?;
+                rdb::write_u64(writer, valid_until.duration_since(SystemTime::UNIX_EPOCH)?.as_millis() as u64)?;
+            }
+            write_rdb_entry(writer, key, value)?;
+        }
+
+        rdb::write_byte(writer, 0xFF)?;
+        checksum.write_checksum(checksum_enabled)
+    }
+
+    /// writes the commands recreating the store, as an append only file rewrite.
+    /// collections are written in batches of elements, and keys with an expiration are followed by PEXPIREAT
+    pub fn write_aof(&self, writer: &mut impl Write) -> anyhow::Result<()> {
+        for (key, value, valid_until) in self.iter() {
+            for command in rewrite_value(key, value) {
+                RESP::from(&command).write_to(writer)?;
+            }
+            if let Some(valid_until) = valid_until {
+                let valid_until_ms = valid_until.duration_since(SystemTime::UNIX_EPOCH)?.as_millis();
+                let command = CommandRequest::new(Command::PEXPIREAT, vec![key.to_vec(), valid_until_ms.to_string().into_bytes()]);
+                RESP::from(&command).write_to(writer)?;
+            }
+        }
+        writer.flush()?;
+        Ok(())
+    }
+
+    /**
+    load rdb file into the store.
+    a failure reports the byte offset and the opcode of the entry which could not be parsed.
+    with `skip_unknown` an unknown opcode ends the load keeping the keys loaded before it,
+    for files written by newer versions.
+    with `verify_checksum` a file whose CRC64 checksum differs from the one it ends with is refused,
+    unless it was saved without a checksum
+     */
+    pub fn load_rdb(&mut self, reader: impl Read, skip_unknown: bool, verify_checksum: bool) -> anyhow::Result<()> {
+        // Loading of the RDB file is based on the https://rdb.fnordig.de/file_format.html
+        let mut reader = rdb::ChecksumReader::new(reader);
+        let mut header = [0x00; 9];
+        reader.read_exact(&mut header).context("rdb header is truncated")?;
+        let header = String::from_utf8_lossy(&header);
+        if !header.starts_with("REDIS") {
+            bail!("invalid header: {}", header);
+        }
+
+        let version = header["REDIS".len()..header.len()].parse::<u32>()
+            .map_err(|_| format_err!("invalid version: {}", header))?;
+        debug!("rdb version: {}", version);
+        let mut valid_until_ms = None;
+        // the libraries of the file replace the ones loaded before, like the keys replace the flushed ones
+        self.function_libraries.clear();
+
+        loop {
+            let offset = reader.offset();
+            let Ok(op) = rdb::read_byte(&mut reader) else {
+                // files without the end of file opcode end here
+                return Ok(());
+            };
+            match self.load_rdb_entry(op, &mut reader, &mut valid_until_ms) {
+                Ok(true) => return verify_rdb_checksum(&mut reader, version, verify_checksum),
+                Ok(false) => {}
+                Err(err) if skip_unknown && err.is::<rdb::UnknownOpcode>() => {
+                    warning!("rdb loading stopped at byte offset {} on unknown opcode 0x{:02X}, {} keys loaded", offset, op, self.len);
+                    return Ok(());
+                }
+                Err(err) => bail!("rdb parsing failed at byte offset {} in opcode 0x{:02X}: {}", offset, op, err),
+            }
+        }
+    }
+
+    /// loads the entry starting with the opcode, returns true at the end of the file
+    fn load_rdb_entry(&mut self, op: u8, reader: &mut impl Read, valid_until_ms: &mut Option<u64>) -> anyhow::Result<bool> {
+        match op {
+            0xFA => {
+                // AUX fields
+                let key = rdb::read_string(reader)?;
+                let value = rdb::read_string(reader)?;
+                debug!("aux: {} {}", key, value);
+                // TODO
+            }
+            0xF5 => {
+                // function library, scripting is not supported so the code is only kept to be saved again
+                let library = rdb::read_bytes(reader)?;
+                notice!("function library of {} bytes kept, functions can't be called", library.len());
+                self.function_libraries.push(library);
+            }
+            0xF8 => {
+                // idle time of the following key for LRU eviction, keys are loaded as just accessed
+                rdb::read_int(reader)?;
+            }
+            0xF9 => {
+                // access frequency of the following key for LFU eviction, which is not supported
+                rdb::read_byte(reader)?;
+            }
+            0xFE => {
+                // Database selector
+                let db_number = rdb::read_len(reader)?;
+                debug!("database selector {}", db_number);
+                // TODO
+            }
+            0xFB => {
+                // resize db field
+                let hash_size = rdb::read_int(reader)?;
+                let expire_size = rdb::read_int(reader)?;
+                debug!("sizes {} {}", hash_size, expire_size);
+                // TODO
+            }
+            0xFD => {
+                // The following expire value is specified in seconds. The following 4 bytes represent the Unix timestamp as an unsigned integer.
+                *valid_until_ms = Some((rdb::read_u32(reader)? as u64) * 1000);
+            }
+            0xFC => {
+                // The following expire value is specified in milliseconds. The following 8 bytes represent the Unix timestamp as an unsigned long.
+                *valid_until_ms = Some(rdb::read_u64(reader)?);
+            }
+            0xFF => {
+                // rdb load finished, the checksum follows
+                return Ok(true);
+            }
+            // types of the values, with the compact encodings of collections
+            0..=5 | 9..=21 => {
+                let key = rdb::read_bytes(reader)?;
+                let value = read_rdb_value(op, reader)?;
+                let valid_until = valid_until_ms.take().map(|epoch_ms| {
+                    SystemTime::UNIX_EPOCH + Duration::from_millis(epoch_ms)
+                });
+                self.insert(key, StoreEntry::new(value, valid_until));
+            }
+
+            _ => {
+                return Err(rdb::UnknownOpcode(op).into());
+            }
+        }
+        Ok(false)
+    }
+}
+
+/// writes the type of the value, the key and the value. collections are written with their plain encodings,
+/// which every version loads, streams as listpacks which is the only encoding of streams
+fn write_rdb_entry(writer: &mut impl Write, key: &[u8], value: ValueRef) -> anyhow::Result<()> {
+    let value_type = match value {
+        ValueRef::String(_) => 0,
+        ValueRef::List(_) => 1,
+        ValueRef::Set(_) => 2,
+        ValueRef::Hash(_) => 4,
+        ValueRef::ZSet(_) => 5,
+        ValueRef::Stream(_) => STREAM_LISTPACKS_3,
+    };
+    rdb::write_byte(writer, value_type)?;
+    rdb::write_bytes(writer, key)?;
+    write_rdb_value(writer, value)
+}
+
+/// writes the value in the encoding of its type, see [write_rdb_entry]
+fn write_rdb_value(writer: &mut impl Write, value: ValueRef) -> anyhow::Result<()> {
+    match value {
+        ValueRef::String(value) => rdb::write_bytes(writer, value)?,
+        ValueRef::List(list) => {
+            rdb::write_length(writer, list.len())?;
+            for element in list {
+                rdb::write_bytes(writer, element)?;
+            }
+        }
+        ValueRef::Set(set) => {
+            rdb::write_length(writer, set.len())?;
+            for member in set {
+                rdb::write_bytes(writer, member)?;
+            }
+        }
+        ValueRef::Hash(hash) => {
+            rdb::write_length(writer, hash.len())?;
+            for (field, value) in hash {
+                rdb::write_bytes(writer, field)?;
+                rdb::write_bytes(writer, value)?;
+            }
+        }
+        ValueRef::ZSet(zset) => {
+            rdb::write_length(writer, zset.len())?;
+            for (member, score) in zset.range(&ZRange::Rank(0, -1), false, None) {
+                rdb::write_bytes(writer, &member)?;
+                rdb::write_f64(writer, score)?;
+            }
+        }
+        ValueRef::Stream(stream) => write_rdb_stream(writer, stream)?,
+    }
+    Ok(())
+}
+
+/// entries of a stream in a listpack node, as redis limits them by default
+const STREAM_NODE_MAX_ENTRIES: usize = 100;
+
+/// stream as listpacks of entries, followed by its metadata and its consumer groups
+fn write_rdb_stream(writer: &mut impl Write, stream: &Stream) -> anyhow::Result<()> {
+    let nodes = stream.records().chunks(STREAM_NODE_MAX_ENTRIES).collect::<Vec<&[StreamRecord]>>();
+    rdb::write_length(writer, nodes.len())?;
+    for node in nodes {
+        let master_id = &node[0].id;
+        rdb::write_bytes(writer, &raw_stream_id_bytes(master_id))?;
+        let elements = stream_node_elements(master_id, node);
+        rdb::write_bytes(writer, &rdb::listpack(&elements.iter().map(Vec::as_slice).collect::<Vec<&[u8]>>()))?;
+    }
+    let write_id = |writer: &mut _, id: &StreamRecordId| -> anyhow::Result<()> {
+        rdb::write_uint(writer, id.time_id())?;
+        rdb::write_uint(writer, id.seq_id())
+    };
+    rdb::write_length(writer, stream.len())?;
+    write_id(writer, &stream.last_id()?)?;
+    write_id(writer, stream.first().map_or(&StreamRecordId::MIN, |first| &first.id))?;
+    write_id(writer, stream.max_deleted_id())?;
+    rdb::write_uint(writer, stream.entries_added())?;
+
+    rdb::write_length(writer, stream.groups().count())?;
+    for (name, group) in stream.groups() {
+        rdb::write_bytes(writer, name)?;
+        write_id(writer, &group.last_delivered)?;
+        // -1 when the entries read can't be told
+        rdb::write_uint(writer, stream.entries_read(&group.last_delivered).unwrap_or(u64::MAX))?;
+        rdb::write_length(writer, group.pending.len())?;
+        for (id, entry) in &group.pending {
+            writer.write_all(&raw_stream_id_bytes(id))?;
+            rdb::write_u64(writer, entry.delivered_ms)?;
+            rdb::write_uint(writer, entry.delivery_count)?;
+        }
+        rdb::write_length(writer, group.consumers.len())?;
+        for (consumer_name, consumer) in &group.consumers {
+            rdb::write_bytes(writer, consumer_name)?;
+            rdb::write_u64(writer, consumer.seen_ms)?;
+            // a consumer which was never active has the time -1
+            rdb::write_u64(writer, consumer.active_ms.unwrap_or(u64::MAX))?;
+            let pending = group.pending.iter()
+                .filter(|(_, entry)| entry.consumer == *consumer_name)
+                .map(|(id, _)| id)
+                .collect::<Vec<&StreamRecordId>>();
+            rdb::write_length(writer, pending.len())?;
+            for id in pending {
+                writer.write_all(&raw_stream_id_bytes(id))?;
+            }
+        }
+    }
+    Ok(())
+}
+
+/// elements of the listpack node of the entries, the first entry being the master entry whose fields are shared
+/// by the entries with the same fields
+fn stream_node_elements(master_id: &StreamRecordId, node: &[StreamRecord]) -> Vec<Vec<u8>> {
+    let master_fields = node[0].attributes.iter().map(|(field, _)| field).collect::<Vec<&Vec<u8>>>();
+    let mut elements = vec![node.len().to_string().into_bytes(), b"0".to_vec(), master_fields.len().to_string().into_bytes()];
+    elements.extend(master_fields.iter().map(|&field| field.clone()));
+    // end of the master entry
+    elements.push(b"0".to_vec());
+    for record in node {
+        let same_fields = record.attributes.len() == master_fields.len()
+            && record.attributes.iter().zip(&master_fields).all(|((field, _), master_field)| field == *master_field);
+        let flags = if same_fields { STREAM_ITEM_SAME_FIELDS } else { 0 };
+        elements.push(flags.to_string().into_bytes());
+        elements.push(record.id.time_id().wrapping_sub(master_id.time_id()).to_string().into_bytes());
+        elements.push(record.id.seq_id().wrapping_sub(master_id.seq_id()).to_string().into_bytes());
+        let fields = record.attributes.len();
+        if same_fields {
+            elements.extend(record.attributes.iter().map(|(_, value)| value.clone()));
+        } else {
+            elements.push(fields.to_string().into_bytes());
+            elements.extend(record.attributes.iter().flat_map(|(field, value)| [field.clone(), value.clone()]));
+        }
+        // number of elements of the entry, for walking the node backwards
+        let count = if same_fields { fields + 3 } else { 2 * fields + 4 };
+        elements.push(count.to_string().into_bytes());
+    }
+    elements
+}
+
+/// compares the checksum of the bytes read with the one ending the file, which files before version 5 don't have
+fn verify_rdb_checksum<R: Read>(reader: &mut rdb::ChecksumReader<R>, version: u32, verify_checksum: bool) -> anyhow::Result<()> {
+    if version < 5 {
+        return Ok(());
+    }
+    let computed = reader.checksum();
+    let expected = rdb::read_u64(reader).context("rdb checksum is truncated")?;
+    if !verify_checksum {
+        return Ok(());
+    }
+    match expected {
+        // files saved with rdbchecksum no end with a zero checksum
+        0 => warning!("RDB file was saved with checksum disabled: no check performed."),
+        _ if expected != computed => bail!("wrong RDB checksum expected: {:016x} got: {:016x}", expected, computed),
+        _ => {}
+    }
+    Ok(())
+}
+
+/// value of the type read from an RDB file, the elements of compact encodings are decoded into the collection
+fn read_rdb_value(value_type: u8, reader: &mut impl Read) -> anyhow::Result<Value> {
+    Ok(match value_type {
+        0 => Value::String(rdb::read_bytes(reader)?),
+        1 => Value::List(read_rdb_elements(reader)?.into()),
+        2 => Value::Set(Arc::new(read_rdb_elements(reader)?.into_iter().collect())),
+        3 | 5 => {
+            let len = rdb::read_len(reader)?;
+            let mut zset = SortedSet::default();
+            for _ in 0..len {
+                let member = rdb::read_bytes(reader)?;
+                // the scores of the first encoding are text, later ones binary
+                let score = if value_type == 3 { rdb::read_text_f64(reader)? } else { rdb::read_f64(reader)? };
+                zset.insert(&member, score);
+            }
+            Value::ZSet(zset)
+        }
+        4 => {
+            let len = rdb::read_len(reader)?;
+            let fields = (0..len)
+                .map(|_| Ok((rdb::read_bytes(reader)?, rdb::read_bytes(reader)?)))
+                .collect::<anyhow::Result<HashMap<Vec<u8>, Vec<u8>>>>()?;
+            Value::Hash(fields)
+        }
+        9 => {
+            Value::Hash(rdb::zipmap_entries(&rdb::read_bytes(reader)?)?.into_iter().collect())
+        }
+        10 => Value::List(rdb::ziplist_entries(&rdb::read_bytes(reader)?)?.into()),
+        11 => {
+            let members = rdb::intset_members(&rdb::read_bytes(reader)?)?;
+            Value::Set(Arc::new(members.iter().map(|member| member.to_string().into_bytes()).collect()))
+        }
+        12 => zset_of_pairs(rdb::ziplist_entries(&rdb::read_bytes(reader)?)?)?,
+        13 => Value::Hash(pairs(rdb::ziplist_entries(&rdb::read_bytes(reader)?)?)?.into_iter().collect()),
+        14 => {
+            // a list of ziplists
+            let len = rdb::read_len(reader)?;
+            let mut list = VecDeque::new();
+            for _ in 0..len {
+                list.extend(rdb::ziplist_entries(&rdb::read_bytes(reader)?)?);
+            }
+            Value::List(list)
+        }
+        16 => Value::Hash(pairs(rdb::listpack_entries(&rdb::read_bytes(reader)?)?)?.into_iter().collect()),
+        17 => zset_of_pairs(rdb::listpack_entries(&rdb::read_bytes(reader)?)?)?,
+        18 => {
+            // a list of nodes, each a listpack or a single big element
+            let len = rdb::read_len(reader)?;
+            let mut list = VecDeque::new();
+            for _ in 0..len {
+                let container = rdb::read_len(reader)?;
+                let node = rdb::read_bytes(reader)?;
+                match container {
+                    QUICKLIST_PLAIN => list.push_back(node),
+                    QUICKLIST_PACKED => list.extend(rdb::listpack_entries(&node)?),
+                    _ => bail!("invalid quicklist container {}", container),
+                }
+            }
+            Value::List(list)
+        }
+        20 => Value::Set(Arc::new(rdb::listpack_entries(&rdb::read_bytes(reader)?)?.into_iter().collect())),
+        STREAM_LISTPACKS | STREAM_LISTPACKS_2 | STREAM_LISTPACKS_3 => Value::Stream(read_rdb_stream(value_type, reader)?),
+        _ => return Err(rdb::UnknownOpcode(value_type).into()),
+    })
+}
+
+/// versions of the stream type: the second adds the metadata of deleted entries and the entries read by groups,
+/// the third the last time consumers were active
+const STREAM_LISTPACKS: u8 = 15;
+const STREAM_LISTPACKS_2: u8 = 19;
+const STREAM_LISTPACKS_3: u8 = 21;
+
+/// flags of the entries of the listpacks of a stream
+const STREAM_ITEM_DELETED: i64 = 1;
+const STREAM_ITEM_SAME_FIELDS: i64 = 2;
+
+/// stream stored as listpacks of entries, followed by its metadata and its consumer groups
+fn read_rdb_stream(value_type: u8, reader: &mut impl Read) -> anyhow::Result<Stream> {
+    let nodes = rdb::read_len(reader)?;
+    let mut records = vec![];
+    for _ in 0..nodes {
+        // the entries of a node are stored relative to the id of its master entry
+        let master_id = raw_stream_id(&rdb::read_bytes(reader)?)?;
+        let node = rdb::listpack_entries(&rdb::read_bytes(reader)?)?;
+        records.extend(stream_node_records(&master_id, node)?);
+    }
+    let len = rdb::read_len(reader)?;
+    if len != records.len() {
+        bail!("stream of {} entries has {} entries in its listpacks", len, records.len());
+    }
+    let last_id = StreamRecordId::new(rdb::read_uint(reader)?, rdb::read_uint(reader)?);
+    let (max_deleted_id, entries_added) = if value_type >= STREAM_LISTPACKS_2 {
+        // the first id is known from the entries
+        rdb::read_uint(reader)?;
+        rdb::read_uint(reader)?;
+        let max_deleted_id = StreamRecordId::new(rdb::read_uint(reader)?, rdb::read_uint(reader)?);
+        (max_deleted_id, rdb::read_uint(reader)?)
+    } else {
+        (StreamRecordId::MIN, len as u64)
+    };
+
+    let mut groups = BTreeMap::new();
+    for _ in 0..rdb::read_len(reader)? {
+        let name = rdb::read_bytes(reader)?;
+        let mut group = ConsumerGroup::new(StreamRecordId::new(rdb::read_uint(reader)?, rdb::read_uint(reader)?));
+        if value_type >= STREAM_LISTPACKS_2 {
+            // the entries read are counted from the stream
+            rdb::read_uint(reader)?;
+        }
+        // the pending entries of the group, which the consumers claim after
+        let mut unclaimed = BTreeMap::new();
+        for _ in 0..rdb::read_len(reader)? {
+            let id = raw_stream_id(&rdb::read_raw(reader, 16)?)?;
+            let delivered_ms = rdb::read_u64(reader)?;
+            let delivery_count = rdb::read_uint(reader)?;
+            unclaimed.insert(id, (delivered_ms, delivery_count));
+        }
+        for _ in 0..rdb::read_len(reader)? {
+            let consumer = rdb::read_bytes(reader)?;
+            let seen_ms = rdb::read_u64(reader)?;
+            let active_ms = if value_type >= STREAM_LISTPACKS_3 { rdb::read_u64(reader)? } else { seen_ms };
+            for _ in 0..rdb::read_len(reader)? {
+                let id = raw_stream_id(&rdb::read_raw(reader, 16)?)?;
+                let (delivered_ms, delivery_count) = unclaimed.remove(&id)
+                    .ok_or_else(|| format_err!("pending entry {} of consumer {} is not pending in group {}", id, consumer.text(), name.text()))?;
+                group.pending.insert(id, PendingEntry { consumer: consumer.clone(), delivered_ms, delivery_count });
+            }
+            // a consumer which was never active has the time -1
+            let active_ms = (active_ms != u64::MAX).then_some(active_ms);
+            group.consumers.insert(consumer, Consumer { seen_ms, active_ms });
+        }
+        if !unclaimed.is_empty() {
+            bail!("{} pending entries of group {} have no consumer", unclaimed.len(), name.text());
+        }
+        groups.insert(name, group);
+    }
+    Ok(Stream::restore(records, last_id, entries_added, max_deleted_id, groups))
+}
+
+/// id of a stream entry stored as 16 bytes, the milliseconds and the sequence number in big endian
+fn raw_stream_id_bytes(id: &StreamRecordId) -> [u8; 16] {
+    let mut bytes = [0; 16];
+    bytes[..8].copy_from_slice(&id.time_id().to_be_bytes());
+    bytes[8..].copy_from_slice(&id.seq_id().to_be_bytes());
+    bytes
+}
+
+fn raw_stream_id(bytes: &[u8]) -> anyhow::Result<StreamRecordId> {
+    if bytes.len() != 16 {
+        bail!("stream id of {} bytes", bytes.len());
+    }
+    Ok(StreamRecordId::new(u64::from_be_bytes(bytes[..8].try_into()?), u64::from_be_bytes(bytes[8..].try_into()?)))
+}
+
+/// entries of a listpack node of a stream, which starts with the master entry: the number of entries,
+/// the number of deleted entries and the fields shared by entries with the same fields
+fn stream_node_records(master_id: &StreamRecordId, node: Vec<Vec<u8>>) -> anyhow::Result<Vec<StreamRecord>> {
+    let mut elements = node.into_iter();
+    let mut next = || elements.next().ok_or_else(|| format_err!("truncated stream node"));
+    let int = |element: Vec<u8>| element.parse::<i64>().map_err(|_| format_err!("invalid stream node integer {}", element.text()));
+    let count = int(next()?)? as usize;
+    let deleted = int(next()?)? as usize;
+    let master_fields = (0..int(next()?)?).map(|_| next()).collect::<anyhow::Result<Vec<Vec<u8>>>>()?;
+    // end of the master entry
+    next()?;
+
+    // the counts are not trusted for the allocation, a corrupt count fails as a truncated node
+    let mut records = vec![];
+    for _ in 0..count.saturating_add(deleted) {
+        let flags = int(next()?)?;
+        let id = StreamRecordId::new(
+            master_id.time_id().wrapping_add(int(next()?)? as u64),
+            master_id.seq_id().wrapping_add(int(next()?)? as u64),
+        );
+        let attributes = if flags & STREAM_ITEM_SAME_FIELDS != 0 {
+            master_fields.iter().map(|field| Ok((field.clone(), next()?))).collect::<anyhow::Result<Vec<(Vec<u8>, Vec<u8>)>>>()?
+        } else {
+            (0..int(next()?)?).map(|_| Ok((next()?, next()?))).collect::<anyhow::Result<Vec<(Vec<u8>, Vec<u8>)>>>()?
+        };
+        // number of elements of the entry, for walking the node backwards
+        next()?;
+        if flags & STREAM_ITEM_DELETED == 0 {
+            records.push(StreamRecord { id, attributes });
+        }
+    }
+    Ok(records)
+}
+
+/// containers of the nodes of a quicklist: a single element or a listpack of elements
+const QUICKLIST_PLAIN: usize = 1;
+const QUICKLIST_PACKED: usize = 2;
+
+/// elements of a collection in an RDB file, preceded by their number
+fn read_rdb_elements(reader: &mut impl Read) -> anyhow::Result<Vec<Vec<u8>>> {
+    let len = rdb::read_len(reader)?;
+    (0..len).map(|_| rdb::read_bytes(reader)).collect()
+}
+
+/// elements of a compact encoding alternating between fields or members and their values or scores
+fn pairs(elements: Vec<Vec<u8>>) -> anyhow::Result<Vec<(Vec<u8>, Vec<u8>)>> {
+    if !elements.len().is_multiple_of(2) {
+        bail!("odd number of elements of a map");
+    }
+    let mut elements = elements.into_iter();
+    let mut pairs = vec![];
+    while let (Some(first), Some(second)) = (elements.next(), elements.next()) {
+        pairs.push((first, second));
+    }
+    Ok(pairs)
+}
+
+fn zset_of_pairs(elements: Vec<Vec<u8>>) -> anyhow::Result<Value> {
+    let mut zset = SortedSet::default();
+    for (member, score) in pairs(elements)? {
+        let score = parse_score(&score).ok_or_else(|| format_err!("invalid score {} of member {}", score.text(), member.text()))?;
+        zset.insert(&member, score);
+    }
+    Ok(Value::ZSet(zset))
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::Condvar;
+
+    use super::*;
+
+    fn new_watch() -> Arc<KeyWatch> {
+        Arc::new((Mutex::new(VecDeque::new()), Condvar::new()))
+    }
+
+    #[test]
+    fn events_are_delivered_once_the_write_lock_is_released() {
+        let store = StoreLock::new(Store::new());
+        let watch = new_watch();
+        let keys = [b"list".to_vec()];
+        store.write().unwrap().watch(&keys.iter().collect::<Vec<&Vec<u8>>>(), Arc::downgrade(&watch));
+
+        let mut locked = store.write().unwrap();
+        locked.push_to_list(b"list", ListEnd::Right, &[b"a".to_vec()]).unwrap();
+        locked.push_to_list(b"list", ListEnd::Right, &[b"b".to_vec()]).unwrap();
+        assert!(watch.0.lock().unwrap().is_empty());
+        drop(locked);
+
+        let events = watch.0.lock().unwrap().drain(..).collect::<Vec<KeyEvent>>();
+        assert_eq!(events.iter().map(|event| &event.kind).collect::<Vec<_>>(), [&KeyEventKind::ListPushed, &KeyEventKind::ListPushed]);
+        assert!(events[0].seq < events[1].seq);
+    }
+
+    #[test]
+    fn watch_is_removed_when_its_client_stops_waiting() {
+        let store = StoreLock::new(Store::new());
+        let keys = [b"a".to_vec(), b"b".to_vec()];
+        let watch = new_watch();
+        let other = new_watch();
+        {
+            let _registration = WatchRegistration::new(&store, &keys, &watch);
+            store.write().unwrap().wait_to_pop(&keys, Arc::downgrade(&watch));
+            store.write().unwrap().watch(&[&keys[0]], Arc::downgrade(&other));
+            assert_eq!(store.read().unwrap().watchers.len(), 2);
+        }
+        // the watch of the other client is kept while it waits
+        assert_eq!(store.read().unwrap().watchers.keys().collect::<Vec<&Vec<u8>>>(), [&keys[0]]);
+        assert_eq!(store.read().unwrap().watchers[&keys[0]].len(), 1);
+
+        let registration = WatchRegistration::new(&store, &keys[..1], &other);
+        drop(other);
+        drop(registration);
+        assert!(store.read().unwrap().watchers.is_empty());
+    }
+
+    #[test]
+    fn keys_which_are_not_text_are_distinct() {
+        let mut store = Store::new();
+        store.insert_value(b"\xfe", b"a", None);
+        store.insert_value(b"\xff", b"b", None);
+        assert_eq!(store.get_value(b"\xfe"), Some(b"a".to_vec()));
+        assert_eq!(store.get_value(b"\xff"), Some(b"b".to_vec()));
+    }
+
+    #[test]
+    fn members_which_are_not_text_are_distinct() {
+        let mut store = Store::new();
+        assert_eq!(store.add_to_set(b"set", &[b"\xfe".to_vec(), b"\xff".to_vec()]).unwrap(), 2);
+        let members = store.set_snapshot(b"set").unwrap().unwrap();
+        assert!(members.contains(&b"\xfe".to_vec()) && members.contains(&b"\xff".to_vec()));
+    }
+
+    /// store with a key expiring in an hour and a key without expiration
+    fn store_with_ttl() -> (Store, SystemTime) {
+        let mut store = Store::new();
+        let valid_until = SystemTime::now() + Duration::from_secs(3600);
+        store.insert_value(b"volatile", b"1", None);
+        store.set_expiration(b"volatile", Some(valid_until));
+        store.insert_value(b"persistent", b"2", None);
+        (store, valid_until)
+    }
+
+    #[test]
+    fn rename_keeps_the_expiration_of_the_source() {
+        let (mut store, valid_until) = store_with_ttl();
+        assert!(store.rename(b"volatile", b"renamed"));
+        assert_eq!(store.expiration(b"renamed"), Some(Some(valid_until)));
+        assert_eq!(store.expiration(b"volatile"), None);
+
+        // the destination takes the source without expiration
+        let (mut store, _) = store_with_ttl();
+        assert!(store.rename(b"persistent", b"volatile"));
+        assert_eq!(store.expiration(b"volatile"), Some(None));
+
+        let (mut store, valid_until) = store_with_ttl();
+        assert!(store.rename(b"volatile", b"volatile"));
+        assert_eq!(store.expiration(b"volatile"), Some(Some(valid_until)));
+    }
+
+    #[test]
+    fn copy_keeps_the_expiration_of_the_source() {
+        let (mut store, valid_until) = store_with_ttl();
+        assert!(store.copy(b"volatile", b"copied", false).unwrap());
+        assert_eq!(store.expiration(b"copied"), Some(Some(valid_until)));
+        assert_eq!(store.expiration(b"volatile"), Some(Some(valid_until)));
+
+        // a replaced destination takes the source without expiration
+        let (mut store, _) = store_with_ttl();
+        assert!(store.copy(b"persistent", b"volatile", true).unwrap());
+        assert_eq!(store.expiration(b"volatile"), Some(None));
+
+        // a kept destination keeps its expiration
+        let (mut store, valid_until) = store_with_ttl();
+        assert!(!store.copy(b"persistent", b"volatile", false).unwrap());
+        assert_eq!(store.expiration(b"volatile"), Some(Some(valid_until)));
+        assert_eq!(store.get_value(b"volatile"), Some(b"1".to_vec()));
+    }
+
+    #[test]
+    fn copy_to_the_same_key_is_refused() {
+        let (mut store, valid_until) = store_with_ttl();
+        let err = store.copy(b"volatile", b"volatile", true).unwrap_err();
+        assert_eq!(err.to_string(), "ERR source and destination objects are the same");
+        assert_eq!(store.expiration(b"volatile"), Some(Some(valid_until)));
+    }
+
+    #[test]
+    fn expired_keys_are_neither_renamed_nor_copied() {
+        let (mut store, _) = store_with_ttl();
+        store.set_expiration(b"volatile", Some(SystemTime::now() - Duration::from_secs(1)));
+        assert!(!store.copy(b"volatile", b"copied", false).unwrap());
+        assert!(!store.rename(b"volatile", b"renamed"));
+        assert_eq!(store.expiration(b"copied"), None);
+        assert_eq!(store.expiration(b"renamed"), None);
+    }
+
+    #[test]
+    fn iter_yields_every_live_key_with_its_value_and_expiration() {
+        let mut store = Store::new();
+        let valid_until = SystemTime::now() + Duration::from_secs(3600);
+        store.insert_value(b"string", b"\xfe", None);
+        store.set_expiration(b"string", Some(valid_until));
+        store.push_to_list(b"list", ListEnd::Right, &[b"a".to_vec(), b"b".to_vec()]).unwrap();
+        store.add_to_set(b"set", &[b"a".to_vec()]).unwrap();
+        store.set_hash_fields(b"hash", &[(b"field".to_vec(), b"value".to_vec())]).unwrap();
+        store.add_to_zset(b"zset", &[(1.5, b"member".to_vec())], &AddOptions::default()).unwrap();
+
+        let mut keys = vec![];
+        for (key, value, expiration) in store.iter() {
+            match (key, value) {
+                (b"string", ValueRef::String(value)) => {
+                    assert_eq!(value, b"\xfe");
+                    assert_eq!(expiration, Some(valid_until));
+                }
+                (b"list", ValueRef::List(list)) => assert_eq!(list.iter().collect::<Vec<_>>(), [b"a", b"b"]),
+                (b"set", ValueRef::Set(set)) => assert!(set.contains(b"a".as_slice())),
+                (b"hash", ValueRef::Hash(hash)) => assert_eq!(hash.get(b"field".as_slice()), Some(&b"value".to_vec())),
+                (b"zset", ValueRef::ZSet(zset)) => assert_eq!(zset.score(b"member"), Some(1.5)),
+                (key, _) => panic!("unexpected value of {}", key.text()),
+            }
+            if key != b"string" {
+                assert_eq!(expiration, None);
+            }
+            keys.push(key.to_vec());
+        }
+        keys.sort();
+        assert_eq!(keys, [b"hash".to_vec(), b"list".to_vec(), b"set".to_vec(), b"string".to_vec(), b"zset".to_vec()]);
+    }
+
+    #[test]
+    fn iter_skips_expired_keys() {
+        let mut store = Store::new();
+        store.insert_value(b"live", b"1", None);
+        store.insert_value(b"expired", b"2", None);
+        store.set_expiration(b"expired", Some(SystemTime::now() - Duration::from_secs(1)));
+        assert_eq!(store.iter().map(|(key, _, _)| key).collect::<Vec<_>>(), [b"live"]);
+    }
+
+    #[test]
+    fn iter_visits_the_keys_of_all_shards_once() {
+        let mut store = Store::new();
+        for i in 0..1000 {
+            store.insert_value(format!("key:{}", i).as_bytes(), b"", None);
+        }
+        let keys = store.iter().map(|(key, _, _)| key).collect::<HashSet<_>>();
+        assert_eq!(keys.len(), 1000);
+        assert_eq!(store.iter().count(), 1000);
+    }
+
+    #[test]
+    fn iter_runs_alongside_other_readers() {
+        let store = StoreLock::new(Store::new());
+        store.write().unwrap().insert_value(b"key", b"value", None);
+        let iterating = store.read().unwrap();
+        let mut values = iterating.iter();
+        assert_eq!(store.read().unwrap().get_value(b"key"), Some(b"value".to_vec()));
+        assert!(matches!(values.next(), Some((b"key", ValueRef::String(b"value"), None))));
+    }
+
+    #[test]
+    fn load_rdb_rejects_oversized_string_lengths() {
+        // a 64 bit length for a key of 3 bytes
+        let rdb = b"REDIS0011\x00\x03key\x81\xff\xff\xff\xff\xff\xff\xff\xff";
+        let err = Store::new().load_rdb(&rdb[..], false, false).unwrap_err();
+        assert!(err.to_string().contains("byte offset"), "{}", err);
+
+        let rdb = b"REDIS0011\x00\x03key\x80\x7f\xff\xff\xffvalue";
+        let err = Store::new().load_rdb(&rdb[..], false, false).unwrap_err();
+        assert!(err.to_string().contains("truncated"), "{}", err);
+    }
+
+    #[test]
+    fn load_rdb_rejects_truncated_files() {
+        let mut store = Store::new();
+        store.insert_value(b"key", b"value", None);
+        let mut rdb = vec![];
+        store.save_rdb(&mut rdb, true).unwrap();
+        // cut anywhere inside the entry of the key, files may end between entries
+        let entry = b"\x00\x03key\x05value";
+        let start = rdb.windows(entry.len()).position(|window| window == entry).unwrap();
+        for len in start + 1..start + entry.len() {
+            assert!(Store::new().load_rdb(&rdb[..len], false, false).is_err(), "loaded {} bytes", len);
+        }
+    }
+
+    #[test]
+    fn save_rdb_resize_hint_counts_live_keys() {
+        let mut store = Store::new();
+        store.insert_value(b"live", b"1", None);
+        store.set_expiration(b"live", Some(SystemTime::now() + Duration::from_secs(60)));
+        store.insert_value(b"expired", b"2", None);
+        store.set_expiration(b"expired", Some(SystemTime::now() - Duration::from_secs(1)));
+        let mut rdb = vec![];
+        store.save_rdb(&mut rdb, true).unwrap();
+        let hint = rdb.windows(2).position(|window| window == b"\xFE\x00").unwrap() + 2;
+        assert_eq!(rdb[hint..hint + 3], [0xFB, 1, 1]);
+    }
+
+    #[test]
+    fn function_libraries_are_saved_again() {
+        let library = b"#!lua name=mylib\nredis.register_function('f', function() return 1 end)";
+        let mut rdb = b"REDIS0011\xF5".to_vec();
+        rdb::write_bytes(&mut rdb, library).unwrap();
+        // end of file without a checksum
+        rdb.extend([0xFF, 0, 0, 0, 0, 0, 0, 0, 0]);
+        let mut store = Store::new();
+        store.load_rdb(&rdb[..], false, false).unwrap();
+
+        let mut saved = vec![];
+        store.snapshot().save_rdb(&mut saved, true).unwrap();
+        let mut reloaded = Store::new();
+        reloaded.load_rdb(&saved[..], false, true).unwrap();
+        assert_eq!(reloaded.function_libraries, [library.to_vec()]);
+    }
+}
@@ -1,11 +1,13 @@
 use std::cmp::Ordering;
+use std::collections::BTreeMap;
 use std::fmt::Display;
-use std::ops::Deref;
+use std::ops::Bound;
 use std::str::FromStr;
-use std::sync::{Condvar, Mutex, Weak};
 use std::time::SystemTime;
 
-use anyhow::bail;
+use anyhow::{bail, format_err};
+
+use crate::args::Arg;
 
 #[derive(Clone, Debug)]
 pub(crate) struct StreamRecordId(u64, u64);
@@ -16,17 +18,17 @@ impl PartialEq<Self> for StreamRecordId {
     }
 }
 
+impl Eq for StreamRecordId {}
+
 impl PartialOrd for StreamRecordId {
     fn partial_cmp(&self, other: &Self) -> Option<Ordering> {
-        if self.0 > other.0 || (self.0 == other.0 && self.1 > other.1) {
-            Some(Ordering::Greater)
-        } else if self.0 < other.0 || (self.0 == other.0 && self.1 < other.1) {
-            Some(Ordering::Less)
-        } else if self.0 == other.0 && self.1 == other.1 {
-            Some(Ordering::Equal)
-        } else {
-            None
-        }
+        Some(self.cmp(other))
+    }
+}
+
+impl Ord for StreamRecordId {
+    fn cmp(&self, other: &Self) -> Ordering {
+        (self.0, self.1).cmp(&(other.0, other.1))
     }
 }
 
@@ -56,6 +58,16 @@ impl StreamRecordId {
         Self(time_id, seq_id)
     }
 
+    /// unix time in milliseconds part of the id
+    pub(crate) fn time_id(&self) -> u64 {
+        self.0
+    }
+
+    /// sequence number of the id among the ids of the same milliseconds
+    pub(crate) fn seq_id(&self) -> u64 {
+        self.1
+    }
+
     pub fn from_pattern(pattern: String, last_id: Option<&StreamRecordId>) -> anyhow::Result<Self> {
         if pattern == "*" {
             Ok(match last_id {
@@ -102,35 +114,215 @@ impl StreamRecordId {
 #[derive(Clone, Debug)]
 pub struct StreamRecord {
     pub(crate) id: StreamRecordId,
-    pub(crate) attributes: Vec<(String, String)>,
+    pub(crate) attributes: Vec<(Vec<u8>, Vec<u8>)>,
 }
 
+impl StreamRecord {
+    /// approximate bytes held by the record
+    pub(crate) fn memory_usage(&self) -> usize {
+        16 + self.attributes.iter().map(|(name, value)| name.len() + value.len() + 32).sum::<usize>()
+    }
+}
+
+/// approximate bytes of bookkeeping of a consumer group, of a consumer and of a pending entry,
+/// on top of the names of the groups and the consumers
+const GROUP_OVERHEAD: usize = 64;
+const CONSUMER_OVERHEAD: usize = 32;
+const PENDING_ENTRY_OVERHEAD: usize = 48;
+
+/// entry delivered to a consumer of a group which was not acknowledged yet
 #[derive(Clone, Debug)]
-pub(crate) struct StreamEvent {
-    pub(crate) key: String,
-    pub(crate) id: StreamRecordId,
+pub(crate) struct PendingEntry {
+    pub(crate) consumer: Vec<u8>,
+    /// unix time in milliseconds of the last delivery
+    pub(crate) delivered_ms: u64,
+    pub(crate) delivery_count: u64,
+}
+
+/// consumer of a group, known from its first read or claim
+#[derive(Clone, Debug)]
+pub(crate) struct Consumer {
+    /// unix time in milliseconds of the last read or claim
+    pub(crate) seen_ms: u64,
+    /// unix time in milliseconds of the last read or claim which delivered entries, None if there was none
+    pub(crate) active_ms: Option<u64>,
 }
 
+/// consumer group of a stream with the entries delivered to its consumers and not acknowledged yet,
+/// the pending entries list (PEL)
 #[derive(Clone, Debug)]
-pub struct StreamListener(Weak<(Mutex<Option<StreamEvent>>, Condvar)>);
+pub(crate) struct ConsumerGroup {
+    pub(crate) last_delivered: StreamRecordId,
+    pub(crate) pending: BTreeMap<StreamRecordId, PendingEntry>,
+    pub(crate) consumers: BTreeMap<Vec<u8>, Consumer>,
+}
+
+impl ConsumerGroup {
+    pub(crate) fn new(last_delivered: StreamRecordId) -> Self {
+        Self { last_delivered, pending: BTreeMap::new(), consumers: BTreeMap::new() }
+    }
 
-pub struct Stream(String, Vec<StreamRecord>, Vec<StreamListener>);
+    /// registers the consumer if it is new, returns whether it was created
+    fn touch_consumer(&mut self, consumer: &[u8], now_ms: u64) -> bool {
+        match self.consumers.get_mut(consumer) {
+            Some(existing) => {
+                existing.seen_ms = now_ms;
+                false
+            }
+            None => {
+                self.consumers.insert(consumer.to_vec(), Consumer { seen_ms: now_ms, active_ms: None });
+                true
+            }
+        }
+    }
+
+    /// records that entries were delivered to the consumer
+    fn activate_consumer(&mut self, consumer: &[u8], now_ms: u64) {
+        if let Some(consumer) = self.consumers.get_mut(consumer) {
+            consumer.active_ms = Some(now_ms);
+        }
+    }
+
+    /// approximate bytes held by the group, its consumers and its pending entries
+    fn memory_usage(&self, name: &[u8]) -> usize {
+        GROUP_OVERHEAD + name.len()
+            + self.consumers.keys().map(|consumer| CONSUMER_OVERHEAD + consumer.len()).sum::<usize>()
+            + self.pending.len() * PENDING_ENTRY_OVERHEAD
+    }
+
+    /// number of pending entries of the consumer
+    pub(crate) fn consumer_pending(&self, consumer: &[u8]) -> usize {
+        self.pending.values().filter(|entry| entry.consumer == consumer).count()
+    }
+}
+
+/// entries delivered to a consumer by id, with the fields of the entry or None if it was deleted from the stream
+pub(crate) type Delivery = Vec<(StreamRecordId, Option<Vec<(Vec<u8>, Vec<u8>)>>)>;
+
+/// options of claiming pending entries with XCLAIM
+pub(crate) struct Claim {
+    pub(crate) min_idle_ms: u64,
+    /// time of the delivery set on the claimed entries
+    pub(crate) delivered_ms: u64,
+    /// delivery count set on the claimed entries, otherwise it is incremented
+    pub(crate) retry_count: Option<u64>,
+    /// entries missing from the PEL are created as long as they are in the stream
+    pub(crate) force: bool,
+    /// the delivery count is not incremented
+    pub(crate) just_id: bool,
+    /// the last delivered id of the group is moved forward to it
+    pub(crate) last_id: Option<StreamRecordId>,
+}
+
+/// entries trimmed by default from the start of a stream by a single approximate trim
+const APPROXIMATE_TRIM_LIMIT: usize = 10_000;
+
+/// which entries are trimmed from the start of a stream
+#[derive(Clone, Debug, PartialEq)]
+pub(crate) enum TrimStrategy {
+    /// the oldest entries over the length
+    MaxLen(usize),
+    /// the entries with an id lower than the id
+    MinId(StreamRecordId),
+}
+
+/// trimming of a stream by XTRIM: `strategy [= | ~] threshold [LIMIT count]`.
+/// entries are not grouped in nodes, so an approximate trim removes the same entries as an exact one,
+/// but no more than the limit
+#[derive(Clone, Debug, PartialEq)]
+pub(crate) struct Trim {
+    strategy: TrimStrategy,
+    limit: Option<usize>,
+}
+
+impl Trim {
+    /// parses the options of a trim, returns the trim and the number of parameters read
+    pub(crate) fn parse(params: &[Vec<u8>]) -> anyhow::Result<(Self, usize)> {
+        let (Some(strategy), Some(mut threshold)) = (params.first(), params.get(1)) else {
+            bail!("ERR syntax error");
+        };
+        let mut read = 2;
+        let mut approximate = false;
+        if threshold == b"=" || threshold == b"~" {
+            approximate = threshold == b"~";
+            let Some(param) = params.get(2) else {
+                bail!("ERR syntax error");
+            };
+            threshold = param;
+            read += 1;
+        }
+        let strategy = match strategy.to_uppercase().as_str() {
+            "MAXLEN" => match threshold.parse::<i64>() {
+                Ok(max_len) if max_len >= 0 => TrimStrategy::MaxLen(max_len as usize),
+                Ok(_) => bail!("ERR The MAXLEN argument must be >= 0."),
+                Err(_) => bail!("ERR value is not an integer or out of range"),
+            },
+            "MINID" => TrimStrategy::MinId(
+                threshold.parse().map_err(|_| format_err!("ERR Invalid stream ID specified as stream command argument"))?,
+            ),
+            _ => bail!("ERR syntax error"),
+        };
+        let mut limit = approximate.then_some(APPROXIMATE_TRIM_LIMIT);
+        if params.get(read).is_some_and(|param| param.eq_ignore_ascii_case(b"LIMIT")) {
+            let count = match params.get(read + 1).map(|count| count.parse::<i64>()) {
+                Some(Ok(count)) if count >= 0 => count as usize,
+                Some(_) => bail!("ERR The LIMIT argument must be >= 0."),
+                None => bail!("ERR syntax error"),
+            };
+            if !approximate {
+                bail!("ERR syntax error, LIMIT cannot be used without the special ~ option");
+            }
+            // a limit of 0 trims without limit
+            limit = (count > 0).then_some(count);
+            read += 2;
+        }
+        Ok((Trim { strategy, limit }, read))
+    }
+}
+
+#[derive(Clone)]
+pub struct Stream {
+    records: Vec<StreamRecord>,
+    groups: BTreeMap<Vec<u8>, ConsumerGroup>,
+    /// id of the last entry added, kept when the entry is deleted so that ids are never reused
+    last_id: StreamRecordId,
+    /// number of entries added over the lifetime of the stream, including deleted ones
+    entries_added: u64,
+    /// highest id of the entries deleted or trimmed from the stream
+    max_deleted_id: StreamRecordId,
+}
 
 impl Stream {
-    pub fn new(key: String) -> Self {
-        Stream(key.to_string(), Vec::new(), Vec::new())
+    pub fn new() -> Self {
+        Stream {
+            records: Vec::new(),
+            groups: BTreeMap::new(),
+            last_id: StreamRecordId::MIN,
+            entries_added: 0,
+            max_deleted_id: StreamRecordId::MIN,
+        }
+    }
+
+    /// stream with the entries, metadata and consumer groups it had, as loaded from an RDB file
+    pub(crate) fn restore(
+        records: Vec<StreamRecord>,
+        last_id: StreamRecordId,
+        entries_added: u64,
+        max_deleted_id: StreamRecordId,
+        groups: BTreeMap<Vec<u8>, ConsumerGroup>,
+    ) -> Self {
+        Stream { records, groups, last_id, entries_added, max_deleted_id }
     }
 
     pub(crate) fn add_entry(
         &mut self,
         id_pattern: String,
-        entry: Vec<(String, String)>,
-    ) -> anyhow::Result<String> {
-        let entries = &mut self.1;
-        let listeners = &mut self.2;
+        entry: Vec<(Vec<u8>, Vec<u8>)>,
+    ) -> anyhow::Result<StreamRecordId> {
+        let last_id = (self.last_id != StreamRecordId::MIN).then_some(&self.last_id);
         // new id is either explicit or pattern
         let new_id: StreamRecordId = if id_pattern.contains('*') {
-            StreamRecordId::from_pattern(id_pattern, entries.last().map(|e| &e.id))?
+            StreamRecordId::from_pattern(id_pattern, last_id)?
         } else {
             id_pattern.parse()?
         };
@@ -139,46 +331,18 @@ impl Stream {
             bail!("ERR The ID specified in XADD must be greater than 0-0");
         }
 
-        if let Some(last) = entries.last() {
-            let last_id = &last.id;
-            if &new_id <= last_id {
-                bail!("ERR The ID specified in XADD is equal or smaller than the target stream top item");
-            }
+        if last_id.is_some_and(|last_id| &new_id <= last_id) {
+            bail!("ERR The ID specified in XADD is equal or smaller than the target stream top item");
         }
-        let new_ids = new_id.to_string();
         let stream_entry = StreamRecord {
             id: new_id.clone(),
             attributes: entry,
         };
-        entries.push(stream_entry.clone());
-
-        let event = StreamEvent {
-            key: self.0.clone(),
-            id: new_id,
-        };
-
-        // notify listeners while removing the dropped ones
-        listeners.retain_mut(|l| {
-            if let Some(arc) = l.0.upgrade() {
-                let (lock, cvar) = arc.deref();
-                lock.lock().unwrap().replace(event.clone());
-                cvar.notify_one();
-                true
-            } else {
-                false
-            }
-        });
-
-        Ok(new_ids)
-    }
+        self.records.push(stream_entry);
+        self.last_id = new_id.clone();
+        self.entries_added += 1;
 
-    pub(crate) fn add_listener(
-        &mut self,
-        listener: Weak<(Mutex<Option<StreamEvent>>, Condvar)>,
-    ) -> anyhow::Result<StreamListener> {
-        let listener1 = StreamListener(listener);
-        self.2.push(listener1.clone());
-        Ok(listener1)
+        Ok(new_id)
     }
 
     pub(crate) fn range(
@@ -187,7 +351,7 @@ impl Stream {
         to_id: &StreamRecordId,
         inclusive_range: bool,
     ) -> anyhow::Result<Vec<&StreamRecord>> {
-        Ok(self.1
+        Ok(self.records
             .iter()
             .filter(|&e| {
                 let inclusive = e.id >= *from_id && e.id <= *to_id;
@@ -201,7 +365,282 @@ impl Stream {
             .collect())
     }
 
+    /// number of records of the stream
+    pub(crate) fn len(&self) -> usize {
+        self.records.len()
+    }
+
+    /// approximate bytes held by the records and the consumer groups of the stream
+    pub(crate) fn memory_usage(&self) -> usize {
+        self.records.iter().map(|record| record.memory_usage()).sum::<usize>() + self.groups_memory_usage()
+    }
+
+    /// approximate bytes held by the consumer groups, which does not walk the pending entries
+    pub(crate) fn groups_memory_usage(&self) -> usize {
+        self.groups.iter().map(|(name, group)| group.memory_usage(name)).sum()
+    }
+
+    pub(crate) fn first(&self) -> Option<&StreamRecord> {
+        self.records.first()
+    }
+
+    pub(crate) fn last(&self) -> Option<&StreamRecord> {
+        self.records.last()
+    }
+
+    /// id of the last entry added, also if it was deleted since
     pub(crate) fn last_id(&self) -> anyhow::Result<StreamRecordId> {
-        Ok(self.1.last().map_or(StreamRecordId::MIN, |e| e.id.clone()))
+        Ok(self.last_id.clone())
+    }
+
+    pub(crate) fn entries_added(&self) -> u64 {
+        self.entries_added
+    }
+
+    pub(crate) fn max_deleted_id(&self) -> &StreamRecordId {
+        &self.max_deleted_id
+    }
+
+    /// number of entries added to the stream up to the id, which a group delivered up to the id has read.
+    /// None when it can't be told because entries were deleted after the first entry of the stream
+    pub(crate) fn entries_read(&self, id: &StreamRecordId) -> Option<u64> {
+        if self.entries_added == 0 {
+            return Some(0);
+        }
+        if *id >= self.last_id {
+            return Some(self.entries_added);
+        }
+        match self.records.first() {
+            Some(first) if self.max_deleted_id < first.id => {
+                let after = self.records.len() - self.records.partition_point(|record| record.id <= *id);
+                Some(self.entries_added - after as u64)
+            }
+            _ => None,
+        }
+    }
+
+    /// removes the entries with the ids, returns the removed entries
+    pub(crate) fn delete(&mut self, ids: &[StreamRecordId]) -> Vec<StreamRecord> {
+        let mut deleted = vec![];
+        for id in ids {
+            if let Ok(index) = self.records.binary_search_by(|record| record.id.cmp(id)) {
+                deleted.push(self.records.remove(index));
+            }
+        }
+        self.deleted(&deleted);
+        deleted
+    }
+
+    /// removes the entries from the start of the stream as selected by the trim, returns the removed entries
+    pub(crate) fn trim(&mut self, trim: &Trim) -> Vec<StreamRecord> {
+        let mut count = match &trim.strategy {
+            TrimStrategy::MaxLen(max_len) => self.records.len().saturating_sub(*max_len),
+            TrimStrategy::MinId(min_id) => self.records.partition_point(|record| record.id < *min_id),
+        };
+        if let Some(limit) = trim.limit {
+            count = count.min(limit);
+        }
+        let trimmed = self.records.drain(..count).collect::<Vec<StreamRecord>>();
+        self.deleted(&trimmed);
+        trimmed
+    }
+
+    fn deleted(&mut self, records: &[StreamRecord]) {
+        if let Some(max) = records.iter().map(|record| &record.id).max() {
+            if *max > self.max_deleted_id {
+                self.max_deleted_id = max.clone();
+            }
+        }
     }
+
+    /// sets the last id and optionally the metadata of the stream, as XSETID does
+    pub(crate) fn set_id(
+        &mut self,
+        last_id: StreamRecordId,
+        entries_added: Option<u64>,
+        max_deleted_id: Option<StreamRecordId>,
+    ) -> anyhow::Result<()> {
+        if self.records.last().is_some_and(|last| last_id < last.id) {
+            bail!("ERR The ID specified in XSETID is smaller than the target stream top item");
+        }
+        if entries_added.is_some_and(|entries_added| entries_added < self.records.len() as u64) {
+            bail!("ERR The entries_added specified in XSETID is smaller than the target stream length");
+        }
+        if max_deleted_id.as_ref().is_some_and(|max_deleted_id| last_id < *max_deleted_id) {
+            bail!("ERR The ID specified in XSETID is smaller than the provided max_deleted_entry_id");
+        }
+        if max_deleted_id.is_none() && last_id < self.max_deleted_id {
+            bail!("ERR The ID specified in XSETID is smaller than current max_deleted_entry_id");
+        }
+        self.last_id = last_id;
+        if let Some(entries_added) = entries_added {
+            self.entries_added = entries_added;
+        }
+        if let Some(max_deleted_id) = max_deleted_id {
+            self.max_deleted_id = max_deleted_id;
+        }
+        Ok(())
+    }
+
+    pub(crate) fn groups(&self) -> impl Iterator<Item=(&Vec<u8>, &ConsumerGroup)> {
+        self.groups.iter()
+    }
+
+    pub(crate) fn records(&self) -> &[StreamRecord] {
+        &self.records
+    }
+
+    pub(crate) fn group(&self, group: &[u8]) -> Option<&ConsumerGroup> {
+        self.groups.get(group)
+    }
+
+    /// creates a group delivering the entries after the id, returns false if the group exists
+    pub(crate) fn create_group(&mut self, group: &[u8], last_delivered: StreamRecordId) -> bool {
+        if self.groups.contains_key(group) {
+            return false;
+        }
+        self.groups.insert(group.to_vec(), ConsumerGroup::new(last_delivered));
+        true
+    }
+
+    pub(crate) fn destroy_group(&mut self, group: &[u8]) -> bool {
+        self.groups.remove(group).is_some()
+    }
+
+    /// sets the last delivered id of the group, None if the group does not exist
+    pub(crate) fn set_group_id(&mut self, group: &[u8], last_delivered: StreamRecordId) -> Option<()> {
+        self.groups.get_mut(group)?.last_delivered = last_delivered;
+        Some(())
+    }
+
+    /// returns whether the consumer was created, None if the group does not exist
+    pub(crate) fn create_consumer(&mut self, group: &[u8], consumer: &[u8], now_ms: u64) -> Option<bool> {
+        let group = self.groups.get_mut(group)?;
+        if group.consumers.contains_key(consumer) {
+            return Some(false);
+        }
+        Some(group.touch_consumer(consumer, now_ms))
+    }
+
+    /// removes the consumer with its pending entries, returns the number of pending entries it had.
+    /// None if the group does not exist
+    pub(crate) fn delete_consumer(&mut self, group: &[u8], consumer: &[u8]) -> Option<usize> {
+        let group = self.groups.get_mut(group)?;
+        if group.consumers.remove(consumer).is_none() {
+            return Some(0);
+        }
+        let before = group.pending.len();
+        group.pending.retain(|_, entry| entry.consumer != consumer);
+        Some(before - group.pending.len())
+    }
+
+    /// delivers up to count entries to the consumer, None if the group does not exist.
+    /// without a start id the entries never delivered to the group are read and added to the PEL, unless `no_ack`.
+    /// with a start id the pending entries of the consumer after it are delivered again
+    pub(crate) fn read_group(
+        &mut self,
+        group: &[u8],
+        consumer: &[u8],
+        after: Option<&StreamRecordId>,
+        count: usize,
+        no_ack: bool,
+        now_ms: u64,
+    ) -> Option<Delivery> {
+        let group = self.groups.get_mut(group)?;
+        group.touch_consumer(consumer, now_ms);
+        let records = &self.records;
+        let Some(after) = after else {
+            let start = records.partition_point(|record| record.id <= group.last_delivered);
+            let delivered = records[start..].iter().take(count).collect::<Vec<&StreamRecord>>();
+            if let Some(last) = delivered.last() {
+                group.last_delivered = last.id.clone();
+                group.activate_consumer(consumer, now_ms);
+            }
+            if !no_ack {
+                for record in &delivered {
+                    let entry = group.pending.entry(record.id.clone()).or_insert_with(|| PendingEntry {
+                        consumer: consumer.to_vec(),
+                        delivered_ms: now_ms,
+                        delivery_count: 0,
+                    });
+                    entry.consumer = consumer.to_vec();
+                    entry.delivered_ms = now_ms;
+                    entry.delivery_count += 1;
+                }
+            }
+            return Some(delivered.iter().map(|record| (record.id.clone(), Some(record.attributes.clone()))).collect());
+        };
+        let mut delivery = vec![];
+        for (id, entry) in group.pending.range_mut((Bound::Excluded(after), Bound::Unbounded)) {
+            if delivery.len() == count {
+                break;
+            }
+            if entry.consumer != consumer {
+                continue;
+            }
+            entry.delivered_ms = now_ms;
+            entry.delivery_count += 1;
+            delivery.push((id.clone(), find_record(records, id).map(|record| record.attributes.clone())));
+        }
+        if !delivery.is_empty() {
+            group.activate_consumer(consumer, now_ms);
+        }
+        Some(delivery)
+    }
+
+    /// acknowledges the pending entries, returns the number of acknowledged entries, None if the group does not exist
+    pub(crate) fn ack(&mut self, group: &[u8], ids: &[StreamRecordId]) -> Option<usize> {
+        let group = self.groups.get_mut(group)?;
+        Some(ids.iter().filter(|id| group.pending.remove(id).is_some()).count())
+    }
+
+    /// changes the owner of the pending entries idle for at least the minimal time to the consumer.
+    /// pending entries deleted from the stream are removed from the PEL and not claimed.
+    /// returns the claimed entries, None if the group does not exist
+    pub(crate) fn claim(&mut self, group: &[u8], consumer: &[u8], ids: &[StreamRecordId], claim: &Claim, now_ms: u64) -> Option<Delivery> {
+        let group = self.groups.get_mut(group)?;
+        let records = &self.records;
+        if let Some(last_id) = &claim.last_id {
+            if *last_id > group.last_delivered {
+                group.last_delivered = last_id.clone();
+            }
+        }
+        group.touch_consumer(consumer, now_ms);
+        let mut claimed = vec![];
+        for id in ids {
+            let Some(attributes) = find_record(records, id).map(|record| record.attributes.clone()) else {
+                group.pending.remove(id);
+                continue;
+            };
+            let entry = match group.pending.get_mut(id) {
+                Some(entry) => entry,
+                None if claim.force => group.pending.entry(id.clone()).or_insert(PendingEntry {
+                    consumer: consumer.to_vec(),
+                    delivered_ms: now_ms,
+                    delivery_count: 0,
+                }),
+                None => continue,
+            };
+            if now_ms.saturating_sub(entry.delivered_ms) < claim.min_idle_ms {
+                continue;
+            }
+            entry.consumer = consumer.to_vec();
+            entry.delivered_ms = claim.delivered_ms;
+            match claim.retry_count {
+                Some(retry_count) => entry.delivery_count = retry_count,
+                None if !claim.just_id => entry.delivery_count += 1,
+                None => {}
+            }
+            claimed.push((id.clone(), Some(attributes)));
+        }
+        if !claimed.is_empty() {
+            group.activate_consumer(consumer, now_ms);
+        }
+        Some(claimed)
+    }
+}
+
+/// record with the id in records ordered by id
+fn find_record<'a>(records: &'a [StreamRecord], id: &StreamRecordId) -> Option<&'a StreamRecord> {
+    records.binary_search_by(|record| record.id.cmp(id)).ok().map(|index| &records[index])
 }
@@ -0,0 +1,275 @@
+use std::cell::Cell;
+use std::sync::{Arc, Condvar, Mutex};
+use std::sync::atomic::{AtomicU64, Ordering};
+
+use crate::protocol::command::CommandRequest;
+use crate::protocol::resp::RESP;
+use crate::redis::RedisServer;
+
+/// maximal number of commands queued by a transaction, whatever their size
+const MAX_QUEUED_COMMANDS: usize = 100_000;
+
+/// command queued with the message it was received in, which is replicated as is
+pub(crate) type QueuedCommand = (usize, RESP, CommandRequest);
+
+/// lock which keeps the commands of other clients out of a transaction.
+///
+/// EXEC, and the snapshot of a full resync, hold the lock exclusively, while the other commands share it from their
+/// execution to their replication. a blocked command releases its share while it waits, so that it doesn't hold up
+/// transactions, and takes it again before changing the store
+#[derive(Default)]
+pub(crate) struct ExecLock {
+    state: Mutex<ExecLockState>,
+    changed: Condvar,
+}
+
+#[derive(Default)]
+struct ExecLockState {
+    shares: usize,
+    exclusive: bool,
+    /// threads waiting to hold the lock exclusively, new shares wait for them so that transactions are not starved
+    waiting_exclusive: usize,
+}
+
+thread_local! {
+    /// the command running on the thread holds a share of the exec lock
+    static HOLDS_SHARE: Cell<bool> = const { Cell::new(false) };
+}
+
+impl ExecLock {
+    /// shares the lock for running a command, until the guard is dropped
+    pub(crate) fn shared(&self) -> SharedExec<'_> {
+        self.acquire_shared();
+        HOLDS_SHARE.with(|holds| holds.set(true));
+        SharedExec(self)
+    }
+
+    /// holds the lock exclusively, once all commands sharing it completed or wait
+    pub(crate) fn exclusive(&self) -> ExclusiveExec<'_> {
+        debug_assert!(!HOLDS_SHARE.with(Cell::get), "exec lock taken exclusively while shared by the thread");
+        let mut state = self.state.lock().unwrap();
+        state.waiting_exclusive += 1;
+        let mut state = self.changed.wait_while(state, |state| state.exclusive || state.shares > 0).unwrap();
+        state.waiting_exclusive -= 1;
+        state.exclusive = true;
+        ExclusiveExec(self)
+    }
+
+    /// releases the share of the command running on the thread while it waits, taking it again after
+    pub(crate) fn released_while<T>(&self, wait: impl FnOnce() -> T) -> T {
+        if !HOLDS_SHARE.with(Cell::get) {
+            return wait();
+        }
+        self.release_shared();
+        // the share is taken again also when waiting panics, as its guard releases it
+        let _reacquire = Reacquire(self);
+        wait()
+    }
+
+    fn acquire_shared(&self) {
+        let state = self.state.lock().unwrap();
+        let mut state = self.changed.wait_while(state, |state| state.exclusive || state.waiting_exclusive > 0).unwrap();
+        state.shares += 1;
+    }
+
+    fn release_shared(&self) {
+        self.state.lock().unwrap().shares -= 1;
+        self.changed.notify_all();
+    }
+}
+
+/// share of the exec lock held by a running command
+pub(crate) struct SharedExec<'a>(&'a ExecLock);
+
+impl Drop for SharedExec<'_> {
+    fn drop(&mut self) {
+        HOLDS_SHARE.with(|holds| holds.set(false));
+        self.0.release_shared();
+    }
+}
+
+/// exec lock held exclusively
+pub(crate) struct ExclusiveExec<'a>(&'a ExecLock);
+
+impl Drop for ExclusiveExec<'_> {
+    fn drop(&mut self) {
+        self.0.state.lock().unwrap().exclusive = false;
+        self.0.changed.notify_all();
+    }
+}
+
+struct Reacquire<'a>(&'a ExecLock);
+
+impl Drop for Reacquire<'_> {
+    fn drop(&mut self) {
+        self.0.acquire_shared();
+    }
+}
+
+/// commands queued by a client between MULTI and EXEC.
+///
+/// the queued messages count as memory of the client, limited by maxmemory-clients for all clients together.
+/// a command which can't be queued aborts the transaction, which EXEC then discards
+pub struct Transaction {
+    queued: Vec<QueuedCommand>,
+    queued_bytes: u64,
+    aborted: bool,
+    client_memory: Arc<AtomicU64>,
+}
+
+impl Transaction {
+    pub fn new(server: &RedisServer) -> Self {
+        Self {
+            queued: vec![],
+            queued_bytes: 0,
+            aborted: false,
+            client_memory: server.client_memory.clone(),
+        }
+    }
+
+    /// queues the command for EXEC, replying QUEUED or the error which aborted the transaction
+    pub fn queue(&mut self, server: &RedisServer, message_bytes: usize, message: RESP, command: CommandRequest) -> RESP {
+        if command.may_block() {
+            return self.abort(format!("ERR Command {} is not allowed inside a transaction", command.0));
+        }
+        // an aborted transaction is discarded by EXEC, the commands following the failure are not kept
+        if self.aborted {
+            return RESP::String("QUEUED".to_string());
+        }
+        if self.queued.len() >= MAX_QUEUED_COMMANDS {
+            return self.abort(format!("ERR Transaction exceeds the limit of {} queued commands", MAX_QUEUED_COMMANDS));
+        }
+        let limit = server.config.read().unwrap().get_bytes("maxmemory-clients").unwrap_or(0);
+        let client_memory = self.client_memory.fetch_add(message_bytes as u64, Ordering::Relaxed) + message_bytes as u64;
+        self.queued_bytes += message_bytes as u64;
+        if limit > 0 && client_memory > limit {
+            return self.abort("ERR Transaction exceeds the memory of clients limited by 'maxmemory-clients'".to_string());
+        }
+        self.queued.push((message_bytes, message, command));
+        RESP::String("QUEUED".to_string())
+    }
+
+    /// number of commands queued so far
+    pub fn queued_count(&self) -> usize {
+        self.queued.len()
+    }
+
+    /// the queued commands, or EXECABORT if queuing failed
+    pub fn into_commands(mut self) -> Result<Vec<QueuedCommand>, RESP> {
+        if self.aborted {
+            return Err(RESP::Error("EXECABORT Transaction discarded because of previous errors.".to_string()));
+        }
+        Ok(std::mem::take(&mut self.queued))
+    }
+
+    /// discards the queued commands, releasing their memory, and fails the transaction.
+    /// replies with the error of the command which could not be queued
+    pub fn abort(&mut self, error: String) -> RESP {
+        self.aborted = true;
+        self.queued.clear();
+        self.release();
+        RESP::Error(error)
+    }
+
+    fn release(&mut self) {
+        self.client_memory.fetch_sub(self.queued_bytes, Ordering::Relaxed);
+        self.queued_bytes = 0;
+    }
+}
+
+/// keys watched by a client with WATCH, with the versions they had then.
+///
+/// EXEC fails if any of them was written, removed or expired since, even if the value is the same
+#[derive(Default)]
+pub struct WatchedKeys {
+    keys: Vec<Vec<u8>>,
+    versions: Vec<Option<u64>>,
+}
+
+impl WatchedKeys {
+    /// watches the keys at their current version, keys already watched keep the version they were watched at
+    pub fn watch(&mut self, server: &RedisServer, keys: &[Vec<u8>]) {
+        let keys = keys.iter().filter(|key| !self.keys.contains(key)).cloned().collect::<Vec<Vec<u8>>>();
+        self.versions.extend(server.key_versions(&keys));
+        self.keys.extend(keys);
+    }
+
+    /// whether any watched key changed since it was watched
+    pub fn is_modified(&self, server: &RedisServer) -> bool {
+        !self.keys.is_empty() && server.key_versions(&self.keys) != self.versions
+    }
+
+    pub fn clear(&mut self) {
+        self.keys.clear();
+        self.versions.clear();
+    }
+}
+
+impl Drop for Transaction {
+    fn drop(&mut self) {
+        self.release();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::mpsc;
+    use std::thread;
+    use std::time::Duration;
+
+    use super::*;
+
+    #[test]
+    fn exclusive_waits_for_the_shares() {
+        let lock = Arc::new(ExecLock::default());
+        let shared = lock.shared();
+        let (tx, rx) = mpsc::channel();
+        let exclusive = {
+            let lock = lock.clone();
+            thread::spawn(move || {
+                let _exclusive = lock.exclusive();
+                tx.send(()).unwrap();
+            })
+        };
+        assert!(rx.recv_timeout(Duration::from_millis(100)).is_err());
+        drop(shared);
+        rx.recv_timeout(Duration::from_secs(5)).unwrap();
+        exclusive.join().unwrap();
+    }
+
+    #[test]
+    fn share_is_released_while_waiting_and_taken_again_after_exclusive() {
+        let lock = Arc::new(ExecLock::default());
+        let (tx, rx) = mpsc::channel();
+        let waiting = {
+            let lock = lock.clone();
+            thread::spawn(move || {
+                let _shared = lock.shared();
+                lock.released_while(|| rx.recv().unwrap());
+                // the share is held again, so the lock is not exclusive anymore
+                assert!(!lock.state.lock().unwrap().exclusive);
+                assert_eq!(lock.state.lock().unwrap().shares, 1);
+            })
+        };
+        // the waiting command does not keep the transaction from running
+        let exclusive = loop {
+            if lock.state.lock().unwrap().shares == 0 {
+                break lock.exclusive();
+            }
+            thread::sleep(Duration::from_millis(1));
+        };
+        tx.send(()).unwrap();
+        thread::sleep(Duration::from_millis(50));
+        assert!(!waiting.is_finished());
+        drop(exclusive);
+        waiting.join().unwrap();
+        assert_eq!(lock.state.lock().unwrap().shares, 0);
+    }
+
+    #[test]
+    fn released_while_without_share_just_waits() {
+        let lock = ExecLock::default();
+        let _exclusive = lock.exclusive();
+        assert_eq!(lock.released_while(|| 42), 42);
+    }
+}
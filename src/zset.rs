@@ -0,0 +1,222 @@
+use std::cmp::Ordering;
+use std::collections::{BTreeSet, HashMap};
+use std::ops::Bound;
+
+use anyhow::bail;
+
+use crate::args::Arg;
+use crate::range::normalize_range;
+
+/// score of a member, ordered with the total order of floats so that it can be a key.
+/// NaN is never stored, commands reject it
+#[derive(Clone, Copy, Debug)]
+struct Score(f64);
+
+impl PartialEq for Score {
+    fn eq(&self, other: &Self) -> bool {
+        self.cmp(other) == Ordering::Equal
+    }
+}
+
+impl Eq for Score {}
+
+impl PartialOrd for Score {
+    fn partial_cmp(&self, other: &Self) -> Option<Ordering> {
+        Some(self.cmp(other))
+    }
+}
+
+impl Ord for Score {
+    fn cmp(&self, other: &Self) -> Ordering {
+        self.0.total_cmp(&other.0)
+    }
+}
+
+/// conditions of ZADD on the members it adds or updates
+#[derive(Clone, Copy, Default)]
+pub(crate) struct AddOptions {
+    /// only adds new members
+    pub(crate) nx: bool,
+    /// only updates existing members
+    pub(crate) xx: bool,
+    /// only updates to a greater score
+    pub(crate) gt: bool,
+    /// only updates to a lower score
+    pub(crate) lt: bool,
+    /// the score is added to the current score of the member
+    pub(crate) incr: bool,
+}
+
+/// what adding a member did to the sorted set
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub(crate) enum Added {
+    New,
+    Updated,
+    Unchanged,
+    /// the conditions prevented the change
+    Skipped,
+}
+
+/// members selected by ZRANGE
+#[derive(Clone, Debug)]
+pub(crate) enum ZRange {
+    /// positions between the indexes inclusive, negative indexes count from the end
+    Rank(i64, i64),
+    /// scores between the bounds
+    Score(Bound<f64>, Bound<f64>),
+    /// members between the bounds, when all members have the same score
+    Lex(Bound<Vec<u8>>, Bound<Vec<u8>>),
+}
+
+/// members ordered by their score, members with the same score are ordered lexicographically
+#[derive(Clone, Debug, Default)]
+pub struct SortedSet {
+    scores: HashMap<Vec<u8>, f64>,
+    ordered: BTreeSet<(Score, Vec<u8>)>,
+}
+
+impl SortedSet {
+    pub(crate) fn len(&self) -> usize {
+        self.scores.len()
+    }
+
+    pub(crate) fn is_empty(&self) -> bool {
+        self.scores.is_empty()
+    }
+
+    pub(crate) fn score(&self, member: &[u8]) -> Option<f64> {
+        self.scores.get(member).copied()
+    }
+
+    /// position of the member from the lowest score, or from the highest with `rev`
+    pub(crate) fn rank(&self, member: &[u8], rev: bool) -> Option<usize> {
+        let score = self.score(member)?;
+        let below = self.ordered.range(..(Score(score), member.to_vec())).count();
+        Some(if rev { self.len() - 1 - below } else { below })
+    }
+
+    /// adds the member or updates its score as allowed by the options, returns the change with the score of the member
+    pub(crate) fn add(&mut self, member: &[u8], score: f64, options: &AddOptions) -> anyhow::Result<(Added, f64)> {
+        let current = self.score(member);
+        let score = match (current, options.incr) {
+            (Some(current), true) => current + score,
+            _ => score,
+        };
+        if score.is_nan() {
+            bail!("ERR resulting score is not a number (NaN)");
+        }
+        let allowed = match current {
+            None => !options.xx,
+            Some(current) => !options.nx && (!options.gt || score > current) && (!options.lt || score < current),
+        };
+        match current {
+            _ if !allowed => Ok((Added::Skipped, current.unwrap_or(score))),
+            Some(current) if current == score => Ok((Added::Unchanged, score)),
+            current => {
+                self.insert(member, score);
+                Ok((if current.is_some() { Added::Updated } else { Added::New }, score))
+            }
+        }
+    }
+
+    /// sets the score of the member, returns whether the member is new
+    pub(crate) fn insert(&mut self, member: &[u8], score: f64) -> bool {
+        // negative zero is stored as zero, so that it is not ordered before it
+        let score = score + 0.0;
+        let previous = self.scores.insert(member.to_vec(), score);
+        if let Some(previous) = previous {
+            self.ordered.remove(&(Score(previous), member.to_vec()));
+        }
+        self.ordered.insert((Score(score), member.to_vec()));
+        previous.is_none()
+    }
+
+    /// removes the member, returns whether it existed
+    pub(crate) fn remove(&mut self, member: &[u8]) -> bool {
+        match self.scores.remove(member) {
+            Some(score) => self.ordered.remove(&(Score(score), member.to_vec())),
+            None => false,
+        }
+    }
+
+    /// members in the range with their scores, from the lowest score or from the highest with `rev`.
+    /// the limit skips a number of the selected members and returns up to a count of the rest, all without a count
+    pub(crate) fn range(&self, range: &ZRange, rev: bool, limit: Option<(usize, Option<usize>)>) -> Vec<(Vec<u8>, f64)> {
+        let in_order: Box<dyn Iterator<Item=&(Score, Vec<u8>)>> = if rev {
+            Box::new(self.ordered.iter().rev())
+        } else {
+            Box::new(self.ordered.iter())
+        };
+        let selected: Box<dyn Iterator<Item=&(Score, Vec<u8>)>> = match range {
+            ZRange::Rank(start, stop) => match normalize_range(self.len(), *start, *stop) {
+                Some(positions) => Box::new(in_order.skip(positions.start).take(positions.len())),
+                None => Box::new(std::iter::empty()),
+            },
+            ZRange::Score(min, max) => {
+                let (min, max) = (*min, *max);
+                Box::new(in_order.filter(move |(score, _)| above(&min, &score.0) && below(&max, &score.0)))
+            }
+            ZRange::Lex(min, max) => Box::new(in_order.filter(move |(_, member)| {
+                above(&min.as_ref().map(Vec::as_slice), &member.as_slice())
+                    && below(&max.as_ref().map(Vec::as_slice), &member.as_slice())
+            })),
+        };
+        let (offset, count) = limit.unwrap_or((0, None));
+        selected
+            .skip(offset)
+            .take(count.unwrap_or(usize::MAX))
+            .map(|(score, member)| (member.clone(), score.0))
+            .collect()
+    }
+
+    pub(crate) fn members(&self) -> impl Iterator<Item=&Vec<u8>> {
+        self.scores.keys()
+    }
+}
+
+/// value is not below the lower bound
+fn above<T: PartialOrd>(min: &Bound<T>, value: &T) -> bool {
+    match min {
+        Bound::Included(min) => value >= min,
+        Bound::Excluded(min) => value > min,
+        Bound::Unbounded => true,
+    }
+}
+
+/// value is not above the upper bound
+fn below<T: PartialOrd>(max: &Bound<T>, value: &T) -> bool {
+    match max {
+        Bound::Included(max) => value <= max,
+        Bound::Excluded(max) => value < max,
+        Bound::Unbounded => true,
+    }
+}
+
+/// parses a score, which can be infinite but not NaN
+pub(crate) fn parse_score(score: &[u8]) -> Option<f64> {
+    score.parse::<f64>().ok().filter(|score| !score.is_nan())
+}
+
+/// parses a score bound of ZRANGE BYSCORE: a score, inclusive, or a score prefixed with `(`, exclusive
+pub(crate) fn parse_score_bound(bound: &[u8]) -> Option<Bound<f64>> {
+    match bound.strip_prefix(b"(") {
+        Some(score) => parse_score(score).map(Bound::Excluded),
+        None => parse_score(bound).map(Bound::Included),
+    }
+}
+
+/// parses a member bound of ZRANGE BYLEX: `[member` inclusive, `(member` exclusive,
+/// and `-` or `+` for no bound, which callers check to be on the right side
+pub(crate) fn parse_lex_bound(bound: &[u8]) -> Option<Bound<Vec<u8>>> {
+    match bound {
+        b"-" | b"+" => Some(Bound::Unbounded),
+        [b'[', member @ ..] => Some(Bound::Included(member.to_vec())),
+        [b'(', member @ ..] => Some(Bound::Excluded(member.to_vec())),
+        _ => None,
+    }
+}
+
+/// score as replied to clients, the shortest representation which parses back to the same score
+pub(crate) fn format_score(score: f64) -> String {
+    score.to_string()
+}